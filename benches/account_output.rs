@@ -0,0 +1,57 @@
+//! End phase of a run: the account report of every possible client, written with
+//! `csv::Writer::serialize` and with `AccountCsvWriter`. Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+use trx_processor::account_csv::AccountCsvWriter;
+use trx_processor::model::account::{Account, AccountRow};
+
+const ROUNDS: u32 = 20;
+
+/// One account per client id, as many as a report can have
+fn rows() -> Vec<AccountRow> {
+    (0..=u16::MAX)
+        .map(|client| {
+            let mut account = Account::new(client);
+            account.available = Decimal::new(client as i64 * 12_345, 4);
+            account.held = Decimal::new(client as i64 % 7 * 10_000, 4);
+            AccountRow::V1(account.to_output())
+        })
+        .collect()
+}
+
+fn time(name: &str, mut write: impl FnMut(&mut Vec<u8>)) -> Duration {
+    let mut output = Vec::with_capacity(4 << 20);
+    let started = Instant::now();
+    for _ in 0..ROUNDS {
+        output.clear();
+        write(&mut output);
+        black_box(&output);
+    }
+    let elapsed = started.elapsed() / ROUNDS;
+    println!("{:<10} {:>8.2?} per report of {} bytes", name, elapsed, output.len());
+    elapsed
+}
+
+fn main() {
+    let rows = rows();
+
+    let serde = time("serde", |output| {
+        let mut writer = csv::Writer::from_writer(output);
+        for row in &rows {
+            writer.serialize(row).unwrap();
+        }
+        writer.flush().unwrap();
+    });
+    let manual = time("manual", |output| {
+        let mut writer = AccountCsvWriter::new(output);
+        for row in &rows {
+            writer.write(row).unwrap();
+        }
+        writer.flush().unwrap();
+    });
+
+    println!("speedup    {:.1}x", serde.as_secs_f64() / manual.as_secs_f64());
+}
@@ -0,0 +1,69 @@
+//! Storing the deposits of a big file: the slabs of `MemoryTransactionStore`, sized as they
+//! grow and up front, against the `DashMap` of whole transactions the store used to be.
+//! Every variant runs in a process of its own, so each reports its own peak RSS. Run with
+//! `cargo bench --bench transaction_store`.
+
+use std::env;
+use std::hint::black_box;
+use std::process::Command;
+use std::time::Instant;
+
+use dashmap::DashMap;
+use rust_decimal::Decimal;
+use trx_processor::model::transaction::{Transaction, TransactionType, TxId};
+use trx_processor::resources;
+use trx_processor::store::{MemoryTransactionStore, TransactionStore};
+
+const TRANSACTIONS: u64 = 2_000_000;
+const CLIENTS: u64 = 1_000;
+/// Set to the variant a child process runs
+const VARIANT_ENV: &str = "TRX_BENCH_VARIANT";
+const VARIANTS: [&str; 3] = ["dashmap", "slabs", "slabs-presized"];
+
+fn deposit(tx: u64) -> Transaction {
+    Transaction::new(TxId::Numeric(tx), (tx % CLIENTS) as u16, TransactionType::Deposit, Decimal::new(tx as i64 % 100_000, 2), None)
+}
+
+/// Stores every deposit and prints the time it took and the peak RSS it added
+fn run(variant: &str) {
+    let baseline = resources::peak_rss().unwrap_or(0);
+    let started = Instant::now();
+    match variant {
+        "dashmap" => {
+            let transactions = DashMap::new();
+            for tx in 0..TRANSACTIONS {
+                let transaction = deposit(tx);
+                transactions.insert(transaction.tx_id.clone(), transaction);
+            }
+            black_box(&transactions);
+        }
+        "slabs" | "slabs-presized" => {
+            let store = match variant {
+                "slabs" => MemoryTransactionStore::new(),
+                _ => MemoryTransactionStore::with_capacity(TRANSACTIONS as usize),
+            };
+            for tx in 0..TRANSACTIONS {
+                store.insert(deposit(tx)).unwrap();
+            }
+            black_box(&store);
+        }
+        _ => panic!("unknown variant {}", variant),
+    }
+    let elapsed = started.elapsed();
+    let peak = resources::peak_rss().unwrap_or(0).saturating_sub(baseline);
+    println!("{:<15} {:>8.2?} {:>8} MiB peak RSS", variant, elapsed, peak >> 20);
+}
+
+fn main() {
+    if let Ok(variant) = env::var(VARIANT_ENV) {
+        run(&variant);
+        return;
+    }
+
+    println!("{} deposits of {} clients", TRANSACTIONS, CLIENTS);
+    let executable = env::current_exe().unwrap();
+    for variant in VARIANTS {
+        let status = Command::new(&executable).env(VARIANT_ENV, variant).status().unwrap();
+        assert!(status.success(), "{} failed with {}", variant, status);
+    }
+}
@@ -0,0 +1,56 @@
+//! `--workers` with and without `--pin-cores`: a file of many clients applied by one worker
+//! per core the process may use, each variant run several times and its best time kept.
+//! Run with `cargo bench --bench workers`.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant};
+
+use trx_processor::processor::TransactionProcessor;
+use trx_processor::scheduler::FairScheduler;
+
+const ROWS: u64 = 1_000_000;
+const CLIENTS: u64 = 10_000;
+const ROUNDS: u32 = 3;
+
+/// Deposits and withdrawals spread over the clients
+fn write_input(path: &str) {
+    let mut output = BufWriter::new(File::create(path).unwrap());
+    writeln!(output, "type,client,tx,amount").unwrap();
+    for tx in 0..ROWS {
+        let client = tx % CLIENTS;
+        match tx % 4 {
+            3 => writeln!(output, "withdrawal,{},{},1.5", client, tx).unwrap(),
+            _ => writeln!(output, "deposit,{},{},10.25", client, tx).unwrap(),
+        }
+    }
+    output.flush().unwrap();
+}
+
+fn time(name: &str, path: &str, workers: usize, pin_cores: bool) -> Duration {
+    let best = (0..ROUNDS)
+        .map(|_| {
+            let processor = TransactionProcessor::new();
+            let started = Instant::now();
+            processor.process_file_fairly(path, workers, FairScheduler::DEFAULT_BUDGET, pin_cores).unwrap();
+            started.elapsed()
+        })
+        .min()
+        .unwrap();
+    println!("{:<10} {:>8.2?}", name, best);
+    best
+}
+
+fn main() {
+    let path = std::env::temp_dir().join(format!("trx_bench_workers_{}.csv", std::process::id()));
+    let path = path.to_str().unwrap();
+    write_input(path);
+
+    let workers = core_affinity::get_core_ids().map_or(1, |cores| cores.len());
+    println!("{} rows of {} clients on {} workers", ROWS, CLIENTS, workers);
+    let unpinned = time("unpinned", path, workers, false);
+    let pinned = time("pinned", path, workers, true);
+    println!("pinned takes {:.2}x the time", pinned.as_secs_f64() / unpinned.as_secs_f64());
+
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,69 @@
+/* C ABI of the trx_processor library, built with `cargo build --release --features ffi`.
+ * Amounts are integers in ten-thousandths: 1.5 is passed as 15000. */
+
+#ifndef TRX_PROCESSOR_H
+#define TRX_PROCESSOR_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TRX_OK 0
+#define TRX_INVALID_ARGUMENT 1
+#define TRX_NOT_FOUND 2
+#define TRX_ERROR 3
+
+#define TRX_TX_DEPOSIT 0
+#define TRX_TX_WITHDRAWAL 1
+#define TRX_TX_DISPUTE 2
+#define TRX_TX_RESOLVE 3
+#define TRX_TX_CHARGEBACK 4
+#define TRX_TX_ADJUSTMENT 5
+#define TRX_TX_RESERVE 6
+#define TRX_TX_CAPTURE 7
+#define TRX_TX_CANCEL 8
+#define TRX_TX_FREEZE 9
+#define TRX_TX_UNFREEZE 10
+#define TRX_TX_APPROVE 11
+#define TRX_TX_REJECT 12
+
+typedef struct TrxProcessor TrxProcessor;
+typedef struct TrxAccountIter TrxAccountIter;
+
+typedef struct {
+    uint32_t tx_type;
+    uint16_t client;
+    uint64_t tx;
+    bool has_amount;
+    int64_t amount;
+} TrxTransaction;
+
+typedef struct {
+    uint16_t client;
+    int64_t available;
+    int64_t held;
+    int64_t total;
+    bool locked;
+} TrxAccount;
+
+TrxProcessor *trx_processor_new(void);
+void trx_processor_free(TrxProcessor *processor);
+
+/* Rejected transactions, e.g. for insufficient funds, still return TRX_OK */
+int32_t trx_processor_submit(const TrxProcessor *processor, const TrxTransaction *transaction);
+/* Returns TRX_NOT_FOUND for unknown clients */
+int32_t trx_processor_get_account(const TrxProcessor *processor, uint16_t client, TrxAccount *account);
+
+/* Iterates over a copy of the accounts taken at the time of the call, NULL on failure */
+TrxAccountIter *trx_processor_accounts(const TrxProcessor *processor);
+bool trx_account_iter_next(TrxAccountIter *iter, TrxAccount *account);
+void trx_account_iter_free(TrxAccountIter *iter);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
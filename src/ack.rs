@@ -0,0 +1,223 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+
+use crate::input;
+use crate::model::error::ProcessorError;
+use crate::model::rejection::RejectionReason;
+use crate::model::transaction::{TransactionType, TxId};
+
+/// Placeholders of a record line
+const RECORD_FIELDS: [&str; 6] = ["line", "client", "tx", "type", "status", "reason"];
+/// Placeholders of the header and trailer lines
+const BATCH_FIELDS: [&str; 7] = ["file", "date", "time", "records", "accepted", "rejected", "pending"];
+
+/// What became of an input row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AckStatus {
+    Accepted,
+    Rejected,
+    /// Parked in the review queue
+    Pending,
+}
+
+impl AckStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AckStatus::Accepted => "ACK",
+            AckStatus::Rejected => "NAK",
+            AckStatus::Pending => "PEND",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AckEntry {
+    pub line: Option<u64>,
+    /// None for a row that could not be read
+    pub record: Option<(u16, String, String)>,
+    pub status: AckStatus,
+    pub reason: Option<String>,
+}
+
+/// Layout of an acknowledgement file, loaded with `--ack-format`. The file has a `record:`
+/// line and optional `header:` and `trailer:` lines, e.g.
+///
+/// ```text
+/// header: H|{file}|{date}|{records:06}
+/// record: D|{line:06}|{tx:10}|{status}|{reason}
+/// trailer: T|{accepted:06}|{rejected:06}
+/// ```
+///
+/// `{name:N}` pads the value with spaces to N characters, `{name:0N}` with leading zeros.
+#[derive(Debug, Clone)]
+pub struct AckTemplate {
+    header: Option<String>,
+    record: String,
+    trailer: Option<String>,
+}
+
+impl Default for AckTemplate {
+    fn default() -> Self {
+        AckTemplate {
+            header: Some("line,client,tx,type,status,reason".to_string()),
+            record: "{line},{client},{tx},{type},{status},{reason}".to_string(),
+            trailer: None,
+        }
+    }
+}
+
+impl AckTemplate {
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let (mut header, mut record, mut trailer) = (None, None, None);
+        for line in fs::read_to_string(path)?.lines().filter(|line| !line.trim().is_empty()) {
+            let (section, template) = match line.split_once(':') {
+                Some(("header", template)) => (&mut header, template),
+                Some(("record", template)) => (&mut record, template),
+                Some(("trailer", template)) => (&mut trailer, template),
+                _ => return Err(ProcessorError::InvalidArguments(format!("{}: expected header:, record: or trailer:, got '{}'", path, line))),
+            };
+            *section = Some(template.trim_start().to_string());
+        }
+        let Some(record) = record else {
+            return Err(ProcessorError::InvalidArguments(format!("{}: missing record: line", path)));
+        };
+
+        // Unknown placeholders are found now rather than after processing the whole file
+        render(&record, |name| RECORD_FIELDS.contains(&name).then(String::new))?;
+        for template in header.iter().chain(&trailer) {
+            render(template, |name| BATCH_FIELDS.contains(&name).then(String::new))?;
+        }
+        Ok(AckTemplate { header, record, trailer })
+    }
+}
+
+/// Replaces every `{name}` or `{name:format}` of `template` with what `lookup` returns for it
+fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, ProcessorError> {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            return Err(ProcessorError::InvalidArguments(format!("unclosed placeholder in ack format '{}'", template)));
+        };
+        let placeholder = &rest[start + 1..start + end];
+        let (name, format) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+        let value = lookup(name)
+            .ok_or_else(|| ProcessorError::InvalidArguments(format!("unknown placeholder {{{}}} in ack format", name)))?;
+        let width = format.parse::<usize>().map_err(|_| ProcessorError::InvalidArguments(format!("invalid width {{{}}} in ack format", placeholder)));
+        match format {
+            "" => output.push_str(&value),
+            _ if format.starts_with('0') => output.push_str(&format!("{:0>width$}", value, width = width?)),
+            _ => output.push_str(&format!("{:<width$}", value, width = width?)),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Outcome of every input row, written as an ACK/NAK file for the submitter of the batch
+/// once the run is over. Rows are processed one at a time, so the outcome reported while
+/// a row is processed belongs to the last row started.
+pub struct AckLog {
+    template: AckTemplate,
+    line: Mutex<Option<u64>>,
+    outcome: Mutex<Option<(AckStatus, String)>>,
+    entries: Mutex<Vec<AckEntry>>,
+}
+
+impl AckLog {
+    pub fn new(template: AckTemplate) -> Self {
+        AckLog {
+            template,
+            line: Mutex::new(None),
+            outcome: Mutex::new(None),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Starts the row at `line` of the input
+    pub fn set_line(&self, line: Option<u64>) {
+        *self.line.lock() = line;
+        *self.outcome.lock() = None;
+    }
+
+    pub fn rejected(&self, reason: RejectionReason) {
+        *self.outcome.lock() = Some((AckStatus::Rejected, reason.to_string()));
+    }
+
+    pub fn parked(&self) {
+        *self.outcome.lock() = Some((AckStatus::Pending, "in_review".to_string()));
+    }
+
+    /// Records the outcome of the current row, accepted unless it was rejected or parked
+    pub fn finish_row(&self, client: u16, tx: &TxId, transaction_type: &TransactionType) {
+        let (status, reason) = match self.outcome.lock().take() {
+            Some((status, reason)) => (status, Some(reason)),
+            None => (AckStatus::Accepted, None),
+        };
+        self.entries.lock().push(AckEntry {
+            line: *self.line.lock(),
+            record: Some((client, tx.to_string(), transaction_type.to_string())),
+            status,
+            reason,
+        });
+    }
+
+    /// Records a row that could not be read
+    pub fn malformed(&self, line: Option<u64>) {
+        self.entries.lock().push(AckEntry { line, record: None, status: AckStatus::Rejected, reason: Some("malformed".to_string()) });
+    }
+
+    pub fn entries(&self) -> Vec<AckEntry> {
+        self.entries.lock().clone()
+    }
+
+    /// Writes the acknowledgement of `input_file` to `path`, dated `now`
+    pub fn write(&self, path: &str, input_file: &str, now: DateTime<Utc>) -> Result<(), ProcessorError> {
+        let entries = self.entries();
+        let count = |status: AckStatus| entries.iter().filter(|entry| entry.status == status).count().to_string();
+        let input_file = input::display_name(input_file);
+        let file = Path::new(input_file).file_name().map_or(input_file.to_string(), |name| name.to_string_lossy().into_owned());
+        let batch_field = |name: &str| match name {
+            "file" => Some(file.clone()),
+            "date" => Some(now.format("%Y%m%d").to_string()),
+            "time" => Some(now.format("%H%M%S").to_string()),
+            "records" => Some(entries.len().to_string()),
+            "accepted" => Some(count(AckStatus::Accepted)),
+            "rejected" => Some(count(AckStatus::Rejected)),
+            "pending" => Some(count(AckStatus::Pending)),
+            _ => None,
+        };
+
+        let template = &self.template;
+        let mut output = String::new();
+        if let Some(ref header) = template.header {
+            output.push_str(&render(header, batch_field)?);
+            output.push('\n');
+        }
+        for entry in &entries {
+            let (client, tx, transaction_type) = entry.record.clone().unwrap_or_default();
+            output.push_str(&render(&template.record, |name| match name {
+                "line" => Some(entry.line.map(|line| line.to_string()).unwrap_or_default()),
+                "client" => Some(entry.record.as_ref().map(|_| client.to_string()).unwrap_or_default()),
+                "tx" => Some(tx.clone()),
+                "type" => Some(transaction_type.clone()),
+                "status" => Some(entry.status.as_str().to_string()),
+                "reason" => Some(entry.reason.clone().unwrap_or_default()),
+                _ => None,
+            })?);
+            output.push('\n');
+        }
+        if let Some(ref trailer) = template.trailer {
+            output.push_str(&render(trailer, batch_field)?);
+            output.push('\n');
+        }
+
+        fs::write(path, output)?;
+        Ok(())
+    }
+}
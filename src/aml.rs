@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::model::account::{serialize_decimal, AmountFormat, FormatAmounts};
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType, TxId};
+
+/// A reporting threshold, e.g. deposits of a client adding up to 10000 within 24 hours
+#[derive(Debug, Deserialize, Clone)]
+pub struct Threshold {
+    pub name: String,
+    pub transaction_type: Vec<TransactionType>,
+    pub min_total: Decimal,
+    pub window_hours: i64,
+}
+
+/// Thresholds loaded with `--aml-thresholds`
+#[derive(Debug, Deserialize, Default)]
+pub struct AmlConfig {
+    pub thresholds: Vec<Threshold>,
+}
+
+impl AmlConfig {
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// Row of the `--aml-report` file, a client crossing a threshold
+#[derive(Debug, Serialize, Clone)]
+pub struct SuspiciousActivity {
+    pub client: u16,
+    pub threshold: String,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub total: Decimal,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Ids of the transactions within the window, separated by spaces
+    pub transactions: String,
+}
+
+impl FormatAmounts for SuspiciousActivity {
+    fn format_amounts(&mut self, format: AmountFormat) {
+        self.total = format.apply(self.total);
+    }
+}
+
+/// Rows of one client counting towards one threshold
+#[derive(Debug, Default)]
+struct Window {
+    rows: VecDeque<(TxId, Decimal, Option<DateTime<Utc>>)>,
+    total: Decimal,
+    /// Set once the total crossed the threshold, cleared when it fell below it again
+    reported: bool,
+}
+
+/// Sums the amounts of every client per threshold over a sliding window while the input
+/// streams through, and collects a report each time a sum crosses its threshold
+pub struct AmlMonitor {
+    config: AmlConfig,
+    windows: DashMap<(u16, usize), Window>,
+    reports: Mutex<Vec<SuspiciousActivity>>,
+}
+
+impl AmlMonitor {
+    pub fn new(config: AmlConfig) -> Self {
+        AmlMonitor {
+            config,
+            windows: DashMap::new(),
+            reports: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn observe(&self, record: &TransactionInput) {
+        let Some(amount) = record.amount.filter(|amount| *amount > Decimal::ZERO) else {
+            return;
+        };
+
+        for (index, threshold) in self.config.thresholds.iter().enumerate() {
+            if !threshold.transaction_type.contains(&record.transaction_type) {
+                continue;
+            }
+
+            let mut window = self.windows.entry((record.client, index)).or_default();
+            // A row without a timestamp happened at the time of the client's previous one
+            let at = record.timestamp.or_else(|| window.rows.back().and_then(|(_, _, at)| *at));
+
+            if let Some(at) = at {
+                let window_start = at - Duration::hours(threshold.window_hours);
+                while let Some(&(_, amount, Some(seen))) = window.rows.front() {
+                    if seen > window_start {
+                        break;
+                    }
+                    window.total -= amount;
+                    window.rows.pop_front();
+                }
+            }
+            if window.total < threshold.min_total {
+                window.reported = false;
+            }
+
+            window.rows.push_back((record.tx.clone(), amount, at));
+            window.total += amount;
+
+            if !window.reported && window.total >= threshold.min_total {
+                window.reported = true;
+                self.reports.lock().push(SuspiciousActivity {
+                    client: record.client,
+                    threshold: threshold.name.clone(),
+                    total: window.total,
+                    first_seen: window.rows.front().and_then(|(_, _, at)| *at),
+                    last_seen: at,
+                    transactions: window.rows.iter().map(|(tx, _, _)| tx.to_string()).collect::<Vec<_>>().join(" "),
+                });
+            }
+        }
+    }
+
+    pub fn reports(&self) -> Vec<SuspiciousActivity> {
+        self.reports.lock().clone()
+    }
+
+    pub fn write_report(&self, path: &str, amount_format: AmountFormat) -> Result<(), ProcessorError> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(File::create(path)?);
+
+        // Header is written explicitly so an empty report is still a valid CSV
+        writer.write_record(["client", "threshold", "total", "first_seen", "last_seen", "transactions"])?;
+        for mut report in self.reports() {
+            report.format_amounts(amount_format);
+            writer.serialize(report)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
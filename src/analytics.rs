@@ -0,0 +1,275 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType, TxId};
+use crate::processor::open_reader;
+
+/// Standard deviations above the mean for a deposit to count as unusual
+const DEPOSIT_Z_SCORE: f64 = 3.0;
+/// Deposits a client needs before their mean is trusted
+const MIN_DEPOSIT_HISTORY: u64 = 5;
+/// Share of a deposit withdrawn right after it that counts as a cycle
+const CYCLE_RATIO: Decimal = Decimal::from_parts(9, 0, 0, false, 1);
+/// Disputes within the client's recent transactions that count as a storm
+const DISPUTE_STORM_SIZE: usize = 3;
+const DISPUTE_STORM_WINDOW: usize = 10;
+
+/// Entries listed in the top clients and largest transactions sections of `stats`
+const TOP_N: usize = 5;
+/// Upper bounds of the amount distribution buckets of `stats`
+const AMOUNT_BUCKETS: [i64; 5] = [1, 10, 100, 1_000, 10_000];
+
+/// Streaming count/mean/variance (Welford's algorithm)
+#[derive(Debug, Default, Clone)]
+pub struct RunningStats {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        (self.m2 / (self.count - 1) as f64).sqrt()
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    LargeDeposit,
+    DepositWithdrawCycle,
+    DisputeStorm,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Anomaly {
+    pub client: u16,
+    pub tx: TxId,
+    pub kind: AnomalyKind,
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+struct ClientActivity {
+    deposits: RunningStats,
+    last_deposit: Option<Decimal>,
+    recent_types: VecDeque<TransactionType>,
+}
+
+/// Observes every transaction and collects statistically unusual activity
+#[derive(Default)]
+pub struct AnomalyDetector {
+    clients: DashMap<u16, ClientActivity>,
+    anomalies: Mutex<Vec<Anomaly>>,
+}
+
+impl AnomalyDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&self, record: &TransactionInput) {
+        let mut activity = self.clients.entry(record.client).or_default();
+        let amount = record.amount.unwrap_or_default();
+
+        match record.transaction_type {
+            TransactionType::Deposit if amount > Decimal::ZERO => {
+                let value = amount.to_f64().unwrap_or_default();
+                let stats = &activity.deposits;
+                if stats.count() >= MIN_DEPOSIT_HISTORY {
+                    let threshold = stats.mean() + DEPOSIT_Z_SCORE * stats.std_dev();
+                    if value > threshold {
+                        self.report(record, AnomalyKind::LargeDeposit, format!(
+                            "amount={} mean={:.4} std_dev={:.4}", amount, stats.mean(), stats.std_dev()
+                        ));
+                    }
+                }
+                activity.deposits.push(value);
+            }
+            TransactionType::Withdrawal => {
+                let previous_was_deposit = activity.recent_types.back() == Some(&TransactionType::Deposit);
+                if let (true, Some(deposit)) = (previous_was_deposit, activity.last_deposit) {
+                    if amount >= deposit * CYCLE_RATIO {
+                        self.report(record, AnomalyKind::DepositWithdrawCycle, format!(
+                            "deposit={} withdrawal={}", deposit, amount
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if record.transaction_type == TransactionType::Deposit {
+            activity.last_deposit = record.amount;
+        }
+
+        activity.recent_types.push_back(record.transaction_type.clone());
+        if activity.recent_types.len() > DISPUTE_STORM_WINDOW {
+            activity.recent_types.pop_front();
+        }
+
+        if record.transaction_type == TransactionType::Dispute {
+            let disputes = activity.recent_types
+                .iter()
+                .filter(|t| **t == TransactionType::Dispute)
+                .count();
+            if disputes == DISPUTE_STORM_SIZE {
+                self.report(record, AnomalyKind::DisputeStorm, format!(
+                    "disputes={} within last {} transactions", disputes, activity.recent_types.len()
+                ));
+            }
+        }
+    }
+
+    fn report(&self, record: &TransactionInput, kind: AnomalyKind, detail: String) {
+        self.anomalies.lock().push(Anomaly {
+            client: record.client,
+            tx: record.tx.clone(),
+            kind,
+            detail,
+        });
+    }
+
+    pub fn anomalies(&self) -> Vec<Anomaly> {
+        self.anomalies.lock().clone()
+    }
+
+    pub fn write_report(&self, path: &str) -> Result<(), ProcessorError> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(File::create(path)?);
+
+        // Header is written explicitly so an empty report is still a valid CSV
+        writer.write_record(["client", "tx", "kind", "detail"])?;
+        for anomaly in self.anomalies() {
+            writer.serialize(anomaly)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Aggregates printed by the `stats` subcommand
+#[derive(Debug, Default)]
+pub struct FileStats {
+    transactions: u64,
+    volume_by_client: HashMap<u16, Decimal>,
+    largest: Vec<(Decimal, TxId, u16, TransactionType)>,
+    amount_buckets: [u64; AMOUNT_BUCKETS.len() + 1],
+    deposits: u64,
+    disputes: u64,
+    chargebacks: u64,
+    hourly: BTreeMap<String, u64>,
+}
+
+impl FileStats {
+    pub fn from_file(file_path: &str) -> Result<Self, ProcessorError> {
+        let mut stats = FileStats::default();
+        for result in open_reader(file_path)?.deserialize() {
+            let record: TransactionInput = result?;
+            stats.observe(&record);
+        }
+        Ok(stats)
+    }
+
+    fn observe(&mut self, record: &TransactionInput) {
+        self.transactions += 1;
+
+        match record.transaction_type {
+            TransactionType::Deposit => self.deposits += 1,
+            TransactionType::Dispute => self.disputes += 1,
+            TransactionType::Chargeback => self.chargebacks += 1,
+            _ => {}
+        }
+
+        if let Some(timestamp) = record.timestamp {
+            let hour = timestamp.format("%Y-%m-%d %H:00").to_string();
+            *self.hourly.entry(hour).or_default() += 1;
+        }
+
+        let Some(amount) = record.amount.filter(|amount| *amount > Decimal::ZERO) else {
+            return;
+        };
+
+        if matches!(record.transaction_type, TransactionType::Deposit | TransactionType::Withdrawal) {
+            *self.volume_by_client.entry(record.client).or_default() += amount;
+        }
+
+        let bucket = AMOUNT_BUCKETS
+            .iter()
+            .position(|bound| amount < Decimal::from(*bound))
+            .unwrap_or(AMOUNT_BUCKETS.len());
+        self.amount_buckets[bucket] += 1;
+
+        // Keep only the TOP_N largest, sorted descending
+        self.largest.push((amount, record.tx.clone(), record.client, record.transaction_type.clone()));
+        self.largest.sort_by_key(|entry| std::cmp::Reverse(entry.0));
+        self.largest.truncate(TOP_N);
+    }
+
+    pub fn print(&self) {
+        println!("Transactions: {}", self.transactions);
+
+        println!("Top clients by volume:");
+        let mut clients: Vec<_> = self.volume_by_client.iter().collect();
+        clients.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (client, volume) in clients.into_iter().take(TOP_N) {
+            println!("  client={}, volume={}", client, volume);
+        }
+
+        println!("Largest transactions:");
+        for (amount, tx, client, transaction_type) in &self.largest {
+            println!("  tx={}, client={}, type={:?}, amount={}", tx, client, transaction_type, amount);
+        }
+
+        println!("Amount distribution:");
+        let mut lower = 0;
+        for (bound, count) in AMOUNT_BUCKETS.iter().zip(self.amount_buckets.iter()) {
+            println!("  {}-{}: {}", lower, bound, count);
+            lower = *bound;
+        }
+        println!("  {}+: {}", lower, self.amount_buckets[AMOUNT_BUCKETS.len()]);
+
+        println!("Dispute rate: {}/{} deposits ({})", self.disputes, self.deposits, percentage(self.disputes, self.deposits));
+        println!("Chargeback rate: {}/{} disputes ({})", self.chargebacks, self.disputes, percentage(self.chargebacks, self.disputes));
+
+        if !self.hourly.is_empty() {
+            println!("Hourly throughput:");
+            for (hour, count) in &self.hourly {
+                println!("  {}: {}", hour, count);
+            }
+        }
+    }
+}
+
+fn percentage(part: u64, whole: u64) -> String {
+    if whole == 0 {
+        return "n/a".to_string();
+    }
+    format!("{:.2}%", part as f64 * 100.0 / whole as f64)
+}
@@ -0,0 +1,392 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Clock;
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{Transaction, TxId};
+use crate::store::{self, AccountStore, BatchStore, Stores, TransactionStore};
+
+/// Prefix of the audit trail recording the undo of a run
+const UNDO_PREFIX: &str = "undo-";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccountChange {
+    /// None if the batch created the account
+    pub before: Option<Account>,
+    pub after: Account,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransactionChange {
+    pub tx: TxId,
+    /// None if the batch stored the transaction for the first time
+    pub before: Option<Transaction>,
+    /// None if the batch removed the transaction
+    pub after: Option<Transaction>,
+}
+
+/// Section of the audit trail recording the batches of an `--admin-ops` file
+pub const ADMIN_OPS_SECTION: &str = "admin_ops";
+
+/// Before and after images of everything one batch commit changed
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct BatchChanges {
+    pub accounts: Vec<AccountChange>,
+    pub transactions: Vec<TransactionChange>,
+    /// Set for batches not from the input file, e.g. `admin_ops`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+}
+
+impl BatchChanges {
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.transactions.is_empty()
+    }
+}
+
+/// Append-only record of the batches committed by one run, one JSON line per batch,
+/// stored as `<dir>/<run_id>.jsonl`
+pub struct AuditTrail {
+    run_id: String,
+    file: Mutex<File>,
+}
+
+impl AuditTrail {
+    /// Starts the trail of a new run, refusing to reuse the id of an earlier one
+    pub fn create(dir: &str, run_id: &str) -> Result<Self, ProcessorError> {
+        fs::create_dir_all(dir)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(trail_path(dir, run_id))
+            .map_err(|err| match err.kind() {
+                ErrorKind::AlreadyExists => ProcessorError::InvalidArguments(format!(
+                    "run '{}' already has an audit trail in '{}'", run_id, dir
+                )),
+                _ => err.into(),
+            })?;
+
+        Ok(AuditTrail {
+            run_id: run_id.to_string(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Reads back every batch recorded for a run, oldest first
+    pub fn load(dir: &str, run_id: &str) -> Result<Vec<BatchChanges>, ProcessorError> {
+        let file = File::open(trail_path(dir, run_id)).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => ProcessorError::InvalidArguments(format!(
+                "no audit trail for run '{}' in '{}'", run_id, dir
+            )),
+            _ => err.into(),
+        })?;
+
+        let mut batches = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                batches.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(batches)
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Records a committed batch, flushed before returning
+    pub fn append(&self, changes: &BatchChanges) -> Result<(), ProcessorError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut line = serde_json::to_vec(changes)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock();
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Default id of a run, the UTC time it started
+pub fn new_run_id(clock: &dyn Clock) -> String {
+    clock.now().format("%Y%m%dT%H%M%S%.3fZ").to_string()
+}
+
+fn trail_path(dir: &str, run_id: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.jsonl", run_id))
+}
+
+/// Net change of the hold of one transaction in `Account::holds`, None where the ledger
+/// has no entry for it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HoldDelta {
+    pub tx: TxId,
+    pub before: Option<Decimal>,
+    pub after: Option<Decimal>,
+}
+
+impl HoldDelta {
+    /// Adds the change to the current hold, or drops the entry if the delta released it
+    fn apply(&self, holds: &mut BTreeMap<TxId, Decimal>) {
+        match self.after {
+            Some(after) => {
+                let current = holds.get(&self.tx).copied().unwrap_or_default();
+                holds.insert(self.tx.clone(), current + after - self.before.unwrap_or_default());
+            }
+            None => {
+                holds.remove(&self.tx);
+            }
+        }
+    }
+}
+
+/// Net effect of one or more batches on an account
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccountDelta {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked_before: bool,
+    pub locked_after: bool,
+    #[serde(default)]
+    pub frozen_before: bool,
+    #[serde(default)]
+    pub frozen_after: bool,
+    /// Set by a merge, see `Account::merged_into`
+    #[serde(default)]
+    pub merged_into_before: Option<u16>,
+    #[serde(default)]
+    pub merged_into_after: Option<u16>,
+    /// Changes of the holds ledger, which must add up to `held` like the ledger itself
+    #[serde(default)]
+    pub holds: Vec<HoldDelta>,
+    #[serde(default)]
+    pub shortfall: Decimal,
+    #[serde(default)]
+    pub needs_review_before: bool,
+    #[serde(default)]
+    pub needs_review_after: bool,
+    #[serde(default)]
+    pub tx_count: i64,
+    #[serde(default)]
+    pub disputes: i64,
+    #[serde(default)]
+    pub last_activity_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_activity_after: Option<DateTime<Utc>>,
+}
+
+impl AccountDelta {
+    fn new(before: &Account) -> Self {
+        AccountDelta {
+            client: before.client_id,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            locked_before: before.locked,
+            locked_after: before.locked,
+            frozen_before: before.frozen,
+            frozen_after: before.frozen,
+            merged_into_before: before.merged_into,
+            merged_into_after: before.merged_into,
+            holds: Vec::new(),
+            shortfall: Decimal::ZERO,
+            needs_review_before: before.needs_review,
+            needs_review_after: before.needs_review,
+            tx_count: 0,
+            disputes: 0,
+            last_activity_before: before.last_activity,
+            last_activity_after: before.last_activity,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.available.is_zero()
+            && self.held.is_zero()
+            && self.locked_before == self.locked_after
+            && self.frozen_before == self.frozen_after
+            && self.merged_into_before == self.merged_into_after
+            && self.holds.is_empty()
+            && self.shortfall.is_zero()
+            && self.needs_review_before == self.needs_review_after
+            && self.tx_count == 0
+            && self.disputes == 0
+            && self.last_activity_before == self.last_activity_after
+    }
+
+    /// The compensating delta that cancels this one
+    pub fn inverse(&self) -> Self {
+        AccountDelta {
+            client: self.client,
+            available: Decimal::ZERO - self.available,
+            held: Decimal::ZERO - self.held,
+            locked_before: self.locked_after,
+            locked_after: self.locked_before,
+            frozen_before: self.frozen_after,
+            frozen_after: self.frozen_before,
+            merged_into_before: self.merged_into_after,
+            merged_into_after: self.merged_into_before,
+            holds: self
+                .holds
+                .iter()
+                .map(|hold| HoldDelta { tx: hold.tx.clone(), before: hold.after, after: hold.before })
+                .collect(),
+            shortfall: Decimal::ZERO - self.shortfall,
+            needs_review_before: self.needs_review_after,
+            needs_review_after: self.needs_review_before,
+            tx_count: -self.tx_count,
+            disputes: -self.disputes,
+            last_activity_before: self.last_activity_after,
+            last_activity_after: self.last_activity_before,
+        }
+    }
+
+    /// Adds the delta to the account's current balances, holds and counters, and carries over
+    /// a lock, freeze, review or merge change
+    pub fn apply(&self, account: &mut Account) {
+        account.available += self.available;
+        account.held += self.held;
+        for hold in &self.holds {
+            hold.apply(&mut account.holds);
+        }
+        account.shortfall += self.shortfall;
+        account.tx_count = account.tx_count.saturating_add_signed(self.tx_count);
+        account.disputes = account.disputes.saturating_add_signed(self.disputes);
+        if self.locked_before != self.locked_after {
+            account.locked = self.locked_after;
+        }
+        if self.frozen_before != self.frozen_after {
+            account.frozen = self.frozen_after;
+        }
+        if self.needs_review_before != self.needs_review_after {
+            account.needs_review = self.needs_review_after;
+        }
+        if self.merged_into_before != self.merged_into_after {
+            account.merged_into = self.merged_into_after;
+        }
+        // Activity a later run recorded is kept
+        if self.last_activity_before != self.last_activity_after {
+            account.last_activity = match account.last_activity == self.last_activity_before {
+                true => self.last_activity_after,
+                false => account.last_activity.max(self.last_activity_after),
+            };
+        }
+    }
+}
+
+/// Sums the account changes of consecutive batches into one delta per client, ordered by client.
+/// Accounts the batches created get a delta even if it is empty.
+pub fn net_account_deltas<'a>(batches: impl IntoIterator<Item = &'a BatchChanges>) -> Vec<AccountDelta> {
+    let mut deltas: BTreeMap<u16, (AccountDelta, BTreeMap<TxId, HoldDelta>)> = BTreeMap::new();
+
+    for change in batches.into_iter().flat_map(|batch| &batch.accounts) {
+        let client_id = change.after.client_id;
+        let before = change.before.clone().unwrap_or_else(|| Account::new(client_id));
+        let after = &change.after;
+        let (delta, holds) = deltas.entry(client_id).or_insert_with(|| (AccountDelta::new(&before), BTreeMap::new()));
+        delta.available += after.available - before.available;
+        delta.held += after.held - before.held;
+        delta.locked_after = after.locked;
+        delta.frozen_after = after.frozen;
+        delta.merged_into_after = after.merged_into;
+        delta.shortfall += after.shortfall - before.shortfall;
+        delta.needs_review_after = after.needs_review;
+        delta.tx_count += after.tx_count as i64 - before.tx_count as i64;
+        delta.disputes += after.disputes as i64 - before.disputes as i64;
+        delta.last_activity_after = after.last_activity;
+
+        let txs: BTreeSet<&TxId> = before.holds.keys().chain(after.holds.keys()).collect();
+        for tx in txs {
+            let (held_before, held_after) = (before.holds.get(tx).copied(), after.holds.get(tx).copied());
+            if held_before == held_after {
+                continue;
+            }
+            let hold = holds.entry(tx.clone()).or_insert(HoldDelta { tx: tx.clone(), before: held_before, after: held_before });
+            hold.after = held_after.map(|held| hold.after.unwrap_or_default() + held - held_before.unwrap_or_default());
+        }
+    }
+
+    deltas
+        .into_values()
+        .map(|(mut delta, holds)| {
+            delta.holds = holds.into_values().filter(|hold| hold.before != hold.after).collect();
+            delta
+        })
+        .collect()
+}
+
+/// Reverses every mutation recorded in the audit trail of `run_id` by applying compensating
+/// entries to the current state, so changes made by later runs are kept.
+/// The undo is committed as one batch and recorded in its own `undo-<run_id>` trail.
+/// Returns one line per compensating entry.
+pub fn undo(stores: Stores, dir: &str, run_id: &str) -> Result<Vec<String>, ProcessorError> {
+    if trail_path(dir, &format!("{}{}", UNDO_PREFIX, run_id)).exists() {
+        return Err(ProcessorError::InvalidArguments(format!("run '{}' was already undone", run_id)));
+    }
+
+    let batches = AuditTrail::load(dir, run_id)?;
+
+    // First image of every transaction the run touched
+    let mut transactions: BTreeMap<TxId, Option<Transaction>> = BTreeMap::new();
+    for change in batches.iter().flat_map(|batch| &batch.transactions) {
+        transactions.entry(change.tx.clone()).or_insert(change.before.clone());
+    }
+
+    let batch = BatchStore::new(Arc::from(stores.accounts), Arc::from(stores.transactions));
+    let mut entries = Vec::new();
+
+    for delta in net_account_deltas(&batches).iter().filter(|delta| !delta.is_empty()) {
+        let compensation = delta.inverse();
+        batch.update(compensation.client, &mut |account| compensation.apply(account))?;
+        entries.push(format!(
+            "COMPENSATED ACCOUNT: client={}, available={}, held={}, locked={}",
+            compensation.client, compensation.available, compensation.held, compensation.locked_after
+        ));
+    }
+
+    for (tx_id, before) in transactions {
+        match before {
+            None => {
+                batch.remove(&tx_id)?;
+                entries.push(format!("REMOVED TRANSACTION: tx={}", tx_id));
+            }
+            Some(before) => {
+                batch.set_state(&tx_id, before.state.clone())?;
+                // A merge gave the transaction to another client
+                if let Some(mut current) = TransactionStore::get(&batch, &tx_id)?.filter(|current| current.client_id != before.client_id) {
+                    current.client_id = before.client_id;
+                    batch.insert(current)?;
+                }
+                entries.push(format!("RESTORED TRANSACTION: tx={}, state={:?}", tx_id, before.state));
+            }
+        }
+    }
+
+    let changes = batch.commit()?;
+    AuditTrail::create(dir, &format!("{}{}", UNDO_PREFIX, run_id))?.append(&changes)?;
+
+    Ok(entries)
+}
+
+pub fn run_undo(store: &str, dir: &str, run_id: &str) -> Result<(), ProcessorError> {
+    let entries = undo(store::open_stores(store)?, dir, run_id)?;
+    if entries.is_empty() {
+        println!("Nothing to undo");
+    }
+    for entry in entries {
+        println!("{}", entry);
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Bits per key of a bloom filter, about 1% false positives with `BLOOM_HASHES`
+const BLOOM_BITS_PER_KEY: usize = 10;
+const BLOOM_HASHES: u64 = 7;
+
+/// Fixed size set of hashes that may report keys it never saw, but never misses one it did
+pub struct Bloom {
+    bits: Vec<u64>,
+    keys: usize,
+}
+
+impl Bloom {
+    /// Sized for `capacity` keys, about 1.25 bytes of memory per key. More keys only raise
+    /// the rate of false positives.
+    pub fn new(capacity: usize) -> Self {
+        Bloom {
+            bits: vec![0; (capacity * BLOOM_BITS_PER_KEY).div_ceil(64).max(1)],
+            keys: 0,
+        }
+    }
+
+    /// Bit positions of a key, by double hashing
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (first, second) = (hash & 0xFFFF_FFFF, (hash >> 32) | 1);
+        let len = self.bits.len() as u64 * 64;
+        (0..BLOOM_HASHES).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % len) as usize)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.positions(key).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        for bit in self.positions(key).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.keys += 1;
+    }
+
+    /// Keys inserted since the filter was created or cleared, counting repeats
+    pub fn keys(&self) -> usize {
+        self.keys
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+        self.keys = 0;
+    }
+}
@@ -0,0 +1,62 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::model::error::ProcessorError;
+use crate::summary::RunSummary;
+
+/// A finished run: the report as written to stdout and the summary of the run
+#[derive(Serialize, Deserialize)]
+pub struct CachedResult {
+    pub report: String,
+    pub summary: RunSummary,
+}
+
+/// Results of earlier runs in `--cache-dir`, one JSON file per key. The key covers the
+/// contents of the input file, the engine version and the configuration of the run, so
+/// any change to one of them misses the cache.
+pub struct ResultCache {
+    dir: PathBuf,
+}
+
+impl ResultCache {
+    pub fn open(dir: &str) -> Result<Self, ProcessorError> {
+        fs::create_dir_all(dir)?;
+        Ok(ResultCache { dir: PathBuf::from(dir) })
+    }
+
+    /// SHA-256 of the engine version, `config` and the input file, in hex
+    pub fn key(input_file: &str, config: &str) -> Result<String, ProcessorError> {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update([0]);
+        hasher.update(config.as_bytes());
+        hasher.update([0]);
+        io::copy(&mut File::open(input_file)?, &mut hasher)?;
+        Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<CachedResult>, ProcessorError> {
+        match fs::read(self.path(key)) {
+            Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Written to a temporary file and renamed, so a concurrent run never reads half an entry
+    pub fn put(&self, key: &str, result: &CachedResult) -> Result<(), ProcessorError> {
+        let path = self.path(key);
+        let tmp_path = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_vec(result)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::model::error::ProcessorError;
+
+/// One in this many store batch writes fails
+const STORE_FAILURE_ODDS: u64 = 8;
+/// One in this many records is delayed
+const DELAY_ODDS: u64 = 50;
+const MAX_DELAY_MS: u64 = 5;
+/// One in this many records is delivered twice
+const DUPLICATE_ODDS: u64 = 20;
+
+/// Small deterministic generator, so a chaos run can be reproduced from its seed
+pub(crate) struct Lcg(pub(crate) u64);
+
+impl Lcg {
+    /// Next number below `bound`
+    pub(crate) fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
+
+/// Controlled failures injected by `--chaos <seed>`: transient store errors on batch
+/// writes, delayed records and duplicated records. The same seed and input inject the
+/// same failures in the same places.
+pub struct Chaos {
+    seed: u64,
+    rng: Mutex<Lcg>,
+    store_failures: AtomicU64,
+    delayed: AtomicU64,
+    duplicated: AtomicU64,
+}
+
+impl Chaos {
+    pub fn new(seed: u64) -> Self {
+        Chaos {
+            seed,
+            rng: Mutex::new(Lcg(seed)),
+            store_failures: AtomicU64::new(0),
+            delayed: AtomicU64::new(0),
+            duplicated: AtomicU64::new(0),
+        }
+    }
+
+    fn roll(&self, odds: u64) -> bool {
+        self.rng.lock().next(odds) == 0
+    }
+
+    /// Fails a store write, leaving the store untouched
+    pub fn store_write(&self, store: &str) -> Result<(), ProcessorError> {
+        if !self.roll(STORE_FAILURE_ODDS) {
+            return Ok(());
+        }
+        self.store_failures.fetch_add(1, Ordering::Relaxed);
+        Err(ProcessorError::StoreError(format!("chaos: injected transient failure writing {}", store)))
+    }
+
+    /// Sleeps before some records
+    pub fn delay_record(&self) {
+        if !self.roll(DELAY_ODDS) {
+            return;
+        }
+        let millis = self.rng.lock().next(MAX_DELAY_MS) + 1;
+        self.delayed.fetch_add(1, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(millis));
+    }
+
+    /// Whether the next record is delivered twice
+    pub fn duplicate_record(&self) -> bool {
+        let duplicate = self.roll(DUPLICATE_ODDS);
+        if duplicate {
+            self.duplicated.fetch_add(1, Ordering::Relaxed);
+        }
+        duplicate
+    }
+}
+
+impl fmt::Display for Chaos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Chaos: seed={}, store_failures={}, delayed_records={}, duplicated_records={}",
+            self.seed,
+            self.store_failures.load(Ordering::Relaxed),
+            self.delayed.load(Ordering::Relaxed),
+            self.duplicated.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -0,0 +1,1058 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use trx_processor::model::error::ProcessorError;
+use trx_processor::dedup::DedupWindow;
+use trx_processor::diagnostics::ColorChoice;
+use trx_processor::jobs::{JobSpec, JobState};
+use trx_processor::logger::{LogLevels, LogTimeFormat, LogTimezone};
+use trx_processor::model::account::{AmountFormat, FreezePolicy, OutputSchema, ShortfallPolicy};
+use trx_processor::model::transaction::{AmountParsing, TransactionType, TxIdKind};
+use trx_processor::pretty::Locale;
+use trx_processor::query::Query;
+use trx_processor::sftp::SftpLocation;
+use trx_processor::shard::Shard;
+use trx_processor::{input, jobs, latency, replay, throttle};
+
+const USAGE: &str = "Usage: cargo run -- <transactions.csv>|<https://url>|--source sftp://[user@]host[:port]/path [--log-transactions|--log-per-client <dir> [--log-buckets <n>] [--log-timezone utc|local] [--log-time-format rfc3339|<strftime>] [--log-level [<category>=]off|warn|info,...]] [--tx-id-type u32|u64|string] [--output-schema v1|v2] [--amount-format fixed4|minimal|raw] [--amount-parsing standard|lenient|strict] [--pretty|--quiet|--report-template <template>|--output-format csv|html] [--stream-output] [--workers <n> [--client-budget <rows>] [--pin-cores]] [--max-tps <rows>] [--shard <i>/<n>] [--locale <tag>] [--color auto|always|never] [--snapshot <path>] [--allow-adjustments] [--allow-merges] [--admin-ops <ops.csv> [--admin-ops-at before|after]] [--disable <type>,...] [--cut-by day] [--dispute-rules <rules.json>] [--reason-codes <codes.txt>] [--open-disputes <path>] [--dump-dir <dir>] [--dispute-shortfall reject|negative|partial] [--freeze-policy outflows|all] [--risk] [--risk-lock-threshold <score>] [--anomalies <path>] [--exposure <exposure.json>] [--dispute-stats <path>.csv|<path>.json] [--dispute-chains <path>.csv|<path>.json] [--balance-history <path> [--sample-every <rows>] [--history-clients <id>,...]] [--aml-thresholds <thresholds.json> --aml-report <path>] [--screening-denylist <denylist.csv>|--screening-url http://<host>[:port][/path]] [--plugin <rules.wasm>]... [--script <rules.rhai>] [--review-queue <queue.json>] [--review-over <amount>] [--ack-out <path> [--ack-format <format.txt>]] [--dead-letter <path>] [--dedup-window <keys>|<duration>] [--dedup-bloom] [--store memory://|file://<dir>|redis://<host>] [--commit-every <rows>] [--commit-interval <duration>] [--store-retries <attempts>] [--store-backoff <duration>] [--store-timeout <duration>] [--tx-bloom <keys>] [--cold-after <transactions>|--expected-transactions <transactions>] [--audit-dir <dir>] [--run-id <id>] [--propose <proposals.bin>] [--cache-dir <dir>] [--verify] [--input-manifest <manifest.json>] [--preflight <baseline.json> [--force]] [--summary] [--report <report.json>] [--cohorts <cohorts.csv>] [--metrics <metrics.prom>] [--manifest <manifest.json>] [--notify <notify.json>] [--upload-report sftp://[user@]host[:port]/path] [--slow-threshold <duration>] [--otlp-endpoint http://<host>[:port]]
+       cargo run -- snapshot-diff <before.bin> <after.bin>
+       cargo run -- stats <transactions.csv>
+       cargo run -- schema <transactions.csv> [--tx-id-type u32|u64|string] [--amount-parsing standard|lenient|strict]
+       cargo run -- scenario <scenario.yaml>...
+       cargo run -- merge-reports <report.csv>...
+       cargo run -- coordinate <transactions.csv> --workers <n> [--hosts <host>,...] [-- <worker options>...]
+       cargo run -- enqueue --queue <dir> [--workers <n>] <transactions.csv>... [-- <options>...]
+       cargo run -- enqueue --queue <dir> --requeue <job>|--skip <job>
+       cargo run -- worker --queue <dir> [--status]
+       cargo run -- healthcheck [--store file://<dir>|redis://<host>] [--audit-dir <dir>]
+       cargo run -- replay <transactions.csv> [--speed <factor>x] [--timestamps]
+       cargo run -- balance-at <transactions.csv> --client <id> --at <rfc3339 timestamp>
+       cargo run -- query <transactions.csv> \"select <columns>|* from accounts|transactions [where ...] [order by ...] [limit <n>]\" [<options>...]
+       cargo run -- undo --run <run_id> --store file://<dir>|redis://<host> --audit-dir <dir>
+       cargo run -- apply <proposals.bin> --store file://<dir>|redis://<host> [--audit-dir <dir>] [--run-id <id>]";
+
+#[derive(Debug)]
+pub enum Command {
+    Process(Options),
+    SnapshotDiff { before: String, after: String },
+    Stats { input_file: String },
+    Schema { input_file: String, tx_id_kind: TxIdKind, amount_parsing: AmountParsing },
+    MergeReports { files: Vec<String> },
+    Coordinate { input_file: String, workers: u32, hosts: Vec<String>, worker_args: Vec<String> },
+    Enqueue { queue: String, jobs: Vec<JobSpec> },
+    SetJobState { queue: String, job: String, state: JobState },
+    Worker { queue: String, status: bool },
+    Scenario { files: Vec<String> },
+    Healthcheck { store: Option<String>, audit_dir: Option<String> },
+    Replay { input_file: String, speed: f64, restamp: bool },
+    BalanceAt { options: Options, client: u16, at: DateTime<Utc> },
+    Query { options: Options, query: Query },
+    Undo { run_id: String, store: String, audit_dir: String },
+    Apply { proposals: String, store: String, audit_dir: Option<String>, run_id: Option<String> },
+}
+
+/// Period after which `--cut-by` emits a closing report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CutBy {
+    Day,
+}
+
+/// When the `--admin-ops` file is processed, relative to the input file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AdminOpsAt {
+    #[default]
+    Before,
+    After,
+}
+
+/// Format of the account report written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A single-file HTML dashboard
+    Html,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub input_file: String,
+    pub log_transactions: bool,
+    pub log_timezone: LogTimezone,
+    pub log_time_format: LogTimeFormat,
+    pub log_per_client: Option<String>,
+    pub log_buckets: Option<u16>,
+    pub log_levels: Option<LogLevels>,
+    pub tx_id_kind: TxIdKind,
+    pub output_schema: OutputSchema,
+    pub amount_format: AmountFormat,
+    pub amount_parsing: AmountParsing,
+    pub pretty: bool,
+    pub quiet: bool,
+    pub report_template_path: Option<String>,
+    pub output_format: OutputFormat,
+    pub stream_output: bool,
+    pub workers: Option<usize>,
+    pub client_budget: Option<usize>,
+    pub pin_cores: bool,
+    pub max_tps: Option<f64>,
+    pub shard: Option<Shard>,
+    pub locale: Locale,
+    pub color: ColorChoice,
+    pub snapshot_path: Option<String>,
+    pub allow_adjustments: bool,
+    pub allow_merges: bool,
+    pub admin_ops_path: Option<String>,
+    pub admin_ops_at: Option<AdminOpsAt>,
+    pub disabled_types: Vec<TransactionType>,
+    pub cut_by: Option<CutBy>,
+    pub dispute_rules_path: Option<String>,
+    pub reason_codes_path: Option<String>,
+    pub open_disputes_path: Option<String>,
+    /// Directory the account report and open disputes are written to on SIGUSR1
+    pub dump_dir: Option<String>,
+    pub shortfall_policy: ShortfallPolicy,
+    pub freeze_policy: FreezePolicy,
+    pub risk_scoring: bool,
+    pub risk_lock_threshold: Option<u32>,
+    pub anomalies_path: Option<String>,
+    pub exposure_path: Option<String>,
+    pub dispute_stats_path: Option<String>,
+    pub dispute_chains_path: Option<String>,
+    pub balance_history_path: Option<String>,
+    /// Rows between two samples of every account, 1000 unless only clients are followed
+    pub sample_every: Option<u64>,
+    pub history_clients: Vec<u16>,
+    pub aml_thresholds_path: Option<String>,
+    pub aml_report_path: Option<String>,
+    pub screening_denylist_path: Option<String>,
+    pub screening_url: Option<String>,
+    pub plugin_paths: Vec<String>,
+    pub script_path: Option<String>,
+    pub review_queue_path: Option<String>,
+    pub review_limit: Option<Decimal>,
+    pub ack_path: Option<String>,
+    pub ack_format_path: Option<String>,
+    pub dead_letter_path: Option<String>,
+    pub dedup_window: Option<DedupWindow>,
+    pub dedup_bloom: bool,
+    pub store: Option<String>,
+    pub commit_every: Option<usize>,
+    pub commit_interval: Option<Duration>,
+    pub store_retries: Option<u32>,
+    pub store_backoff: Option<Duration>,
+    pub store_timeout: Option<Duration>,
+    pub tx_bloom: Option<usize>,
+    pub cold_after: Option<usize>,
+    pub expected_transactions: Option<usize>,
+    pub audit_dir: Option<String>,
+    pub run_id: Option<String>,
+    pub propose_path: Option<String>,
+    pub cache_dir: Option<String>,
+    pub verify: bool,
+    pub input_manifest_path: Option<String>,
+    pub preflight_path: Option<String>,
+    pub force: bool,
+    pub summary: bool,
+    pub report_path: Option<String>,
+    pub cohorts_path: Option<String>,
+    pub metrics_path: Option<String>,
+    pub manifest_path: Option<String>,
+    pub notify_path: Option<String>,
+    pub upload_report: Option<SftpLocation>,
+    pub slow_threshold: Option<Duration>,
+    pub otlp_endpoint: Option<String>,
+    /// Debug builds only, see `Chaos`
+    pub chaos_seed: Option<u64>,
+}
+
+impl Options {
+    /// The options a cached result depends on, i.e. all but the input, the cache itself,
+    /// where the summary goes and stderr colors
+    pub fn cache_config(&self) -> String {
+        format!(
+            "{:?}",
+            Options {
+                input_file: String::new(),
+                color: ColorChoice::default(),
+                cache_dir: None,
+                summary: false,
+                report_path: None,
+                metrics_path: None,
+                ..self.clone()
+            }
+        )
+    }
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, ProcessorError> {
+    match args.get(1).map(String::as_str) {
+        Some("snapshot-diff") => match &args[2..] {
+            [before, after] => Ok(Command::SnapshotDiff {
+                before: before.clone(),
+                after: after.clone(),
+            }),
+            _ => Err(usage()),
+        },
+        Some("stats") => match &args[2..] {
+            [input_file] => Ok(Command::Stats { input_file: input_file.clone() }),
+            _ => Err(usage()),
+        },
+        Some("schema") => {
+            let mut rest = args[2..].to_vec();
+            let tx_id_kind = match take_optional_value(&mut rest, "--tx-id-type")? {
+                Some(value) => TxIdKind::from_name(&value).ok_or_else(|| invalid_value("--tx-id-type", &value))?,
+                None => TxIdKind::default(),
+            };
+            let amount_parsing = match take_optional_value(&mut rest, "--amount-parsing")? {
+                Some(value) => AmountParsing::from_name(&value).ok_or_else(|| invalid_value("--amount-parsing", &value))?,
+                None => AmountParsing::default(),
+            };
+
+            match rest.as_slice() {
+                [input_file] => Ok(Command::Schema { input_file: input_file.clone(), tx_id_kind, amount_parsing }),
+                _ => Err(usage()),
+            }
+        }
+        Some("scenario") => match &args[2..] {
+            [] => Err(usage()),
+            files => Ok(Command::Scenario { files: files.to_vec() }),
+        },
+        Some("merge-reports") => match &args[2..] {
+            [] => Err(usage()),
+            files => Ok(Command::MergeReports { files: files.to_vec() }),
+        },
+        Some("coordinate") => {
+            let (mut rest, worker_args) = match args[2..].iter().position(|arg| arg == "--") {
+                Some(position) => (args[2..2 + position].to_vec(), args[3 + position..].to_vec()),
+                None => (args[2..].to_vec(), Vec::new()),
+            };
+            let workers = take_value(&mut rest, "--workers")?;
+            let workers = match workers.parse() {
+                Ok(workers) if workers > 0 => workers,
+                _ => return Err(invalid_value("--workers", &workers)),
+            };
+            let hosts = match take_optional_value(&mut rest, "--hosts")? {
+                Some(hosts) if hosts.split(',').any(str::is_empty) => return Err(invalid_value("--hosts", &hosts)),
+                Some(hosts) => hosts.split(',').map(str::to_string).collect(),
+                None => Vec::new(),
+            };
+            let [input_file] = rest.as_slice() else {
+                return Err(usage());
+            };
+
+            // Every worker gets the same options, so none of them may write a shared file
+            let options = parse_process_args(&[std::slice::from_ref(input_file), worker_args.as_slice()].concat())?;
+            let shared_output = options.snapshot_path.is_some()
+                || options.open_disputes_path.is_some()
+                || options.dump_dir.is_some()
+                || options.anomalies_path.is_some()
+                || options.balance_history_path.is_some()
+                || options.exposure_path.is_some()
+                || options.dispute_stats_path.is_some()
+                || options.dispute_chains_path.is_some()
+                || options.aml_report_path.is_some()
+                || options.review_queue_path.is_some()
+                || options.ack_path.is_some()
+                || options.dead_letter_path.is_some()
+                || options.audit_dir.is_some()
+                || options.propose_path.is_some()
+                || options.report_path.is_some()
+                || options.metrics_path.is_some()
+                || options.manifest_path.is_some()
+                || options.notify_path.is_some()
+                || options.upload_report.is_some()
+                || options.store.as_deref().is_some_and(|store| store.starts_with("file://"));
+            let custom_report = options.pretty || options.report_template_path.is_some() || options.output_format == OutputFormat::Html || options.stream_output || options.cut_by.is_some();
+            if shared_output || options.shard.is_some() || custom_report {
+                return Err(ProcessorError::InvalidArguments(format!(
+                    "worker options cannot write files, use a file store, shard, stream or change the report format\n{}",
+                    USAGE
+                )));
+            }
+
+            // Workers are shards, and a merge may join clients of two shards
+            if options.allow_merges || options.admin_ops_path.is_some() {
+                return Err(ProcessorError::InvalidArguments(format!(
+                    "'--allow-merges' and '--admin-ops' cannot be used in workers\n{}", USAGE
+                )));
+            }
+
+            Ok(Command::Coordinate { input_file: input_file.clone(), workers, hosts, worker_args })
+        }
+        Some("enqueue") => {
+            let (mut rest, job_args) = match args[2..].iter().position(|arg| arg == "--") {
+                Some(position) => (args[2..2 + position].to_vec(), args[3 + position..].to_vec()),
+                None => (args[2..].to_vec(), Vec::new()),
+            };
+            let queue = take_value(&mut rest, "--queue")?;
+            let requeue = take_optional_value(&mut rest, "--requeue")?.map(|job| (job, JobState::Queued));
+            let skip = take_optional_value(&mut rest, "--skip")?.map(|job| (job, JobState::Skipped));
+            if let Some((job, state)) = requeue.or(skip) {
+                if !rest.is_empty() || !job_args.is_empty() {
+                    return Err(usage());
+                }
+                return Ok(Command::SetJobState { queue, job, state });
+            }
+
+            let workers = take_optional_value(&mut rest, "--workers")?
+                .map(|workers| match workers.parse::<u32>() {
+                    Ok(workers) if workers > 0 => Ok(workers),
+                    _ => Err(invalid_value("--workers", &workers)),
+                })
+                .transpose()?;
+            if rest.is_empty() {
+                return Err(usage());
+            }
+            // The worker gives every attempt a run id of its own
+            if job_args.iter().any(|arg| arg == "--run-id") {
+                return Err(ProcessorError::InvalidArguments(format!(
+                    "'--run-id' is set by the worker and cannot be given to a job\n{}", USAGE
+                )));
+            }
+
+            // Checked now rather than when the worker gets to the job
+            let mut jobs = Vec::new();
+            for input_file in &rest {
+                let options = parse_process_args(&[std::slice::from_ref(input_file), job_args.as_slice()].concat())?;
+                if let Some(workers) = workers {
+                    let coordinate = [&args[..1], &["coordinate".to_string(), input_file.clone(), "--workers".to_string(), workers.to_string(), "--".to_string()], job_args.as_slice()].concat();
+                    parse_args(&coordinate)?;
+                }
+                jobs.push(jobs::spec(input_file, job_args.clone(), workers, options.store, options.audit_dir)?);
+            }
+
+            Ok(Command::Enqueue { queue, jobs })
+        }
+        Some("worker") => {
+            let mut rest = args[2..].to_vec();
+            let queue = take_value(&mut rest, "--queue")?;
+            match rest.as_slice() {
+                [] => Ok(Command::Worker { queue, status: false }),
+                [status] if status == "--status" => Ok(Command::Worker { queue, status: true }),
+                _ => Err(usage()),
+            }
+        }
+        Some("healthcheck") => {
+            let mut rest = args[2..].to_vec();
+            let store = take_optional_value(&mut rest, "--store")?;
+            let audit_dir = take_optional_value(&mut rest, "--audit-dir")?;
+            // Nothing to check without a store or an audit directory
+            if !rest.is_empty() || (store.is_none() && audit_dir.is_none()) {
+                return Err(usage());
+            }
+
+            Ok(Command::Healthcheck { store, audit_dir })
+        }
+        Some("replay") => {
+            let mut rest = args[2..].to_vec();
+            let speed = match take_optional_value(&mut rest, "--speed")? {
+                Some(value) => replay::parse_speed(&value).ok_or_else(|| invalid_value("--speed", &value))?,
+                None => 1.0,
+            };
+            let restamp = match rest.iter().position(|arg| arg == "--timestamps") {
+                Some(position) => {
+                    rest.remove(position);
+                    true
+                }
+                None => false,
+            };
+
+            match rest.as_slice() {
+                [input_file] => Ok(Command::Replay { input_file: input_file.clone(), speed, restamp }),
+                _ => Err(usage()),
+            }
+        }
+        Some("balance-at") => {
+            let mut rest = args[2..].to_vec();
+            let client = take_value(&mut rest, "--client")?;
+            let at = take_value(&mut rest, "--at")?;
+
+            Ok(Command::BalanceAt {
+                options: parse_process_args(&rest)?,
+                client: client.parse().map_err(|_| invalid_value("--client", &client))?,
+                at: at.parse().map_err(|_| invalid_value("--at", &at))?,
+            })
+        }
+        Some("query") => match &args[2..] {
+            [input_file, query, options @ ..] => Ok(Command::Query {
+                options: parse_process_args(&[std::slice::from_ref(input_file), options].concat())?,
+                query: Query::parse(query)?,
+            }),
+            _ => Err(usage()),
+        },
+        Some("undo") => {
+            let mut rest = args[2..].to_vec();
+            let run_id = take_value(&mut rest, "--run")?;
+            let store = take_value(&mut rest, "--store")?;
+            let audit_dir = take_value(&mut rest, "--audit-dir")?;
+            if !rest.is_empty() {
+                return Err(usage());
+            }
+
+            Ok(Command::Undo { run_id, store, audit_dir })
+        }
+        Some("apply") => {
+            let mut rest = args[2..].to_vec();
+            let store = take_value(&mut rest, "--store")?;
+            let audit_dir = take_optional_value(&mut rest, "--audit-dir")?;
+            let run_id = take_optional_value(&mut rest, "--run-id")?;
+
+            match rest.as_slice() {
+                [proposals] => Ok(Command::Apply {
+                    proposals: proposals.clone(),
+                    store,
+                    audit_dir,
+                    run_id,
+                }),
+                _ => Err(usage()),
+            }
+        }
+        _ => parse_process_args(&args[1..]).map(Command::Process),
+    }
+}
+
+fn parse_process_args(args: &[String]) -> Result<Options, ProcessorError> {
+    let mut input_file = None;
+    let mut log_transactions = false;
+    let mut log_timezone = None;
+    let mut log_time_format = None;
+    let mut log_per_client = None;
+    let mut log_buckets = None;
+    let mut log_levels = None;
+    let mut tx_id_kind = TxIdKind::default();
+    let mut output_schema = OutputSchema::default();
+    let mut amount_format = AmountFormat::default();
+    let mut amount_parsing = AmountParsing::default();
+    let mut pretty = false;
+    let mut quiet = false;
+    let mut report_template_path = None;
+    let mut output_format = OutputFormat::default();
+    let mut locale = Locale::default();
+    let mut color = ColorChoice::default();
+    let mut snapshot_path = None;
+    let mut allow_adjustments = false;
+    let mut allow_merges = false;
+    let mut admin_ops_path = None;
+    let mut admin_ops_at = None;
+    let mut disabled_types = Vec::new();
+    let mut cut_by = None;
+    let mut dispute_rules_path = None;
+    let mut reason_codes_path = None;
+    let mut open_disputes_path = None;
+    let mut dump_dir = None;
+    let mut shortfall_policy = ShortfallPolicy::default();
+    let mut freeze_policy = FreezePolicy::default();
+    let mut risk_scoring = false;
+    let mut risk_lock_threshold = None;
+    let mut anomalies_path = None;
+    let mut exposure_path = None;
+    let mut dispute_stats_path = None;
+    let mut dispute_chains_path = None;
+    let mut balance_history_path = None;
+    let mut sample_every = None;
+    let mut history_clients = Vec::new();
+    let mut aml_thresholds_path = None;
+    let mut aml_report_path = None;
+    let mut screening_denylist_path = None;
+    let mut screening_url = None;
+    let mut plugin_paths = Vec::new();
+    let mut script_path = None;
+    let mut review_queue_path = None;
+    let mut review_limit = None;
+    let mut ack_path = None;
+    let mut ack_format_path = None;
+    let mut dead_letter_path = None;
+    let mut dedup_window = None;
+    let mut dedup_bloom = false;
+    let mut store = None;
+    let mut commit_every = None;
+    let mut commit_interval = None;
+    let mut store_retries = None;
+    let mut store_backoff = None;
+    let mut store_timeout = None;
+    let mut tx_bloom = None;
+    let mut cold_after = None;
+    let mut expected_transactions = None;
+    let mut audit_dir = None;
+    let mut run_id = None;
+    let mut propose_path = None;
+    let mut cache_dir = None;
+    let mut verify = false;
+    let mut input_manifest_path = None;
+    let mut preflight_path = None;
+    let mut force = false;
+    let mut summary = false;
+    let mut stream_output = false;
+    let mut workers = None;
+    let mut client_budget = None;
+    let mut pin_cores = false;
+    let mut max_tps = None;
+    let mut shard = None;
+    let mut report_path = None;
+    let mut cohorts_path = None;
+    let mut metrics_path = None;
+    let mut manifest_path = None;
+    let mut notify_path = None;
+    let mut upload_report = None;
+    let mut slow_threshold = None;
+    let mut otlp_endpoint = None;
+    let mut chaos_seed = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--log-transactions" => log_transactions = true,
+            "--log-timezone" => {
+                let value = next_value(&mut iter, arg)?;
+                log_timezone = Some(LogTimezone::from_name(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--log-time-format" => {
+                let value = next_value(&mut iter, arg)?;
+                log_time_format = Some(LogTimeFormat::parse(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--log-level" => {
+                let value = next_value(&mut iter, arg)?;
+                log_levels = Some(LogLevels::parse(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--log-per-client" => log_per_client = Some(next_value(&mut iter, arg)?.to_string()),
+            "--log-buckets" => {
+                let value = next_value(&mut iter, arg)?;
+                match value.parse() {
+                    Ok(buckets) if buckets > 0 => log_buckets = Some(buckets),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--allow-adjustments" => allow_adjustments = true,
+            "--allow-merges" => allow_merges = true,
+            "--admin-ops" => admin_ops_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--disable" => {
+                let value = next_value(&mut iter, arg)?;
+                for name in value.split(',').map(str::trim) {
+                    disabled_types.push(TransactionType::from_name(name).ok_or_else(|| invalid_value(arg, name))?);
+                }
+            }
+            "--admin-ops-at" => match next_value(&mut iter, arg)? {
+                "before" => admin_ops_at = Some(AdminOpsAt::Before),
+                "after" => admin_ops_at = Some(AdminOpsAt::After),
+                value => return Err(invalid_value(arg, value)),
+            },
+            "--snapshot" => snapshot_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--risk" => risk_scoring = true,
+            "--risk-lock-threshold" => {
+                let value = next_value(&mut iter, arg)?;
+                risk_lock_threshold = Some(value.parse().map_err(|_| invalid_value(arg, value))?);
+                risk_scoring = true;
+            }
+            "--exposure" => exposure_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--dispute-stats" => dispute_stats_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--dispute-chains" => dispute_chains_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--balance-history" => balance_history_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--sample-every" => {
+                let value = next_value(&mut iter, arg)?;
+                match value.parse() {
+                    Ok(rows) if rows > 0 => sample_every = Some(rows),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--history-clients" => {
+                let value = next_value(&mut iter, arg)?;
+                for client in value.split(',').map(str::trim) {
+                    history_clients.push(client.parse().map_err(|_| invalid_value(arg, client))?);
+                }
+            }
+            "--store" => store = Some(next_value(&mut iter, arg)?.to_string()),
+            "--commit-every" => {
+                let value = next_value(&mut iter, arg)?;
+                match value.parse() {
+                    Ok(rows) if rows > 0 => commit_every = Some(rows),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--commit-interval" => {
+                let value = next_value(&mut iter, arg)?;
+                commit_interval = Some(latency::parse_duration(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--store-retries" => {
+                let value = next_value(&mut iter, arg)?;
+                match value.parse() {
+                    Ok(attempts) if attempts > 0 => store_retries = Some(attempts),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--store-backoff" => {
+                let value = next_value(&mut iter, arg)?;
+                store_backoff = Some(latency::parse_duration(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--store-timeout" => {
+                let value = next_value(&mut iter, arg)?;
+                match latency::parse_duration(value) {
+                    Some(timeout) if !timeout.is_zero() => store_timeout = Some(timeout),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--tx-bloom" => {
+                let value = next_value(&mut iter, arg)?;
+                match value.parse() {
+                    Ok(keys) if keys > 0 => tx_bloom = Some(keys),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--cold-after" => {
+                let value = next_value(&mut iter, arg)?;
+                match value.parse() {
+                    Ok(transactions) if transactions > 0 => cold_after = Some(transactions),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--expected-transactions" => {
+                let value = next_value(&mut iter, arg)?;
+                expected_transactions = Some(value.parse().map_err(|_| invalid_value(arg, value))?);
+            }
+            "--audit-dir" => audit_dir = Some(next_value(&mut iter, arg)?.to_string()),
+            "--cache-dir" => cache_dir = Some(next_value(&mut iter, arg)?.to_string()),
+            "--verify" => verify = true,
+            "--input-manifest" => input_manifest_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--preflight" => preflight_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--force" => force = true,
+            "--summary" => summary = true,
+            "--pretty" => pretty = true,
+            "--quiet" => quiet = true,
+            "--stream-output" => stream_output = true,
+            "--workers" => {
+                let value = next_value(&mut iter, arg)?;
+                match value.parse() {
+                    Ok(threads) if threads > 0 => workers = Some(threads),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--client-budget" => {
+                let value = next_value(&mut iter, arg)?;
+                match value.parse() {
+                    Ok(rows) if rows > 0 => client_budget = Some(rows),
+                    _ => return Err(invalid_value(arg, value)),
+                }
+            }
+            "--pin-cores" => pin_cores = true,
+            "--max-tps" => {
+                let value = next_value(&mut iter, arg)?;
+                max_tps = Some(throttle::parse_rate(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--shard" => {
+                let value = next_value(&mut iter, arg)?;
+                shard = Some(Shard::parse(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--locale" => {
+                let value = next_value(&mut iter, arg)?;
+                locale = Locale::from_name(value).ok_or_else(|| invalid_value(arg, value))?;
+            }
+            "--color" => {
+                let value = next_value(&mut iter, arg)?;
+                color = ColorChoice::from_name(value).ok_or_else(|| invalid_value(arg, value))?;
+            }
+            "--report" => report_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--cohorts" => cohorts_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--metrics" => metrics_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--report-template" => report_template_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--output-format" => match next_value(&mut iter, arg)? {
+                "csv" => output_format = OutputFormat::Csv,
+                "html" => output_format = OutputFormat::Html,
+                value => return Err(invalid_value(arg, value)),
+            },
+            "--manifest" => manifest_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--notify" => notify_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--source" if input_file.is_none() => input_file = Some(next_value(&mut iter, arg)?.to_string()),
+            "--upload-report" => upload_report = Some(SftpLocation::parse(next_value(&mut iter, arg)?)?),
+            "--slow-threshold" => {
+                let value = next_value(&mut iter, arg)?;
+                slow_threshold = Some(latency::parse_duration(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--otlp-endpoint" => otlp_endpoint = Some(next_value(&mut iter, arg)?.to_string()),
+            // Failure injection for testing, release builds reject it as an unknown flag
+            "--chaos" if cfg!(debug_assertions) => {
+                let value = next_value(&mut iter, arg)?;
+                chaos_seed = Some(value.parse().map_err(|_| invalid_value(arg, value))?);
+            }
+            "--propose" => propose_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--run-id" => run_id = Some(next_value(&mut iter, arg)?.to_string()),
+            "--anomalies" => anomalies_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--aml-thresholds" => aml_thresholds_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--aml-report" => aml_report_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--screening-denylist" => screening_denylist_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--screening-url" => screening_url = Some(next_value(&mut iter, arg)?.to_string()),
+            "--plugin" => plugin_paths.push(next_value(&mut iter, arg)?.to_string()),
+            "--script" => script_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--review-queue" => review_queue_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--review-over" => {
+                let value = next_value(&mut iter, arg)?;
+                review_limit = Some(value.parse().map_err(|_| invalid_value(arg, value))?);
+            }
+            "--ack-out" => ack_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--ack-format" => ack_format_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--dead-letter" => dead_letter_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--dedup-window" => {
+                let value = next_value(&mut iter, arg)?;
+                dedup_window = Some(DedupWindow::parse(value).ok_or_else(|| invalid_value(arg, value))?);
+            }
+            "--dedup-bloom" => dedup_bloom = true,
+            "--dispute-rules" => dispute_rules_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--reason-codes" => reason_codes_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--open-disputes" => open_disputes_path = Some(next_value(&mut iter, arg)?.to_string()),
+            "--dump-dir" => dump_dir = Some(next_value(&mut iter, arg)?.to_string()),
+            "--output-schema" => {
+                let value = next_value(&mut iter, arg)?;
+                output_schema = OutputSchema::from_name(value).ok_or_else(|| invalid_value(arg, value))?;
+            }
+            "--amount-format" => {
+                let value = next_value(&mut iter, arg)?;
+                amount_format = AmountFormat::from_name(value).ok_or_else(|| invalid_value(arg, value))?;
+            }
+            "--amount-parsing" => {
+                let value = next_value(&mut iter, arg)?;
+                amount_parsing = AmountParsing::from_name(value).ok_or_else(|| invalid_value(arg, value))?;
+            }
+            "--dispute-shortfall" => {
+                let value = next_value(&mut iter, arg)?;
+                shortfall_policy = ShortfallPolicy::from_name(value).ok_or_else(|| invalid_value(arg, value))?;
+            }
+            "--freeze-policy" => {
+                let value = next_value(&mut iter, arg)?;
+                freeze_policy = FreezePolicy::from_name(value).ok_or_else(|| invalid_value(arg, value))?;
+            }
+            "--cut-by" => match next_value(&mut iter, arg)? {
+                "day" => cut_by = Some(CutBy::Day),
+                value => return Err(invalid_value(arg, value)),
+            },
+            "--tx-id-type" => {
+                let value = next_value(&mut iter, arg)?;
+                tx_id_kind = TxIdKind::from_name(value).ok_or_else(|| {
+                    ProcessorError::InvalidArguments(format!("unknown tx id type '{}'\n{}", value, USAGE))
+                })?;
+            }
+            flag if flag.starts_with("--") => {
+                return Err(ProcessorError::InvalidArguments(format!("unknown flag '{}'\n{}", flag, USAGE)));
+            }
+            path if input_file.is_none() => input_file = Some(path.to_string()),
+            _ => return Err(usage()),
+        }
+    }
+
+    // The audit trail records store commits, and a run id only names an audit trail or a proposal
+    if audit_dir.is_some() && store.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--audit-dir' requires '--store'\n{}", USAGE)));
+    }
+    if (log_timezone.is_some() || log_time_format.is_some()) && !log_transactions && log_per_client.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--log-timezone' and '--log-time-format' require '--log-transactions' or '--log-per-client'\n{}", USAGE
+        )));
+    }
+    if log_levels.is_some() && !log_transactions && log_per_client.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--log-level' requires '--log-transactions' or '--log-per-client'\n{}", USAGE
+        )));
+    }
+    if log_transactions && log_per_client.is_some() {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--log-transactions' and '--log-per-client' are mutually exclusive\n{}", USAGE
+        )));
+    }
+    if log_buckets.is_some() && log_per_client.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--log-buckets' requires '--log-per-client'\n{}", USAGE)));
+    }
+    if pretty && quiet {
+        return Err(ProcessorError::InvalidArguments(format!("'--pretty' and '--quiet' are mutually exclusive\n{}", USAGE)));
+    }
+    // Cohorts only break down the summary
+    if cohorts_path.is_some() && !summary && report_path.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--cohorts' requires '--summary' or '--report'\n{}", USAGE)));
+    }
+    // Thresholds are only evaluated to write their report
+    if aml_thresholds_path.is_some() != aml_report_path.is_some() {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--aml-thresholds' and '--aml-report' must be given together\n{}", USAGE
+        )));
+    }
+    if (sample_every.is_some() || !history_clients.is_empty()) && balance_history_path.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--sample-every' and '--history-clients' require '--balance-history'\n{}", USAGE
+        )));
+    }
+    if balance_history_path.is_some() && sample_every.is_none() && history_clients.is_empty() {
+        sample_every = Some(1000);
+    }
+    // Bloom filter generations are sized by a key count
+    if dedup_bloom && !matches!(dedup_window, Some(DedupWindow::Keys(_))) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--dedup-bloom' requires a key count '--dedup-window'\n{}", USAGE
+        )));
+    }
+    if screening_denylist_path.is_some() && screening_url.is_some() {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--screening-denylist' and '--screening-url' are mutually exclusive\n{}", USAGE
+        )));
+    }
+    // Rows over the limit are parked in the queue
+    if review_limit.is_some() && review_queue_path.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--review-over' requires '--review-queue'\n{}", USAGE)));
+    }
+    if ack_format_path.is_some() && ack_path.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--ack-format' requires '--ack-out'\n{}", USAGE)));
+    }
+    // A cached result replaces the report and the summary only, and must not depend on a store
+    // or a remote service
+    let side_effects = store.is_some()
+        || screening_url.is_some()
+        || log_transactions
+        || log_per_client.is_some()
+        || snapshot_path.is_some()
+        || open_disputes_path.is_some()
+        || dump_dir.is_some()
+        || anomalies_path.is_some()
+        || balance_history_path.is_some()
+        || exposure_path.is_some()
+        || dispute_stats_path.is_some()
+        || dispute_chains_path.is_some()
+        || aml_report_path.is_some()
+        || review_queue_path.is_some()
+        || ack_path.is_some()
+        || dead_letter_path.is_some()
+        || propose_path.is_some()
+        || manifest_path.is_some()
+        || upload_report.is_some()
+        || otlp_endpoint.is_some()
+        || chaos_seed.is_some();
+    if cache_dir.is_some() && (side_effects || stream_output) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--cache-dir' cannot be combined with '--store', '--screening-url', '--stream-output' or options writing other files\n{}",
+            USAGE
+        )));
+    }
+    // Streamed rows are written one by one, not as a table or per day
+    if stream_output && (pretty || cut_by.is_some()) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--stream-output' cannot be combined with '--pretty' or '--cut-by'\n{}", USAGE
+        )));
+    }
+    // A merge may join clients of two shards
+    if allow_merges && shard.is_some() {
+        return Err(ProcessorError::InvalidArguments(format!("'--allow-merges' cannot be combined with '--shard'\n{}", USAGE)));
+    }
+    if admin_ops_at.is_some() && admin_ops_path.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--admin-ops-at' requires '--admin-ops'\n{}", USAGE)));
+    }
+    // Admin operations apply around the whole file, and a merge may join clients of two shards.
+    // A cached result would not know the admin file changed.
+    if admin_ops_path.is_some() && (stream_output || cut_by.is_some() || shard.is_some() || cache_dir.is_some()) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--admin-ops' cannot be combined with '--stream-output', '--cut-by', '--shard' or '--cache-dir'\n{}", USAGE
+        )));
+    }
+    // A template renders the final accounts in place of the CSV
+    if report_template_path.is_some() && (pretty || quiet || stream_output || cut_by.is_some()) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--report-template' cannot be combined with '--pretty', '--quiet', '--stream-output' or '--cut-by'\n{}", USAGE
+        )));
+    }
+    // The dashboard is a template of its own
+    if output_format == OutputFormat::Html && (report_template_path.is_some() || pretty || quiet || stream_output || cut_by.is_some()) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--output-format html' cannot be combined with '--report-template', '--pretty', '--quiet', '--stream-output' or '--cut-by'\n{}", USAGE
+        )));
+    }
+    // A proposal is the single uncommitted batch of the whole file
+    if propose_path.is_some() && (store.is_none() || commit_every.is_some() || commit_interval.is_some()) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--propose' requires '--store' and cannot be combined with '--commit-every' or '--commit-interval'\n{}", USAGE
+        )));
+    }
+    if (store_retries.is_some() || store_backoff.is_some() || store_timeout.is_some()) && store.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--store-retries', '--store-backoff' and '--store-timeout' require '--store'\n{}",
+            USAGE
+        )));
+    }
+    if tx_bloom.is_some() && store.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--tx-bloom' requires '--store'\n{}", USAGE)));
+    }
+    // Redis and file stores keep their transactions themselves
+    let persistent = store.as_deref().is_some_and(|store| store.starts_with("redis://") || store.starts_with("file://"));
+    if cold_after.is_some() && persistent {
+        return Err(ProcessorError::InvalidArguments(format!("'--cold-after' cannot be combined with a redis or file store\n{}", USAGE)));
+    }
+    if expected_transactions.is_some() && (persistent || cold_after.is_some()) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--expected-transactions' cannot be combined with '--cold-after' or a redis or file store\n{}", USAGE
+        )));
+    }
+    if run_id.is_some() && audit_dir.is_none() && propose_path.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--run-id' requires '--audit-dir' or '--propose'\n{}", USAGE)));
+    }
+    if force && preflight_path.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--force' requires '--preflight'\n{}", USAGE)));
+    }
+    let input_file = input_file.ok_or_else(usage)?;
+    // Both hash the input before or after the run, which would download it a second time
+    if !input::is_local_file(&input_file) && (cache_dir.is_some() || manifest_path.is_some()) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--cache-dir' and '--manifest' require a local input file\n{}", USAGE
+        )));
+    }
+    // The input is read once to check it and once to process it
+    if !input::is_local_file(&input_file) && (preflight_path.is_some() || verify || input_manifest_path.is_some()) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--preflight', '--verify' and '--input-manifest' require a local input file\n{}", USAGE
+        )));
+    }
+    if (client_budget.is_some() || pin_cores) && workers.is_none() {
+        return Err(ProcessorError::InvalidArguments(format!("'--client-budget' and '--pin-cores' require '--workers'\n{}", USAGE)));
+    }
+    // These rely on the rows of the input being processed one at a time, in file order
+    if workers.is_some()
+        && (stream_output
+            || cut_by.is_some()
+            || store.is_some()
+            || allow_merges
+            || ack_path.is_some()
+            || cohorts_path.is_some()
+            || balance_history_path.is_some())
+    {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--workers' cannot be combined with '--stream-output', '--cut-by', '--store', '--allow-merges', '--ack-out', '--cohorts' or '--balance-history'\n{}",
+            USAGE
+        )));
+    }
+    // The uploaded report is the one written to stdout
+    if upload_report.is_some() && (quiet || stream_output) {
+        return Err(ProcessorError::InvalidArguments(format!(
+            "'--upload-report' cannot be combined with '--quiet' or '--stream-output'\n{}", USAGE
+        )));
+    }
+    if (input_file.starts_with("sftp://") || upload_report.is_some()) && !cfg!(feature = "sftp") {
+        return Err(ProcessorError::InvalidArguments("sftp:// locations require a build with the sftp feature".to_string()));
+    }
+
+    Ok(Options {
+        input_file,
+        log_transactions,
+        log_timezone: log_timezone.unwrap_or_default(),
+        log_time_format: log_time_format.unwrap_or_default(),
+        log_per_client,
+        log_buckets,
+        log_levels,
+        tx_id_kind,
+        output_schema,
+        amount_format,
+        amount_parsing,
+        pretty,
+        quiet,
+        report_template_path,
+        output_format,
+        stream_output,
+        workers,
+        client_budget,
+        pin_cores,
+        max_tps,
+        shard,
+        locale,
+        color,
+        snapshot_path,
+        allow_adjustments,
+        allow_merges,
+        admin_ops_path,
+        admin_ops_at,
+        disabled_types,
+        cut_by,
+        dispute_rules_path,
+        reason_codes_path,
+        open_disputes_path,
+        dump_dir,
+        shortfall_policy,
+        freeze_policy,
+        risk_scoring,
+        risk_lock_threshold,
+        anomalies_path,
+        exposure_path,
+        dispute_stats_path,
+        dispute_chains_path,
+        balance_history_path,
+        sample_every,
+        history_clients,
+        aml_thresholds_path,
+        aml_report_path,
+        screening_denylist_path,
+        screening_url,
+        plugin_paths,
+        script_path,
+        review_queue_path,
+        review_limit,
+        ack_path,
+        ack_format_path,
+        dead_letter_path,
+        dedup_window,
+        dedup_bloom,
+        store,
+        commit_every,
+        commit_interval,
+        store_retries,
+        store_backoff,
+        store_timeout,
+        tx_bloom,
+        cold_after,
+        expected_transactions,
+        audit_dir,
+        run_id,
+        propose_path,
+        cache_dir,
+        verify,
+        input_manifest_path,
+        preflight_path,
+        force,
+        summary,
+        cohorts_path,
+        report_path,
+        metrics_path,
+        manifest_path,
+        notify_path,
+        upload_report,
+        slow_threshold,
+        otlp_endpoint,
+        chaos_seed,
+    })
+}
+
+fn next_value<'a>(iter: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<&'a str, ProcessorError> {
+    iter.next()
+        .map(String::as_str)
+        .ok_or_else(|| ProcessorError::InvalidArguments(format!("missing value for '{}'\n{}", flag, USAGE)))
+}
+
+/// Removes a required `flag value` pair from the arguments and returns the value
+fn take_value(args: &mut Vec<String>, flag: &str) -> Result<String, ProcessorError> {
+    take_optional_value(args, flag)?.ok_or_else(|| {
+        ProcessorError::InvalidArguments(format!("missing required '{}'\n{}", flag, USAGE))
+    })
+}
+
+/// Removes an optional `flag value` pair from the arguments and returns the value
+fn take_optional_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, ProcessorError> {
+    let Some(position) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    if position + 1 >= args.len() {
+        return Err(ProcessorError::InvalidArguments(format!("missing value for '{}'\n{}", flag, USAGE)));
+    }
+
+    let value = args.remove(position + 1);
+    args.remove(position);
+    Ok(Some(value))
+}
+
+fn invalid_value(flag: &str, value: &str) -> ProcessorError {
+    ProcessorError::InvalidArguments(format!("invalid value '{}' for '{}'\n{}", value, flag, USAGE))
+}
+
+fn usage() -> ProcessorError {
+    ProcessorError::InvalidArguments(USAGE.to_string())
+}
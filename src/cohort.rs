@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use dashmap::DashMap;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType};
+use crate::processor::open_reader;
+
+/// Cohort of the clients missing from the mapping file
+const UNMAPPED: &str = "unmapped";
+
+/// Clients mapped to cohorts with `--cohorts <cohorts.csv>`: a `client` column and one
+/// column per dimension, e.g.
+///
+/// ```csv
+/// client,channel,country
+/// 1,paid_search,DE
+/// 2,referral,FR
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Cohorts {
+    dimensions: Vec<String>,
+    clients: HashMap<u16, Vec<String>>,
+}
+
+impl Cohorts {
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let mut reader = open_reader(path)?;
+        let headers = reader.headers()?.clone();
+        if headers.get(0) != Some("client") || headers.len() < 2 {
+            return Err(ProcessorError::InvalidArguments(format!(
+                "{}: expected a client column followed by at least one cohort column",
+                path
+            )));
+        }
+
+        let mut clients = HashMap::new();
+        for record in reader.records() {
+            let record = record?;
+            let client = record[0]
+                .parse()
+                .map_err(|_| ProcessorError::InvalidArguments(format!("{}: invalid client '{}'", path, &record[0])))?;
+            let cohorts = record.iter().skip(1).map(|cohort| match cohort {
+                "" => UNMAPPED.to_string(),
+                cohort => cohort.to_string(),
+            });
+            clients.insert(client, cohorts.collect());
+        }
+        Ok(Cohorts { dimensions: headers.iter().skip(1).map(str::to_string).collect(), clients })
+    }
+
+    fn cohort(&self, client: u16, dimension: usize) -> &str {
+        self.clients.get(&client).and_then(|cohorts| cohorts.get(dimension)).map_or(UNMAPPED, String::as_str)
+    }
+}
+
+/// Figures of the clients of one cohort in the run summary
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CohortFigures {
+    /// Clients of the cohort that had rows in the run
+    pub clients: u64,
+    pub rows: u64,
+    pub rows_rejected: u64,
+    /// Amounts of the applied deposits and withdrawals
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub chargebacks: u64,
+}
+
+impl CohortFigures {
+    fn add(&mut self, other: &CohortFigures) {
+        self.clients += other.clients;
+        self.rows += other.rows;
+        self.rows_rejected += other.rows_rejected;
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.chargebacks += other.chargebacks;
+    }
+}
+
+/// Figures per client, summed up per cohort for the summary. Like the acknowledgement
+/// file, it relies on rows being processed one at a time to tell whether a row was rejected.
+#[derive(Debug)]
+pub struct CohortStats {
+    cohorts: Cohorts,
+    clients: DashMap<u16, CohortFigures>,
+    row_rejected: AtomicBool,
+}
+
+impl CohortStats {
+    pub fn new(cohorts: Cohorts) -> Self {
+        CohortStats { cohorts, clients: DashMap::new(), row_rejected: AtomicBool::new(false) }
+    }
+
+    /// Marks the current row rejected
+    pub fn rejected(&self) {
+        self.row_rejected.store(true, Ordering::Relaxed);
+    }
+
+    /// Takes a row the engine applied
+    pub fn applied(&self, record: &TransactionInput) {
+        let mut figures = self.clients.entry(record.client).or_default();
+        match (&record.transaction_type, record.amount) {
+            (TransactionType::Deposit, Some(amount)) => figures.deposits += amount,
+            (TransactionType::Withdrawal, Some(amount)) => figures.withdrawals += amount,
+            (TransactionType::Chargeback, _) => figures.chargebacks += 1,
+            _ => {}
+        }
+    }
+
+    /// Counts the current row for its client
+    pub fn finish_row(&self, client: u16) {
+        let mut figures = self.clients.entry(client).or_default();
+        figures.clients = 1;
+        figures.rows += 1;
+        if self.row_rejected.swap(false, Ordering::Relaxed) {
+            figures.rows_rejected += 1;
+        }
+    }
+
+    /// Figures per cohort, per dimension
+    pub fn summary(&self) -> BTreeMap<String, BTreeMap<String, CohortFigures>> {
+        let mut summary: BTreeMap<String, BTreeMap<String, CohortFigures>> = BTreeMap::new();
+        for (index, dimension) in self.cohorts.dimensions.iter().enumerate() {
+            let cohorts = summary.entry(dimension.clone()).or_default();
+            for entry in self.clients.iter() {
+                let cohort = self.cohorts.cohort(*entry.key(), index);
+                cohorts.entry(cohort.to_string()).or_default().add(entry.value());
+            }
+        }
+        summary
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::model::error::ProcessorError;
+
+/// A row that could not be read, one JSON line of the dead-letter file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Input the row was read from, None for input that is not a file, e.g. a string
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Input line of the row, if it could be located
+    pub line: Option<u64>,
+    /// Fields of the row joined with commas, None if the row could not be split into fields
+    pub row: Option<String>,
+    pub error: String,
+    /// How many times the row at this line of the source was dead-lettered, counting earlier
+    /// runs on the same file
+    pub attempts: u32,
+    pub at: DateTime<Utc>,
+}
+
+/// Attempts per line of the source rows are currently dead-lettered from
+#[derive(Default)]
+struct Attempts {
+    source: Option<String>,
+    lines: HashMap<u64, u32>,
+}
+
+/// Malformed rows diverted to `--dead-letter <path>` instead of stopping the run.
+/// Entries are appended, so a row redelivered by a later run shows up again with a
+/// higher `attempts` count. Rows are told apart by their source and line rather than their
+/// text, so identical rows of a file keep their own counts, and only the counts of the
+/// source being read are kept in memory.
+pub struct DeadLetterQueue {
+    path: String,
+    file: Mutex<File>,
+    attempts: Mutex<Attempts>,
+    /// Whether rows were appended since the last `sync`
+    unsynced: AtomicBool,
+    written: AtomicU64,
+}
+
+impl DeadLetterQueue {
+    pub fn open(path: &str) -> Result<Self, ProcessorError> {
+        Ok(DeadLetterQueue {
+            path: path.to_string(),
+            file: Mutex::new(OpenOptions::new().create(true).append(true).open(path)?),
+            attempts: Mutex::new(Attempts::default()),
+            unsynced: AtomicBool::new(false),
+            written: AtomicU64::new(0),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Number of rows dead-lettered by this run
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Appends a row of `source` with the error it failed with at `at`. The row is only
+    /// flushed to disk by `sync`.
+    pub fn push(
+        &self,
+        source: Option<&str>,
+        line: Option<u64>,
+        row: Option<String>,
+        error: &str,
+        at: DateTime<Utc>,
+    ) -> Result<(), ProcessorError> {
+        let attempts = match (source, line) {
+            (Some(source), Some(line)) => {
+                let mut attempts = self.attempts.lock();
+                if attempts.source.as_deref() != Some(source) {
+                    *attempts = Attempts {
+                        source: Some(source.to_string()),
+                        lines: self.load_attempts(source)?,
+                    };
+                }
+                let count = attempts.lines.entry(line).or_insert(0);
+                *count += 1;
+                *count
+            }
+            // A row that cannot be located cannot be recognized when it comes again
+            _ => 1,
+        };
+
+        let mut entry = serde_json::to_vec(&DeadLetter {
+            source: source.map(str::to_string),
+            line,
+            row,
+            error: error.to_string(),
+            attempts,
+            at,
+        })?;
+        entry.push(b'\n');
+
+        self.file.lock().write_all(&entry)?;
+        self.unsynced.store(true, Ordering::Relaxed);
+        self.written.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Flushes the rows appended since the last call to disk, called before a batch of
+    /// the input is committed so that no row it skipped is lost
+    pub fn sync(&self) -> Result<(), ProcessorError> {
+        if self.unsynced.swap(false, Ordering::Relaxed) {
+            self.file.lock().sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the highest attempts per line of `source` from the file
+    fn load_attempts(&self, source: &str) -> Result<HashMap<u64, u32>, ProcessorError> {
+        let mut attempts = HashMap::new();
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(attempts),
+            Err(err) => return Err(err.into()),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let letter: DeadLetter = serde_json::from_str(&line)?;
+            if let (Some(line), true) = (letter.line, letter.source.as_deref() == Some(source)) {
+                let count = attempts.entry(line).or_insert(0);
+                *count = letter.attempts.max(*count);
+            }
+        }
+        Ok(attempts)
+    }
+}
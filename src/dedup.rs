@@ -0,0 +1,139 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+
+use crate::bloom::Bloom;
+use crate::latency;
+
+/// How long a key is remembered, set with `--dedup-window`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DedupWindow {
+    /// The last N keys, a row has up to two: its idempotency key and its tx id
+    Keys(usize),
+    /// Keys seen within this time of the row's timestamp
+    Time(Duration),
+}
+
+impl DedupWindow {
+    /// Parses a key count such as `100000` or a duration such as `30s`, `15m` or `24h`
+    pub fn parse(value: &str) -> Option<Self> {
+        if let Ok(keys) = value.parse() {
+            return (keys > 0).then_some(DedupWindow::Keys(keys));
+        }
+        let (amount, unit) = value.split_at(value.find(|c: char| c.is_ascii_alphabetic())?);
+        let amount: u64 = amount.parse().ok()?;
+        match unit {
+            "m" => Some(DedupWindow::Time(Duration::from_secs(amount * 60))),
+            "h" => Some(DedupWindow::Time(Duration::from_secs(amount * 3600))),
+            _ => latency::parse_duration(value).map(DedupWindow::Time),
+        }
+    }
+}
+
+enum Memory {
+    /// Every key of the window, in arrival order for eviction
+    Exact { keys: HashSet<String>, order: VecDeque<(String, Option<DateTime<Utc>>)> },
+    /// Two generations of `capacity` keys each; when the current one is full the older one is
+    /// cleared and takes its place, so a key is remembered for at least `capacity` more keys
+    Bloom { current: Bloom, previous: Bloom, capacity: usize },
+}
+
+/// Keys of rows already applied, to drop rows a source delivers again. Unbounded by default;
+/// `--dedup-window` forgets old keys and `--dedup-bloom` trades exactness for fixed memory.
+pub struct DedupFilter {
+    window: Option<DedupWindow>,
+    memory: Mutex<Memory>,
+    /// Latest row timestamp, the clock of a time window
+    now: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl DedupFilter {
+    pub fn new(window: Option<DedupWindow>) -> Self {
+        DedupFilter {
+            window,
+            memory: Mutex::new(Memory::Exact { keys: HashSet::new(), order: VecDeque::new() }),
+            now: Mutex::new(None),
+        }
+    }
+
+    /// Bloom filter generations of `capacity` keys each, about 2.5 bytes of memory per key
+    pub fn bloom(capacity: usize) -> Self {
+        DedupFilter {
+            window: Some(DedupWindow::Keys(capacity)),
+            memory: Mutex::new(Memory::Bloom { current: Bloom::new(capacity), previous: Bloom::new(capacity), capacity }),
+            now: Mutex::new(None),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        match *self.memory.lock() {
+            Memory::Exact { ref keys, .. } => keys.contains(key),
+            Memory::Bloom { ref current, ref previous, .. } => current.contains(key) || previous.contains(key),
+        }
+    }
+
+    /// Remembers a key seen at `at`, returns whether it was already remembered. Keys seen
+    /// before the first row with a timestamp count as seen at that timestamp.
+    pub fn check_and_insert(&self, key: &str, at: Option<DateTime<Utc>>) -> bool {
+        let (at, first_timestamp) = {
+            let mut now = self.now.lock();
+            let first_timestamp = now.is_none() && at.is_some();
+            if at > *now {
+                *now = at;
+            }
+            (*now, first_timestamp)
+        };
+
+        let mut memory = self.memory.lock();
+        match *memory {
+            Memory::Exact { ref mut keys, ref mut order } => {
+                match self.window {
+                    Some(DedupWindow::Keys(capacity)) => {
+                        while order.len() >= capacity {
+                            let Some((key, _)) = order.pop_front() else { break };
+                            keys.remove(&key);
+                        }
+                    }
+                    Some(DedupWindow::Time(window)) => {
+                        // Only keys seen before it have no time, and the time only moves on
+                        if first_timestamp {
+                            for (_, seen) in order.iter_mut() {
+                                *seen = at;
+                            }
+                        }
+                        let cutoff = at.and_then(|at| chrono::Duration::from_std(window).ok().map(|window| at - window));
+                        while let Some((_, Some(seen))) = order.front() {
+                            if Some(*seen) >= cutoff {
+                                break;
+                            }
+                            let Some((key, _)) = order.pop_front() else { break };
+                            keys.remove(&key);
+                        }
+                    }
+                    None => {}
+                }
+                if keys.contains(key) {
+                    return true;
+                }
+                keys.insert(key.to_string());
+                if self.window.is_some() {
+                    order.push_back((key.to_string(), at));
+                }
+                false
+            }
+            Memory::Bloom { ref mut current, ref mut previous, capacity } => {
+                if current.contains(key) || previous.contains(key) {
+                    return true;
+                }
+                if current.keys() >= capacity {
+                    previous.clear();
+                    std::mem::swap(current, previous);
+                }
+                current.insert(key);
+                false
+            }
+        }
+    }
+}
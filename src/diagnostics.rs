@@ -0,0 +1,91 @@
+use std::io::{self, IsTerminal, Write};
+
+use parking_lot::Mutex;
+
+const YELLOW: &str = "\x1b[1;33m";
+const RED: &str = "\x1b[1;31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether diagnostics are colored, selected with `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorChoice {
+    /// Color when stderr is a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_name(name: &str) -> Option<ColorChoice> {
+        match name {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Input row being processed, quoted under each diagnostic
+struct Row {
+    line: u64,
+    snippet: String,
+}
+
+/// Human readable stderr diagnostics: warnings for rejected rows, errors for malformed ones
+pub struct Diagnostics {
+    color: bool,
+    row: Mutex<Option<Row>>,
+}
+
+impl Diagnostics {
+    pub fn new(color: ColorChoice) -> Self {
+        Diagnostics {
+            color: color.enabled(),
+            row: Mutex::new(None),
+        }
+    }
+
+    /// Sets the row the following diagnostics refer to
+    pub fn set_row(&self, line: u64, snippet: String) {
+        *self.row.lock() = Some(Row { line, snippet });
+    }
+
+    pub fn clear_row(&self) {
+        *self.row.lock() = None;
+    }
+
+    pub fn warning(&self, message: &str) {
+        self.emit("warning", YELLOW, message);
+    }
+
+    pub fn error(&self, message: &str) {
+        self.emit("error", RED, message);
+    }
+
+    fn emit(&self, severity: &str, color: &str, message: &str) {
+        let row = self.row.lock();
+        let mut stderr = io::stderr().lock();
+
+        let (color, dim, reset) = match self.color {
+            true => (color, DIM, RESET),
+            false => ("", "", ""),
+        };
+        let location = row.as_ref().map(|row| format!("line {}: ", row.line)).unwrap_or_default();
+
+        let _ = writeln!(stderr, "{}{}{}: {}{}", color, severity, reset, location, message);
+        if let Some(row) = row.as_ref() {
+            let _ = writeln!(stderr, "  {}|{} {}", dim, reset, row.snippet);
+        }
+    }
+}
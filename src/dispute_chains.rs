@@ -0,0 +1,162 @@
+use std::fs::File;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use dashmap::DashMap;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::engine::EngineState;
+use crate::model::account::{serialize_decimal, serialize_optional_decimal, AmountFormat, FormatAmounts};
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionState, TransactionType, TxId};
+
+/// Columns of the CSV export, the fields of `DisputeChain`
+const COLUMNS: [&str; 14] = [
+    "client",
+    "tx",
+    "type",
+    "amount",
+    "timestamp",
+    "disputes",
+    "resolves",
+    "chargebacks",
+    "opened_at",
+    "closed_at",
+    "final_state",
+    "shortfall",
+    "reason_code",
+    "chain",
+];
+
+/// A dispute, resolve or chargeback row applied to a transaction
+#[derive(Debug, Clone)]
+struct Event {
+    transaction_type: TransactionType,
+    at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct Chain {
+    client: u16,
+    events: Vec<Event>,
+}
+
+/// Row of the `--dispute-chains` export: a disputed transaction and every dispute-related
+/// row applied to it, in order
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DisputeChain {
+    pub client: u16,
+    pub tx: TxId,
+    /// Type of the disputed transaction, empty if it is no longer stored
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+    #[serde(serialize_with = "serialize_optional_decimal")]
+    pub amount: Option<Decimal>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    /// Time of the first dispute
+    pub opened_at: Option<DateTime<Utc>>,
+    /// Time of the last resolve or chargeback, if the transaction is not under dispute
+    pub closed_at: Option<DateTime<Utc>>,
+    pub final_state: Option<TransactionState>,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub shortfall: Decimal,
+    pub reason_code: Option<String>,
+    /// The rows as `<type>@<timestamp>` separated by `>`, e.g. `dispute@2024-03-01T08:00:00Z>resolve`
+    pub chain: String,
+}
+
+impl FormatAmounts for DisputeChain {
+    fn format_amounts(&mut self, format: AmountFormat) {
+        self.amount = self.amount.map(|amount| format.apply(amount));
+        self.shortfall = format.apply(self.shortfall);
+    }
+}
+
+/// Links every applied dispute, resolve and chargeback row to the transaction it refers to,
+/// for reconciliation. Only the rows are kept during the run; the disputed transaction is
+/// looked up in the store when the export is written.
+#[derive(Debug, Default)]
+pub struct DisputeChains {
+    chains: DashMap<TxId, Chain>,
+}
+
+impl DisputeChains {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a row the engine applied
+    pub fn observe(&self, record: &TransactionInput) {
+        if !matches!(record.transaction_type, TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback) {
+            return;
+        }
+        let mut chain = self.chains.entry(record.tx.clone()).or_insert_with(|| Chain { client: record.client, events: Vec::new() });
+        chain.events.push(Event { transaction_type: record.transaction_type.clone(), at: record.timestamp });
+    }
+
+    /// The chains ordered by client and transaction id
+    pub fn chains(&self, state: &dyn EngineState) -> Result<Vec<DisputeChain>, ProcessorError> {
+        let mut chains = Vec::with_capacity(self.chains.len());
+        for entry in self.chains.iter() {
+            let (tx, chain) = (entry.key(), entry.value());
+            let transaction = state.transaction(tx)?;
+            let count = |transaction_type: TransactionType| chain.events.iter().filter(|event| event.transaction_type == transaction_type).count() as u64;
+            let final_state = transaction.as_ref().map(|transaction| transaction.state.clone());
+            let closed_at = match final_state {
+                Some(TransactionState::UnderDispute) => None,
+                _ => chain.events.iter().rev().find(|event| event.transaction_type != TransactionType::Dispute).and_then(|event| event.at),
+            };
+            let links: Vec<String> = chain
+                .events
+                .iter()
+                .map(|event| match event.at {
+                    Some(at) => format!("{}@{}", event.transaction_type, at.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+                    None => event.transaction_type.to_string(),
+                })
+                .collect();
+
+            chains.push(DisputeChain {
+                client: chain.client,
+                tx: tx.clone(),
+                transaction_type: transaction.as_ref().map(|transaction| transaction.transaction_type.clone()),
+                amount: transaction.as_ref().map(|transaction| transaction.amount),
+                timestamp: transaction.as_ref().and_then(|transaction| transaction.timestamp),
+                disputes: count(TransactionType::Dispute),
+                resolves: count(TransactionType::Resolve),
+                chargebacks: count(TransactionType::Chargeback),
+                opened_at: chain.events.first().and_then(|event| event.at),
+                closed_at,
+                final_state,
+                shortfall: transaction.as_ref().and_then(|transaction| transaction.shortfall).unwrap_or_default(),
+                reason_code: transaction.and_then(|transaction| transaction.reason_code),
+                chain: links.join(">"),
+            });
+        }
+        chains.sort_by(|a, b| (a.client, &a.tx).cmp(&(b.client, &b.tx)));
+        Ok(chains)
+    }
+
+    /// Writes JSON to a `.json` path and CSV to anything else
+    pub fn write_report(&self, path: &str, state: &dyn EngineState, amount_format: AmountFormat) -> Result<(), ProcessorError> {
+        let mut chains = self.chains(state)?;
+        for chain in &mut chains {
+            chain.format_amounts(amount_format);
+        }
+        if path.ends_with(".json") {
+            serde_json::to_writer_pretty(File::create(path)?, &chains)?;
+            return Ok(());
+        }
+
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(File::create(path)?);
+        // Header is written explicitly so an empty export is still a valid CSV
+        writer.write_record(COLUMNS)?;
+        for chain in &chains {
+            writer.serialize(chain)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
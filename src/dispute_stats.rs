@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fs::File;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType, TxId};
+
+/// Count, sum and maximum of the seconds a dispute took to close
+#[derive(Debug, Default, Clone, Copy)]
+struct Durations {
+    count: i64,
+    total: i64,
+    max: i64,
+}
+
+impl Durations {
+    fn push(&mut self, seconds: i64) {
+        self.count += 1;
+        self.total += seconds;
+        self.max = self.max.max(seconds);
+    }
+
+    fn merge(&mut self, other: &Durations) {
+        self.count += other.count;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    fn mean(&self) -> Option<i64> {
+        (self.count > 0).then(|| self.total / self.count)
+    }
+
+    fn max(&self) -> Option<i64> {
+        (self.count > 0).then_some(self.max)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Figures {
+    disputes: u64,
+    resolved: u64,
+    charged_back: u64,
+    to_resolve: Durations,
+    to_chargeback: Durations,
+}
+
+impl Figures {
+    fn merge(&mut self, other: &Figures) {
+        self.disputes += other.disputes;
+        self.resolved += other.resolved;
+        self.charged_back += other.charged_back;
+        self.to_resolve.merge(&other.to_resolve);
+        self.to_chargeback.merge(&other.to_chargeback);
+    }
+
+    fn outcomes(&self, client: Option<u16>, open: u64) -> DisputeOutcomes {
+        let closed = self.resolved + self.charged_back;
+        DisputeOutcomes {
+            client,
+            disputes: self.disputes,
+            resolved: self.resolved,
+            charged_back: self.charged_back,
+            open,
+            chargeback_ratio: (closed > 0).then(|| (Decimal::from(self.charged_back) / Decimal::from(closed)).round_dp(4).normalize()),
+            mean_seconds_to_resolve: self.to_resolve.mean(),
+            max_seconds_to_resolve: self.to_resolve.max(),
+            mean_seconds_to_chargeback: self.to_chargeback.mean(),
+            max_seconds_to_chargeback: self.to_chargeback.max(),
+        }
+    }
+}
+
+/// Dispute outcomes of a client, or of all clients without one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DisputeOutcomes {
+    pub client: Option<u16>,
+    /// Disputes opened during the run
+    pub disputes: u64,
+    pub resolved: u64,
+    pub charged_back: u64,
+    /// Opened during the run and still open at its end
+    pub open: u64,
+    /// Share of the closed disputes that ended in a chargeback, the rest were resolved
+    pub chargeback_ratio: Option<Decimal>,
+    /// Only disputes whose rows both had a timestamp are timed
+    pub mean_seconds_to_resolve: Option<i64>,
+    pub max_seconds_to_resolve: Option<i64>,
+    pub mean_seconds_to_chargeback: Option<i64>,
+    pub max_seconds_to_chargeback: Option<i64>,
+}
+
+/// The `--dispute-stats` report in JSON
+#[derive(Debug, Serialize)]
+pub struct DisputeStatsReport {
+    pub overall: DisputeOutcomes,
+    pub clients: Vec<DisputeOutcomes>,
+}
+
+/// Outcomes of the disputes of the run and how long they took to close, from the
+/// timestamps of the dispute, resolve and chargeback rows. Only applied rows count.
+#[derive(Debug, Default)]
+pub struct DisputeStats {
+    /// Disputes open now, with the time they were opened at if known
+    open: DashMap<(u16, TxId), Option<DateTime<Utc>>>,
+    clients: DashMap<u16, Figures>,
+}
+
+impl DisputeStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a row the engine applied
+    pub fn observe(&self, record: &TransactionInput) {
+        let key = (record.client, record.tx.clone());
+        let mut figures = self.clients.entry(record.client).or_default();
+        match record.transaction_type {
+            TransactionType::Dispute => {
+                figures.disputes += 1;
+                self.open.insert(key, record.timestamp);
+            }
+            TransactionType::Resolve => {
+                figures.resolved += 1;
+                if let Some(seconds) = self.close(key, record.timestamp) {
+                    figures.to_resolve.push(seconds);
+                }
+            }
+            TransactionType::Chargeback => {
+                figures.charged_back += 1;
+                if let Some(seconds) = self.close(key, record.timestamp) {
+                    figures.to_chargeback.push(seconds);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Seconds the dispute was open, if it was opened during the run and both rows were timed
+    fn close(&self, key: (u16, TxId), closed_at: Option<DateTime<Utc>>) -> Option<i64> {
+        let (_, opened_at) = self.open.remove(&key)?;
+        let seconds = (closed_at? - opened_at?).num_seconds();
+        // Out of order timestamps say nothing about how long it took
+        (seconds >= 0).then_some(seconds)
+    }
+
+    pub fn report(&self) -> DisputeStatsReport {
+        let mut open: HashMap<u16, u64> = HashMap::new();
+        for entry in self.open.iter() {
+            *open.entry(entry.key().0).or_default() += 1;
+        }
+        let mut overall = Figures::default();
+        let mut clients: Vec<DisputeOutcomes> = self
+            .clients
+            .iter()
+            .filter(|figures| figures.disputes + figures.resolved + figures.charged_back > 0)
+            .map(|figures| {
+                overall.merge(figures.value());
+                figures.outcomes(Some(*figures.key()), open.get(figures.key()).copied().unwrap_or_default())
+            })
+            .collect();
+        clients.sort_by_key(|outcomes| outcomes.client);
+
+        DisputeStatsReport { overall: overall.outcomes(None, self.open.len() as u64), clients }
+    }
+
+    /// Writes JSON to a `.json` path and CSV to anything else, the overall figures as the
+    /// CSV row without a client
+    pub fn write_report(&self, path: &str) -> Result<(), ProcessorError> {
+        let report = self.report();
+        if path.ends_with(".json") {
+            serde_json::to_writer_pretty(File::create(path)?, &report)?;
+            return Ok(());
+        }
+
+        let mut writer = csv::Writer::from_writer(File::create(path)?);
+        for outcomes in report.clients.iter().chain([&report.overall]) {
+            writer.serialize(outcomes)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
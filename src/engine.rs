@@ -0,0 +1,667 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use rust_decimal::Decimal;
+
+use crate::model::account::{Account, ShortfallPolicy};
+use crate::model::error::ProcessorError;
+use crate::model::rejection::RejectionReason;
+use crate::model::transaction::{LifecycleEvent, Transaction, TransactionInput, TransactionState, TransactionType, TxId};
+use crate::reason_codes::ReasonCodes;
+use crate::risk::RiskEvent;
+use crate::rules::RulesConfig;
+use crate::store::{Stores, Transition};
+
+/// Accounts and transactions the handlers read and change. The processor provides them
+/// from its stores; `Stores` does too, so the handlers can be run without any input file.
+pub trait EngineState {
+    fn transaction(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError>;
+
+    fn store_transaction(&self, transaction: Transaction) -> Result<(), ProcessorError>;
+
+    /// Gives every stored transaction of `from` to `to`, returns how many there were
+    fn reassign_transactions(&self, from: u16, to: u16) -> Result<usize, ProcessorError>;
+
+    /// Returns a copy of the client's account
+    fn account(&self, client_id: u16) -> Result<Option<Account>, ProcessorError>;
+
+    /// Applies `f` to the client's account, returns false if `f` declined or there is no account
+    fn update_account(&self, client_id: u16, f: &mut dyn FnMut(&mut Account) -> bool) -> Result<bool, ProcessorError>;
+
+    /// See `TransactionStore::try_transition`
+    fn transition(
+        &self,
+        tx_id: &TxId,
+        event: LifecycleEvent,
+        account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+    ) -> Result<Transition, ProcessorError>;
+}
+
+impl EngineState for Stores {
+    fn transaction(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        self.transactions.get(tx_id)
+    }
+
+    fn store_transaction(&self, transaction: Transaction) -> Result<(), ProcessorError> {
+        self.transactions.insert(transaction).map(|_| ())
+    }
+
+    fn reassign_transactions(&self, from: u16, to: u16) -> Result<usize, ProcessorError> {
+        let transactions = self.transactions.for_client(from)?;
+        let count = transactions.len();
+        for mut transaction in transactions {
+            transaction.client_id = to;
+            self.transactions.insert(transaction)?;
+        }
+        Ok(count)
+    }
+
+    fn account(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        self.accounts.get(client_id)
+    }
+
+    fn update_account(&self, client_id: u16, f: &mut dyn FnMut(&mut Account) -> bool) -> Result<bool, ProcessorError> {
+        let mut applied = false;
+        self.accounts.update(client_id, &mut |account| applied = f(account))?;
+        Ok(applied)
+    }
+
+    fn transition(
+        &self,
+        tx_id: &TxId,
+        event: LifecycleEvent,
+        account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+    ) -> Result<Transition, ProcessorError> {
+        self.transactions.try_transition(self.accounts.as_ref(), tx_id, event, account_op)
+    }
+}
+
+/// Handler of a product-specific transaction type, for rows whose `type` is none of the
+/// built-in ones. It gets the same state as the built-in handlers, with the row's client
+/// locked; other clients are not, so a handler changing them must order that itself.
+pub trait CustomTransaction: Send + Sync {
+    fn apply(&self, state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError>;
+}
+
+/// Handlers of custom transaction types by the name of their `type`
+#[derive(Clone, Default)]
+pub struct CustomTypes {
+    handlers: BTreeMap<String, Arc<dyn CustomTransaction>>,
+}
+
+impl CustomTypes {
+    /// Registers the handler of `name`, replacing any earlier one.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is a built-in type, whose rows would never reach the handler
+    pub fn register(&mut self, name: &str, handler: Arc<dyn CustomTransaction>) {
+        assert!(TransactionType::from_name(name).is_none(), "'{}' is a built-in transaction type", name);
+        self.handlers.insert(name.to_string(), handler);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn CustomTransaction> {
+        self.handlers.get(name).map(|handler| handler.as_ref())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.contains_key(name)
+    }
+}
+
+impl fmt::Debug for CustomTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.handlers.keys()).finish()
+    }
+}
+
+/// Settings the handlers follow
+#[derive(Debug, Default)]
+pub struct EngineConfig {
+    pub allow_adjustments: bool,
+    pub allow_merges: bool,
+    pub shortfall_policy: ShortfallPolicy,
+    pub dispute_rules: Option<RulesConfig>,
+    pub reason_codes: ReasonCodes,
+    pub custom_types: CustomTypes,
+}
+
+/// Why a row was rejected. It is logged as `<message>, reason=<reason> (<detail>)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub reason: RejectionReason,
+    pub message: String,
+    pub detail: Option<String>,
+}
+
+/// What a handler did with a row
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Applied, with the line to log
+    Applied(String),
+    Rejected(Rejection),
+}
+
+impl Outcome {
+    pub fn rejected(reason: RejectionReason, message: String) -> Outcome {
+        Outcome::Rejected(Rejection { reason, message, detail: None })
+    }
+
+    pub fn rejected_with_detail(reason: RejectionReason, message: String, detail: String) -> Outcome {
+        Outcome::Rejected(Rejection { reason, message, detail: Some(detail) })
+    }
+
+    pub fn rejection_reason(&self) -> Option<RejectionReason> {
+        match self {
+            Outcome::Applied(_) => None,
+            Outcome::Rejected(rejection) => Some(rejection.reason),
+        }
+    }
+
+    /// What the outcome of a row of this type tells about the client's risk
+    pub fn risk_event(&self, transaction_type: &TransactionType) -> Option<RiskEvent> {
+        match (transaction_type, self) {
+            (TransactionType::Withdrawal, Outcome::Rejected(_)) => Some(RiskEvent::FailedWithdrawal),
+            (TransactionType::Dispute, Outcome::Applied(_)) => Some(RiskEvent::Dispute),
+            (TransactionType::Chargeback, Outcome::Applied(_)) => Some(RiskEvent::Chargeback),
+            _ => None,
+        }
+    }
+}
+
+/// Applies a row to `state`. Only the row itself is checked: duplicates, freezes, screening
+/// and the review queue are the processor's business, so approve and reject rows are
+/// rejected as not in review. Rows of a custom type go to its handler, and fail without one.
+pub fn apply(state: &dyn EngineState, config: &EngineConfig, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    match record.transaction_type {
+        TransactionType::Deposit => deposit(state, record),
+        TransactionType::Withdrawal => withdrawal(state, record),
+        TransactionType::Dispute => dispute(state, config, record),
+        TransactionType::Resolve => resolve(state, record),
+        TransactionType::Chargeback => chargeback(state, config, record),
+        TransactionType::Adjustment => adjustment(state, config, record),
+        TransactionType::Reserve => reserve(state, record),
+        TransactionType::Capture => capture(state, record),
+        TransactionType::Cancel => cancel(state, record),
+        TransactionType::Freeze => freeze(state, record),
+        TransactionType::Unfreeze => unfreeze(state, record),
+        TransactionType::Merge => merge(state, config, record),
+        TransactionType::Unlock => Ok(Outcome::rejected(
+            RejectionReason::AdminOnly,
+            format!("UNLOCK REJECTED: client={}, tx={}", record.client, record.tx),
+        )),
+        TransactionType::Approve | TransactionType::Reject => Ok(Outcome::rejected(
+            RejectionReason::NotInReview,
+            format!("{} REJECTED: client={}, tx={}", operation(record), record.client, record.tx),
+        )),
+        TransactionType::Custom(ref name) => match config.custom_types.get(name) {
+            Some(handler) => handler.apply(state, record),
+            None => Err(ProcessorError::UnknownTransactionType(name.clone())),
+        },
+    }
+}
+
+/// Applies a row of an `--admin-ops` file. Only admin operations are accepted: unlocks,
+/// freezes and unfreezes, adjustments, merges, and reserves with their captures and cancels.
+/// Adjustments and merges apply without being enabled, the file itself is the admin's doing.
+pub fn apply_admin(state: &dyn EngineState, config: &EngineConfig, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    match record.transaction_type {
+        TransactionType::Unlock => unlock(state, record),
+        TransactionType::Adjustment => adjust(state, record),
+        TransactionType::Merge => merge_accounts(state, record),
+        ref transaction_type if transaction_type.is_admin() => apply(state, config, record),
+        _ => Ok(Outcome::rejected(
+            RejectionReason::NotAdminOperation,
+            format!("{} REJECTED: client={}, tx={}", operation(record), record.client, record.tx),
+        )),
+    }
+}
+
+/// Upper case name of the row's type, as logged
+fn operation(record: &TransactionInput) -> String {
+    record.transaction_type.to_string().to_uppercase()
+}
+
+/// Rejects the row if it carries a reason code that is not in the configured list
+fn unknown_reason_code(config: &EngineConfig, record: &TransactionInput) -> Option<Outcome> {
+    match &record.reason_code {
+        Some(code) if !config.reason_codes.contains(code) => Some(Outcome::rejected_with_detail(
+            RejectionReason::UnknownReasonCode,
+            format!("{} REJECTED: client={}, tx={}", operation(record), record.client, record.tx),
+            format!("reason_code={}", code),
+        )),
+        _ => None,
+    }
+}
+
+/// Looks up the transaction a row refers to, rejecting the row if it does not exist or
+/// belongs to another client
+fn referenced_transaction(state: &dyn EngineState, record: &TransactionInput) -> Result<Result<Transaction, Outcome>, ProcessorError> {
+    let message = format!("{} REJECTED: client={}, tx={}", operation(record), record.client, record.tx);
+
+    // Referenced transaction must exist
+    let Some(transaction) = state.transaction(&record.tx)? else {
+        return Ok(Err(Outcome::rejected(RejectionReason::TransactionNotFound, message)));
+    };
+
+    // Verify the transaction belongs to the same client
+    if transaction.client_id != record.client {
+        return Ok(Err(Outcome::rejected_with_detail(RejectionReason::ClientMismatch, message, format!("tx_client={}", transaction.client_id))));
+    }
+
+    Ok(Ok(transaction))
+}
+
+/// Moves a transaction to the state `event` leads to together with its balance change,
+/// as one step of the transaction store. Returns the rejection when the transition does not
+/// apply: `wrong_state` if the event is not allowed in the transaction's state, `declined`
+/// if `account_op` refused the balance change.
+fn transition(
+    state: &dyn EngineState,
+    record: &TransactionInput,
+    event: LifecycleEvent,
+    (wrong_state, declined): (RejectionReason, RejectionReason),
+    account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+) -> Result<Option<Outcome>, ProcessorError> {
+    let message = format!("{} REJECTED: client={}, tx={}", operation(record), record.client, record.tx);
+    Ok(match state.transition(&record.tx, event, account_op)? {
+        Transition::Applied => None,
+        Transition::TransactionNotFound => Some(Outcome::rejected(RejectionReason::TransactionNotFound, message)),
+        Transition::WrongState(transaction_state) => Some(Outcome::rejected_with_detail(wrong_state, message, format!("state={:?}", transaction_state))),
+        Transition::AccountNotFound => Some(Outcome::rejected(RejectionReason::AccountNotFound, message)),
+        Transition::Declined => Some(Outcome::rejected(declined, message)),
+    })
+}
+
+/// The amount of a row moving funds, which must be given and positive
+fn positive_amount(record: &TransactionInput) -> Result<Decimal, Outcome> {
+    let Some(amount) = record.amount else {
+        return Err(Outcome::rejected(RejectionReason::MissingAmount, format!("{} REJECTED: client={}, tx={}", operation(record), record.client, record.tx)));
+    };
+
+    // Ignore if amount is negative or zero
+    if amount <= Decimal::ZERO {
+        return Err(Outcome::rejected(
+            RejectionReason::NonPositiveAmount,
+            format!("{} REJECTED: client={}, tx={}, amount={}", operation(record), record.client, record.tx, amount),
+        ));
+    }
+    Ok(amount)
+}
+
+pub fn deposit(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let amount = match positive_amount(record) {
+        Ok(amount) => amount,
+        Err(rejected) => return Ok(rejected),
+    };
+
+    // Deposits work if account is not locked
+    // Note: only deposits are stored since they're the only disputable transactions
+    if !state.update_account(record.client, &mut |account| account.deposit(amount))? {
+        return Ok(Outcome::rejected(RejectionReason::AccountLocked, format!("DEPOSIT REJECTED: client={}, tx={}, amount={}", record.client, record.tx, amount)));
+    }
+
+    state.store_transaction(Transaction::new(record.tx.clone(), record.client, record.transaction_type.clone(), amount, record.timestamp))?;
+    Ok(Outcome::Applied(format!("DEPOSIT SUCCESS: client={}, tx={}, amount={}", record.client, record.tx, amount)))
+}
+
+pub fn withdrawal(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let amount = match positive_amount(record) {
+        Ok(amount) => amount,
+        Err(rejected) => return Ok(rejected),
+    };
+
+    // Withdrawals work if funds are available and account is not locked
+    // Note: Withdrawals are not stored since they cannot be disputed
+    if !state.update_account(record.client, &mut |account| account.withdraw(amount))? {
+        return Ok(Outcome::rejected(
+            RejectionReason::InsufficientFundsOrLocked,
+            format!("WITHDRAWAL REJECTED: client={}, tx={}, amount={}", record.client, record.tx, amount),
+        ));
+    }
+
+    Ok(Outcome::Applied(format!("WITHDRAWAL SUCCESS: client={}, tx={}, amount={}", record.client, record.tx, amount)))
+}
+
+pub fn dispute(state: &dyn EngineState, config: &EngineConfig, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    if let Some(rejected) = unknown_reason_code(config, record) {
+        return Ok(rejected);
+    }
+    let transaction = match referenced_transaction(state, record)? {
+        Ok(transaction) => transaction,
+        Err(rejected) => return Ok(rejected),
+    };
+    let message = format!("DISPUTE REJECTED: client={}, tx={}", record.client, record.tx);
+
+    // Only deposits can be disputed
+    if transaction.transaction_type != TransactionType::Deposit {
+        return Ok(Outcome::rejected(RejectionReason::NonDepositTransaction, message));
+    }
+
+    // Transaction must not already be disputed or charged back
+    if transaction.state.try_dispute().is_none() {
+        return Ok(Outcome::rejected_with_detail(RejectionReason::InvalidState, message, format!("state={:?}", transaction.state)));
+    }
+
+    // Transaction must satisfy the configured eligibility rules
+    if let Some(ref rules) = config.dispute_rules {
+        if !rules.dispute_allowed(&transaction, record.timestamp) {
+            return Ok(Outcome::rejected(RejectionReason::NotEligible, message));
+        }
+    }
+
+    let tx_amount = transaction.amount;
+    let policy = config.shortfall_policy;
+    let mut shortfall = Decimal::ZERO;
+
+    // Hold the funds, or as much as the shortfall policy allows, and mark the transaction
+    // as under dispute in one step, remembering any part that could not be held
+    let rejected = transition(
+        state,
+        record,
+        LifecycleEvent::Dispute,
+        (RejectionReason::InvalidState, RejectionReason::InsufficientAvailableFunds),
+        &mut |transaction, account| {
+            let Some(unheld) = account.hold_disputed(&record.tx, tx_amount, policy) else {
+                return false;
+            };
+            account.disputes += 1;
+            transaction.shortfall = (unheld > Decimal::ZERO).then_some(unheld);
+            transaction.reason_code = record.reason_code.clone();
+            transaction.metadata = record.metadata.clone();
+            shortfall = unheld;
+            true
+        },
+    )?;
+    if let Some(rejected) = rejected {
+        return Ok(rejected);
+    }
+
+    Ok(Outcome::Applied(if shortfall > Decimal::ZERO {
+        format!("DISPUTE PARTIAL: client={}, tx={}, amount={}, shortfall={} (flagged for review)", record.client, record.tx, tx_amount, shortfall)
+    } else {
+        format!("DISPUTE SUCCESS: client={}, tx={}, amount={} (moved to held)", record.client, record.tx, tx_amount)
+    }))
+}
+
+pub fn resolve(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let transaction = match referenced_transaction(state, record)? {
+        Ok(transaction) => transaction,
+        Err(rejected) => return Ok(rejected),
+    };
+    let tx_amount = transaction.held_amount();
+
+    // Release the held funds and move the transaction back to normal in one step,
+    // the dispute no longer owes its shortfall
+    let rejected = transition(
+        state,
+        record,
+        LifecycleEvent::Resolve,
+        (RejectionReason::NotUnderDispute, RejectionReason::InsufficientHeldFunds),
+        &mut |transaction, account| {
+            if record.metadata.is_some() {
+                transaction.metadata = record.metadata.clone();
+            }
+            let released = account.release_hold(&record.tx, transaction.held_amount());
+            if released {
+                account.shortfall -= transaction.shortfall.unwrap_or_default();
+            }
+            released
+        },
+    )?;
+
+    Ok(rejected.unwrap_or_else(|| {
+        Outcome::Applied(format!("RESOLVE SUCCESS: client={}, tx={}, amount={} (moved to available)", record.client, record.tx, tx_amount))
+    }))
+}
+
+pub fn chargeback(state: &dyn EngineState, config: &EngineConfig, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    if let Some(rejected) = unknown_reason_code(config, record) {
+        return Ok(rejected);
+    }
+    let transaction = match referenced_transaction(state, record)? {
+        Ok(transaction) => transaction,
+        Err(rejected) => return Ok(rejected),
+    };
+    let tx_amount = transaction.held_amount();
+
+    // Remove the held funds, lock the account and mark the transaction as charged back in one step
+    let rejected = transition(
+        state,
+        record,
+        LifecycleEvent::Chargeback,
+        (RejectionReason::NotUnderDispute, RejectionReason::InsufficientHeldFunds),
+        &mut |transaction, account| {
+            // Networks may assign a final code at chargeback, otherwise the dispute's code stays
+            if record.reason_code.is_some() {
+                transaction.reason_code = record.reason_code.clone();
+            }
+            if record.metadata.is_some() {
+                transaction.metadata = record.metadata.clone();
+            }
+            account.chargeback(&record.tx, transaction.held_amount())
+        },
+    )?;
+
+    Ok(rejected.unwrap_or_else(|| {
+        Outcome::Applied(format!("CHARGEBACK SUCCESS: client={}, tx={}, amount={} (account locked)", record.client, record.tx, tx_amount))
+    }))
+}
+
+pub fn adjustment(state: &dyn EngineState, config: &EngineConfig, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let message = format!("ADJUSTMENT REJECTED: client={}, tx={}", record.client, record.tx);
+
+    // Adjustments are admin corrections and must be explicitly enabled
+    if !config.allow_adjustments {
+        return Ok(Outcome::rejected(RejectionReason::AdjustmentsDisabled, message));
+    }
+
+    adjust(state, record)
+}
+
+fn adjust(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let message = format!("ADJUSTMENT REJECTED: client={}, tx={}", record.client, record.tx);
+
+    // Adjustments must have a signed, non-zero amount and an effective date
+    let Some(amount) = record.amount else {
+        return Ok(Outcome::rejected(RejectionReason::MissingAmount, message));
+    };
+
+    if amount.is_zero() {
+        return Ok(Outcome::rejected(RejectionReason::ZeroAmount, message));
+    }
+
+    let Some(effective_date) = record.effective_date else {
+        return Ok(Outcome::rejected(RejectionReason::MissingEffectiveDate, format!("{}, amount={}", message, amount)));
+    };
+
+    // Corrections apply even to locked accounts, but never overdraw available funds
+    // Note: adjustments are not stored since they cannot be disputed
+    if !state.update_account(record.client, &mut |account| account.adjust(amount))? {
+        return Ok(Outcome::rejected(RejectionReason::InsufficientFunds, format!("{}, amount={}, effective_date={}", message, amount, effective_date)));
+    }
+
+    Ok(Outcome::Applied(format!(
+        "ADJUSTMENT SUCCESS: client={}, tx={}, amount={}, effective_date={} (admin correction)",
+        record.client, record.tx, amount, effective_date
+    )))
+}
+
+pub fn freeze(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    // Freezes are compliance holds on the whole account and never move funds
+    if !state.update_account(record.client, &mut |account| account.freeze())? {
+        return Ok(Outcome::rejected_with_detail(
+            RejectionReason::InvalidState,
+            format!("FREEZE REJECTED: client={}, tx={}", record.client, record.tx),
+            "frozen=true".to_string(),
+        ));
+    }
+
+    Ok(Outcome::Applied(format!("FREEZE SUCCESS: client={}, tx={} (account frozen)", record.client, record.tx)))
+}
+
+pub fn unfreeze(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    // Lifts a freeze only, a chargeback lock stays
+    if !state.update_account(record.client, &mut |account| account.unfreeze())? {
+        return Ok(Outcome::rejected_with_detail(
+            RejectionReason::InvalidState,
+            format!("UNFREEZE REJECTED: client={}, tx={}", record.client, record.tx),
+            "frozen=false".to_string(),
+        ));
+    }
+
+    Ok(Outcome::Applied(format!("UNFREEZE SUCCESS: client={}, tx={} (account unfrozen)", record.client, record.tx)))
+}
+
+pub fn unlock(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    // Lifts a lock only, a freeze stays
+    if !state.update_account(record.client, &mut |account| account.unlock())? {
+        return Ok(Outcome::rejected_with_detail(
+            RejectionReason::InvalidState,
+            format!("UNLOCK REJECTED: client={}, tx={}", record.client, record.tx),
+            "locked=false".to_string(),
+        ));
+    }
+
+    Ok(Outcome::Applied(format!("UNLOCK SUCCESS: client={}, tx={} (account unlocked)", record.client, record.tx)))
+}
+
+pub fn merge(state: &dyn EngineState, config: &EngineConfig, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let message = format!("MERGE REJECTED: client={}, tx={}", record.client, record.tx);
+
+    // Merges are admin operations for clients that ended up with two ids
+    if !config.allow_merges {
+        return Ok(Outcome::rejected(RejectionReason::MergesDisabled, message));
+    }
+
+    merge_accounts(state, record)
+}
+
+fn merge_accounts(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let message = format!("MERGE REJECTED: client={}, tx={}", record.client, record.tx);
+
+    let target = match record.target_client {
+        Some(target) if target != record.client => target,
+        target => {
+            let target = target.map_or("none".to_string(), |target| target.to_string());
+            return Ok(Outcome::rejected_with_detail(RejectionReason::InvalidMergeTarget, message, format!("target_client={}", target)));
+        }
+    };
+    let message = format!("{}, target_client={}", message, target);
+    let (Some(source_account), Some(target_account)) = (state.account(record.client)?, state.account(target)?) else {
+        return Ok(Outcome::rejected(RejectionReason::AccountNotFound, message));
+    };
+
+    // Both accounts must be open, and funds must not escape a chargeback lock or a freeze
+    if let Some(merged_into) = source_account.merged_into {
+        return Ok(Outcome::rejected_with_detail(RejectionReason::AccountMerged, message, format!("merged_into={}", merged_into)));
+    }
+    if let Some(merged_into) = target_account.merged_into {
+        return Ok(Outcome::rejected_with_detail(RejectionReason::InvalidMergeTarget, message, format!("merged_into={}", merged_into)));
+    }
+    if source_account.locked || target_account.locked {
+        return Ok(Outcome::rejected(RejectionReason::AccountLocked, message));
+    }
+    if source_account.frozen || target_account.frozen {
+        return Ok(Outcome::rejected(RejectionReason::AccountFrozen, message));
+    }
+
+    // Disputes and reserves of the source continue on the target, so their holds and
+    // transactions move with the funds
+    state.update_account(target, &mut |account| {
+        account.absorb(&source_account);
+        true
+    })?;
+    state.update_account(record.client, &mut |account| {
+        account.close_into(target);
+        true
+    })?;
+    let transactions = state.reassign_transactions(record.client, target)?;
+
+    Ok(Outcome::Applied(format!(
+        "MERGE SUCCESS: client={}, tx={}, target_client={}, available={}, held={}, transactions={} (account closed)",
+        record.client, record.tx, target, source_account.available, source_account.held, transactions
+    )))
+}
+
+pub fn reserve(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let amount = match positive_amount(record) {
+        Ok(amount) => amount,
+        Err(rejected) => return Ok(rejected),
+    };
+
+    // Reserved funds move to held and stay in the account until captured or cancelled
+    if !state.update_account(record.client, &mut |account| account.reserve(&record.tx, amount))? {
+        return Ok(Outcome::rejected(
+            RejectionReason::InsufficientFundsOrLocked,
+            format!("RESERVE REJECTED: client={}, tx={}, amount={}", record.client, record.tx, amount),
+        ));
+    }
+
+    let mut transaction = Transaction::new(record.tx.clone(), record.client, record.transaction_type.clone(), amount, record.timestamp);
+    transaction.state = TransactionState::Reserved;
+    state.store_transaction(transaction)?;
+    Ok(Outcome::Applied(format!("RESERVE SUCCESS: client={}, tx={}, amount={} (moved to held)", record.client, record.tx, amount)))
+}
+
+/// Looks up the open reserve a capture or cancel refers to, rejecting the row if there is none
+fn open_reserve(state: &dyn EngineState, record: &TransactionInput, event: LifecycleEvent) -> Result<Result<Transaction, Outcome>, ProcessorError> {
+    let transaction = match referenced_transaction(state, record)? {
+        Ok(transaction) => transaction,
+        Err(rejected) => return Ok(Err(rejected)),
+    };
+
+    // Transaction must be a reserve that was neither captured nor cancelled
+    if transaction.state.next(event).is_none() {
+        return Ok(Err(Outcome::rejected_with_detail(
+            RejectionReason::NotReserved,
+            format!("{} REJECTED: client={}, tx={}", operation(record), record.client, record.tx),
+            format!("state={:?}", transaction.state),
+        )));
+    }
+
+    Ok(Ok(transaction))
+}
+
+pub fn capture(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let transaction = match open_reserve(state, record, LifecycleEvent::Capture)? {
+        Ok(transaction) => transaction,
+        Err(rejected) => return Ok(rejected),
+    };
+    let tx_amount = transaction.amount;
+
+    // Captured funds leave the account, even if it was locked after the reserve
+    let rejected = transition(
+        state,
+        record,
+        LifecycleEvent::Capture,
+        (RejectionReason::NotReserved, RejectionReason::InsufficientHeldFunds),
+        &mut |_, account| account.capture(&record.tx, tx_amount),
+    )?;
+
+    Ok(rejected.unwrap_or_else(|| {
+        Outcome::Applied(format!("CAPTURE SUCCESS: client={}, tx={}, amount={} (removed from held)", record.client, record.tx, tx_amount))
+    }))
+}
+
+pub fn cancel(state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+    let transaction = match open_reserve(state, record, LifecycleEvent::Cancel)? {
+        Ok(transaction) => transaction,
+        Err(rejected) => return Ok(rejected),
+    };
+    let tx_amount = transaction.amount;
+
+    // Release the reserved funds
+    let rejected = transition(
+        state,
+        record,
+        LifecycleEvent::Cancel,
+        (RejectionReason::NotReserved, RejectionReason::InsufficientHeldFunds),
+        &mut |_, account| account.release_hold(&record.tx, tx_amount),
+    )?;
+
+    Ok(rejected.unwrap_or_else(|| {
+        Outcome::Applied(format!("CANCEL SUCCESS: client={}, tx={}, amount={} (moved to available)", record.client, record.tx, tx_amount))
+    }))
+}
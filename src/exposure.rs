@@ -0,0 +1,118 @@
+use std::fs::File;
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+
+/// Largest fall of a client's total balance and most funds held for it during the run
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ClientExposure {
+    pub client: u16,
+    /// Largest fall of the total from the highest total before it
+    pub max_drawdown: Decimal,
+    /// Total the largest fall started from, and where it ended
+    pub drawdown_from: Decimal,
+    pub drawdown_to: Decimal,
+    pub peak_held: Decimal,
+    /// Row after which the most was held, 0 if it was held before the run
+    pub peak_held_row: u64,
+}
+
+#[derive(Debug, Default)]
+struct ClientTrack {
+    exposure: ClientExposure,
+    peak_total: Decimal,
+    held: Decimal,
+}
+
+/// The `--exposure <exposure.json>` report
+#[derive(Debug, Serialize)]
+pub struct ExposureReport {
+    /// Most funds held across all accounts at once
+    pub peak_total_held: Decimal,
+    pub peak_total_held_row: u64,
+    /// Clients whose balance fell or who had funds held, by client id
+    pub clients: Vec<ClientExposure>,
+}
+
+/// Drawdowns and held exposure, updated after every row so the figures between two rows
+/// are not lost the way they are in the final balances. Only what changes is kept: the
+/// peak of each client, and the total held of all accounts.
+pub struct ExposureTracker {
+    clients: DashMap<u16, ClientTrack>,
+    /// Funds held across all accounts now and at most, with the row of the most
+    held: Mutex<(Decimal, Decimal, u64)>,
+}
+
+impl ExposureTracker {
+    /// Starts from the funds already held in the store when the run begins
+    pub fn new(accounts: &[Account]) -> Self {
+        let held: Decimal = accounts.iter().map(|account| account.held).sum();
+        ExposureTracker {
+            clients: DashMap::new(),
+            held: Mutex::new((held, held, 0)),
+        }
+    }
+
+    /// Whether the client's balance before its first row of the run is known
+    pub fn knows(&self, client: u16) -> bool {
+        self.clients.contains_key(&client)
+    }
+
+    /// Takes the balance of the client after the row, or before its first row, which is
+    /// only the starting point. Called while the client is locked.
+    pub fn observe(&self, row: u64, client: u16, account: Option<&Account>) {
+        let (total, held) = account.map_or((Decimal::ZERO, Decimal::ZERO), |account| (account.total(), account.held));
+        let Some(mut track) = self.clients.get_mut(&client) else {
+            let exposure = ClientExposure { client, peak_held: held, ..ClientExposure::default() };
+            self.clients.insert(client, ClientTrack { exposure, peak_total: total, held });
+            return;
+        };
+
+        let track = &mut *track;
+        track.peak_total = track.peak_total.max(total);
+        let drawdown = track.peak_total - total;
+        if drawdown > track.exposure.max_drawdown {
+            track.exposure.max_drawdown = drawdown;
+            track.exposure.drawdown_from = track.peak_total;
+            track.exposure.drawdown_to = total;
+        }
+        if held > track.exposure.peak_held {
+            track.exposure.peak_held = held;
+            track.exposure.peak_held_row = row;
+        }
+
+        let change = held - track.held;
+        track.held = held;
+        if !change.is_zero() {
+            let mut all = self.held.lock();
+            all.0 += change;
+            if all.0 > all.1 {
+                all.1 = all.0;
+                all.2 = row;
+            }
+        }
+    }
+
+    pub fn report(&self) -> ExposureReport {
+        let mut clients: Vec<ClientExposure> = self
+            .clients
+            .iter()
+            .map(|track| track.exposure.clone())
+            .filter(|exposure| exposure.max_drawdown > Decimal::ZERO || exposure.peak_held > Decimal::ZERO)
+            .collect();
+        clients.sort_by_key(|exposure| exposure.client);
+
+        let (_, peak_total_held, peak_total_held_row) = *self.held.lock();
+        ExposureReport { peak_total_held, peak_total_held_row, clients }
+    }
+
+    pub fn write_report(&self, path: &str) -> Result<(), ProcessorError> {
+        serde_json::to_writer_pretty(File::create(path)?, &self.report())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+//! C ABI for embedding the engine, declared in `include/trx_processor.h`.
+//! Amounts cross the boundary as integers in ten-thousandths, the precision the engine works with.
+
+use std::ptr;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType, TxId, TxIdKind};
+use crate::processor::TransactionProcessor;
+
+const AMOUNT_SCALE: u32 = 4;
+
+pub const TRX_OK: i32 = 0;
+pub const TRX_INVALID_ARGUMENT: i32 = 1;
+pub const TRX_NOT_FOUND: i32 = 2;
+pub const TRX_ERROR: i32 = 3;
+
+#[repr(C)]
+pub struct TrxTransaction {
+    /// One of the `TRX_TX_*` constants of the header
+    pub tx_type: u32,
+    pub client: u16,
+    pub tx: u64,
+    pub has_amount: bool,
+    pub amount: i64,
+}
+
+#[repr(C)]
+pub struct TrxAccount {
+    pub client: u16,
+    pub available: i64,
+    pub held: i64,
+    pub total: i64,
+    pub locked: bool,
+}
+
+pub struct TrxAccountIter {
+    accounts: std::vec::IntoIter<Account>,
+}
+
+fn transaction_type(tx_type: u32) -> Option<TransactionType> {
+    match tx_type {
+        0 => Some(TransactionType::Deposit),
+        1 => Some(TransactionType::Withdrawal),
+        2 => Some(TransactionType::Dispute),
+        3 => Some(TransactionType::Resolve),
+        4 => Some(TransactionType::Chargeback),
+        5 => Some(TransactionType::Adjustment),
+        6 => Some(TransactionType::Reserve),
+        7 => Some(TransactionType::Capture),
+        8 => Some(TransactionType::Cancel),
+        9 => Some(TransactionType::Freeze),
+        10 => Some(TransactionType::Unfreeze),
+        11 => Some(TransactionType::Approve),
+        12 => Some(TransactionType::Reject),
+        _ => None,
+    }
+}
+
+/// Amounts beyond the range of `int64_t` saturate
+fn to_scaled(amount: Decimal) -> i64 {
+    let scaled = amount.round_dp(AMOUNT_SCALE) * Decimal::from(10_i64.pow(AMOUNT_SCALE));
+    scaled.to_i64().unwrap_or(if amount.is_sign_negative() { i64::MIN } else { i64::MAX })
+}
+
+fn to_ffi(account: &Account) -> TrxAccount {
+    TrxAccount {
+        client: account.client_id,
+        available: to_scaled(account.available),
+        held: to_scaled(account.held),
+        total: to_scaled(account.total()),
+        locked: account.locked,
+    }
+}
+
+fn status(result: Result<(), ProcessorError>) -> i32 {
+    match result {
+        Ok(()) => TRX_OK,
+        Err(ProcessorError::InvalidArguments(_) | ProcessorError::InvalidTransactionId(_)) => TRX_INVALID_ARGUMENT,
+        Err(_) => TRX_ERROR,
+    }
+}
+
+/// Creates an in-memory processor with 64 bit transaction ids
+#[no_mangle]
+pub extern "C" fn trx_processor_new() -> *mut TransactionProcessor {
+    Box::into_raw(Box::new(TransactionProcessor::new().with_tx_id_kind(TxIdKind::U64)))
+}
+
+/// # Safety
+/// `processor` must come from `trx_processor_new` and not be used afterwards. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn trx_processor_free(processor: *mut TransactionProcessor) {
+    if !processor.is_null() {
+        drop(Box::from_raw(processor));
+    }
+}
+
+/// Applies one transaction. Rejected transactions, e.g. for insufficient funds, return `TRX_OK`
+/// like they do in a CSV run.
+///
+/// # Safety
+/// `processor` and `transaction` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn trx_processor_submit(
+    processor: *const TransactionProcessor,
+    transaction: *const TrxTransaction,
+) -> i32 {
+    let (Some(processor), Some(transaction)) = (processor.as_ref(), transaction.as_ref()) else {
+        return TRX_INVALID_ARGUMENT;
+    };
+    let Some(transaction_type) = transaction_type(transaction.tx_type) else {
+        return TRX_INVALID_ARGUMENT;
+    };
+
+    status(processor.process_record(TransactionInput {
+        transaction_type,
+        client: transaction.client,
+        tx: TxId::Numeric(transaction.tx),
+        amount: transaction.has_amount.then(|| Decimal::new(transaction.amount, AMOUNT_SCALE)),
+        effective_date: None,
+        timestamp: None,
+        reason_code: None,
+        metadata: None,
+        partition: None,
+        idempotency_key: None,
+        target_client: None,
+    }))
+}
+
+/// Copies the client's account into `account`, or returns `TRX_NOT_FOUND`
+///
+/// # Safety
+/// `processor` and `account` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn trx_processor_get_account(
+    processor: *const TransactionProcessor,
+    client: u16,
+    account: *mut TrxAccount,
+) -> i32 {
+    let (Some(processor), Some(out)) = (processor.as_ref(), account.as_mut()) else {
+        return TRX_INVALID_ARGUMENT;
+    };
+
+    match processor.account(client) {
+        Ok(Some(account)) => {
+            *out = to_ffi(&account);
+            TRX_OK
+        }
+        Ok(None) => TRX_NOT_FOUND,
+        Err(err) => status(Err(err)),
+    }
+}
+
+/// Iterates over a copy of all accounts taken at the time of the call.
+/// Returns null on failure.
+///
+/// # Safety
+/// `processor` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn trx_processor_accounts(processor: *const TransactionProcessor) -> *mut TrxAccountIter {
+    let Some(processor) = processor.as_ref() else {
+        return ptr::null_mut();
+    };
+
+    match processor.accounts() {
+        Ok(accounts) => Box::into_raw(Box::new(TrxAccountIter { accounts: accounts.into_iter() })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Copies the next account into `account`. Returns false when the iterator is exhausted.
+///
+/// # Safety
+/// `iter` and `account` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn trx_account_iter_next(iter: *mut TrxAccountIter, account: *mut TrxAccount) -> bool {
+    let (Some(iter), Some(out)) = (iter.as_mut(), account.as_mut()) else {
+        return false;
+    };
+
+    match iter.accounts.next() {
+        Some(account) => {
+            *out = to_ffi(&account);
+            true
+        }
+        None => false,
+    }
+}
+
+/// # Safety
+/// `iter` must come from `trx_processor_accounts` and not be used afterwards. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn trx_account_iter_free(iter: *mut TrxAccountIter) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::TxId;
+use crate::store;
+
+/// Runs the self-checks for the given configuration and prints one line per check.
+/// Meant as an exec probe: fails if any check failed.
+///
+/// - `store`: the stores can be opened and read
+/// - `journal`: the file store directory, where batch journals are written, is writable
+/// - `audit`: the audit directory is writable
+pub fn run_healthcheck(store: Option<&str>, audit_dir: Option<&str>) -> Result<(), ProcessorError> {
+    let mut checks = Vec::new();
+    if let Some(location) = store {
+        checks.push(("store", check_store(location)));
+        if let Some(("file", dir)) = location.split_once("://") {
+            checks.push(("journal", check_writable(dir)));
+        }
+    }
+    if let Some(dir) = audit_dir {
+        checks.push(("audit", check_writable(dir)));
+    }
+
+    let mut failed = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(()) => println!("ok   {}", name),
+            Err(err) => {
+                failed += 1;
+                println!("FAIL {}: {}", name, err);
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(ProcessorError::Unhealthy(format!("{} of {} check(s) failed", failed, checks.len()))),
+    }
+}
+
+fn check_store(location: &str) -> Result<(), ProcessorError> {
+    let stores = store::open_stores(location)?;
+    stores.accounts.get(0)?;
+    stores.transactions.get(&TxId::Numeric(0))?;
+    Ok(())
+}
+
+fn check_writable(dir: &str) -> Result<(), ProcessorError> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!(".healthcheck-{}", process::id()));
+    fs::write(&path, b"ok")?;
+    fs::remove_file(path)?;
+    Ok(())
+}
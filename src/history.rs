@@ -0,0 +1,93 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+
+/// A balance at a point of the run, one row of the `--balance-history` file
+#[derive(Debug, Serialize)]
+pub struct BalanceSample {
+    /// Rows processed when the sample was taken, counting the one that triggered it
+    pub row: u64,
+    /// Timestamp of that row, if it had one
+    pub timestamp: Option<DateTime<Utc>>,
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl BalanceSample {
+    fn of(row: u64, timestamp: Option<DateTime<Utc>>, account: &Account) -> Self {
+        BalanceSample {
+            row,
+            timestamp,
+            client: account.client_id,
+            available: account.available,
+            held: account.held,
+            total: account.total(),
+            locked: account.locked,
+        }
+    }
+}
+
+/// Balances written to `--balance-history <path>` over the course of the run: every account
+/// each `--sample-every` rows, and the account of each `--history-clients` client after every
+/// one of its rows. Samples are written as they are taken, not kept in memory.
+pub struct BalanceHistory {
+    sample_every: Option<u64>,
+    clients: BTreeSet<u16>,
+    writer: Mutex<csv::Writer<File>>,
+    /// Row of the last sample of all accounts
+    last_sampled: AtomicU64,
+}
+
+impl BalanceHistory {
+    pub fn create(path: &str, sample_every: Option<u64>, clients: BTreeSet<u16>) -> Result<Self, ProcessorError> {
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(File::create(path)?);
+        // Header is written explicitly so a history without samples is still a valid CSV
+        writer.write_record(["row", "timestamp", "client", "available", "held", "total", "locked"])?;
+        Ok(BalanceHistory { sample_every, clients, writer: Mutex::new(writer), last_sampled: AtomicU64::new(0) })
+    }
+
+    /// Whether the row with this number is one to sample every account at
+    pub fn samples_all(&self, row: u64) -> bool {
+        self.sample_every.is_some_and(|every| row.is_multiple_of(every))
+    }
+
+    /// Whether every row of the client is sampled
+    pub fn follows(&self, client: u16) -> bool {
+        self.clients.contains(&client)
+    }
+
+    /// Writes the balances of the accounts, sorted by client, as of the row
+    pub fn record(&self, row: u64, timestamp: Option<DateTime<Utc>>, accounts: &[Account]) -> Result<(), ProcessorError> {
+        let mut writer = self.writer.lock();
+        for account in accounts {
+            writer.serialize(BalanceSample::of(row, timestamp, account))?;
+        }
+        Ok(())
+    }
+
+    /// Writes every account as of the row
+    pub fn record_all(&self, row: u64, timestamp: Option<DateTime<Utc>>, accounts: &[Account]) -> Result<(), ProcessorError> {
+        self.last_sampled.fetch_max(row, Ordering::Relaxed);
+        self.record(row, timestamp, accounts)
+    }
+
+    /// Samples the final balances unless the last row was sampled already, and flushes the file
+    pub fn finish(&self, rows: u64, accounts: &[Account]) -> Result<(), ProcessorError> {
+        if self.sample_every.is_some() && rows > self.last_sampled.load(Ordering::Relaxed) {
+            self.record_all(rows, None, accounts)?;
+        }
+        self.writer.lock().flush()?;
+        Ok(())
+    }
+}
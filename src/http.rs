@@ -0,0 +1,39 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use crate::model::error::ProcessorError;
+
+/// Answer to a request: the status code and the body
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Posts a JSON body with HTTP/1.1 and reads the answer until the server closes the
+/// connection. Chunked answers are not decoded, servers must send a `Content-Length`.
+pub fn post_json(address: &str, host: &str, path: &str, body: &[u8]) -> Result<Response, ProcessorError> {
+    let mut stream = TcpStream::connect(address)?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        host,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| io::Error::other(format!("{} answered without headers", address)))?;
+    let status_line = String::from_utf8_lossy(&response[..header_end]).lines().next().unwrap_or_default().to_string();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::other(format!("{} answered {}", address, status_line)))?;
+
+    Ok(Response { status, body: response[header_end + 4..].to_vec() })
+}
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::{self, Read};
+
+use crate::model::error::ProcessorError;
+use crate::sftp::{self, SftpLocation};
+
+/// Environment variable holding the `Authorization` header sent with a URL input, e.g.
+/// `Bearer <token>`, so the token stays out of the command line
+pub const AUTHORIZATION_ENV: &str = "TRX_INPUT_AUTHORIZATION";
+
+/// Input name for rows read from stdin, as `coordinate` feeds its workers
+pub const STDIN: &str = "-";
+
+/// Whether the input can be read more than once, to hash or check it apart from processing it
+pub fn is_local_file(path: &str) -> bool {
+    !is_url(path) && path != STDIN
+}
+
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("https://") || path.starts_with("http://") || path.starts_with("sftp://")
+}
+
+/// The input as it may be shown: a URL loses its query string, which holds the signature
+/// of a signed download link
+pub fn display_name(path: &str) -> &str {
+    match is_url(path) {
+        true => path.split(['?', '#']).next().unwrap_or(path),
+        false => path,
+    }
+}
+
+/// Opens a local file, an `http://` or `https://` URL, an `sftp://` location or stdin,
+/// streaming remote files as they are read
+pub fn open(path: &str) -> Result<Box<dyn Read + Send>, ProcessorError> {
+    if path == STDIN {
+        return Ok(Box::new(io::stdin()));
+    }
+    match is_url(path) {
+        true if path.starts_with("sftp://") => sftp::open(&SftpLocation::parse(path)?),
+        true => download(path),
+        false => Ok(Box::new(File::open(path)?)),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn download(url: &str) -> Result<Box<dyn Read + Send>, ProcessorError> {
+    let mut request = ureq::get(url);
+    if let Ok(authorization) = std::env::var(AUTHORIZATION_ENV) {
+        request = request.set("Authorization", &authorization);
+    }
+    // ureq errors quote the whole URL, signature included
+    match request.call() {
+        Ok(response) => Ok(Box::new(response.into_reader())),
+        Err(ureq::Error::Status(status, _)) => {
+            Err(std::io::Error::other(format!("{} answered {}", display_name(url), status)).into())
+        }
+        Err(ureq::Error::Transport(err)) => Err(std::io::Error::other(format!(
+            "could not download {}: {}{}",
+            display_name(url),
+            err.kind(),
+            err.message().map(|message| format!(": {}", message)).unwrap_or_default()
+        ))
+        .into()),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn download(url: &str) -> Result<Box<dyn Read + Send>, ProcessorError> {
+    Err(ProcessorError::InvalidArguments(format!("cannot download {} in the browser build", display_name(url))))
+}
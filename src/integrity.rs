@@ -0,0 +1,162 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::manifest::FileDigest;
+use crate::model::error::ProcessorError;
+use crate::processor::open_reader;
+
+/// What a sidecar file says the input should be. Every field is optional, only the given
+/// ones are checked.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Expected {
+    /// Name of the file the entry describes, used to find it in a manifest of several files
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Data rows, not counting the header
+    #[serde(default)]
+    pub rows: Option<u64>,
+    #[serde(default)]
+    pub bytes: Option<u64>,
+}
+
+/// A provider manifest: one entry, or a `files` list with an entry per delivered file
+///
+/// ```json
+/// { "files": [{ "name": "settlement.csv", "sha256": "9f86d0…", "rows": 1200, "bytes": 53122 }] }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ProviderManifest {
+    Files { files: Vec<Expected> },
+    Single(Expected),
+}
+
+/// `<input>.sha256` in the format of `sha256sum`, next to the input
+pub fn checksum_sidecar(input: &str) -> String {
+    format!("{}.sha256", input)
+}
+
+/// `<input>.manifest.json`, next to the input
+pub fn manifest_sidecar(input: &str) -> String {
+    format!("{}.manifest.json", input)
+}
+
+/// The sidecars to check: with `discover`, the checksum and manifest sidecars that exist
+/// next to the input, at least one of them, and the provider manifest at `manifest` if given
+pub fn sidecars(input: &str, discover: bool, manifest: Option<&str>) -> Result<Vec<(String, Expected)>, ProcessorError> {
+    let mut sidecars = Vec::new();
+    if discover {
+        let checksum = checksum_sidecar(input);
+        if Path::new(&checksum).exists() {
+            sidecars.push((checksum.clone(), load_checksum(&checksum, input)?));
+        }
+        let manifest = manifest_sidecar(input);
+        if Path::new(&manifest).exists() {
+            sidecars.push((manifest.clone(), load_manifest(&manifest, input)?));
+        }
+        if sidecars.is_empty() {
+            return Err(ProcessorError::Integrity(format!("neither {} nor {} found", checksum, manifest)));
+        }
+    }
+    if let Some(path) = manifest {
+        sidecars.push((path.to_string(), load_manifest(path, input)?));
+    }
+    Ok(sidecars)
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or(path)
+}
+
+/// Reads a `sha256sum` file: `<hex>  <name>` lines, or a single bare hash
+pub fn load_checksum(path: &str, input: &str) -> Result<Expected, ProcessorError> {
+    let text = fs::read_to_string(path)?;
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let entry = lines.iter().find_map(|line| {
+        let (hash, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        // `sha256sum -b` marks binary mode with a `*` before the name
+        let name = name.trim_start().trim_start_matches('*');
+        let matches = match name.is_empty() {
+            true => lines.len() == 1,
+            false => file_name(name) == file_name(input),
+        };
+        matches.then(|| hash.to_lowercase())
+    });
+
+    match entry {
+        Some(hash) if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(Expected { name: None, sha256: Some(hash), rows: None, bytes: None })
+        }
+        Some(hash) => Err(ProcessorError::Integrity(format!("{}: '{}' is not a SHA-256 hash", path, hash))),
+        None => Err(ProcessorError::Integrity(format!("{}: no checksum for {}", path, file_name(input)))),
+    }
+}
+
+/// Reads a provider manifest, picking the entry of `input` from a list of files
+pub fn load_manifest(path: &str, input: &str) -> Result<Expected, ProcessorError> {
+    match serde_json::from_slice(&fs::read(path)?)? {
+        ProviderManifest::Single(expected) => Ok(expected),
+        ProviderManifest::Files { files } => files
+            .into_iter()
+            .find(|entry| entry.name.as_deref().is_some_and(|name| file_name(name) == file_name(input)))
+            .ok_or_else(|| ProcessorError::Integrity(format!("{}: no entry for {}", path, file_name(input)))),
+    }
+}
+
+/// Checks the input against every expectation before any row is processed. Returns a line
+/// describing what was verified, or fails with every mismatch found.
+pub fn verify(input: &str, sidecars: &[(String, Expected)]) -> Result<String, ProcessorError> {
+    let digest = FileDigest::of(input)?;
+    let needs_rows = sidecars.iter().any(|(_, expected)| expected.rows.is_some());
+    let rows = match needs_rows {
+        true => Some(count_rows(input)?),
+        false => None,
+    };
+
+    let mut mismatches = Vec::new();
+    let mut verified = BTreeSet::new();
+    for (path, expected) in sidecars {
+        if let Some(sha256) = &expected.sha256 {
+            if sha256.eq_ignore_ascii_case(&digest.sha256) {
+                verified.insert("sha256");
+            } else {
+                mismatches.push(format!("sha256 is {}, {} expects {}", digest.sha256, path, sha256));
+            }
+        }
+        if let (Some(expected_rows), Some(rows)) = (expected.rows, rows) {
+            if expected_rows == rows {
+                verified.insert("rows");
+            } else {
+                mismatches.push(format!("{} rows, {} expects {}", rows, path, expected_rows));
+            }
+        }
+        if let Some(bytes) = expected.bytes {
+            if bytes == digest.bytes {
+                verified.insert("bytes");
+            } else {
+                mismatches.push(format!("{} bytes, {} expects {}", digest.bytes, path, bytes));
+            }
+        }
+    }
+
+    if !mismatches.is_empty() {
+        return Err(ProcessorError::Integrity(format!("{}: {}", input, mismatches.join("; "))));
+    }
+    if verified.is_empty() {
+        let paths: Vec<&str> = sidecars.iter().map(|(path, _)| path.as_str()).collect();
+        return Err(ProcessorError::Integrity(format!("{} has nothing to check", paths.join(", "))));
+    }
+    Ok(format!("{} verified: {}", input, verified.into_iter().collect::<Vec<_>>().join(", ")))
+}
+
+/// Data rows of the file, a row that cannot be read counts too
+fn count_rows(input: &str) -> Result<u64, ProcessorError> {
+    let mut reader = open_reader(input)?;
+    reader.headers()?;
+    Ok(reader.records().count() as u64)
+}
@@ -0,0 +1,420 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, SystemClock};
+use crate::input;
+use crate::model::error::ProcessorError;
+
+const JOBS_FILE: &str = "jobs.json";
+/// Held while `jobs.json` is read and rewritten, so enqueueing during a run loses nothing
+const JOBS_LOCK: &str = "jobs.lock";
+/// Held by the worker for as long as it runs
+const WORKER_LOCK: &str = "worker.lock";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Skipped,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Skipped => "skipped",
+        }
+    }
+}
+
+/// A file to process, as given to `enqueue`
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobSpec {
+    pub input: String,
+    /// Options of the run, after the input
+    pub args: Vec<String>,
+    /// Runs the file with `coordinate` and that many workers
+    pub workers: Option<u32>,
+    /// Directory relative paths of the job are resolved from
+    pub dir: PathBuf,
+    /// `--store` and `--audit-dir` of the run, to recover an interrupted attempt
+    pub store: Option<String>,
+    pub audit_dir: Option<String>,
+}
+
+/// A job of the queue and the record of its last attempt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub input: String,
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workers: Option<u32>,
+    pub dir: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_dir: Option<String>,
+    pub state: JobState,
+    pub attempts: u32,
+    /// Run id of the last attempt, given to it with `--run-id` when it has an audit trail
+    #[serde(default)]
+    pub run_id: Option<String>,
+    pub enqueued_at: DateTime<Utc>,
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl Job {
+    /// Whether an attempt may have left changes behind: only a store outliving the run keeps them
+    fn persists(&self) -> bool {
+        self.store.as_deref().is_some_and(|store| !store.starts_with("memory://"))
+    }
+
+    /// Arguments of this executable for the job
+    fn command(&self) -> Vec<String> {
+        let mut command = Vec::new();
+        if let Some(workers) = self.workers {
+            command.extend(["coordinate".to_string(), self.input.clone(), "--workers".to_string(), workers.to_string(), "--".to_string()]);
+        } else {
+            command.push(self.input.clone());
+        }
+        command.extend(self.args.iter().cloned());
+        if let Some(run_id) = &self.run_id {
+            command.extend(["--run-id".to_string(), run_id.clone()]);
+        }
+        command
+    }
+}
+
+/// Files waiting to be processed one after the other, kept in `jobs.json` of a directory
+/// next to the report (`<id>.csv`) and stderr (`<id>.log`) of every job
+pub struct JobQueue {
+    dir: PathBuf,
+    /// Stamps when jobs were enqueued, started and finished
+    clock: Arc<dyn Clock>,
+}
+
+impl JobQueue {
+    pub fn open(dir: &str) -> Result<Self, ProcessorError> {
+        fs::create_dir_all(dir)?;
+        Ok(JobQueue { dir: PathBuf::from(dir), clock: Arc::new(SystemClock) })
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn jobs(&self) -> Result<Vec<Job>, ProcessorError> {
+        match fs::read(self.dir.join(JOBS_FILE)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Reads the jobs, applies `f` and writes them back, holding the jobs lock throughout
+    fn update<T>(&self, f: impl FnOnce(&mut Vec<Job>) -> Result<T, ProcessorError>) -> Result<T, ProcessorError> {
+        let _lock = LockFile::acquire(&self.dir.join(JOBS_LOCK), Duration::from_secs(5))?;
+        let mut jobs = self.jobs()?;
+        let result = f(&mut jobs)?;
+
+        // Write to a temporary file first so a crash never leaves a truncated queue
+        let path = self.dir.join(JOBS_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&jobs)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(result)
+    }
+
+    /// Adds a job per file, in the order given, and returns their ids
+    pub fn enqueue(&self, specs: Vec<JobSpec>) -> Result<Vec<String>, ProcessorError> {
+        let now = self.clock.now();
+        self.update(|jobs| {
+            let mut next = jobs.iter().filter_map(|job| job.id.strip_prefix("job-")?.parse::<u32>().ok()).max().unwrap_or(0);
+            let mut ids = Vec::new();
+            for spec in specs {
+                next += 1;
+                let id = format!("job-{}", next);
+                jobs.push(Job {
+                    id: id.clone(),
+                    input: spec.input,
+                    args: spec.args,
+                    workers: spec.workers,
+                    dir: spec.dir,
+                    store: spec.store,
+                    audit_dir: spec.audit_dir,
+                    state: JobState::Queued,
+                    attempts: 0,
+                    run_id: None,
+                    enqueued_at: now,
+                    started_at: None,
+                    finished_at: None,
+                    error: None,
+                });
+                ids.push(id);
+            }
+            Ok(ids)
+        })
+    }
+
+    /// Puts a failed job back in its place in the queue, or skips a failed or queued one
+    pub fn set_state(&self, id: &str, state: JobState) -> Result<(), ProcessorError> {
+        self.update(|jobs| {
+            let job = jobs
+                .iter_mut()
+                .find(|job| job.id == id)
+                .ok_or_else(|| ProcessorError::InvalidArguments(format!("no job '{}' in {}", id, self.dir.display())))?;
+            match (job.state, state) {
+                (JobState::Failed, JobState::Queued) | (JobState::Failed | JobState::Queued, JobState::Skipped) => {
+                    job.state = state;
+                    Ok(())
+                }
+                (current, _) => Err(ProcessorError::InvalidArguments(format!(
+                    "job '{}' is {} and cannot be {}",
+                    id,
+                    current.as_str(),
+                    match state {
+                        JobState::Queued => "requeued",
+                        _ => "skipped",
+                    }
+                ))),
+            }
+        })
+    }
+
+    fn report_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.csv", id))
+    }
+
+    fn log_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.log", id))
+    }
+
+    /// Prints one line per job: id, state, attempts, input and the run id or error
+    pub fn print_status(&self) -> Result<(), ProcessorError> {
+        for job in self.jobs()? {
+            let detail = match (&job.error, &job.run_id) {
+                (Some(error), _) if job.state == JobState::Failed => error.clone(),
+                (_, Some(run_id)) => format!("run {}", run_id),
+                _ => String::new(),
+            };
+            println!("{:<8} {:<8} {:>2} {} {}", job.id, job.state.as_str(), job.attempts, job.input, detail);
+        }
+        Ok(())
+    }
+}
+
+/// Processes the queued jobs oldest first, each in a process of this executable, until
+/// the queue is empty or a job fails. A job still running when an earlier worker died is
+/// recovered first, and a failed job stops the queue until it is requeued or skipped, so
+/// files are applied to a store in the order they were enqueued.
+pub fn run_worker(dir: &str, clock: Arc<dyn Clock>) -> Result<(), ProcessorError> {
+    let queue = JobQueue::open(dir)?.with_clock(clock);
+    let _lock = LockFile::acquire(&queue.dir.join(WORKER_LOCK), Duration::ZERO)?;
+
+    for job in queue.jobs()?.into_iter().filter(|job| job.state == JobState::Running) {
+        recover(&queue, &job)?;
+    }
+
+    let mut done = 0;
+    loop {
+        let next = queue.update(|jobs| {
+            if let Some(failed) = jobs.iter().find(|job| job.state == JobState::Failed) {
+                return Err(ProcessorError::JobFailed(format!(
+                    "{} ({}): {}; requeue it with 'enqueue --queue {} --requeue {}' or skip it with '--skip {}'",
+                    failed.id,
+                    failed.input,
+                    failed.error.as_deref().unwrap_or("failed"),
+                    dir,
+                    failed.id,
+                    failed.id
+                )));
+            }
+            let Some(job) = jobs.iter_mut().find(|job| job.state == JobState::Queued) else {
+                return Ok(None);
+            };
+            job.state = JobState::Running;
+            job.attempts += 1;
+            job.started_at = Some(queue.clock.now());
+            job.finished_at = None;
+            job.error = None;
+            // Every attempt has an audit trail of its own, so an interrupted one can be undone
+            job.run_id = job.audit_dir.as_ref().map(|_| format!("{}-{}", job.id, job.attempts));
+            Ok(Some(job.clone()))
+        })?;
+        let Some(job) = next else {
+            break;
+        };
+
+        eprintln!("{} started: {}", job.id, job.input);
+        let error = run_job(&queue, &job)?;
+        let finished_at = queue.clock.now();
+        queue.update(|jobs| {
+            if let Some(stored) = jobs.iter_mut().find(|stored| stored.id == job.id) {
+                stored.state = if error.is_none() { JobState::Done } else { JobState::Failed };
+                stored.finished_at = Some(finished_at);
+                stored.error = error.clone();
+            }
+            Ok(())
+        })?;
+
+        match error {
+            None => {
+                done += 1;
+                eprintln!("{} done, report in {}", job.id, queue.report_path(&job.id).display());
+            }
+            Some(error) => {
+                return Err(ProcessorError::JobFailed(format!(
+                    "{} ({}): {}, see {}",
+                    job.id,
+                    job.input,
+                    error,
+                    queue.log_path(&job.id).display()
+                )));
+            }
+        }
+    }
+
+    eprintln!("{} job(s) done", done);
+    Ok(())
+}
+
+/// Runs one attempt, writing its report and stderr next to the queue. Returns the error
+/// the attempt failed with, the last line it wrote to stderr.
+fn run_job(queue: &JobQueue, job: &Job) -> Result<Option<String>, ProcessorError> {
+    let log = OpenOptions::new().create(true).append(true).open(queue.log_path(&job.id))?;
+    let status = process::Command::new(env::current_exe()?)
+        .args(job.command())
+        .current_dir(&job.dir)
+        .stdout(File::create(queue.report_path(&job.id))?)
+        .stderr(log)
+        .status()?;
+
+    if status.success() {
+        return Ok(None);
+    }
+    let log = fs::read_to_string(queue.log_path(&job.id))?;
+    let last = log.lines().rev().find(|line| !line.trim().is_empty());
+    Ok(Some(match last {
+        Some(line) => line.trim_start_matches("Error: ").to_string(),
+        None => format!("exited with {}", status),
+    }))
+}
+
+/// Puts a job an earlier worker left running back in the queue. What the attempt committed
+/// to a store is undone with its audit trail first; without one it cannot be told apart
+/// from the rest of the store, and the job fails until someone looked at it.
+fn recover(queue: &JobQueue, job: &Job) -> Result<(), ProcessorError> {
+    let mut state = JobState::Queued;
+    let mut error = None;
+
+    if job.persists() {
+        match (&job.store, &job.audit_dir, &job.run_id) {
+            (Some(store), Some(audit_dir), Some(run_id)) => {
+                let trail = job.dir.join(audit_dir).join(format!("{}.jsonl", run_id));
+                // The trail is created before the first row, no trail means nothing was committed
+                if trail.exists() {
+                    let log = OpenOptions::new().create(true).append(true).open(queue.log_path(&job.id))?;
+                    let undone = process::Command::new(env::current_exe()?)
+                        .args(["undo", "--run", run_id, "--store", store, "--audit-dir", audit_dir])
+                        .current_dir(&job.dir)
+                        .stdout(log.try_clone()?)
+                        .stderr(log)
+                        .status()?;
+                    if !undone.success() {
+                        state = JobState::Failed;
+                        error = Some(format!("interrupted, and undoing run {} failed with {}", run_id, undone));
+                    }
+                }
+            }
+            (store, _, _) => {
+                state = JobState::Failed;
+                error = Some(format!(
+                    "interrupted with rows possibly committed to {} and no audit trail to undo them",
+                    store.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+    }
+
+    match &error {
+        Some(error) => eprintln!("{} was interrupted: {}", job.id, error),
+        None => eprintln!("{} was interrupted and is queued again", job.id),
+    }
+    queue.update(|jobs| {
+        if let Some(stored) = jobs.iter_mut().find(|stored| stored.id == job.id) {
+            stored.state = state;
+            stored.error = error.clone();
+        }
+        Ok(())
+    })
+}
+
+/// Builds the spec of a job, resolving a local input from the current directory
+pub fn spec(input: &str, args: Vec<String>, workers: Option<u32>, store: Option<String>, audit_dir: Option<String>) -> Result<JobSpec, ProcessorError> {
+    let input = match input::is_url(input) {
+        true => input.to_string(),
+        false => fs::canonicalize(input)
+            .map_err(|err| ProcessorError::InvalidArguments(format!("cannot enqueue '{}': {}", input, err)))?
+            .to_string_lossy()
+            .into_owned(),
+    };
+    Ok(JobSpec { input, args, workers, dir: env::current_dir()?, store, audit_dir })
+}
+
+/// A file created exclusively, removed again when dropped
+struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    /// Retries until `wait` is over while another process holds the lock
+    fn acquire(path: &Path, wait: Duration) -> Result<Self, ProcessorError> {
+        let retry = Duration::from_millis(20);
+        let mut waited = Duration::ZERO;
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(_) => return Ok(LockFile { path: path.to_path_buf() }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists && waited < wait => {
+                    thread::sleep(retry);
+                    waited += retry;
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    return Err(io::Error::other(format!(
+                        "{} is held by another process; remove it if none is running",
+                        path.display()
+                    ))
+                    .into());
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds of the histogram buckets in microseconds, slower rows fall in the last bucket
+const BUCKET_BOUNDS_US: [u64; 9] = [10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000, 100_000];
+/// Clients listed in the summary, by total processing time
+const SLOWEST_CLIENTS: usize = 5;
+
+/// Parses a `--slow-threshold` such as `500us`, `20ms` or `2s`
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let (amount, unit) = value.split_at(value.find(|c: char| c.is_ascii_alphabetic())?);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "us" => Some(Duration::from_micros(amount)),
+        "ms" => Some(Duration::from_millis(amount)),
+        "s" => Some(Duration::from_secs(amount)),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct ClientLatency {
+    rows: u64,
+    total: Duration,
+    max: Duration,
+}
+
+/// Time spent processing each row, as a histogram over all rows and totals per client
+pub struct LatencyTracker {
+    buckets: [AtomicU64; BUCKET_BOUNDS_US.len() + 1],
+    total_nanos: AtomicU64,
+    clients: DashMap<u16, ClientLatency>,
+    slow_threshold: Option<Duration>,
+    slow_rows: AtomicU64,
+}
+
+/// Rows per histogram bucket, `le_us` is None for the bucket above the last bound
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LatencyBucket {
+    pub le_us: Option<u64>,
+    pub rows: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientLatencySummary {
+    pub client: u16,
+    pub rows: u64,
+    pub total_us: u64,
+    pub max_us: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub buckets: Vec<LatencyBucket>,
+    pub total_us: u64,
+    pub slowest_clients: Vec<ClientLatencySummary>,
+    pub slow_threshold_us: Option<u64>,
+    pub slow_rows: u64,
+}
+
+impl LatencyTracker {
+    pub fn new(slow_threshold: Option<Duration>) -> Self {
+        LatencyTracker {
+            buckets: Default::default(),
+            total_nanos: AtomicU64::new(0),
+            clients: DashMap::new(),
+            slow_threshold,
+            slow_rows: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the time a row of `client` took, returns whether it was over the slow threshold
+    pub fn record(&self, client: u16, elapsed: Duration) -> bool {
+        let micros = elapsed.as_micros() as u64;
+        let bucket = BUCKET_BOUNDS_US.iter().position(|&bound| micros <= bound).unwrap_or(BUCKET_BOUNDS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+
+        let mut latency = self.clients.entry(client).or_default();
+        latency.rows += 1;
+        latency.total += elapsed;
+        latency.max = latency.max.max(elapsed);
+
+        let slow = self.slow_threshold.is_some_and(|threshold| elapsed > threshold);
+        if slow {
+            self.slow_rows.fetch_add(1, Ordering::Relaxed);
+        }
+        slow
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, rows)| LatencyBucket {
+                le_us: BUCKET_BOUNDS_US.get(i).copied(),
+                rows: rows.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        let mut slowest_clients: Vec<_> = self
+            .clients
+            .iter()
+            .map(|entry| ClientLatencySummary {
+                client: *entry.key(),
+                rows: entry.rows,
+                total_us: entry.total.as_micros() as u64,
+                max_us: entry.max.as_micros() as u64,
+            })
+            .collect();
+        slowest_clients.sort_by(|a, b| b.total_us.cmp(&a.total_us).then(a.client.cmp(&b.client)));
+        slowest_clients.truncate(SLOWEST_CLIENTS);
+
+        LatencySummary {
+            buckets,
+            total_us: self.total_nanos.load(Ordering::Relaxed) / 1_000,
+            slowest_clients,
+            slow_threshold_us: self.slow_threshold.map(|threshold| threshold.as_micros() as u64),
+            slow_rows: self.slow_rows.load(Ordering::Relaxed),
+        }
+    }
+}
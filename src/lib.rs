@@ -0,0 +1,66 @@
+pub mod account_csv;
+pub mod ack;
+pub mod aml;
+pub mod analytics;
+pub mod audit;
+pub mod bloom;
+pub mod cache;
+pub mod chaos;
+pub mod clock;
+pub mod cohort;
+pub mod dead_letter;
+pub mod dedup;
+pub mod diagnostics;
+pub mod dispute_chains;
+pub mod dispute_stats;
+pub mod engine;
+pub mod exposure;
+pub mod health;
+pub mod history;
+pub mod http;
+pub mod input;
+pub mod integrity;
+pub mod jobs;
+pub mod latency;
+pub mod locking;
+pub mod logger;
+pub mod manifest;
+pub mod model;
+pub mod notify;
+pub mod preflight;
+pub mod plugin;
+pub mod pretty;
+pub mod processor;
+pub mod proposal;
+pub mod query;
+pub mod reason_codes;
+pub mod reference;
+pub mod replay;
+pub mod report_template;
+pub mod resources;
+pub mod retry;
+pub mod review;
+pub mod risk;
+pub mod rules;
+pub mod scenario;
+pub mod scheduler;
+pub mod schema;
+pub mod screening;
+pub mod script;
+pub mod sftp;
+pub mod shard;
+pub mod signals;
+pub mod snapshot;
+pub mod store;
+pub mod summary;
+pub mod telemetry;
+pub mod throttle;
+
+#[cfg(feature = "python")]
+mod python;
+
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
@@ -0,0 +1,56 @@
+// Under loom the blocking primitives are swapped for loom's model checked ones
+#[cfg(loom)]
+use loom::sync::{Condvar, Mutex};
+#[cfg(not(loom))]
+use std::sync::{Condvar, Mutex};
+use std::sync::Arc;
+
+/// Per-client ordering lock. Its guard keeps the lock alive by itself, so it can be held
+/// after the map the lock was looked up in has been released.
+#[derive(Default)]
+pub struct ClientLock {
+    locked: Mutex<bool>,
+    released: Condvar,
+}
+
+/// Releases the client lock when dropped
+pub struct ClientGuard {
+    lock: Arc<ClientLock>,
+}
+
+impl ClientLock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Blocks until the lock is free, then takes it
+    pub fn lock(self: &Arc<Self>) -> ClientGuard {
+        let mut locked = self.locked.lock().unwrap();
+        while *locked {
+            locked = self.released.wait(locked).unwrap();
+        }
+        *locked = true;
+
+        ClientGuard { lock: Arc::clone(self) }
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        *self.lock.locked.lock().unwrap() = false;
+        self.lock.released.notify_one();
+    }
+}
+
+/// Acquires one lock per key, in ascending key order and each key once, whatever order the
+/// keys are given in. Any two callers therefore take shared locks in the same order and
+/// cannot deadlock each other; the guards are returned in that order.
+pub fn lock_in_order<K, G>(keys: &[K], acquire: impl FnMut(K) -> G) -> Vec<G>
+where
+    K: Ord + Copy,
+{
+    let mut keys = keys.to_vec();
+    keys.sort_unstable();
+    keys.dedup();
+    keys.into_iter().map(acquire).collect()
+}
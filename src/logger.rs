@@ -1,26 +1,298 @@
-use std::fs::OpenOptions;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
 use std::io::{BufWriter, Write};
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+
+use crate::clock::{Clock, SystemClock};
+
+/// Time zone of the log timestamps, selected with `--log-timezone`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogTimezone {
+    Utc,
+    #[default]
+    Local,
+}
+
+impl LogTimezone {
+    pub fn from_name(name: &str) -> Option<LogTimezone> {
+        match name {
+            "utc" => Some(LogTimezone::Utc),
+            "local" => Some(LogTimezone::Local),
+            _ => None,
+        }
+    }
+}
+
+/// How the log timestamps are written, selected with `--log-time-format`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum LogTimeFormat {
+    /// With milliseconds and the offset, e.g. `2024-03-01T09:00:00.000+01:00` or `2024-03-01T08:00:00.000Z`
+    #[default]
+    Rfc3339,
+    /// A strftime pattern, e.g. `%Y-%m-%d %H:%M:%S%.3f`
+    Pattern(String),
+}
+
+impl LogTimeFormat {
+    /// Accepts `rfc3339` or a strftime pattern, None if the pattern is invalid
+    pub fn parse(value: &str) -> Option<LogTimeFormat> {
+        match value {
+            "rfc3339" => Some(LogTimeFormat::Rfc3339),
+            pattern if StrftimeItems::new(pattern).any(|item| item == Item::Error) => None,
+            pattern => Some(LogTimeFormat::Pattern(pattern.to_string())),
+        }
+    }
+
+    fn format<Tz: chrono::TimeZone>(&self, at: DateTime<Tz>) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        match self {
+            LogTimeFormat::Rfc3339 => at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            LogTimeFormat::Pattern(pattern) => at.format(pattern).to_string(),
+        }
+    }
+}
+
+/// Kinds of log lines, by the transaction type they start with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogCategory {
+    Deposits,
+    Withdrawals,
+    /// Disputes, resolves and chargebacks
+    Disputes,
+    /// Adjustments, freezes, unlocks, merges, review decisions and admin operations files
+    Admin,
+    /// Every other line, e.g. reservations, risk locks and slow rows
+    Other,
+}
+
+impl LogCategory {
+    pub fn from_name(name: &str) -> Option<LogCategory> {
+        match name {
+            "deposits" => Some(LogCategory::Deposits),
+            "withdrawals" => Some(LogCategory::Withdrawals),
+            "disputes" => Some(LogCategory::Disputes),
+            "admin" => Some(LogCategory::Admin),
+            "other" => Some(LogCategory::Other),
+            _ => None,
+        }
+    }
+
+    /// Category of a line such as `DISPUTE REJECTED: client=1, ...`
+    fn of(message: &str) -> LogCategory {
+        match message.split([' ', ':']).next().unwrap_or_default() {
+            "DEPOSIT" => LogCategory::Deposits,
+            "WITHDRAWAL" => LogCategory::Withdrawals,
+            "DISPUTE" | "RESOLVE" | "CHARGEBACK" => LogCategory::Disputes,
+            "ADJUSTMENT" | "FREEZE" | "UNFREEZE" | "UNLOCK" | "MERGE" | "APPROVE" | "REJECT" | "ADMIN" => LogCategory::Admin,
+            _ => LogCategory::Other,
+        }
+    }
+}
+
+/// How much of a category is logged
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum LogLevel {
+    Off,
+    /// Everything but the lines of applied rows: rejections, parked rows, locks, mismatches
+    Warn,
+    #[default]
+    Info,
+}
+
+impl LogLevel {
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name {
+            "off" => Some(LogLevel::Off),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            _ => None,
+        }
+    }
+
+    /// Level of a line: applied rows are routine, anything else is worth a look
+    fn of(message: &str) -> LogLevel {
+        let status = message.split(':').next().unwrap_or_default();
+        match status.ends_with(" SUCCESS") || status == "ADMIN OPS START" || status == "ADMIN OPS END" {
+            true => LogLevel::Info,
+            false => LogLevel::Warn,
+        }
+    }
+}
+
+/// Level per category, set with `--log-level`; every category logs everything by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogLevels([LogLevel; 5]);
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        LogLevels([LogLevel::Info; 5])
+    }
+}
+
+impl LogLevels {
+    /// Parses `<level>` for every category and `<category>=<level>` for one, comma separated
+    /// and applied in order, e.g. `warn,disputes=info` or `deposits=off`
+    pub fn parse(value: &str) -> Option<LogLevels> {
+        let mut levels = LogLevels::default();
+        for part in value.split(',') {
+            match part.split_once('=') {
+                Some((category, level)) => {
+                    let category = LogCategory::from_name(category)?;
+                    levels.0[category as usize] = LogLevel::from_name(level)?;
+                }
+                None => levels.0 = [LogLevel::from_name(part)?; 5],
+            }
+        }
+        Some(levels)
+    }
+
+    pub fn level(&self, category: LogCategory) -> LogLevel {
+        self.0[category as usize]
+    }
+
+    fn enabled(&self, message: &str) -> bool {
+        LogLevel::of(message) <= self.level(LogCategory::of(message))
+    }
+}
+
+/// Log files kept open at once by `--log-per-client`, the least recently written is closed
+/// beyond that
+const OPEN_FILES: usize = 256;
+
+fn open_append(path: &std::path::Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// The client a log line is about, from its `client=<id>` field
+fn client_of(message: &str) -> Option<u16> {
+    let start = match message.strip_prefix("client=") {
+        Some(_) => 0,
+        None => message.find(" client=")? + 1,
+    };
+    let digits = message[start + "client=".len()..].split(|c: char| !c.is_ascii_digit()).next()?;
+    digits.parse().ok()
+}
+
+/// Log files of `--log-per-client`: `client-<id>.log` per client, or `bucket-<n>.log` for the
+/// clients whose id modulo the bucket count is `n`, and `run.log` for lines about no client
+struct ClientFiles {
+    dir: PathBuf,
+    buckets: Option<u16>,
+    open: HashMap<String, (BufWriter<File>, u64)>,
+    /// Writes so far, to tell which open file was written least recently
+    writes: u64,
+}
+
+impl ClientFiles {
+    fn file_name(&self, client: Option<u16>) -> String {
+        match (client, self.buckets) {
+            (None, _) => "run.log".to_string(),
+            (Some(client), None) => format!("client-{}.log", client),
+            (Some(client), Some(buckets)) => format!("bucket-{}.log", client % buckets),
+        }
+    }
+
+    fn writer(&mut self, client: Option<u16>) -> std::io::Result<&mut BufWriter<File>> {
+        let name = self.file_name(client);
+        self.writes += 1;
+        if !self.open.contains_key(&name) {
+            if self.open.len() >= OPEN_FILES {
+                let oldest = self.open.iter().min_by_key(|(_, (_, written))| *written).map(|(name, _)| name.clone());
+                if let Some(mut writer) = oldest.and_then(|name| self.open.remove(&name)) {
+                    writer.0.flush()?;
+                }
+            }
+            let file = open_append(&self.dir.join(&name))?;
+            self.open.insert(name.clone(), (BufWriter::new(file), 0));
+        }
+        let (writer, written) = self.open.get_mut(&name).expect("opened above");
+        *written = self.writes;
+        Ok(writer)
+    }
+}
+
+enum Sink {
+    File(BufWriter<File>),
+    PerClient(ClientFiles),
+}
 
 pub struct Logger {
-    writer: Mutex<BufWriter<std::fs::File>>,
+    writer: Mutex<Sink>,
+    clock: Arc<dyn Clock>,
+    timezone: LogTimezone,
+    time_format: LogTimeFormat,
+    levels: LogLevels,
 }
 
 impl Logger {
     pub fn new(log_path: &str) -> std::io::Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_path)?;
+        let file = open_append(log_path.as_ref())?;
+        Ok(Self::with_sink(Sink::File(BufWriter::new(file))))
+    }
+
+    /// Splits the log into a file per client in `dir`, or into `buckets` files shared by
+    /// several clients each, so the lines of one client can be found without the others
+    pub fn per_client(dir: &str, buckets: Option<u16>) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self::with_sink(Sink::PerClient(ClientFiles { dir: PathBuf::from(dir), buckets, open: HashMap::new(), writes: 0 })))
+    }
 
-        Ok(Logger {
-            writer: Mutex::new(BufWriter::new(file)),
-        })
+    fn with_sink(sink: Sink) -> Self {
+        Logger {
+            writer: Mutex::new(sink),
+            clock: Arc::new(SystemClock),
+            timezone: LogTimezone::default(),
+            time_format: LogTimeFormat::default(),
+            levels: LogLevels::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_timezone(mut self, timezone: LogTimezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn with_time_format(mut self, time_format: LogTimeFormat) -> Self {
+        self.time_format = time_format;
+        self
+    }
+
+    pub fn with_levels(mut self, levels: LogLevels) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    fn timestamp(&self, now: DateTime<Utc>) -> String {
+        match self.timezone {
+            LogTimezone::Utc => self.time_format.format(now),
+            LogTimezone::Local => self.time_format.format(now.with_timezone(&Local)),
+        }
     }
 
     pub fn log(&self, message: &str) {
-        if let Ok(mut writer) = self.writer.lock() {
-            let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+        if !self.levels.enabled(message) {
+            return;
+        }
+        if let Ok(mut sink) = self.writer.lock() {
+            let timestamp = self.timestamp(self.clock.now());
+            let writer = match *sink {
+                Sink::File(ref mut writer) => writer,
+                Sink::PerClient(ref mut files) => match files.writer(client_of(message)) {
+                    Ok(writer) => writer,
+                    Err(_) => return,
+                },
+            };
             let _ = writeln!(writer, "[{}] {}", timestamp, message);
             let _ = writer.flush();
         }
@@ -1,14 +1,56 @@
-mod logger;
-mod model;
-mod processor;
+mod cli;
 
+use std::collections::BTreeSet;
 use std::env;
+use std::io::{self, Write};
 use std::process;
 use std::sync::Arc;
+use std::time::Instant;
 
-use logger::Logger;
-use model::error::ProcessorError;
-use crate::processor::TransactionProcessor;
+use chrono::{DateTime, Utc};
+
+use trx_processor::ack::{AckLog, AckTemplate};
+use trx_processor::aml::{AmlConfig, AmlMonitor};
+use trx_processor::analytics::{AnomalyDetector, FileStats};
+use trx_processor::audit::{self, AuditTrail};
+use trx_processor::cache::{CachedResult, ResultCache};
+use trx_processor::chaos::Chaos;
+use trx_processor::clock::{Clock, SystemClock};
+use trx_processor::cohort::Cohorts;
+use trx_processor::dead_letter::DeadLetterQueue;
+use trx_processor::dedup::{DedupFilter, DedupWindow};
+use trx_processor::diagnostics::Diagnostics;
+use trx_processor::dispute_chains::DisputeChains;
+use trx_processor::dispute_stats::DisputeStats;
+use trx_processor::exposure::ExposureTracker;
+use trx_processor::history::BalanceHistory;
+use trx_processor::jobs::{self, JobQueue};
+use trx_processor::logger::Logger;
+use trx_processor::manifest::{self, DigestWriter, FileDigest, RunManifest};
+use trx_processor::model::error::ProcessorError;
+use trx_processor::notify::{self, NotifyConfig, RunOutcome};
+use trx_processor::plugin::Plugin;
+use trx_processor::preflight::{Baseline, FileProfile};
+use trx_processor::processor::TransactionProcessor;
+use trx_processor::proposal::{self, Proposal};
+use trx_processor::query::Query;
+use trx_processor::reason_codes::ReasonCodes;
+use trx_processor::retry::RetryPolicy;
+use trx_processor::report_template::ReportTemplate;
+use trx_processor::review::ReviewQueue;
+use trx_processor::risk::RiskEngine;
+use trx_processor::rules::RulesConfig;
+use trx_processor::scheduler::FairScheduler;
+use trx_processor::schema::SchemaReport;
+use trx_processor::screening::{Denylist, HttpScreening};
+use trx_processor::script::Script;
+use trx_processor::snapshot::{self, Snapshot};
+use trx_processor::store::{MemoryTransactionStore, TieredTransactionStore};
+use trx_processor::summary::RunSummary;
+use trx_processor::telemetry::Tracer;
+use trx_processor::{health, integrity, pretty, replay, scenario, sftp, shard, signals, store};
+
+use cli::{AdminOpsAt, Command, CutBy, Options, OutputFormat};
 
 fn main() {
     if let Err(e) = run() {
@@ -20,32 +62,574 @@ fn main() {
 fn run() -> Result<(), ProcessorError> {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 || args.len() > 3 {
-        return Err(ProcessorError::InvalidArguments(
-            "Usage: cargo run -- <transactions.csv> [--log-transactions]".to_string(),
-        ));
+    match cli::parse_args(&args)? {
+        Command::Process(options) => process_transactions(options, &args[1..]),
+        Command::SnapshotDiff { before, after } => snapshot::run_diff(&before, &after),
+        Command::Stats { input_file } => {
+            FileStats::from_file(&input_file)?.print();
+            Ok(())
+        }
+        Command::Schema { input_file, tx_id_kind, amount_parsing } => {
+            amount_parsing.install();
+            SchemaReport::from_file(&input_file, tx_id_kind)?.print();
+            Ok(())
+        }
+        Command::MergeReports { files } => shard::run_merge_reports(&files),
+        Command::Coordinate { input_file, workers, hosts, worker_args } => shard::run_coordinate(&input_file, workers, &hosts, &worker_args),
+        Command::Enqueue { queue, jobs } => {
+            for id in JobQueue::open(&queue)?.enqueue(jobs)? {
+                println!("{}", id);
+            }
+            Ok(())
+        }
+        Command::SetJobState { queue, job, state } => JobQueue::open(&queue)?.set_state(&job, state),
+        Command::Worker { queue, status: true } => JobQueue::open(&queue)?.print_status(),
+        Command::Worker { queue, status: false } => jobs::run_worker(&queue, Arc::new(SystemClock)),
+        Command::Scenario { files } => scenario::run_scenarios(&files),
+        Command::Healthcheck { store, audit_dir } => health::run_healthcheck(store.as_deref(), audit_dir.as_deref()),
+        Command::Replay { input_file, speed, restamp } => replay::run_replay(&input_file, speed, restamp, &SystemClock),
+        Command::BalanceAt { options, client, at } => balance_at(options, client, at),
+        Command::Query { options, query } => run_query(options, query),
+        Command::Undo { run_id, store, audit_dir } => audit::run_undo(&store, &audit_dir, &run_id),
+        Command::Apply { proposals, store, audit_dir, run_id } => {
+            proposal::run_apply(&proposals, &store, audit_dir.as_deref(), run_id)
+        }
     }
+}
 
-    let input_file = &args[1];
-    let enable_logging = args.len() == 3 && args[2] == "--log-transactions";
+fn process_transactions(options: Options, arguments: &[String]) -> Result<(), ProcessorError> {
+    // Loaded up front, so a broken config fails like any other bad option, unnotified
+    let notify_config = options.notify_path.as_deref().map(NotifyConfig::load).transpose()?;
+
+    let result = run_batch(&options, arguments);
+
+    if let Some(config) = &notify_config {
+        let outcome = match &result {
+            Ok(summary) => RunOutcome::Finished(summary.as_ref()),
+            Err(err) => RunOutcome::Failed(err),
+        };
+        // The run itself is over, so a lost notification does not fail it
+        if let Err(err) = notify::notify(config, &options.input_file, &outcome, SystemClock.now()) {
+            eprintln!("Notification failed: {}", err);
+        }
+    }
+    result.map(|_| ())
+}
+
+/// Processes the input and writes every output, returning the summary if one was needed
+fn run_batch(options: &Options, arguments: &[String]) -> Result<Option<RunSummary>, ProcessorError> {
+    let started = Instant::now();
+
+    // A truncated or altered transfer fails before anything is processed
+    if options.verify || options.input_manifest_path.is_some() {
+        let sidecars = integrity::sidecars(&options.input_file, options.verify, options.input_manifest_path.as_deref())?;
+        eprintln!("{}", integrity::verify(&options.input_file, &sidecars)?);
+    }
+
+    let preflight = match &options.preflight_path {
+        Some(path) => Some(preflight(options, path)?),
+        None => None,
+    };
+
+    let cache = match &options.cache_dir {
+        Some(dir) => {
+            let cache = ResultCache::open(dir)?;
+            let key = ResultCache::key(&options.input_file, &options.cache_config())?;
+            if let Some(cached) = cache.get(&key)? {
+                eprintln!("Cached result {} reused", key);
+                io::stdout().write_all(cached.report.as_bytes())?;
+                output_summary(options, &cached.summary)?;
+                return Ok(Some(cached.summary));
+            }
+            Some((cache, key))
+        }
+        None => None,
+    };
+
+    let template = match (&options.report_template_path, options.output_format) {
+        (Some(path), _) => Some(ReportTemplate::load(path)?),
+        (None, OutputFormat::Html) => Some(ReportTemplate::dashboard()?),
+        (None, OutputFormat::Csv) => None,
+    };
+    let processor = build_processor(options)?;
+    let started_at = processor.clock().now();
+
+    // A report to be cached or uploaded is kept until the run succeeded
+    let mut report = Vec::new();
+    let mut stdout = io::stdout();
+    let keep_report = cache.is_some() || options.upload_report.is_some();
+    let mut destination = DigestWriter::new(if keep_report { &mut report as &mut dyn Write } else { &mut stdout });
+    let destination = &mut destination;
+    let processed = match options.cut_by {
+        Some(CutBy::Day) => output_report(options, destination, |output| {
+            processor.output_daily_accounts(&options.input_file, output)
+        }),
+        None if options.stream_output => output_report(options, destination, |output| {
+            processor.output_accounts_streaming(&options.input_file, output)
+        }),
+        None => process_input(options, &processor).and_then(|()| {
+            output_report(options, destination, |output| match &template {
+                Some(template) => {
+                    let summary = RunSummary::collect(&processor, started.elapsed())?;
+                    template.render(&processor, &summary, &options.input_file, output)
+                }
+                None => processor.output_accounts(output),
+            })
+        }),
+    };
+
+    // The run span is exported even when the run failed
+    if let Some(tracer) = processor.tracer() {
+        tracer.finish(processor.rows_processed());
+        match tracer.last_error() {
+            Some(err) => eprintln!("Trace {}: {} span(s) exported, {} dropped: {}", tracer.trace_id(), tracer.exported(), tracer.dropped(), err),
+            None => eprintln!("Trace {}: {} span(s) exported", tracer.trace_id(), tracer.exported()),
+        }
+    }
+    processed?;
+    let report_digest = destination.digest(manifest::STDOUT);
+
+    if let Some(path) = &options.propose_path {
+        let id = options.run_id.clone().unwrap_or_else(|| audit::new_run_id(processor.clock()));
+        Proposal::from_changes(id, &options.input_file, &processor.pending_changes()?).save(path)?;
+    }
+
+    if let Some(path) = &options.snapshot_path {
+        Snapshot::capture(&processor)?.save(path)?;
+    }
+
+    let mismatches = processor.reconcile_holds()?;
+    if mismatches > 0 {
+        eprintln!("{} account(s) with held funds not matching their holds ledger", mismatches);
+    }
+
+    if let Some(path) = &options.open_disputes_path {
+        processor.write_open_disputes(path)?;
+    }
+
+    if let (Some(path), Some(anomaly_detector)) = (&options.anomalies_path, processor.anomaly_detector()) {
+        anomaly_detector.write_report(path)?;
+    }
+
+    if let (Some(path), Some(tracker)) = (&options.exposure_path, processor.exposure_tracker()) {
+        tracker.write_report(path)?;
+    }
+
+    if let (Some(path), Some(dispute_stats)) = (&options.dispute_stats_path, processor.dispute_stats()) {
+        dispute_stats.write_report(path)?;
+    }
+
+    if let (Some(path), Some(dispute_chains)) = (&options.dispute_chains_path, processor.dispute_chains()) {
+        dispute_chains.write_report(path, &processor, options.amount_format)?;
+    }
+
+    if let Some(history) = processor.balance_history() {
+        history.finish(processor.rows_processed(), &processor.accounts()?)?;
+    }
+
+    if let (Some(path), Some(aml_monitor)) = (&options.aml_report_path, processor.aml_monitor()) {
+        aml_monitor.write_report(path, options.amount_format)?;
+    }
+
+    if let Some(queue) = processor.review_queue() {
+        queue.save()?;
+        if !queue.is_empty() {
+            eprintln!("{} transaction(s) waiting for review in {}", queue.len(), queue.path());
+        }
+    }
+
+    if let (Some(path), Some(ack_log)) = (&options.ack_path, processor.ack_log()) {
+        ack_log.write(path, &options.input_file, processor.clock().now())?;
+    }
+
+    if let Some(queue) = processor.dead_letter_queue().filter(|queue| queue.written() > 0) {
+        eprintln!("{} malformed row(s) written to {}", queue.written(), queue.path());
+    }
+
+    if let Some(chaos) = processor.chaos() {
+        eprintln!("{}", chaos);
+    }
+
+    if let Some(location) = &options.upload_report {
+        io::stdout().write_all(&report)?;
+        sftp::upload(location, &report)?;
+        eprintln!("Account report uploaded to {}", location);
+    }
+
+    if let Some((cache, key)) = cache {
+        io::stdout().write_all(&report)?;
+        let cached = CachedResult {
+            report: String::from_utf8_lossy(&report).into_owned(),
+            summary: RunSummary::collect(&processor, started.elapsed())?,
+        };
+        cache.put(&key, &cached)?;
+        output_summary(options, &cached.summary)?;
+        return Ok(Some(cached.summary));
+    }
+
+    let needs_summary = options.summary || options.report_path.is_some() || options.metrics_path.is_some() || options.notify_path.is_some();
+    let summary = match needs_summary {
+        true => Some(RunSummary::collect(&processor, started.elapsed())?),
+        false => None,
+    };
+    if let Some(summary) = &summary {
+        output_summary(options, summary)?;
+    }
+
+    if let Some(path) = &options.manifest_path {
+        let mut manifest = RunManifest::new(arguments, started_at);
+        manifest.run_id = processor.audit_trail().map(|audit_trail| audit_trail.run_id().to_string());
+        record_files(options, &mut manifest, report_digest)?;
+        manifest.finished_at = processor.clock().now();
+        manifest.save(path)?;
+    }
+
+    // Only files that were processed become part of the baseline
+    if let (Some(path), Some((mut baseline, profile, clients))) = (&options.preflight_path, preflight) {
+        baseline.record(profile, clients);
+        baseline.save(path)?;
+    }
+
+    Ok(summary)
+}
+
+/// Refuses an input that deviates from the files of earlier runs, unless `--force` is given
+fn preflight(options: &Options, path: &str) -> Result<(Baseline, FileProfile, BTreeSet<u16>), ProcessorError> {
+    let baseline = Baseline::load(path)?;
+    let (profile, clients) = FileProfile::scan(&options.input_file)?;
+
+    let deviations = baseline.deviations(&profile, &clients);
+    if !deviations.is_empty() {
+        if !options.force {
+            return Err(ProcessorError::Quarantined(format!(
+                "{} deviates from the baseline in {}: {}; rerun with '--force' to process it anyway",
+                options.input_file,
+                path,
+                deviations.join("; ")
+            )));
+        }
+        eprintln!("Preflight overridden with '--force': {}", deviations.join("; "));
+    }
+    Ok((baseline, profile, clients))
+}
+
+/// Records the digests of the files the run read and wrote, once all of them are written
+fn record_files(options: &Options, manifest: &mut RunManifest, report: FileDigest) -> Result<(), ProcessorError> {
+    let inputs = [
+        Some(&options.input_file),
+        options.input_manifest_path.as_ref(),
+        options.admin_ops_path.as_ref(),
+        options.dispute_rules_path.as_ref(),
+        options.reason_codes_path.as_ref(),
+        options.aml_thresholds_path.as_ref(),
+        options.screening_denylist_path.as_ref(),
+        options.script_path.as_ref(),
+        options.cohorts_path.as_ref(),
+        options.ack_format_path.as_ref(),
+    ];
+    for path in inputs.into_iter().flatten().chain(&options.plugin_paths) {
+        manifest.add_input(path)?;
+    }
+
+    manifest.outputs.push(report);
+    let outputs = [
+        options.snapshot_path.as_ref(),
+        options.open_disputes_path.as_ref(),
+        options.anomalies_path.as_ref(),
+        options.balance_history_path.as_ref(),
+        options.exposure_path.as_ref(),
+        options.dispute_stats_path.as_ref(),
+        options.dispute_chains_path.as_ref(),
+        options.aml_report_path.as_ref(),
+        options.review_queue_path.as_ref(),
+        options.ack_path.as_ref(),
+        options.dead_letter_path.as_ref(),
+        options.propose_path.as_ref(),
+        options.report_path.as_ref(),
+        options.metrics_path.as_ref(),
+    ];
+    for path in outputs.into_iter().flatten() {
+        manifest.add_output(path)?;
+    }
+    Ok(())
+}
+
+fn output_summary(options: &Options, summary: &RunSummary) -> Result<(), ProcessorError> {
+    if options.summary {
+        summary.print();
+    }
+    if let Some(path) = &options.report_path {
+        summary.write_json(path)?;
+    }
+    if let Some(path) = &options.metrics_path {
+        summary.write_prometheus(path)?;
+    }
+    Ok(())
+}
+
+/// Processes the input file, and the `--admin-ops` file before or after it
+fn process_input(options: &Options, processor: &TransactionProcessor) -> Result<(), ProcessorError> {
+    let admin_ops_at = options.admin_ops_at.unwrap_or_default();
+    if admin_ops_at == AdminOpsAt::Before {
+        process_admin_ops(options, processor)?;
+    }
+    match options.workers {
+        Some(workers) => processor.process_file_fairly(
+            &options.input_file,
+            workers,
+            options.client_budget.unwrap_or(FairScheduler::DEFAULT_BUDGET),
+            options.pin_cores,
+        )?,
+        None => processor.process_file(&options.input_file)?,
+    }
+    if admin_ops_at == AdminOpsAt::After {
+        process_admin_ops(options, processor)?;
+    }
+    Ok(())
+}
+
+fn process_admin_ops(options: &Options, processor: &TransactionProcessor) -> Result<(), ProcessorError> {
+    if let Some(path) = &options.admin_ops_path {
+        let (rows, rejected) = processor.process_admin_file(path)?;
+        eprintln!("{} admin operation(s) from {}, {} rejected", rows, path, rejected);
+    }
+    Ok(())
+}
+
+fn balance_at(options: Options, client: u16, at: DateTime<Utc>) -> Result<(), ProcessorError> {
+    let processor = build_processor(&options)?;
+
+    // Admin operations after the file come after any point in time of it
+    if options.admin_ops_at.unwrap_or_default() == AdminOpsAt::Before {
+        process_admin_ops(&options, &processor)?;
+    }
+    processor.process_file_until(&options.input_file, Some(at))?;
+    output_report(&options, &mut io::stdout(), |output| processor.output_account(client, output))?;
+
+    Ok(())
+}
+
+fn run_query(options: Options, query: Query) -> Result<(), ProcessorError> {
+    let processor = build_processor(&options)?;
+
+    process_input(&options, &processor)?;
+    output_report(&options, &mut io::stdout(), |output| query.write(&processor, output))?;
+
+    Ok(())
+}
+
+/// Sends the account report to `destination` as CSV, as a table with `--pretty`, or nowhere with `--quiet`
+fn output_report<F>(options: &Options, destination: &mut dyn Write, write: F) -> Result<(), ProcessorError>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), ProcessorError>,
+{
+    if options.quiet {
+        return write(&mut io::sink());
+    }
+    if !options.pretty {
+        return write(destination);
+    }
+
+    let mut csv = Vec::new();
+    write(&mut csv)?;
+    destination.write_all(pretty::render_table(&csv, options.locale)?.as_bytes())?;
+    Ok(())
+}
+
+fn build_processor(options: &Options) -> Result<TransactionProcessor, ProcessorError> {
+    options.amount_parsing.install();
+
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
 
     // Create logger for corner case tracking (append-only) if flag is set
-    let logger = if enable_logging {
-        Logger::new("transactions.log")
-            .map(Arc::new)
-            .ok()
-    } else {
-        None
+    let logger = match options.log_per_client {
+        Some(ref dir) => Some(Logger::per_client(dir, options.log_buckets)?),
+        None if options.log_transactions => Logger::new("transactions.log").ok(),
+        None => None,
     };
+    let logger = logger.map(|logger| {
+        Arc::new(
+            logger
+                .with_clock(clock.clone())
+                .with_timezone(options.log_timezone)
+                .with_time_format(options.log_time_format.clone())
+                .with_levels(options.log_levels.unwrap_or_default()),
+        )
+    });
 
     let processor = if let Some(logger) = logger {
         TransactionProcessor::with_logger(logger)
     } else {
         TransactionProcessor::new()
     };
+    let mut processor = processor
+        .with_clock(clock.clone())
+        .with_tx_id_kind(options.tx_id_kind)
+        .with_adjustments(options.allow_adjustments)
+        .with_merges(options.allow_merges)
+        .with_disabled_types(options.disabled_types.clone())
+        .with_shortfall_policy(options.shortfall_policy)
+        .with_freeze_policy(options.freeze_policy)
+        .with_output_schema(options.output_schema)
+        .with_amount_format(options.amount_format);
+    // Diagnostics quote the row being read, which with workers is not the row being applied
+    if options.workers.is_none() {
+        processor = processor.with_diagnostics(Diagnostics::new(options.color));
+    }
 
-    processor.process_file(input_file)?;
-    processor.output_accounts()?;
+    let chaos = options.chaos_seed.map(|seed| Arc::new(Chaos::new(seed)));
+    if let Some(chaos) = &chaos {
+        processor = processor.with_chaos(chaos.clone());
+    }
 
-    Ok(())
+    if let Some(transactions) = options.cold_after {
+        processor = processor.with_transaction_store(Box::new(TieredTransactionStore::new(transactions)));
+    }
+    if let Some(transactions) = options.expected_transactions {
+        processor = processor.with_transaction_store(Box::new(MemoryTransactionStore::with_capacity(transactions)));
+    }
+    if let Some(location) = &options.store {
+        let mut stores = store::open_stores_with_timeout(location, options.store_timeout.unwrap_or(store::DEFAULT_STORE_TIMEOUT))?;
+        if let Some(transactions) = options.cold_after {
+            stores = stores.with_cold_tier(transactions);
+        }
+        if let Some(transactions) = options.expected_transactions {
+            stores = stores.with_transaction_capacity(transactions);
+        }
+        if let Some(keys) = options.tx_bloom {
+            stores = stores.with_tx_bloom(keys)?;
+        }
+        if let Some(chaos) = &chaos {
+            stores = stores.with_chaos(chaos.clone());
+        }
+        let defaults = RetryPolicy::default();
+        let retry = RetryPolicy {
+            attempts: options.store_retries.unwrap_or(defaults.attempts),
+            backoff: options.store_backoff.unwrap_or(defaults.backoff),
+            ..defaults
+        };
+        processor = processor
+            .with_account_store(stores.accounts)
+            .with_transaction_store(stores.transactions)
+            .with_batch_commits(options.commit_every, retry);
+        if let Some(interval) = options.commit_interval {
+            processor = processor.with_commit_interval(interval);
+        }
+        if options.propose_path.is_some() {
+            processor = processor.with_deferred_commit();
+        }
+    }
+
+    if let Some(dir) = &options.audit_dir {
+        let run_id = options.run_id.clone().unwrap_or_else(|| audit::new_run_id(clock.as_ref()));
+        let audit_trail = AuditTrail::create(dir, &run_id)?;
+        // stdout carries the account CSV, so the id needed for `undo` goes to stderr
+        eprintln!("Run id: {}", audit_trail.run_id());
+        processor = processor.with_audit_trail(audit_trail);
+    }
+
+    if let Some(path) = &options.dispute_rules_path {
+        processor = processor.with_dispute_rules(RulesConfig::load(path)?);
+    }
+
+    if let Some(path) = &options.reason_codes_path {
+        processor = processor.with_reason_codes(ReasonCodes::load(path)?);
+    }
+
+    if let Some(path) = &options.dead_letter_path {
+        processor = processor.with_dead_letter_queue(DeadLetterQueue::open(path)?);
+    }
+
+    // Chaos runs deliver rows twice, which the filter is there to catch
+    match (options.dedup_window, options.dedup_bloom) {
+        (Some(DedupWindow::Keys(keys)), true) => processor = processor.with_dedup_filter(DedupFilter::bloom(keys)),
+        (Some(window), _) => processor = processor.with_dedup_filter(DedupFilter::new(Some(window))),
+        (None, _) if chaos.is_some() => processor = processor.with_dedup_filter(DedupFilter::new(None)),
+        (None, _) => {}
+    }
+
+    if let Some(shard) = options.shard {
+        processor = processor.with_shard(shard);
+    }
+
+    if let Some(threshold) = options.slow_threshold {
+        processor = processor.with_slow_threshold(threshold);
+    }
+
+    if let Some(rows_per_sec) = options.max_tps {
+        processor = processor.with_max_tps(rows_per_sec);
+    }
+
+    if let Some(dir) = &options.dump_dir {
+        signals::install_dump_handler();
+        processor = processor.with_state_dumps(dir);
+    }
+
+    if let Some(endpoint) = &options.otlp_endpoint {
+        processor = processor.with_tracer(Tracer::new(endpoint)?);
+    }
+
+    if options.anomalies_path.is_some() {
+        processor = processor.with_anomaly_detector(AnomalyDetector::new());
+    }
+
+    // Funds already held in the store count towards the peak held
+    if options.exposure_path.is_some() {
+        let tracker = ExposureTracker::new(&processor.accounts()?);
+        processor = processor.with_exposure_tracker(tracker);
+    }
+
+    if let Some(path) = &options.cohorts_path {
+        processor = processor.with_cohorts(Cohorts::load(path)?);
+    }
+
+    if options.dispute_stats_path.is_some() {
+        processor = processor.with_dispute_stats(DisputeStats::new());
+    }
+    if options.dispute_chains_path.is_some() {
+        processor = processor.with_dispute_chains(DisputeChains::new());
+    }
+
+    if let Some(path) = &options.balance_history_path {
+        let clients = options.history_clients.iter().copied().collect();
+        processor = processor.with_balance_history(BalanceHistory::create(path, options.sample_every, clients)?);
+    }
+
+    if let Some(path) = &options.screening_denylist_path {
+        processor = processor.with_screening(Box::new(Denylist::load(path)?));
+    }
+
+    if let Some(url) = &options.screening_url {
+        processor = processor.with_screening(Box::new(HttpScreening::new(url)?));
+    }
+
+    for path in &options.plugin_paths {
+        processor = processor.with_plugin(Plugin::load(path)?);
+    }
+
+    if let Some(path) = &options.script_path {
+        processor = processor.with_script(Script::load(path)?);
+    }
+
+    if let Some(path) = &options.review_queue_path {
+        processor = processor.with_review_queue(ReviewQueue::open(path)?.with_limit(options.review_limit));
+    }
+
+    if options.ack_path.is_some() {
+        let template = match &options.ack_format_path {
+            Some(path) => AckTemplate::load(path)?,
+            None => AckTemplate::default(),
+        };
+        processor = processor.with_ack_log(AckLog::new(template));
+    }
+
+    if let Some(path) = &options.aml_thresholds_path {
+        processor = processor.with_aml_monitor(AmlMonitor::new(AmlConfig::load(path)?));
+    }
+
+    if options.risk_scoring {
+        processor = processor.with_risk_engine(RiskEngine::new(options.risk_lock_threshold));
+    }
+
+    Ok(processor)
 }
\ No newline at end of file
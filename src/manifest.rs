@@ -0,0 +1,114 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::model::error::ProcessorError;
+
+/// Path recorded for the account report written to stdout
+pub const STDOUT: &str = "-";
+
+/// A file read or written by a run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileDigest {
+    pub path: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+impl FileDigest {
+    pub fn of(path: &str) -> Result<Self, ProcessorError> {
+        let mut writer = DigestWriter::new(io::sink());
+        io::copy(&mut File::open(path)?, &mut writer)?;
+        Ok(writer.digest(path))
+    }
+}
+
+/// Passes everything written through to `inner` and hashes it on the way
+pub struct DigestWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub fn new(inner: W) -> Self {
+        DigestWriter { inner, hasher: Sha256::new(), bytes: 0 }
+    }
+
+    /// Digest of what was written so far, recorded as `path`
+    pub fn digest(&self, path: &str) -> FileDigest {
+        FileDigest {
+            path: path.to_string(),
+            sha256: self.hasher.clone().finalize().iter().map(|byte| format!("{:02x}", byte)).collect(),
+            bytes: self.bytes,
+        }
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// What a run read, how it was configured and what it wrote, saved with `--manifest` so
+/// an account report can be traced back to exactly what produced it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub engine: String,
+    pub version: String,
+    /// Set when the run has an audit trail
+    pub run_id: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// The command line options of the run, input file first
+    pub arguments: Vec<String>,
+    /// The transactions file and the configuration files the run loaded
+    pub inputs: Vec<FileDigest>,
+    /// The account report, as `-`, and the other files the run wrote
+    pub outputs: Vec<FileDigest>,
+}
+
+impl RunManifest {
+    pub fn new(arguments: &[String], started_at: DateTime<Utc>) -> Self {
+        RunManifest {
+            engine: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            run_id: None,
+            started_at,
+            finished_at: started_at,
+            arguments: arguments.to_vec(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn add_input(&mut self, path: &str) -> Result<(), ProcessorError> {
+        self.inputs.push(FileDigest::of(path)?);
+        Ok(())
+    }
+
+    pub fn add_output(&mut self, path: &str) -> Result<(), ProcessorError> {
+        self.outputs.push(FileDigest::of(path)?);
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ProcessorError> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
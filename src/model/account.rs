@@ -1,15 +1,205 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use chrono::{DateTime, NaiveDate, Utc};
 use rust_decimal::Decimal;
-use serde::Serialize;
-use parking_lot::Mutex;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+
+use crate::model::transaction::{TransactionType, TxId};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Account {
     pub client_id: u16,
     pub available: Decimal,
     pub held: Decimal,
     pub locked: bool,
-    pub ordering_lock: Arc<Mutex<()>>,
+    /// Set by a `freeze` row, e.g. for sanctions screening. Unlike `locked` it is lifted
+    /// again by `unfreeze`, and only blocks the rows of the `FreezePolicy`
+    #[serde(default)]
+    pub frozen: bool,
+    /// Disputed funds that could not be held because they were already gone
+    #[serde(default)]
+    pub shortfall: Decimal,
+    /// Set when a dispute could not be fully covered, for manual review
+    #[serde(default)]
+    pub needs_review: bool,
+    /// Rows processed for the client, applied or rejected
+    #[serde(default)]
+    pub tx_count: u64,
+    /// Disputes opened on the client's deposits
+    #[serde(default)]
+    pub disputes: u64,
+    /// Latest timestamp among the client's rows
+    #[serde(default)]
+    pub last_activity: Option<DateTime<Utc>>,
+    /// Funds held per dispute or reserve, by transaction id. Adds up to `held` unless the
+    /// account was stored before the ledger was kept, see `unattributed_held`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub holds: BTreeMap<TxId, Decimal>,
+    /// Set when the account was merged into another client's account and closed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merged_into: Option<u16>,
+}
+
+/// Columns of the account report, selected with `--output-schema`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputSchema {
+    /// client, available, held, total, locked
+    #[default]
+    V1,
+    /// V1 plus tx_count, disputes, last_activity and frozen
+    V2,
+}
+
+impl OutputSchema {
+    pub fn from_name(name: &str) -> Option<OutputSchema> {
+        match name {
+            "v1" => Some(OutputSchema::V1),
+            "v2" => Some(OutputSchema::V2),
+            _ => None,
+        }
+    }
+}
+
+/// How amounts are written to the CSV and JSON outputs, selected with `--amount-format`
+/// and handed to the writers of the outputs. Every amount is rounded to the four decimal
+/// places the engine works with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AmountFormat {
+    /// Always four decimal places, e.g. `0.0000` and `1.5000`
+    Fixed4,
+    /// No trailing zeros, e.g. `0` and `1.5`
+    Minimal,
+    /// The scale left by the arithmetic, e.g. `2.00` after deposits of `1.25` and `0.75`
+    #[default]
+    Raw,
+}
+
+impl AmountFormat {
+    pub fn from_name(name: &str) -> Option<AmountFormat> {
+        match name {
+            "fixed4" => Some(AmountFormat::Fixed4),
+            "minimal" => Some(AmountFormat::Minimal),
+            "raw" => Some(AmountFormat::Raw),
+            _ => None,
+        }
+    }
+
+    /// The amount rounded and scaled so that it displays, and serializes, in this format
+    pub fn apply(&self, value: Decimal) -> Decimal {
+        let mut rounded = value.round_dp(4);
+        match self {
+            AmountFormat::Fixed4 => rounded.rescale(4),
+            AmountFormat::Minimal => rounded = rounded.normalize(),
+            AmountFormat::Raw => {}
+        }
+        rounded
+    }
+
+    pub fn format(&self, value: Decimal) -> String {
+        self.apply(value).to_string()
+    }
+
+    /// Appends the formatted amount to `out`, for writers that reuse one buffer
+    pub fn write_to(&self, value: Decimal, out: &mut String) {
+        // Writing to a String cannot fail
+        let _ = write!(out, "{}", self.apply(value));
+    }
+}
+
+/// An output row whose amounts its writer brings into the `--amount-format` before
+/// serializing it. serde has no way to hand a setting to a field serializer, so the amounts
+/// are scaled to display in the format instead.
+pub trait FormatAmounts {
+    fn format_amounts(&mut self, format: AmountFormat);
+}
+
+impl FormatAmounts for AccountOutput {
+    fn format_amounts(&mut self, format: AmountFormat) {
+        for amount in [&mut self.available, &mut self.held, &mut self.total] {
+            *amount = format.apply(*amount);
+        }
+        self.shortfall = self.shortfall.map(|shortfall| format.apply(shortfall));
+    }
+}
+
+impl FormatAmounts for AccountOutputV2 {
+    fn format_amounts(&mut self, format: AmountFormat) {
+        for amount in [&mut self.available, &mut self.held, &mut self.total] {
+            *amount = format.apply(*amount);
+        }
+        self.shortfall = self.shortfall.map(|shortfall| format.apply(shortfall));
+    }
+}
+
+impl FormatAmounts for AccountRow {
+    fn format_amounts(&mut self, format: AmountFormat) {
+        match self {
+            AccountRow::V1(output) => output.format_amounts(format),
+            AccountRow::V2(output) => output.format_amounts(format),
+        }
+    }
+}
+
+impl FormatAmounts for DailyAccountOutput {
+    fn format_amounts(&mut self, format: AmountFormat) {
+        for amount in [&mut self.available, &mut self.held, &mut self.total] {
+            *amount = format.apply(*amount);
+        }
+    }
+}
+
+/// What a dispute does when the client no longer has the disputed funds available,
+/// selected with `--dispute-shortfall`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ShortfallPolicy {
+    /// Reject the dispute
+    #[default]
+    Reject,
+    /// Hold the full amount, letting available funds go negative
+    Negative,
+    /// Hold what is available and record the rest as the client's shortfall
+    Partial,
+}
+
+impl ShortfallPolicy {
+    pub fn from_name(name: &str) -> Option<ShortfallPolicy> {
+        match name {
+            "reject" => Some(ShortfallPolicy::Reject),
+            "negative" => Some(ShortfallPolicy::Negative),
+            "partial" => Some(ShortfallPolicy::Partial),
+            _ => None,
+        }
+    }
+}
+
+/// Rows a frozen account rejects, selected with `--freeze-policy`. Disputes, resolves and
+/// chargebacks come from the card networks and adjustments from admins, so they always apply.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FreezePolicy {
+    /// Withdrawals, reserves and captures, so no funds leave the account
+    #[default]
+    Outflows,
+    /// Every row the client initiates: deposits, withdrawals, reserves, captures and cancels
+    All,
+}
+
+impl FreezePolicy {
+    pub fn from_name(name: &str) -> Option<FreezePolicy> {
+        match name {
+            "outflows" => Some(FreezePolicy::Outflows),
+            "all" => Some(FreezePolicy::All),
+            _ => None,
+        }
+    }
+
+    pub fn blocks(&self, transaction_type: &TransactionType) -> bool {
+        match transaction_type {
+            TransactionType::Withdrawal | TransactionType::Reserve | TransactionType::Capture => true,
+            TransactionType::Deposit | TransactionType::Cancel => *self == FreezePolicy::All,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -22,16 +212,81 @@ pub struct AccountOutput {
     #[serde(serialize_with = "serialize_decimal")]
     pub total: Decimal,
     pub locked: bool,
+    /// Only present when risk scoring is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk_score: Option<u32>,
+    /// Only present with a `--dispute-shortfall` policy other than reject
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_optional_decimal")]
+    pub shortfall: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub needs_review: Option<bool>,
 }
 
-fn serialize_decimal<S>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+/// Account row of the `--output-schema v2` report. Columns are only ever appended,
+/// so consumers of V1 can read it by position.
+#[derive(Debug, Serialize, Clone)]
+pub struct AccountOutputV2 {
+    pub client: u16,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub available: Decimal,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub held: Decimal,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub total: Decimal,
+    pub locked: bool,
+    pub tx_count: u64,
+    pub disputes: u64,
+    pub last_activity: Option<DateTime<Utc>>,
+    pub frozen: bool,
+    /// Only present when risk scoring is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk_score: Option<u32>,
+    /// Only present with a `--dispute-shortfall` policy other than reject
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_optional_decimal")]
+    pub shortfall: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub needs_review: Option<bool>,
+}
+
+/// Account row of the report in the selected `--output-schema`
+#[derive(Debug, Serialize, Clone)]
+#[serde(untagged)]
+pub enum AccountRow {
+    V1(AccountOutput),
+    V2(AccountOutputV2),
+}
+
+/// Account row of a `--cut-by day` report, the closing balances of one day
+#[derive(Debug, Serialize, Clone)]
+pub struct DailyAccountOutput {
+    pub date: NaiveDate,
+    pub client: u16,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub available: Decimal,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub held: Decimal,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+pub(crate) fn serialize_decimal<S>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    let rounded = value.round_dp(4);
-    serializer.serialize_str(&rounded.to_string())
+    // Rows brought into another format are already rounded and keep their scale
+    serializer.serialize_str(&AmountFormat::Raw.format(*value))
 }
 
+pub(crate) fn serialize_optional_decimal<S>(value: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match value {
+        Some(value) => serialize_decimal(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
 
 impl Account {
 
@@ -41,7 +296,14 @@ impl Account {
             available: Decimal::ZERO,
             held: Decimal::ZERO,
             locked: false,
-            ordering_lock: Arc::new(Mutex::new(())),
+            frozen: false,
+            shortfall: Decimal::ZERO,
+            needs_review: false,
+            tx_count: 0,
+            disputes: 0,
+            last_activity: None,
+            holds: BTreeMap::new(),
+            merged_into: None,
         }
     }
 
@@ -70,7 +332,7 @@ impl Account {
     }
 
     /// Returns true if successful, false if insufficient available funds
-    pub fn hold_funds(&mut self, amount: Decimal) -> bool {
+    fn hold_funds(&mut self, amount: Decimal) -> bool {
         if self.available < amount {
             return false;
         }
@@ -80,28 +342,146 @@ impl Account {
         true
     }
 
+    /// Holds the disputed funds of `tx`, falling back to `policy` when not enough are available.
+    /// Returns the part that could not be held, or None if the dispute is rejected
+    pub fn hold_disputed(&mut self, tx: &TxId, amount: Decimal, policy: ShortfallPolicy) -> Option<Decimal> {
+        let shortfall = if self.hold_funds(amount) {
+            Decimal::ZERO
+        } else {
+            match policy {
+                ShortfallPolicy::Reject => return None,
+                ShortfallPolicy::Negative => {
+                    self.available -= amount;
+                    self.held += amount;
+                    self.needs_review = true;
+                    Decimal::ZERO
+                }
+                ShortfallPolicy::Partial => {
+                    let held = self.available.max(Decimal::ZERO);
+                    self.available -= held;
+                    self.held += held;
+                    self.shortfall += amount - held;
+                    self.needs_review = true;
+                    amount - held
+                }
+            }
+        };
+
+        self.holds.insert(tx.clone(), amount - shortfall);
+        Some(shortfall)
+    }
+
+    /// Amount held for `tx`. Holds placed before the account kept its ledger are not in it,
+    /// for those `amount` is taken as recorded with the transaction
+    fn held_for(&self, tx: &TxId, amount: Decimal) -> Decimal {
+        self.holds.get(tx).copied().unwrap_or(amount)
+    }
+
+    /// Releases the hold of `tx` back to available funds.
     /// Returns true if successful, false if insufficient held funds
-    pub fn release_funds(&mut self, amount: Decimal) -> bool {
+    pub fn release_hold(&mut self, tx: &TxId, amount: Decimal) -> bool {
+        let amount = self.held_for(tx, amount);
         if self.held < amount {
             return false;
         }
 
         self.held -= amount;
         self.available += amount;
+        self.holds.remove(tx);
+        true
+    }
+
+    /// Moves funds from available to held for a withdrawal authorization `tx`.
+    /// Returns true if successful, false if insufficient funds or account locked
+    pub fn reserve(&mut self, tx: &TxId, amount: Decimal) -> bool {
+        if self.locked || !self.hold_funds(amount) {
+            return false;
+        }
+
+        self.holds.insert(tx.clone(), amount);
+        true
+    }
+
+    /// Removes the reserved funds of `tx` from the account.
+    /// Returns true if successful, false if insufficient held funds
+    pub fn capture(&mut self, tx: &TxId, amount: Decimal) -> bool {
+        let amount = self.held_for(tx, amount);
+        if self.held < amount {
+            return false;
+        }
+
+        self.held -= amount;
+        self.holds.remove(tx);
         true
     }
 
+    /// Removes the disputed funds of `tx` and locks the account.
     /// Returns true if successful, false if insufficient held funds
-    pub fn chargeback(&mut self, amount: Decimal) -> bool {
+    pub fn chargeback(&mut self, tx: &TxId, amount: Decimal) -> bool {
+        let amount = self.held_for(tx, amount);
         if self.held < amount {
             return false;
         }
 
         self.held -= amount;
+        self.holds.remove(tx);
         self.locked = true;
         true
     }
 
+    /// Held funds no entry of the ledger accounts for, zero for an account whose every hold
+    /// went through it. Negative if the ledger claims more than is held.
+    pub fn unattributed_held(&self) -> Decimal {
+        self.held - self.holds.values().sum::<Decimal>()
+    }
+
+    /// Returns true if successful, false if the account is already frozen
+    pub fn freeze(&mut self) -> bool {
+        !std::mem::replace(&mut self.frozen, true)
+    }
+
+    /// Returns true if successful, false if the account is not frozen
+    pub fn unfreeze(&mut self) -> bool {
+        std::mem::replace(&mut self.frozen, false)
+    }
+
+    /// Returns true if successful, false if the account is not locked. Lifts a chargeback
+    /// or risk lock, a freeze stays
+    pub fn unlock(&mut self) -> bool {
+        std::mem::replace(&mut self.locked, false)
+    }
+
+    /// Takes over the funds of `source`, with its holds and any shortfall it owes
+    pub fn absorb(&mut self, source: &Account) {
+        self.available += source.available;
+        self.held += source.held;
+        self.holds.extend(source.holds.iter().map(|(tx, amount)| (tx.clone(), *amount)));
+        self.shortfall += source.shortfall;
+        self.needs_review |= source.needs_review;
+    }
+
+    /// Empties the account after its funds moved to `target`. Its counters stay as a record
+    /// of the client's own rows.
+    pub fn close_into(&mut self, target: u16) {
+        self.available = Decimal::ZERO;
+        self.held = Decimal::ZERO;
+        self.holds.clear();
+        self.shortfall = Decimal::ZERO;
+        self.needs_review = false;
+        self.merged_into = Some(target);
+    }
+
+    /// Applies a signed admin correction, ignoring the lock.
+    /// Returns true if successful, false if it would make available funds negative
+    pub fn adjust(&mut self, amount: Decimal) -> bool {
+        if self.available + amount < Decimal::ZERO {
+            return false;
+        }
+
+        self.available += amount;
+        true
+    }
+
     pub fn to_output(&self) -> AccountOutput {
         AccountOutput {
             client: self.client_id,
@@ -109,6 +489,45 @@ impl Account {
             held: self.held,
             total: self.total(),
             locked: self.locked,
+            risk_score: None,
+            shortfall: None,
+            needs_review: None,
+        }
+    }
+
+    /// Counts a processed row of the client, applied or not
+    pub fn record_activity(&mut self, timestamp: Option<DateTime<Utc>>) {
+        self.tx_count += 1;
+        if timestamp > self.last_activity {
+            self.last_activity = timestamp;
+        }
+    }
+
+    pub fn to_output_v2(&self) -> AccountOutputV2 {
+        AccountOutputV2 {
+            client: self.client_id,
+            available: self.available,
+            held: self.held,
+            total: self.total(),
+            locked: self.locked,
+            tx_count: self.tx_count,
+            disputes: self.disputes,
+            last_activity: self.last_activity,
+            frozen: self.frozen,
+            risk_score: None,
+            shortfall: None,
+            needs_review: None,
+        }
+    }
+
+    pub fn to_daily_output(&self, date: NaiveDate) -> DailyAccountOutput {
+        DailyAccountOutput {
+            date,
+            client: self.client_id,
+            available: self.available,
+            held: self.held,
+            total: self.total(),
+            locked: self.locked,
         }
     }
 }
\ No newline at end of file
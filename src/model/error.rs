@@ -5,6 +5,24 @@ pub enum ProcessorError {
     InvalidArguments(String),
     IoError(std::io::Error),
     CsvError(csv::Error),
+    InvalidTransactionId(String),
+    /// A `type` that is neither built in nor has a custom handler registered
+    UnknownTransactionType(String),
+    JsonError(serde_json::Error),
+    StoreError(String),
+    /// A store kept failing through every retry, or its circuit breaker is open
+    StoreUnavailable(String),
+    /// Another writer changed what a batch read before the batch was committed
+    StoreConflict(String),
+    YamlError(serde_yaml::Error),
+    ScenarioFailed(String),
+    Unhealthy(String),
+    /// The input was refused by `--preflight`
+    Quarantined(String),
+    /// The input does not match its checksum or manifest
+    Integrity(String),
+    /// A job of the `worker` queue failed or stops the queue
+    JobFailed(String),
 }
 
 impl fmt::Display for ProcessorError {
@@ -13,6 +31,18 @@ impl fmt::Display for ProcessorError {
             ProcessorError::InvalidArguments(msg) => write!(f, "Invalid arguments: {}", msg),
             ProcessorError::IoError(err) => write!(f, "I/O error: {}", err),
             ProcessorError::CsvError(err) => write!(f, "CSV error: {}", err),
+            ProcessorError::InvalidTransactionId(id) => write!(f, "Invalid transaction id: {}", id),
+            ProcessorError::UnknownTransactionType(name) => write!(f, "Unknown transaction type: {}", name),
+            ProcessorError::JsonError(err) => write!(f, "JSON error: {}", err),
+            ProcessorError::StoreError(msg) => write!(f, "Store error: {}", msg),
+            ProcessorError::StoreUnavailable(msg) => write!(f, "Store unavailable: {}", msg),
+            ProcessorError::StoreConflict(msg) => write!(f, "Store conflict: {}", msg),
+            ProcessorError::YamlError(err) => write!(f, "YAML error: {}", err),
+            ProcessorError::ScenarioFailed(msg) => write!(f, "Scenario failed: {}", msg),
+            ProcessorError::Unhealthy(msg) => write!(f, "Unhealthy: {}", msg),
+            ProcessorError::Quarantined(msg) => write!(f, "Quarantined: {}", msg),
+            ProcessorError::Integrity(msg) => write!(f, "Integrity check failed: {}", msg),
+            ProcessorError::JobFailed(msg) => write!(f, "Job failed: {}", msg),
         }
     }
 }
@@ -29,4 +59,15 @@ impl From<csv::Error> for ProcessorError {
     fn from(err: csv::Error) -> Self {
         ProcessorError::CsvError(err)
     }
-}
\ No newline at end of file
+}
+
+impl From<serde_json::Error> for ProcessorError {
+    fn from(err: serde_json::Error) -> Self {
+        ProcessorError::JsonError(err)
+    }
+}
+impl From<serde_yaml::Error> for ProcessorError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ProcessorError::YamlError(err)
+    }
+}
@@ -1,3 +1,4 @@
 pub mod account;
 pub mod transaction;
+pub mod rejection;
 pub mod error;
\ No newline at end of file
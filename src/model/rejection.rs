@@ -0,0 +1,127 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Why a transaction row was not applied, logged as `reason=<name>`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    MissingAmount,
+    NonPositiveAmount,
+    ZeroAmount,
+    MissingEffectiveDate,
+    AccountLocked,
+    AccountFrozen,
+    ScreeningHit,
+    ScreeningUnavailable,
+    PluginRejected,
+    PluginFailed,
+    ScriptRejected,
+    ScriptFailed,
+    AccountNotFound,
+    InsufficientFunds,
+    InsufficientFundsOrLocked,
+    InsufficientAvailableFunds,
+    InsufficientHeldFunds,
+    TransactionNotFound,
+    ClientMismatch,
+    NonDepositTransaction,
+    InvalidState,
+    NotUnderDispute,
+    NotEligible,
+    NotReserved,
+    NotInReview,
+    AdjustmentsDisabled,
+    MergesDisabled,
+    InvalidMergeTarget,
+    AccountMerged,
+    AdminOnly,
+    NotAdminOperation,
+    TypeDisabled,
+    UnknownReasonCode,
+    DuplicateTransaction,
+}
+
+impl RejectionReason {
+    pub const ALL: [RejectionReason; 34] = [
+        RejectionReason::MissingAmount,
+        RejectionReason::NonPositiveAmount,
+        RejectionReason::ZeroAmount,
+        RejectionReason::MissingEffectiveDate,
+        RejectionReason::AccountLocked,
+        RejectionReason::AccountFrozen,
+        RejectionReason::ScreeningHit,
+        RejectionReason::ScreeningUnavailable,
+        RejectionReason::PluginRejected,
+        RejectionReason::PluginFailed,
+        RejectionReason::ScriptRejected,
+        RejectionReason::ScriptFailed,
+        RejectionReason::AccountNotFound,
+        RejectionReason::InsufficientFunds,
+        RejectionReason::InsufficientFundsOrLocked,
+        RejectionReason::InsufficientAvailableFunds,
+        RejectionReason::InsufficientHeldFunds,
+        RejectionReason::TransactionNotFound,
+        RejectionReason::ClientMismatch,
+        RejectionReason::NonDepositTransaction,
+        RejectionReason::InvalidState,
+        RejectionReason::NotUnderDispute,
+        RejectionReason::NotEligible,
+        RejectionReason::NotReserved,
+        RejectionReason::NotInReview,
+        RejectionReason::AdjustmentsDisabled,
+        RejectionReason::MergesDisabled,
+        RejectionReason::InvalidMergeTarget,
+        RejectionReason::AccountMerged,
+        RejectionReason::AdminOnly,
+        RejectionReason::NotAdminOperation,
+        RejectionReason::TypeDisabled,
+        RejectionReason::UnknownReasonCode,
+        RejectionReason::DuplicateTransaction,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectionReason::MissingAmount => "missing_amount",
+            RejectionReason::NonPositiveAmount => "non_positive_amount",
+            RejectionReason::ZeroAmount => "zero_amount",
+            RejectionReason::MissingEffectiveDate => "missing_effective_date",
+            RejectionReason::AccountLocked => "account_locked",
+            RejectionReason::AccountFrozen => "account_frozen",
+            RejectionReason::ScreeningHit => "screening_hit",
+            RejectionReason::ScreeningUnavailable => "screening_unavailable",
+            RejectionReason::PluginRejected => "plugin_rejected",
+            RejectionReason::PluginFailed => "plugin_failed",
+            RejectionReason::ScriptRejected => "script_rejected",
+            RejectionReason::ScriptFailed => "script_failed",
+            RejectionReason::AccountNotFound => "account_not_found",
+            RejectionReason::InsufficientFunds => "insufficient_funds",
+            RejectionReason::InsufficientFundsOrLocked => "insufficient_funds_or_locked",
+            RejectionReason::InsufficientAvailableFunds => "insufficient_available_funds",
+            RejectionReason::InsufficientHeldFunds => "insufficient_held_funds",
+            RejectionReason::TransactionNotFound => "transaction_not_found",
+            RejectionReason::ClientMismatch => "client_mismatch",
+            RejectionReason::NonDepositTransaction => "non_deposit_transaction",
+            RejectionReason::InvalidState => "invalid_state",
+            RejectionReason::NotUnderDispute => "not_under_dispute",
+            RejectionReason::NotEligible => "not_eligible",
+            RejectionReason::NotReserved => "not_reserved",
+            RejectionReason::NotInReview => "not_in_review",
+            RejectionReason::AdjustmentsDisabled => "adjustments_disabled",
+            RejectionReason::MergesDisabled => "merges_disabled",
+            RejectionReason::InvalidMergeTarget => "invalid_merge_target",
+            RejectionReason::AccountMerged => "account_merged",
+            RejectionReason::AdminOnly => "admin_only",
+            RejectionReason::NotAdminOperation => "not_admin_operation",
+            RejectionReason::TypeDisabled => "type_disabled",
+            RejectionReason::UnknownReasonCode => "unknown_reason_code",
+            RejectionReason::DuplicateTransaction => "duplicate_transaction",
+        }
+    }
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
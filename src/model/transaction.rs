@@ -1,7 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use chrono::{DateTime, NaiveDate, Utc};
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::model::account::{serialize_decimal, AmountFormat, FormatAmounts};
+
+/// Transaction identifier. Numeric ids are stored as `u64`, anything else
+/// (e.g. UUIDs) is kept verbatim.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TxId {
+    Numeric(u64),
+    Text(String),
+}
+
+impl TxId {
+    pub fn parse(raw: &str) -> TxId {
+        // Only canonical numbers become numeric so that "007" stays a distinct string id
+        let canonical = raw == "0" || !raw.starts_with('0');
+        match raw.parse::<u64>() {
+            Ok(n) if canonical => TxId::Numeric(n),
+            _ => TxId::Text(raw.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxId::Numeric(n) => write!(f, "{}", n),
+            TxId::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Serialize for TxId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TxId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let raw = String::deserialize(deserializer)?;
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return Err(Error::custom("missing transaction id"));
+        }
+        Ok(TxId::parse(raw))
+    }
+}
+
+/// Accepted shape of transaction ids, selected with `--tx-id-type`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TxIdKind {
+    #[default]
+    U32,
+    U64,
+    String,
+}
+
+impl TxIdKind {
+    pub fn from_name(name: &str) -> Option<TxIdKind> {
+        match name {
+            "u32" => Some(TxIdKind::U32),
+            "u64" => Some(TxIdKind::U64),
+            "string" => Some(TxIdKind::String),
+            _ => None,
+        }
+    }
 
-#[derive(Debug, Deserialize, Clone, PartialEq)]
+    /// Returns the id in canonical form, or None if it is not valid for this kind
+    pub fn normalize(&self, id: TxId) -> Option<TxId> {
+        match (self, id) {
+            (TxIdKind::String, id) => Some(id),
+            (TxIdKind::U32, TxId::Numeric(n)) if n <= u32::MAX as u64 => Some(TxId::Numeric(n)),
+            (TxIdKind::U32, TxId::Text(s)) => s.parse::<u32>().ok().map(|n| TxId::Numeric(n as u64)),
+            (TxIdKind::U64, TxId::Numeric(n)) => Some(TxId::Numeric(n)),
+            (TxIdKind::U64, TxId::Text(s)) => s.parse::<u64>().ok().map(TxId::Numeric),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -9,6 +101,55 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Adjustment,
+    Reserve,
+    Capture,
+    Cancel,
+    Freeze,
+    Unfreeze,
+    Merge,
+    Unlock,
+    Approve,
+    Reject,
+    /// A type the processor does not know, applied by the handler registered for it with
+    /// `TransactionProcessor::with_custom_type`
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl TransactionType {
+    /// Parses the name of one of the built-in types, e.g. `chargeback`
+    pub fn from_name(name: &str) -> Option<TransactionType> {
+        use serde::de::IntoDeserializer;
+
+        let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> = name.into_deserializer();
+        TransactionType::deserialize(deserializer).ok().filter(|transaction_type| !matches!(transaction_type, TransactionType::Custom(_)))
+    }
+
+    /// Whether rows of this type are admin operations, the rows an `--admin-ops` file may hold
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Unlock
+                | TransactionType::Freeze
+                | TransactionType::Unfreeze
+                | TransactionType::Adjustment
+                | TransactionType::Merge
+                | TransactionType::Reserve
+                | TransactionType::Capture
+                | TransactionType::Cancel
+        )
+    }
+}
+
+/// The name of the `type` column, e.g. `chargeback`
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionType::Custom(name) => f.write_str(name),
+            transaction_type => write!(f, "{}", format!("{:?}", transaction_type).to_lowercase()),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -16,9 +157,149 @@ pub struct TransactionInput {
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
     pub client: u16,
-    pub tx: u32,
+    pub tx: TxId,
     #[serde(deserialize_with = "deserialize_optional_amount")]
     pub amount: Option<Decimal>,
+    /// Only used by adjustments, the date the correction applies to
+    #[serde(default)]
+    pub effective_date: Option<NaiveDate>,
+    /// Optional RFC 3339 time the transaction happened at
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Only used by disputes and chargebacks, a card-network reason code such as `10.4`
+    #[serde(default)]
+    pub reason_code: Option<String>,
+    /// Only used by disputes, resolves and chargebacks, free-form JSON such as a CRM case id
+    #[serde(default, deserialize_with = "deserialize_optional_metadata")]
+    pub metadata: Option<serde_json::Value>,
+    /// Source partition of the row, e.g. the Kafka partition of an exported topic
+    #[serde(default)]
+    pub partition: Option<u32>,
+    /// Key a source attaches to every message, the same for every delivery of it
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// Only used by merges, the client the row's client is merged into
+    #[serde(default)]
+    pub target_client: Option<u16>,
+}
+
+/// Decimal places the engine keeps, see `AmountParsing::Strict`
+const AMOUNT_SCALE: u32 = 4;
+/// Characters `AmountParsing::Lenient` strips from amounts, and the digit grouping it takes
+const CURRENCY_SYMBOLS: [char; 7] = ['$', '€', '£', '¥', '₹', '₽', '₩'];
+const GROUPING_SEPARATORS: [char; 4] = [',', '_', '\'', ' '];
+
+/// How the text of an `amount` cell is read, selected with `--amount-parsing`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AmountParsing {
+    /// Plain decimals and scientific notation, e.g. `1234.56` and `1e5`
+    #[default]
+    Standard,
+    /// Also amounts with currency symbols and digit grouping, e.g. `$1,234.56`
+    Lenient,
+    /// Only plain decimals of at most four decimal places
+    Strict,
+}
+
+/// Amount parsing of the process, see `AmountParsing::install`
+static AMOUNT_PARSING: AtomicU8 = AtomicU8::new(AmountParsing::Standard as u8);
+
+impl AmountParsing {
+    pub fn from_name(name: &str) -> Option<AmountParsing> {
+        match name {
+            "standard" => Some(AmountParsing::Standard),
+            "lenient" => Some(AmountParsing::Lenient),
+            "strict" => Some(AmountParsing::Strict),
+            _ => None,
+        }
+    }
+
+    /// Makes this the parsing of every amount deserialized from now on. The rows are
+    /// deserialized by serde, which has no way to hand a setting to a field deserializer.
+    pub fn install(self) {
+        AMOUNT_PARSING.store(self as u8, Ordering::Relaxed);
+    }
+
+    pub fn current() -> AmountParsing {
+        match AMOUNT_PARSING.load(Ordering::Relaxed) {
+            parsing if parsing == AmountParsing::Lenient as u8 => AmountParsing::Lenient,
+            parsing if parsing == AmountParsing::Strict as u8 => AmountParsing::Strict,
+            _ => AmountParsing::Standard,
+        }
+    }
+
+    /// Parses a non-empty amount, or says why it is not one
+    pub fn parse(&self, text: &str) -> Result<Decimal, String> {
+        let invalid = |reason: &str| format!("Invalid amount: {}{}", text, reason);
+        match self {
+            AmountParsing::Standard => parse_number(text).ok_or_else(|| invalid("")),
+            AmountParsing::Lenient => {
+                let unsymbolled: String = text.chars().filter(|c| !CURRENCY_SYMBOLS.contains(c)).collect();
+                let ungrouped = ungroup(unsymbolled.trim())
+                    .ok_or_else(|| invalid(" (grouping separators only go between groups of three digits)"))?;
+                parse_number(&ungrouped).ok_or_else(|| invalid(""))
+            }
+            AmountParsing::Strict => {
+                if text.contains(['e', 'E']) {
+                    return Err(invalid(" (scientific notation is not accepted)"));
+                }
+                let exact = Decimal::from_str(text).map_err(|_| invalid(""))?;
+                if exact.normalize().scale() > AMOUNT_SCALE {
+                    return Err(invalid(&format!(" (more than {} decimal places)", AMOUNT_SCALE)));
+                }
+                parse_number(text).ok_or_else(|| invalid(""))
+            }
+        }
+    }
+}
+
+/// Drops the digit grouping of an amount such as `-1,234,567.5`. Separators are only taken
+/// between groups of exactly three digits of the integer part, all of one kind, so that
+/// `1,5` or `1.234,56` are refused rather than read as `15` or `1.23456`.
+fn ungroup(text: &str) -> Option<String> {
+    let Some(separator) = text.chars().find(|c| GROUPING_SEPARATORS.contains(c)) else {
+        return Some(text.to_string());
+    };
+    let (sign, unsigned) = match text.strip_prefix(['-', '+']) {
+        Some(unsigned) => (&text[..1], unsigned),
+        None => ("", text),
+    };
+    let (integer, fraction) = unsigned.split_once('.').map_or((unsigned, None), |(integer, fraction)| (integer, Some(fraction)));
+    if fraction.is_some_and(|fraction| fraction.contains(GROUPING_SEPARATORS)) {
+        return None;
+    }
+
+    let mut groups = integer.split(separator);
+    let first = groups.next()?;
+    if !(1..=3).contains(&first.len()) || !first.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut digits = first.to_string();
+    for group in groups {
+        if group.len() != 3 || !group.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        digits.push_str(group);
+    }
+    Some(match fraction {
+        Some(fraction) => format!("{}{}.{}", sign, digits, fraction),
+        None => format!("{}{}", sign, digits),
+    })
+}
+
+/// The amount a CSV cell has always been read as: csv hands rust_decimal an integer or
+/// a float when the cell is one, so `100.0` reads as `100`, and text otherwise
+fn parse_number(text: &str) -> Option<Decimal> {
+    if let Ok(integer) = text.parse::<i64>() {
+        return Some(Decimal::from(integer));
+    }
+    if let Ok(integer) = text.parse::<u64>() {
+        return Some(Decimal::from(integer));
+    }
+    match text.parse::<f64>() {
+        Ok(float) => Decimal::from_str(&float.to_string()).ok(),
+        Err(_) => Decimal::from_str(text).ok(),
+    }
 }
 
 fn deserialize_optional_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
@@ -27,49 +308,142 @@ where
 {
     use serde::de::Error;
 
-    #[derive(Deserialize)]
-    #[serde(untagged)]
-    enum AmountField {
-        Value(Decimal),
-        Empty(String),
-    }
-
-    match Option::<AmountField>::deserialize(deserializer)? {
-        Some(AmountField::Value(v)) => Ok(Some(v)),
-        Some(AmountField::Empty(s)) if s.trim().is_empty() => Ok(None),
-        None => Ok(None),
-        Some(AmountField::Empty(s)) => {
-            // Try to parse as decimal
-            s.trim()
-                .parse::<Decimal>()
-                .map(Some)
-                .map_err(|_| Error::custom(format!("Invalid amount: {}", s)))
-        }
+    // The cell as written, so that the installed `AmountParsing` sees it before any number parsing
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.trim().is_empty() => AmountParsing::current().parse(s.trim()).map(Some).map_err(Error::custom),
+        _ => Ok(None),
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Parses the JSON text of a `metadata` cell
+fn deserialize_optional_metadata<'de, D>(deserializer: D) -> Result<Option<serde_json::Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) if !text.trim().is_empty() => serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|err| Error::custom(format!("Invalid metadata: {}", err))),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum TransactionState {
     Normal,
     UnderDispute,
     ChargedBack,
+    /// A reserve whose funds are held until captured or cancelled
+    Reserved,
+    Captured,
+    Cancelled,
+}
+
+/// Row types that move a referenced transaction to another state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LifecycleEvent {
+    Dispute,
+    Resolve,
+    Chargeback,
+    Capture,
+    Cancel,
 }
 
-#[derive(Debug, Clone)]
+impl TransactionState {
+    /// The transaction lifecycle, the one place that knows which event is allowed in which state:
+    ///
+    /// ```text
+    /// Normal -> UnderDispute -> Normal | ChargedBack
+    /// Reserved -> Captured | Cancelled
+    /// ```
+    ///
+    /// Returns the state the event leads to, or None if the event is not allowed.
+    pub fn next(&self, event: LifecycleEvent) -> Option<TransactionState> {
+        match (self, event) {
+            (TransactionState::Normal, LifecycleEvent::Dispute) => Some(TransactionState::UnderDispute),
+            (TransactionState::UnderDispute, LifecycleEvent::Resolve) => Some(TransactionState::Normal),
+            (TransactionState::UnderDispute, LifecycleEvent::Chargeback) => Some(TransactionState::ChargedBack),
+            (TransactionState::Reserved, LifecycleEvent::Capture) => Some(TransactionState::Captured),
+            (TransactionState::Reserved, LifecycleEvent::Cancel) => Some(TransactionState::Cancelled),
+            _ => None,
+        }
+    }
+
+    pub fn try_dispute(&self) -> Option<TransactionState> {
+        self.next(LifecycleEvent::Dispute)
+    }
+
+    pub fn try_resolve(&self) -> Option<TransactionState> {
+        self.next(LifecycleEvent::Resolve)
+    }
+
+    pub fn try_chargeback(&self) -> Option<TransactionState> {
+        self.next(LifecycleEvent::Chargeback)
+    }
+
+    pub fn try_capture(&self) -> Option<TransactionState> {
+        self.next(LifecycleEvent::Capture)
+    }
+
+    pub fn try_cancel(&self) -> Option<TransactionState> {
+        self.next(LifecycleEvent::Cancel)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Transaction {
     pub client_id: u16,
-    pub tx_id: u32,
+    pub tx_id: TxId,
     pub transaction_type: TransactionType,
     pub amount: Decimal,
     pub state: TransactionState,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Part of the amount a partial-hold dispute could not hold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shortfall: Option<Decimal>,
+    /// Reason code of the latest dispute or chargeback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<String>,
+    /// Metadata of the latest dispute-related row that carried any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// Row of the `--open-disputes` report
+#[derive(Debug, Serialize, Clone)]
+pub struct OpenDisputeOutput {
+    pub client: u16,
+    pub tx: TxId,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub amount: Decimal,
+    #[serde(serialize_with = "serialize_decimal")]
+    pub held: Decimal,
+    pub reason_code: Option<String>,
+    /// Compact JSON
+    pub metadata: Option<String>,
+}
+
+impl FormatAmounts for OpenDisputeOutput {
+    fn format_amounts(&mut self, format: AmountFormat) {
+        self.amount = format.apply(self.amount);
+        self.held = format.apply(self.held);
+    }
 }
 
 impl Transaction {
+    /// Amount actually held while the transaction is under dispute
+    pub fn held_amount(&self) -> Decimal {
+        self.amount - self.shortfall.unwrap_or(Decimal::ZERO)
+    }
+
     pub fn new(
-        tx_id: u32,
+        tx_id: TxId,
         client_id: u16,
         transaction_type: TransactionType,
         amount: Decimal,
+        timestamp: Option<DateTime<Utc>>,
     ) -> Self {
         Transaction {
             client_id,
@@ -77,6 +451,21 @@ impl Transaction {
             transaction_type,
             amount,
             state: TransactionState::Normal,
+            timestamp,
+            shortfall: None,
+            reason_code: None,
+            metadata: None,
+        }
+    }
+
+    pub fn to_open_dispute(&self) -> OpenDisputeOutput {
+        OpenDisputeOutput {
+            client: self.client_id,
+            tx: self.tx_id.clone(),
+            amount: self.amount,
+            held: self.held_amount(),
+            reason_code: self.reason_code.clone(),
+            metadata: self.metadata.as_ref().map(|metadata| metadata.to_string()),
         }
     }
 }
\ No newline at end of file
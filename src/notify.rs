@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::input;
+use crate::model::error::ProcessorError;
+use crate::summary::RunSummary;
+
+/// Where the outcome of a run is sent, loaded from the `--notify` JSON file
+///
+/// ```json
+/// {
+///   "slack_webhook": "https://hooks.slack.com/services/T000/B000/XXXX",
+///   "smtp": { "server": "mail.internal:25", "from": "settlement@example.com", "to": ["ops@example.com"] },
+///   "report_url": "https://reports.example.com/settlement/latest",
+///   "on_success": true
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotifyConfig {
+    pub slack_webhook: Option<String>,
+    pub smtp: Option<SmtpConfig>,
+    /// Link to the account report, included in every message
+    pub report_url: Option<String>,
+    /// Failed runs are always notified, finished ones unless this is false
+    #[serde(default = "default_on_success")]
+    pub on_success: bool,
+}
+
+fn default_on_success() -> bool {
+    true
+}
+
+/// A relay accepting mail without authentication, as usual for relays on the internal network
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    /// `host:port`
+    pub server: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl NotifyConfig {
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let config: NotifyConfig = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if config.slack_webhook.is_none() && config.smtp.is_none() {
+            return Err(ProcessorError::InvalidArguments(format!("{}: no slack_webhook or smtp to notify", path)));
+        }
+        if config.smtp.as_ref().is_some_and(|smtp| smtp.to.is_empty()) {
+            return Err(ProcessorError::InvalidArguments(format!("{}: smtp has no recipients", path)));
+        }
+        Ok(config)
+    }
+}
+
+/// How a run ended. The summary is missing when the run failed before processing.
+pub enum RunOutcome<'a> {
+    Finished(Option<&'a RunSummary>),
+    Failed(&'a ProcessorError),
+}
+
+/// Subject and body of the message about the run of `input_file`
+pub fn message(config: &NotifyConfig, input_file: &str, outcome: &RunOutcome) -> (String, String) {
+    let input_file = input::display_name(input_file);
+    let file = Path::new(input_file).file_name().map_or(input_file.to_string(), |name| name.to_string_lossy().into_owned());
+    let mut body = String::new();
+    let subject = match outcome {
+        RunOutcome::Finished(summary) => {
+            if let Some(summary) = summary {
+                body.push_str(&format!("Rows processed: {}\n", summary.rows_processed));
+                body.push_str(&format!("Rows rejected: {}\n", summary.rows_rejected));
+                for (reason, count) in &summary.rejections {
+                    body.push_str(&format!("  {}: {}\n", reason, count));
+                }
+                body.push_str(&format!("Accounts: {} ({} locked)\n", summary.accounts, summary.locked_accounts));
+                body.push_str(&format!("Wall time: {:.1?}\n", Duration::from_secs_f64(summary.resources.wall_time_secs)));
+            }
+            format!("Settlement run for {} finished", file)
+        }
+        RunOutcome::Failed(err) => {
+            body.push_str(&format!("Error: {}\n", err));
+            format!("Settlement run for {} FAILED", file)
+        }
+    };
+    if let Some(url) = &config.report_url {
+        body.push_str(&format!("Report: {}\n", url));
+    }
+    (subject, body)
+}
+
+/// Sends the outcome to every configured channel. Every channel is tried, the first
+/// failure is returned.
+pub fn notify(config: &NotifyConfig, input_file: &str, outcome: &RunOutcome, now: DateTime<Utc>) -> Result<(), ProcessorError> {
+    if matches!(outcome, RunOutcome::Finished(_)) && !config.on_success {
+        return Ok(());
+    }
+    let (subject, body) = message(config, input_file, outcome);
+    let slack = config.slack_webhook.as_ref().map(|url| post_slack(url, &subject, &body));
+    let mail = config.smtp.as_ref().map(|smtp| send_mail(smtp, &subject, &body, now));
+    slack.into_iter().chain(mail).collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn post_slack(url: &str, subject: &str, body: &str) -> Result<(), ProcessorError> {
+    let payload = serde_json::json!({ "text": format!("*{}*\n{}", subject, body) });
+    match ureq::post(url).set("Content-Type", "application/json").send_string(&payload.to_string()) {
+        Ok(_) => Ok(()),
+        // The webhook URL is the credential, so it is not repeated in the error
+        Err(ureq::Error::Status(status, _)) => Err(io::Error::other(format!("Slack webhook answered {}", status)).into()),
+        Err(ureq::Error::Transport(err)) => Err(io::Error::other(format!("Slack webhook unreachable: {}", err.kind())).into()),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn post_slack(_url: &str, _subject: &str, _body: &str) -> Result<(), ProcessorError> {
+    Err(ProcessorError::InvalidArguments("Slack notifications are not available in the browser build".to_string()))
+}
+
+/// Plain SMTP: one message to every recipient, then QUIT
+fn send_mail(smtp: &SmtpConfig, subject: &str, body: &str, now: DateTime<Utc>) -> Result<(), ProcessorError> {
+    let stream = TcpStream::connect(&smtp.server)?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let domain = smtp.from.rsplit('@').next().unwrap_or("localhost");
+    expect_reply(&mut reader, &smtp.server, 220)?;
+    smtp_command(&mut writer, &mut reader, &smtp.server, &format!("HELO {}", domain), 250)?;
+    smtp_command(&mut writer, &mut reader, &smtp.server, &format!("MAIL FROM:<{}>", smtp.from), 250)?;
+    for to in &smtp.to {
+        smtp_command(&mut writer, &mut reader, &smtp.server, &format!("RCPT TO:<{}>", to), 250)?;
+    }
+    smtp_command(&mut writer, &mut reader, &smtp.server, "DATA", 354)?;
+
+    let mut data = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        smtp.from,
+        smtp.to.join(", "),
+        subject,
+        now.to_rfc2822()
+    );
+    for line in body.lines() {
+        // A line starting with a dot would otherwise end the message early
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push_str(".\r\n");
+    writer.write_all(data.as_bytes())?;
+    expect_reply(&mut reader, &smtp.server, 250)?;
+
+    smtp_command(&mut writer, &mut reader, &smtp.server, "QUIT", 221)
+}
+
+fn smtp_command(writer: &mut TcpStream, reader: &mut impl BufRead, server: &str, command: &str, expected: u16) -> Result<(), ProcessorError> {
+    writer.write_all(format!("{}\r\n", command).as_bytes())?;
+    expect_reply(reader, server, expected)
+}
+
+/// Reads a reply, with all its `250-...` continuation lines, and checks its code
+fn expect_reply(reader: &mut impl BufRead, server: &str, expected: u16) -> Result<(), ProcessorError> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::other(format!("SMTP server {} closed the connection", server)).into());
+        }
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        return match line.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+            Some(code) if code == expected => Ok(()),
+            _ => Err(io::Error::other(format!("SMTP server {} answered {}", server, line.trim_end())).into()),
+        };
+    }
+}
@@ -0,0 +1,201 @@
+use std::path::Path;
+
+/// What a plugin decided about a row
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Accept,
+    /// Rejected, with the reason the plugin gave
+    Reject(String),
+    /// The plugin trapped or ran out of fuel, with the error
+    Failed(String),
+}
+
+/// Name of a plugin in rejections, its file name without the extension
+fn plugin_name(path: &str) -> String {
+    Path::new(path).file_stem().map_or_else(|| path.to_string(), |stem| stem.to_string_lossy().into_owned())
+}
+
+#[cfg(feature = "plugins")]
+mod host {
+    use chrono::{DateTime, Utc};
+    use parking_lot::Mutex;
+    use rust_decimal::Decimal;
+    use serde::Serialize;
+    use wasmi::{Config, Engine, Instance, Linker, Memory, Module, Store, TypedFunc};
+
+    use super::{plugin_name, Decision};
+    use crate::model::account::Account;
+    use crate::model::error::ProcessorError;
+    use crate::model::transaction::TransactionInput;
+
+    /// Instructions a plugin may run per row before it is stopped, far more than any rule needs
+    const FUEL_PER_ROW: u64 = 10_000_000;
+
+    /// The JSON a plugin's `validate` gets, the row and the account of its client before it
+    ///
+    /// ```json
+    /// {
+    ///   "transaction": { "type": "withdrawal", "client": 1, "tx": "5", "amount": "50.0", "timestamp": null },
+    ///   "account": { "available": "100", "held": "0", "total": "100", "locked": false, "frozen": false }
+    /// }
+    /// ```
+    ///
+    /// `type` is always the first field, so a plugin may look at it without parsing JSON.
+    #[derive(Debug, Serialize)]
+    struct Input<'a> {
+        transaction: TransactionView<'a>,
+        account: Option<AccountView>,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct TransactionView<'a> {
+        #[serde(rename = "type")]
+        transaction_type: String,
+        client: u16,
+        tx: String,
+        amount: Option<Decimal>,
+        timestamp: Option<DateTime<Utc>>,
+        reason_code: Option<&'a str>,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct AccountView {
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+        locked: bool,
+        frozen: bool,
+    }
+
+    fn input(record: &TransactionInput, account: Option<&Account>) -> Result<Vec<u8>, ProcessorError> {
+        let input = Input {
+            transaction: TransactionView {
+                transaction_type: record.transaction_type.to_string(),
+                client: record.client,
+                tx: record.tx.to_string(),
+                amount: record.amount,
+                timestamp: record.timestamp,
+                reason_code: record.reason_code.as_deref(),
+            },
+            account: account.map(|account| AccountView {
+                available: account.available,
+                held: account.held,
+                total: account.total(),
+                locked: account.locked,
+                frozen: account.frozen,
+            }),
+        };
+        Ok(serde_json::to_vec(&input)?)
+    }
+
+    /// A validation plugin loaded with `--plugin`: a WebAssembly module exporting
+    ///
+    /// - `memory`
+    /// - `alloc(len: i32) -> i32`: where the host may write an input of `len` bytes
+    /// - `validate(ptr: i32, len: i32) -> i64`: decides about the JSON input at `ptr`, see
+    ///   `Input`. 0 accepts the row; anything else rejects it and is the address of the
+    ///   UTF-8 reason in the upper 32 bits and its length in the lower ones.
+    ///
+    /// Modules may not import anything, so a plugin cannot reach files, the network or
+    /// the clock. A call that traps or runs out of fuel decides `Decision::Failed`.
+    pub struct Plugin {
+        name: String,
+        instance: Mutex<Loaded>,
+    }
+
+    struct Loaded {
+        store: Store<()>,
+        memory: Memory,
+        alloc: TypedFunc<i32, i32>,
+        validate: TypedFunc<(i32, i32), i64>,
+    }
+
+    impl Plugin {
+        pub fn load(path: &str) -> Result<Self, ProcessorError> {
+            let invalid = |err: &dyn std::fmt::Display| ProcessorError::InvalidArguments(format!("plugin {}: {}", path, err));
+            let bytes = std::fs::read(path)?;
+
+            let mut config = Config::default();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config);
+            let module = Module::new(&engine, &bytes[..]).map_err(|err| invalid(&err))?;
+            let mut store = Store::new(&engine, ());
+            let instance: Instance = Linker::<()>::new(&engine)
+                .instantiate(&mut store, &module)
+                .and_then(|instance| instance.start(&mut store))
+                .map_err(|err| invalid(&err))?;
+
+            let memory = instance.get_memory(&store, "memory").ok_or_else(|| invalid(&"no exported memory"))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc").map_err(|err| invalid(&err))?;
+            let validate = instance.get_typed_func::<(i32, i32), i64>(&store, "validate").map_err(|err| invalid(&err))?;
+
+            Ok(Plugin {
+                name: plugin_name(path),
+                instance: Mutex::new(Loaded { store, memory, alloc, validate }),
+            })
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub fn validate(&self, record: &TransactionInput, account: Option<&Account>) -> Result<Decision, ProcessorError> {
+            let input = input(record, account)?;
+            let mut loaded = self.instance.lock();
+            Ok(match loaded.call(&input) {
+                Ok(decision) => decision,
+                Err(err) => Decision::Failed(err),
+            })
+        }
+    }
+
+    impl Loaded {
+        fn call(&mut self, input: &[u8]) -> Result<Decision, String> {
+            let Loaded { store, memory, alloc, validate } = self;
+            store.set_fuel(FUEL_PER_ROW).map_err(|err| err.to_string())?;
+
+            let ptr = alloc.call(&mut *store, input.len() as i32).map_err(|err| err.to_string())?;
+            memory.write(&mut *store, ptr as u32 as usize, input).map_err(|err| err.to_string())?;
+            let result = validate.call(&mut *store, (ptr, input.len() as i32)).map_err(|err| err.to_string())?;
+            if result == 0 {
+                return Ok(Decision::Accept);
+            }
+
+            let mut reason = vec![0; (result as u64 & 0xFFFF_FFFF) as usize];
+            memory.read(&*store, (result as u64 >> 32) as usize, &mut reason).map_err(|err| err.to_string())?;
+            Ok(Decision::Reject(String::from_utf8_lossy(&reason).into_owned()))
+        }
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+mod host {
+    use super::{plugin_name, Decision};
+    use crate::model::account::Account;
+    use crate::model::error::ProcessorError;
+    use crate::model::transaction::TransactionInput;
+
+    /// Without the `plugins` feature no plugin can be loaded
+    pub struct Plugin {
+        name: String,
+    }
+
+    impl Plugin {
+        pub fn load(path: &str) -> Result<Self, ProcessorError> {
+            Err(ProcessorError::InvalidArguments(format!(
+                "cannot load plugin {}, built without the plugins feature",
+                plugin_name(path)
+            )))
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub fn validate(&self, _record: &TransactionInput, _account: Option<&Account>) -> Result<Decision, ProcessorError> {
+            Ok(Decision::Accept)
+        }
+    }
+}
+
+pub use host::Plugin;
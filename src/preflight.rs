@@ -0,0 +1,173 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind};
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::FileDigest;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType};
+use crate::processor::open_reader;
+
+/// Runs kept in the baseline, older ones are dropped
+const BASELINE_RUNS: usize = 20;
+
+/// Figures of an input file that `--preflight` compares with the files of earlier runs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileProfile {
+    pub file: String,
+    pub sha256: String,
+    /// Rows of the file, readable or not
+    pub rows: u64,
+    /// Sum of the deposit and withdrawal amounts
+    pub volume: Decimal,
+    pub clients: usize,
+}
+
+impl FileProfile {
+    /// Reads the file once, returning its profile and its clients. Rows that cannot be
+    /// read are counted but not looked into, processing decides what happens to them.
+    pub fn scan(path: &str) -> Result<(Self, BTreeSet<u16>), ProcessorError> {
+        let mut reader = open_reader(path)?;
+        let headers = reader.headers()?.clone();
+        let mut rows = 0;
+        let mut volume = Decimal::ZERO;
+        let mut clients = BTreeSet::new();
+
+        for raw in reader.records() {
+            rows += 1;
+            let Ok(record) = raw.and_then(|raw| raw.deserialize::<TransactionInput>(Some(&headers))) else {
+                continue;
+            };
+            clients.insert(record.client);
+            if let (TransactionType::Deposit | TransactionType::Withdrawal, Some(amount)) = (&record.transaction_type, record.amount) {
+                volume += amount.abs();
+            }
+        }
+
+        let profile = FileProfile {
+            file: path.to_string(),
+            sha256: FileDigest::of(path)?.sha256,
+            rows,
+            volume,
+            clients: clients.len(),
+        };
+        Ok((profile, clients))
+    }
+}
+
+/// Profiles of the files of earlier runs, kept in the `--preflight` JSON file
+///
+/// ```json
+/// {
+///   "tolerance": 2.0,
+///   "runs": [{ "file": "2024-03-01.csv", "sha256": "…", "rows": 1200, "volume": "53120.5", "clients": 310 }],
+///   "clients": [1, 2, 5]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    /// How many times larger or smaller than usual a figure may be
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// Oldest first
+    #[serde(default)]
+    pub runs: Vec<FileProfile>,
+    /// Clients of the file of the last run
+    #[serde(default)]
+    pub clients: Vec<u16>,
+}
+
+fn default_tolerance() -> f64 {
+    2.0
+}
+
+impl Default for Baseline {
+    fn default() -> Self {
+        Baseline { tolerance: default_tolerance(), runs: Vec::new(), clients: Vec::new() }
+    }
+}
+
+impl Baseline {
+    /// A missing file is an empty baseline, the first run starts it
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let baseline: Baseline = match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Baseline::default()),
+            Err(err) => return Err(err.into()),
+        };
+        if baseline.tolerance.is_nan() || baseline.tolerance < 1.0 {
+            return Err(ProcessorError::InvalidArguments(format!("{}: tolerance must be at least 1", path)));
+        }
+        Ok(baseline)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ProcessorError> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Every way the file deviates from the earlier runs, none for a file that looks as usual
+    /// or when there are no earlier runs to compare with
+    pub fn deviations(&self, profile: &FileProfile, clients: &BTreeSet<u16>) -> Vec<String> {
+        let mut deviations = Vec::new();
+        // An identical file is the one mistake a tolerance cannot catch
+        if let Some(run) = self.runs.iter().find(|run| run.sha256 == profile.sha256) {
+            deviations.push(format!("same content as {}, processed before", run.file));
+        }
+        if self.runs.is_empty() {
+            return deviations;
+        }
+
+        let rows = median(self.runs.iter().map(|run| run.rows as f64));
+        if let Some(deviation) = self.deviation("rows", profile.rows as f64, rows) {
+            deviations.push(deviation);
+        }
+        let volume = median(self.runs.iter().filter_map(|run| run.volume.to_f64()));
+        if let Some(deviation) = self.deviation("volume", profile.volume.to_f64().unwrap_or_default(), volume) {
+            deviations.push(deviation);
+        }
+
+        // A file of another provider or environment has mostly unknown clients
+        if !self.clients.is_empty() && !clients.is_empty() {
+            let known = clients.iter().filter(|client| self.clients.binary_search(client).is_ok()).count();
+            let share = known as f64 / clients.len() as f64;
+            if share < 1.0 / self.tolerance {
+                deviations.push(format!("clients: {:.0}% of {} seen in the last run", share * 100.0, clients.len()));
+            }
+        }
+        deviations
+    }
+
+    fn deviation(&self, figure: &str, value: f64, usual: f64) -> Option<String> {
+        if usual <= 0.0 {
+            return None;
+        }
+        let ratio = value / usual;
+        (ratio > self.tolerance || ratio < 1.0 / self.tolerance)
+            .then(|| format!("{}: {} against a median of {} over the last {} run(s)", figure, value, usual, self.runs.len()))
+    }
+
+    /// Adds the file of a finished run
+    pub fn record(&mut self, profile: FileProfile, clients: BTreeSet<u16>) {
+        self.runs.push(profile);
+        let excess = self.runs.len().saturating_sub(BASELINE_RUNS);
+        self.runs.drain(..excess);
+        self.clients = clients.into_iter().collect();
+    }
+}
+
+fn median(values: impl Iterator<Item = f64>) -> f64 {
+    let mut values: Vec<f64> = values.collect();
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[middle - 1] + values[middle]) / 2.0,
+        _ => values[middle],
+    }
+}
@@ -0,0 +1,116 @@
+use rust_decimal::Decimal;
+
+use crate::model::error::ProcessorError;
+
+/// Report columns holding amounts, rendered with grouping and a fixed scale
+const AMOUNT_COLUMNS: [&str; 4] = ["available", "held", "total", "shortfall"];
+/// Decimal places shown for amounts, the precision the engine works with
+const AMOUNT_SCALE: u32 = 4;
+
+/// Separators of human readable amounts, selected with `--locale`.
+/// Machine readable CSV output is never localized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Locale {
+    grouping: char,
+    decimal: char,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale { grouping: ',', decimal: '.' }
+    }
+}
+
+impl Locale {
+    /// Accepts a language tag such as `en`, `de-DE`, `fr_FR` or `de-CH`
+    pub fn from_name(name: &str) -> Option<Locale> {
+        let name = name.replace('_', "-").to_lowercase();
+        let language = name.split('-').next().unwrap_or_default();
+
+        let (grouping, decimal) = match (language, name.as_str()) {
+            (_, "de-ch" | "it-ch" | "fr-ch") => ('\'', '.'),
+            ("en" | "ja" | "zh" | "ko", _) => (',', '.'),
+            ("de" | "nl" | "it" | "es" | "pt" | "da" | "id" | "tr", _) => ('.', ','),
+            // Narrow no-break space, the standard grouping of these locales
+            ("fr" | "pl" | "cs" | "sv" | "fi" | "nb" | "ru" | "uk", _) => ('\u{202f}', ','),
+            _ => return None,
+        };
+        Some(Locale { grouping, decimal })
+    }
+}
+
+/// Formats an amount with thousands separators and a fixed scale, e.g. `-1,234.5000`
+pub fn format_amount(amount: Decimal, locale: Locale) -> String {
+    let rounded = amount.round_dp(AMOUNT_SCALE).abs();
+    let text = format!("{:.*}", AMOUNT_SCALE as usize, rounded);
+    let (integer, fraction) = text.split_once('.').unwrap_or((&text, ""));
+
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(locale.grouping);
+        }
+        grouped.push(digit);
+    }
+
+    let sign = if amount.is_sign_negative() && !rounded.is_zero() { "-" } else { "" };
+    format!("{}{}{}{}", sign, grouped, locale.decimal, fraction)
+}
+
+/// Renders a CSV report as an aligned table for the terminal.
+/// Amount columns are formatted with `format_amount`, numeric columns are right aligned.
+pub fn render_table(csv: &[u8], locale: Locale) -> Result<String, ProcessorError> {
+    let mut reader = csv::ReaderBuilder::new().from_reader(csv);
+    let headers: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let row: Vec<String> = record
+            .iter()
+            .zip(&headers)
+            .map(|(cell, header)| match cell.parse::<Decimal>() {
+                Ok(amount) if AMOUNT_COLUMNS.contains(&header.as_str()) => format_amount(amount, locale),
+                _ => cell.to_string(),
+            })
+            .collect();
+        rows.push(row);
+    }
+
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| rows.iter().map(|row| row[i].chars().count()).fold(header.len(), usize::max))
+        .collect();
+    let right_aligned: Vec<bool> = (0..headers.len())
+        .map(|i| {
+            AMOUNT_COLUMNS.contains(&headers[i].as_str())
+                || (!rows.is_empty() && rows.iter().all(|row| row[i].parse::<i64>().is_ok()))
+        })
+        .collect();
+
+    let format_row = |cells: &[String]| -> String {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| match right_aligned[i] {
+                true => format!(" {:>width$} ", cell, width = widths[i]),
+                false => format!(" {:<width$} ", cell, width = widths[i]),
+            })
+            .collect::<Vec<_>>()
+            .join("|")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut table = format_row(&headers);
+    table.push('\n');
+    table.push_str(&widths.iter().map(|width| "-".repeat(width + 2)).collect::<Vec<_>>().join("+"));
+    table.push('\n');
+    for row in &rows {
+        table.push_str(&format_row(row));
+        table.push('\n');
+    }
+
+    Ok(table)
+}
@@ -1,35 +1,529 @@
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, NaiveDate, Utc};
 use dashmap::DashMap;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
 
+use crate::account_csv::AccountCsvWriter;
+use crate::ack::AckLog;
+use crate::aml::AmlMonitor;
+use crate::analytics::AnomalyDetector;
+use crate::chaos::Chaos;
+use crate::clock::{Clock, SystemClock};
+use crate::cohort::{CohortStats, Cohorts};
+use crate::dead_letter::DeadLetterQueue;
+use crate::dedup::DedupFilter;
+use crate::input;
+use crate::engine::{self, CustomTransaction, EngineConfig, EngineState, Outcome, Rejection};
+use crate::diagnostics::Diagnostics;
+use crate::dispute_chains::DisputeChains;
+use crate::dispute_stats::DisputeStats;
+use crate::exposure::ExposureTracker;
+use crate::history::BalanceHistory;
+use crate::latency::LatencyTracker;
+use crate::locking::{self, ClientGuard, ClientLock};
+use crate::audit::{self, AuditTrail, BatchChanges};
 use crate::logger::Logger;
-use crate::model::account::Account;
+use crate::model::account::{Account, AccountRow, AmountFormat, FormatAmounts, FreezePolicy, OutputSchema, ShortfallPolicy};
 use crate::model::error::ProcessorError;
-use crate::model::transaction::{Transaction, TransactionInput, TransactionState, TransactionType};
+use crate::model::rejection::RejectionReason;
+use crate::model::transaction::{LifecycleEvent, Transaction, TransactionInput, TransactionState, TransactionType, TxId, TxIdKind};
+use crate::plugin::{Decision, Plugin};
+use crate::reason_codes::ReasonCodes;
+use crate::retry::{RetryPolicy, RetryStats};
+use crate::review::{ParkedTransaction, ReviewQueue};
+use crate::screening::{Screening, ScreeningResult};
+use crate::script::Script;
+use crate::risk::{RiskEngine, RiskEvent};
+use crate::scheduler::FairScheduler;
+use crate::rules::RulesConfig;
+use crate::shard::Shard;
+use crate::signals;
+use crate::telemetry::Tracer;
+use crate::throttle::RateLimiter;
+use crate::store::{AccountStore, BatchStore, MemoryAccountStore, MemoryTransactionStore, TransactionStore, Transition};
 
 
 pub struct TransactionProcessor {
-    accounts: DashMap<u16, Account>,
-    transactions: DashMap<u32, Transaction>,
+    accounts: Arc<dyn AccountStore>,
+    ordering_locks: DashMap<u16, Arc<ClientLock>>,
+    transactions: Arc<dyn TransactionStore>,
+    dedup: Option<DedupFilter>,
+    client_partitions: DashMap<u16, u32>,
+    partition_violations: AtomicU64,
     logger: Option<Arc<Logger>>,
+    tx_id_kind: TxIdKind,
+    engine_config: EngineConfig,
+    risk: Option<RiskEngine>,
+    anomaly_detector: Option<AnomalyDetector>,
+    balance_history: Option<BalanceHistory>,
+    exposure_tracker: Option<ExposureTracker>,
+    dispute_stats: Option<DisputeStats>,
+    dispute_chains: Option<DisputeChains>,
+    cohort_stats: Option<CohortStats>,
+    aml_monitor: Option<AmlMonitor>,
+    screening: Option<Box<dyn Screening>>,
+    plugins: Vec<Plugin>,
+    script: Option<Script>,
+    review_queue: Option<ReviewQueue>,
+    ack_log: Option<AckLog>,
+    batch: Option<Arc<BatchStore>>,
+    commit_every: Option<usize>,
+    commit_interval: Option<Duration>,
+    /// When the last batch was committed, or the processor created
+    last_commit: Mutex<Instant>,
+    audit_trail: Option<AuditTrail>,
+    defer_commit: bool,
+    /// Set while the rows of an `--admin-ops` file are processed
+    admin_ops: AtomicBool,
+    rows_processed: AtomicU64,
+    bytes_read: AtomicU64,
+    rejections: DashMap<RejectionReason, u64>,
+    rows_by_type: DashMap<String, u64>,
+    freeze_policy: FreezePolicy,
+    /// Types whose rows are rejected without being applied, see `with_disabled_types`
+    disabled_types: Vec<TransactionType>,
+    output_schema: OutputSchema,
+    /// Format of the amounts in the reports the processor writes
+    amount_format: AmountFormat,
+    diagnostics: Option<Diagnostics>,
+    chaos: Option<Arc<Chaos>>,
+    dead_letter_queue: Option<DeadLetterQueue>,
+    tracer: Option<Tracer>,
+    latency: LatencyTracker,
+    shard: Option<Shard>,
+    rate_limiter: Option<RateLimiter>,
+    /// Directory of the state dumps SIGUSR1 requests
+    state_dumps: Option<String>,
+    clock: Arc<dyn Clock>,
 }
 
 impl TransactionProcessor {
 
     pub fn new() -> Self {
         TransactionProcessor {
-            accounts: DashMap::new(),
-            transactions: DashMap::new(),
+            accounts: Arc::new(MemoryAccountStore::new()),
+            ordering_locks: DashMap::new(),
+            transactions: Arc::new(MemoryTransactionStore::new()),
+            dedup: None,
+            client_partitions: DashMap::new(),
+            partition_violations: AtomicU64::new(0),
             logger: None,
+            tx_id_kind: TxIdKind::default(),
+            engine_config: EngineConfig::default(),
+            risk: None,
+            anomaly_detector: None,
+            balance_history: None,
+            exposure_tracker: None,
+            dispute_stats: None,
+            dispute_chains: None,
+            cohort_stats: None,
+            aml_monitor: None,
+            screening: None,
+            plugins: Vec::new(),
+            script: None,
+            review_queue: None,
+            ack_log: None,
+            batch: None,
+            commit_every: None,
+            commit_interval: None,
+            last_commit: Mutex::new(Instant::now()),
+            audit_trail: None,
+            defer_commit: false,
+            admin_ops: AtomicBool::new(false),
+            rows_processed: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            rejections: DashMap::new(),
+            rows_by_type: DashMap::new(),
+            freeze_policy: FreezePolicy::default(),
+            disabled_types: Vec::new(),
+            output_schema: OutputSchema::default(),
+            amount_format: AmountFormat::default(),
+            diagnostics: None,
+            chaos: None,
+            dead_letter_queue: None,
+            tracer: None,
+            latency: LatencyTracker::new(None),
+            shard: None,
+            rate_limiter: None,
+            state_dumps: None,
+            clock: Arc::new(SystemClock),
         }
     }
 
     pub fn with_logger(logger: Arc<Logger>) -> Self {
         TransactionProcessor {
-            accounts: DashMap::new(),
-            transactions: DashMap::new(),
+            accounts: Arc::new(MemoryAccountStore::new()),
+            ordering_locks: DashMap::new(),
+            transactions: Arc::new(MemoryTransactionStore::new()),
+            dedup: None,
+            client_partitions: DashMap::new(),
+            partition_violations: AtomicU64::new(0),
             logger: Some(logger),
+            tx_id_kind: TxIdKind::default(),
+            engine_config: EngineConfig::default(),
+            risk: None,
+            anomaly_detector: None,
+            balance_history: None,
+            exposure_tracker: None,
+            dispute_stats: None,
+            dispute_chains: None,
+            cohort_stats: None,
+            aml_monitor: None,
+            screening: None,
+            plugins: Vec::new(),
+            script: None,
+            review_queue: None,
+            ack_log: None,
+            batch: None,
+            commit_every: None,
+            commit_interval: None,
+            last_commit: Mutex::new(Instant::now()),
+            audit_trail: None,
+            defer_commit: false,
+            admin_ops: AtomicBool::new(false),
+            rows_processed: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            rejections: DashMap::new(),
+            rows_by_type: DashMap::new(),
+            freeze_policy: FreezePolicy::default(),
+            disabled_types: Vec::new(),
+            output_schema: OutputSchema::default(),
+            amount_format: AmountFormat::default(),
+            diagnostics: None,
+            chaos: None,
+            dead_letter_queue: None,
+            tracer: None,
+            latency: LatencyTracker::new(None),
+            shard: None,
+            rate_limiter: None,
+            state_dumps: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_account_store(mut self, accounts: Box<dyn AccountStore>) -> Self {
+        self.accounts = Arc::from(accounts);
+        self
+    }
+
+    pub fn with_transaction_store(mut self, transactions: Box<dyn TransactionStore>) -> Self {
+        self.transactions = Arc::from(transactions);
+        self
+    }
+
+    /// Stages all mutations and commits them to the stores every `commit_every` rows,
+    /// or once per file if None. A failing file rolls back its uncommitted rows.
+    /// Store operations are retried under `retry`. Must be called after the stores are set.
+    pub fn with_batch_commits(mut self, commit_every: Option<usize>, retry: RetryPolicy) -> Self {
+        let batch = Arc::new(BatchStore::new(self.accounts.clone(), self.transactions.clone()).with_retry_policy(retry));
+        self.accounts = batch.clone();
+        self.transactions = batch.clone();
+        self.batch = Some(batch);
+        self.commit_every = commit_every;
+        self
+    }
+
+    /// Also commits once `interval` has passed since the last commit, so that a slow input
+    /// does not keep rows uncommitted for long. Needs batch commits.
+    pub fn with_commit_interval(mut self, interval: Duration) -> Self {
+        self.commit_interval = Some(interval);
+        self
+    }
+
+    /// Records every committed batch so the run can be undone later. Needs batch commits.
+    pub fn with_audit_trail(mut self, audit_trail: AuditTrail) -> Self {
+        self.audit_trail = Some(audit_trail);
+        self
+    }
+
+    /// Leaves the processed file staged instead of committing it, see `pending_changes`.
+    /// Needs batch commits without `commit_every`.
+    pub fn with_deferred_commit(mut self) -> Self {
+        self.defer_commit = true;
+        self
+    }
+
+    /// Changes staged but not committed to the stores
+    pub fn pending_changes(&self) -> Result<BatchChanges, ProcessorError> {
+        match self.batch {
+            Some(ref batch) => batch.changes(),
+            None => Ok(BatchChanges::default()),
+        }
+    }
+
+    pub fn with_tx_id_kind(mut self, tx_id_kind: TxIdKind) -> Self {
+        self.tx_id_kind = tx_id_kind;
+        self
+    }
+
+    pub fn with_adjustments(mut self, allow_adjustments: bool) -> Self {
+        self.engine_config.allow_adjustments = allow_adjustments;
+        self
+    }
+
+    pub fn with_merges(mut self, allow_merges: bool) -> Self {
+        self.engine_config.allow_merges = allow_merges;
+        self
+    }
+
+    pub fn with_shortfall_policy(mut self, shortfall_policy: ShortfallPolicy) -> Self {
+        self.engine_config.shortfall_policy = shortfall_policy;
+        self
+    }
+
+    pub fn with_freeze_policy(mut self, freeze_policy: FreezePolicy) -> Self {
+        self.freeze_policy = freeze_policy;
+        self
+    }
+
+    /// Applies the rows whose `type` is `name` with `handler`. Without a handler, a row of
+    /// an unknown type cannot be read, like a row with an invalid amount.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is a built-in type
+    pub fn with_custom_type(mut self, name: &str, handler: impl CustomTransaction + 'static) -> Self {
+        self.engine_config.custom_types.register(name, Arc::new(handler));
+        self
+    }
+
+    /// Rejects every row of these types with `type_disabled`, e.g. disputes, resolves and
+    /// chargebacks for a balance build that ignores the dispute machinery
+    pub fn with_disabled_types(mut self, disabled_types: Vec<TransactionType>) -> Self {
+        self.disabled_types = disabled_types;
+        self
+    }
+
+    pub fn disabled_types(&self) -> &[TransactionType] {
+        &self.disabled_types
+    }
+
+    pub fn with_output_schema(mut self, output_schema: OutputSchema) -> Self {
+        self.output_schema = output_schema;
+        self
+    }
+
+    pub fn with_amount_format(mut self, amount_format: AmountFormat) -> Self {
+        self.amount_format = amount_format;
+        self
+    }
+
+    pub fn amount_format(&self) -> AmountFormat {
+        self.amount_format
+    }
+
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    pub fn with_dispute_rules(mut self, dispute_rules: RulesConfig) -> Self {
+        self.engine_config.dispute_rules = Some(dispute_rules);
+        self
+    }
+
+    pub fn with_reason_codes(mut self, reason_codes: ReasonCodes) -> Self {
+        self.engine_config.reason_codes = reason_codes;
+        self
+    }
+
+    pub fn with_risk_engine(mut self, risk: RiskEngine) -> Self {
+        self.risk = Some(risk);
+        self
+    }
+
+    pub fn with_balance_history(mut self, balance_history: BalanceHistory) -> Self {
+        self.balance_history = Some(balance_history);
+        self
+    }
+
+    pub fn with_exposure_tracker(mut self, exposure_tracker: ExposureTracker) -> Self {
+        self.exposure_tracker = Some(exposure_tracker);
+        self
+    }
+
+    pub fn with_dispute_stats(mut self, dispute_stats: DisputeStats) -> Self {
+        self.dispute_stats = Some(dispute_stats);
+        self
+    }
+
+    pub fn with_dispute_chains(mut self, dispute_chains: DisputeChains) -> Self {
+        self.dispute_chains = Some(dispute_chains);
+        self
+    }
+
+    /// Breaks the summary down by the cohorts of the clients
+    pub fn with_cohorts(mut self, cohorts: Cohorts) -> Self {
+        self.cohort_stats = Some(CohortStats::new(cohorts));
+        self
+    }
+
+    pub fn with_anomaly_detector(mut self, anomaly_detector: AnomalyDetector) -> Self {
+        self.anomaly_detector = Some(anomaly_detector);
+        self
+    }
+
+    pub fn with_aml_monitor(mut self, aml_monitor: AmlMonitor) -> Self {
+        self.aml_monitor = Some(aml_monitor);
+        self
+    }
+
+    pub fn with_screening(mut self, screening: Box<dyn Screening>) -> Self {
+        self.screening = Some(screening);
+        self
+    }
+
+    /// Adds a validation plugin, consulted in the order added before any row is applied
+    pub fn with_plugin(mut self, plugin: Plugin) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    /// Sets the script deciding the fees and limits of the rows
+    pub fn with_script(mut self, script: Script) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    pub fn with_review_queue(mut self, review_queue: ReviewQueue) -> Self {
+        self.review_queue = Some(review_queue);
+        self
+    }
+
+    /// Records whether each row of the input was accepted or rejected, see `--ack-out`
+    pub fn with_ack_log(mut self, ack_log: AckLog) -> Self {
+        self.ack_log = Some(ack_log);
+        self
+    }
+
+    /// Injects the delays and duplicates of a chaos run into every file processed.
+    /// Store failures come from wrapping the stores, see `Stores::with_chaos`.
+    pub fn with_chaos(mut self, chaos: Arc<Chaos>) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Writes malformed rows to the queue and carries on instead of failing the run
+    pub fn with_dead_letter_queue(mut self, queue: DeadLetterQueue) -> Self {
+        self.dead_letter_queue = Some(queue);
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Remembers idempotency keys and the ids of withdrawals and adjustments in `dedup`, to
+    /// drop redelivered rows. Without it only deposits and reserves reusing a stored id are.
+    pub fn with_dedup_filter(mut self, dedup: DedupFilter) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// Skips the rows of clients outside `shard`
+    pub fn with_shard(mut self, shard: Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// Applies at most `rows_per_sec` rows of a file per second
+    pub fn with_max_tps(mut self, rows_per_sec: f64) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(rows_per_sec));
+        self
+    }
+
+    /// Writes the account report and the open disputes to timestamped files in `dir` whenever
+    /// SIGUSR1 is received while a file is read, see `signals::install_dump_handler`
+    pub fn with_state_dumps(mut self, dir: &str) -> Self {
+        self.state_dumps = Some(dir.to_string());
+        self
+    }
+
+    /// Logs rows that take longer than `threshold` to process
+    pub fn with_slow_threshold(mut self, threshold: Duration) -> Self {
+        self.latency = LatencyTracker::new(Some(threshold));
+        self
+    }
+
+    /// Replaces the system clock, e.g. with a `MockClock` in tests
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    pub fn latency(&self) -> &LatencyTracker {
+        &self.latency
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn audit_trail(&self) -> Option<&AuditTrail> {
+        self.audit_trail.as_ref()
+    }
+
+    pub fn dead_letter_queue(&self) -> Option<&DeadLetterQueue> {
+        self.dead_letter_queue.as_ref()
+    }
+
+    pub fn chaos(&self) -> Option<&Chaos> {
+        self.chaos.as_deref()
+    }
+
+    pub fn anomaly_detector(&self) -> Option<&AnomalyDetector> {
+        self.anomaly_detector.as_ref()
+    }
+
+    pub fn balance_history(&self) -> Option<&BalanceHistory> {
+        self.balance_history.as_ref()
+    }
+
+    pub fn exposure_tracker(&self) -> Option<&ExposureTracker> {
+        self.exposure_tracker.as_ref()
+    }
+
+    pub fn dispute_stats(&self) -> Option<&DisputeStats> {
+        self.dispute_stats.as_ref()
+    }
+
+    pub fn dispute_chains(&self) -> Option<&DisputeChains> {
+        self.dispute_chains.as_ref()
+    }
+
+    pub fn cohort_stats(&self) -> Option<&CohortStats> {
+        self.cohort_stats.as_ref()
+    }
+
+    pub fn aml_monitor(&self) -> Option<&AmlMonitor> {
+        self.aml_monitor.as_ref()
+    }
+
+    pub fn review_queue(&self) -> Option<&ReviewQueue> {
+        self.review_queue.as_ref()
+    }
+
+    pub fn ack_log(&self) -> Option<&AckLog> {
+        self.ack_log.as_ref()
+    }
+
+    fn record_risk(&self, client_id: u16, event: RiskEvent) {
+        if let Some(ref risk) = self.risk {
+            risk.record(client_id, event);
         }
     }
 
@@ -39,253 +533,1063 @@ impl TransactionProcessor {
         }
     }
 
+    /// Counts a rejected row and logs it as `<message>, reason=<reason>`
+    fn reject(&self, reason: RejectionReason, message: String) {
+        self.record_rejection(reason, &format!("{}, reason={}", message, reason));
+    }
+
+    /// Like `reject`, with extra context after the reason
+    fn reject_with_detail(&self, reason: RejectionReason, message: String, detail: String) {
+        self.record_rejection(reason, &format!("{}, reason={} ({})", message, reason, detail));
+    }
+
+    fn record_rejection(&self, reason: RejectionReason, message: &str) {
+        *self.rejections.entry(reason).or_default() += 1;
+        if let Some(ref ack_log) = self.ack_log {
+            ack_log.rejected(reason);
+        }
+        if let Some(ref cohort_stats) = self.cohort_stats {
+            cohort_stats.rejected();
+        }
+        self.log(message);
+        if let Some(ref diagnostics) = self.diagnostics {
+            diagnostics.warning(message);
+        }
+    }
+
+    /// Reports a row that cannot be processed at all
+    fn diagnose_error(&self, message: &str) {
+        if let Some(ref diagnostics) = self.diagnostics {
+            diagnostics.error(message);
+        }
+    }
+
     pub fn process_file(&self, file_path: &str) -> Result<(), ProcessorError> {
-        let file = File::open(file_path)?;
-        let mut reader = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All)
-            .from_reader(file);
+        self.process_file_until(file_path, None)
+    }
+
+    /// Processes CSV from any source, e.g. a string handed over by the WASM bindings
+    pub fn process_reader<R: Read>(&self, input: R) -> Result<(), ProcessorError> {
+        self.for_each_record(csv_reader(input), None, |record| {
+            self.process_transaction(record)?;
+            Ok(true)
+        })
+    }
+
+    /// Processes the file in order, stopping at the first transaction stamped after `cutoff`
+    pub fn process_file_until(&self, file_path: &str, cutoff: Option<DateTime<Utc>>) -> Result<(), ProcessorError> {
+        self.for_each_record(open_reader(file_path)?, Some(file_path), |record| {
+            if let (Some(cutoff), Some(timestamp)) = (cutoff, record.timestamp) {
+                if timestamp > cutoff {
+                    return Ok(false);
+                }
+            }
+            self.process_transaction(record)?;
+            Ok(true)
+        })
+    }
+
+    /// Processes the file on `workers` threads that take turns of up to `budget` rows per
+    /// client, see `FairScheduler`. Needs the processor to work row by row without batch
+    /// commits or anything else that relies on rows being processed one at a time. With
+    /// `pin_cores`, worker `i` only runs on the `i`th core the process may use, wrapping
+    /// around if there are more workers than cores; the reading thread is left unpinned.
+    pub fn process_file_fairly(&self, file_path: &str, workers: usize, budget: usize, pin_cores: bool) -> Result<(), ProcessorError> {
+        let cores = match pin_cores {
+            true => match core_affinity::get_core_ids() {
+                Some(cores) if !cores.is_empty() => cores,
+                _ => return Err(ProcessorError::InvalidArguments("the cores to pin workers to cannot be listed on this system".to_string())),
+            },
+            false => Vec::new(),
+        };
+        let reader = open_reader(file_path)?;
+        let scheduler = FairScheduler::new(budget);
+        let read = thread::scope(|scope| {
+            for worker in 0..workers {
+                let core = (!cores.is_empty()).then(|| cores[worker % cores.len()]);
+                let (scheduler, processor) = (&scheduler, self);
+                scope.spawn(move || {
+                    // A worker the system refuses to pin still works, only wherever it is scheduled
+                    if let Some(core) = core {
+                        core_affinity::set_for_current(core);
+                    }
+                    scheduler.work(|record| processor.process_transaction(record))
+                });
+            }
+            // Workers only return once closed, whether reading succeeded or not
+            let read = self.for_each_record(reader, Some(file_path), |record| Ok(scheduler.push(record)));
+            scheduler.close();
+            read
+        });
+        // A failed worker stops the reading, so its error comes first
+        scheduler.finish()?;
+        read
+    }
+
+    /// Processes a file of admin operations, see `engine::apply_admin`. Other rows are rejected
+    /// with `not_admin_operation`. With a store, the file is committed as batches of their own,
+    /// recorded in the audit trail's `admin_ops` section. Returns how many rows the file had
+    /// and how many of them were rejected.
+    pub fn process_admin_file(&self, file_path: &str) -> Result<(u64, u64), ProcessorError> {
+        let rejections = || self.rejections.iter().map(|entry| *entry.value()).sum::<u64>();
+        let (rows, rejected) = (self.rows_processed(), rejections());
+        self.log(&format!("ADMIN OPS START: file={}", input::display_name(file_path)));
+        self.admin_ops.store(true, Ordering::Relaxed);
+        let result = self.for_each_record(open_reader(file_path)?, Some(file_path), |record| {
+            self.process_transaction(record)?;
+            Ok(true)
+        });
+        self.admin_ops.store(false, Ordering::Relaxed);
+        result?;
+
+        let (rows, rejected) = (self.rows_processed() - rows, rejections() - rejected);
+        self.log(&format!("ADMIN OPS END: file={}, rows={}, rejected={}", input::display_name(file_path), rows, rejected));
+        Ok((rows, rejected))
+    }
 
-        for result in reader.deserialize() {
-            let record: TransactionInput = result?;
-            self.process_transaction(record);
+    /// Processes the file in order, calling `close_day` with the processor state at the end of
+    /// every day. Rows without a timestamp belong to the day of the preceding row.
+    pub fn process_file_by_day<F>(&self, file_path: &str, mut close_day: F) -> Result<(), ProcessorError>
+    where
+        F: FnMut(&Self, NaiveDate) -> Result<(), ProcessorError>,
+    {
+        let mut current_day = None;
+
+        self.for_each_record(open_reader(file_path)?, Some(file_path), |record| {
+            let day = record.timestamp.map(|timestamp| timestamp.date_naive());
+            if let (Some(current), Some(day)) = (current_day, day) {
+                if day != current {
+                    close_day(self, current)?;
+                }
+            }
+            current_day = day.or(current_day);
+            self.process_transaction(record)?;
+            Ok(true)
+        })?;
+
+        match current_day {
+            Some(day) => close_day(self, day),
+            None => Err(ProcessorError::InvalidArguments(
+                "cutting by day requires a timestamp column".to_string(),
+            )),
+        }
+    }
+
+    /// Reads and validates every record of `source`, stopping early when `f` returns false.
+    /// With batch commits enabled, commits at batch boundaries and rolls back on failure.
+    fn for_each_record<R, F>(&self, reader: csv::Reader<R>, source: Option<&str>, f: F) -> Result<(), ProcessorError>
+    where
+        R: Read,
+        F: FnMut(TransactionInput) -> Result<bool, ProcessorError>,
+    {
+        let result = match self.batch {
+            None => self.read_records(reader, source, f).and_then(|()| self.sync_dead_letters()),
+            Some(ref batch) => match self.read_records(reader, source, f) {
+                Ok(()) => self.commit_batch(batch),
+                Err(err) => {
+                    batch.rollback();
+                    Err(err)
+                }
+            },
+        };
+
+        if let Some(ref tracer) = self.tracer {
+            tracer.end_batch(result.as_ref().err());
+        }
+        result
+    }
+
+    fn commit_batch(&self, batch: &BatchStore) -> Result<(), ProcessorError> {
+        // Rows dead-lettered in the batch are on disk before it counts as processed
+        self.sync_dead_letters()?;
+        if self.defer_commit {
+            return Ok(());
+        }
+
+        let mut changes = batch.commit()?;
+        *self.last_commit.lock() = Instant::now();
+        if self.admin_ops.load(Ordering::Relaxed) {
+            changes.section = Some(audit::ADMIN_OPS_SECTION.to_string());
+        }
+        if let Some(ref tracer) = self.tracer {
+            tracer.end_batch(None);
+        }
+        match self.audit_trail {
+            Some(ref audit_trail) => audit_trail.append(&changes),
+            None => Ok(()),
+        }
+    }
+
+    fn read_records<R, F>(&self, mut reader: csv::Reader<R>, source: Option<&str>, mut f: F) -> Result<(), ProcessorError>
+    where
+        R: Read,
+        F: FnMut(TransactionInput) -> Result<bool, ProcessorError>,
+    {
+        let headers = reader.headers()?.clone();
+
+        for (row, result) in reader.records().enumerate() {
+            if let Some(ref diagnostics) = self.diagnostics {
+                match result {
+                    Ok(ref raw) => {
+                        let line = raw.position().map_or(0, |position| position.line());
+                        diagnostics.set_row(line, raw.iter().collect::<Vec<_>>().join(", ").trim_end().to_string());
+                    }
+                    // The error message carries the position of an unreadable row
+                    Err(ref err) => {
+                        diagnostics.clear_row();
+                        diagnostics.error(&err.to_string());
+                    }
+                }
+            }
+            if let (Some(ack_log), Ok(raw)) = (&self.ack_log, &result) {
+                ack_log.set_line(raw.position().map(|position| position.line()));
+            }
+            let raw = match result {
+                Ok(raw) => raw,
+                Err(err) => {
+                    let line = err.position().map(|position| position.line());
+                    self.dead_letter(source, line, None, err.into())?;
+                    continue;
+                }
+            };
+
+            let parsed = raw
+                .deserialize::<TransactionInput>(Some(&headers))
+                .inspect_err(|err| self.diagnose_error(&err.to_string()))
+                .map_err(ProcessorError::from)
+                .and_then(|record| self.normalize_tx_id(record))
+                .and_then(|record| self.check_type(record));
+            let record = match parsed {
+                Ok(record) => record,
+                Err(err) => {
+                    let line = raw.position().map(|position| position.line());
+                    self.dead_letter(source, line, Some(raw.iter().collect::<Vec<_>>().join(",")), err)?;
+                    continue;
+                }
+            };
+            if self.shard.is_some_and(|shard| !shard.contains(record.client)) {
+                continue;
+            }
+            if let Some(ref rate_limiter) = self.rate_limiter {
+                rate_limiter.acquire();
+            }
+            if let Some(ref chaos) = self.chaos {
+                chaos.delay_record();
+                if chaos.duplicate_record() && !f(record.clone())? {
+                    break;
+                }
+            }
+            match f(record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                // With a dead-letter queue, a row the store could not be reached for is set
+                // aside like an unreadable one rather than failing the run
+                Err(err @ ProcessorError::StoreUnavailable(_)) if self.dead_letter_queue.is_some() => {
+                    let line = raw.position().map(|position| position.line());
+                    self.dead_letter(source, line, Some(raw.iter().collect::<Vec<_>>().join(",")), err)?;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+
+            if let Some(ref batch) = self.batch {
+                let rows_due = self.commit_every.is_some_and(|every| (row + 1) % every == 0);
+                let time_due = self.commit_interval.is_some_and(|interval| self.last_commit.lock().elapsed() >= interval);
+                if rows_due || time_due {
+                    self.commit_batch(batch)?;
+                }
+            }
+            if let Some(ref dir) = self.state_dumps {
+                if signals::take_dump_request() {
+                    self.dump_state(dir);
+                }
+            }
         }
 
+        self.bytes_read.fetch_add(reader.position().byte(), Ordering::Relaxed);
         Ok(())
     }
 
-    fn process_transaction(&self, record: TransactionInput) {
-        // Get or create account to ensure ordering lock exists
-        let ordering_lock = {
-            let account = self.accounts
-                .entry(record.client)
-                .or_insert_with(|| Account::new(record.client));
-            account.ordering_lock.clone()
-        };
+    /// Writes `accounts-<time>.csv` and `open-disputes-<time>.csv` to `dir`. A dump that
+    /// fails is reported and the run goes on, as it was only asked for to look at the run.
+    fn dump_state(&self, dir: &str) {
+        let at = self.clock.now().format("%Y%m%dT%H%M%S%.3fZ");
+        let accounts = Path::new(dir).join(format!("accounts-{}.csv", at));
+        let open_disputes = Path::new(dir).join(format!("open-disputes-{}.csv", at));
+        let result = fs::create_dir_all(dir)
+            .map_err(ProcessorError::from)
+            .and_then(|()| self.output_accounts(File::create(&accounts)?))
+            .and_then(|()| self.write_open_disputes(&open_disputes.to_string_lossy()));
+        match result {
+            Ok(()) => eprintln!("State dumped to {} and {}", accounts.display(), open_disputes.display()),
+            Err(err) => eprintln!("State dump failed: {}", err),
+        }
+    }
 
-        // Lock only this client (other clients can process concurrently)
-        let _guard = ordering_lock.lock();
+    /// Diverts a row that cannot be read to the dead-letter queue, or fails without one
+    fn dead_letter(&self, source: Option<&str>, line: Option<u64>, row: Option<String>, err: ProcessorError) -> Result<(), ProcessorError> {
+        match self.dead_letter_queue {
+            Some(ref queue) => {
+                if let Some(ref ack_log) = self.ack_log {
+                    ack_log.malformed(line);
+                }
+                queue.push(source.map(input::display_name), line, row, &err.to_string(), self.clock.now())
+            }
+            None => Err(err),
+        }
+    }
 
-        // Process transaction with guaranteed ordering for this client
-        match record.transaction_type {
-            TransactionType::Deposit => self.handle_deposit(record),
-            TransactionType::Withdrawal => self.handle_withdrawal(record),
-            TransactionType::Dispute => self.handle_dispute(record),
-            TransactionType::Resolve => self.handle_resolve(record),
-            TransactionType::Chargeback => self.handle_chargeback(record),
+    fn sync_dead_letters(&self) -> Result<(), ProcessorError> {
+        match self.dead_letter_queue {
+            Some(ref queue) => queue.sync(),
+            None => Ok(()),
         }
     }
 
-    fn handle_deposit(&self, record: TransactionInput) {
-        // Deposits must have an amount
-        let Some(amount) = record.amount else {
-            self.log(&format!("DEPOSIT REJECTED: client={}, tx={}, reason=missing_amount", record.client, record.tx));
-            return;
+    /// Brings the transaction id into the canonical form of the configured id kind
+    fn normalize_tx_id(&self, mut record: TransactionInput) -> Result<TransactionInput, ProcessorError> {
+        record.tx = match self.tx_id_kind.normalize(record.tx.clone()) {
+            Some(tx) => tx,
+            None => {
+                let err = ProcessorError::InvalidTransactionId(record.tx.to_string());
+                self.diagnose_error(&err.to_string());
+                return Err(err);
+            }
         };
+        Ok(record)
+    }
 
-        // Ignore if amount is negative or zero
-        if amount <= rust_decimal::Decimal::ZERO {
-            self.log(&format!("DEPOSIT REJECTED: client={}, tx={}, amount={}, reason=non_positive_amount", record.client, record.tx, amount));
-            return;
+    /// Refuses a row of a custom type without a registered handler
+    fn check_type(&self, record: TransactionInput) -> Result<TransactionInput, ProcessorError> {
+        match record.transaction_type {
+            TransactionType::Custom(ref name) if !self.engine_config.custom_types.contains(name) => {
+                let err = ProcessorError::UnknownTransactionType(name.clone());
+                self.diagnose_error(&err.to_string());
+                Err(err)
+            }
+            _ => Ok(record),
         }
+    }
 
-        // Deposits work if account is not locked
-        // Note: only deposits are stored since they're the only disputable transactions
-        let mut account = self.accounts
-            .entry(record.client)
-            .or_insert_with(|| Account::new(record.client));
+    /// Processes a single record that did not come from a file, e.g. from the Python bindings
+    pub fn process_record(&self, record: TransactionInput) -> Result<(), ProcessorError> {
+        self.process_transaction(self.check_type(self.normalize_tx_id(record)?)?)
+    }
 
-        if account.deposit(amount) {
-            let transaction = Transaction::new(
-                record.tx,
-                record.client,
-                record.transaction_type,
-                amount,
-            );
-            self.transactions.insert(transaction.tx_id, transaction);
-            self.log(&format!("DEPOSIT SUCCESS: client={}, tx={}, amount={}", record.client, record.tx, amount));
-        } else {
-            self.log(&format!("DEPOSIT REJECTED: client={}, tx={}, amount={}, reason=account_locked", record.client, record.tx, amount));
+    /// Locks the ordering locks of all `clients`, always in ascending client order so that
+    /// operations spanning several accounts cannot deadlock. Holding the guards serializes
+    /// against every other row of those clients.
+    pub fn lock_accounts(&self, clients: &[u16]) -> Vec<ClientGuard> {
+        locking::lock_in_order(clients, |client| {
+            // Clone the lock out of the map so no map shard stays locked while waiting
+            let lock = self.ordering_locks.entry(client).or_default().clone();
+            lock.lock()
+        })
+    }
+
+    fn process_transaction(&self, record: TransactionInput) -> Result<(), ProcessorError> {
+        let span = self.tracer.as_ref().map(|tracer| tracer.start_record(&record));
+        let (client, tx, transaction_type) = (record.client, record.tx.clone(), record.transaction_type.clone());
+        let started = Instant::now();
+        let result = self.apply_transaction(record);
+        let elapsed = started.elapsed();
+        if let Some(ref ack_log) = self.ack_log {
+            ack_log.finish_row(client, &tx, &transaction_type);
+        }
+        if let Some(ref cohort_stats) = self.cohort_stats {
+            cohort_stats.finish_row(client);
+        }
+
+        if self.latency.record(client, elapsed) {
+            let message = format!("SLOW TRANSACTION: client={}, tx={}, type={}, elapsed={:.1?}", client, tx, transaction_type, elapsed);
+            self.log(&message);
+            if let Some(ref diagnostics) = self.diagnostics {
+                diagnostics.warning(&message);
+            }
         }
+        if let (Some(tracer), Some(span)) = (&self.tracer, span) {
+            tracer.end_record(span, result.as_ref().err());
+        }
+        result
     }
 
-    fn handle_withdrawal(&self, record: TransactionInput) {
-        // Withdrawals must have an amount
-        let Some(amount) = record.amount else {
-            self.log(&format!("WITHDRAWAL REJECTED: client={}, tx={}, reason=missing_amount", record.client, record.tx));
-            return;
+    fn apply_transaction(&self, record: TransactionInput) -> Result<(), ProcessorError> {
+        // Lock only this client (other clients can process concurrently), and the target of a merge
+        let clients = match (&record.transaction_type, record.target_client) {
+            (TransactionType::Merge, Some(target)) => [record.client, target],
+            _ => [record.client, record.client],
         };
+        let clients = &clients[..if clients[0] == clients[1] { 1 } else { 2 }];
+        let _guards = self.lock_accounts(clients);
+        let row = self.rows_processed.fetch_add(1, Ordering::Relaxed) + 1;
+        let (client, timestamp) = (record.client, record.timestamp);
+        self.observe_exposure(row, clients, true)?;
+        self.apply_locked(record)?;
+        self.observe_exposure(row, clients, false)?;
+        self.sample_balances(row, client, timestamp)
+    }
 
-        // Ignore if amount is negative or zero
-        if amount <= rust_decimal::Decimal::ZERO {
-            self.log(&format!("WITHDRAWAL REJECTED: client={}, tx={}, amount={}, reason=non_positive_amount", record.client, record.tx, amount));
-            return;
+    /// Hands the balances of the row's clients to the exposure tracker: before the row only
+    /// the ones it has not seen yet, after the row all of them
+    fn observe_exposure(&self, row: u64, clients: &[u16], before: bool) -> Result<(), ProcessorError> {
+        let Some(tracker) = &self.exposure_tracker else {
+            return Ok(());
+        };
+        for &client in clients {
+            if !before || !tracker.knows(client) {
+                tracker.observe(row, client, self.account(client)?.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a row while its client is locked
+    fn apply_locked(&self, record: TransactionInput) -> Result<(), ProcessorError> {
+        *self.rows_by_type.entry(record.transaction_type.to_string()).or_default() += 1;
+        self.check_partition(&record);
+
+        // Counted like any other rejection, so a run never silently skips rows
+        if self.disabled_types.contains(&record.transaction_type) {
+            self.reject(RejectionReason::TypeDisabled, format!("{} REJECTED: client={}, tx={}", record.transaction_type.to_string().to_uppercase(), record.client, record.tx));
+            return Ok(());
+        }
+
+        // The rows of an admin file are admin operations, and admin only rows come from one
+        let admin_ops = self.admin_ops.load(Ordering::Relaxed);
+        if admin_ops && !record.transaction_type.is_admin() {
+            self.reject(RejectionReason::NotAdminOperation, format!("{} REJECTED: client={}, tx={}", record.transaction_type.to_string().to_uppercase(), record.client, record.tx));
+            return Ok(());
+        }
+
+        if self.is_duplicate(&record)? {
+            self.reject(RejectionReason::DuplicateTransaction, format!("{} REJECTED: client={}, tx={}", record.transaction_type.to_string().to_uppercase(), record.client, record.tx));
+            return Ok(());
+        }
+
+        self.accounts.ensure(record.client)?;
+        if self.merged_away(&record)? {
+            return Ok(());
+        }
+        self.update_account(record.client, |account| account.record_activity(record.timestamp))?;
+
+        let client_id = record.client;
+        if let (Some(risk), Some(timestamp)) = (&self.risk, record.timestamp) {
+            risk.record_activity(client_id, timestamp);
+        }
+        if let Some(ref anomaly_detector) = self.anomaly_detector {
+            anomaly_detector.observe(&record);
+        }
+        if let Some(ref aml_monitor) = self.aml_monitor {
+            aml_monitor.observe(&record);
         }
 
-        // Withdrawals work if funds are available and account is not locked
-        // Note: Withdrawals are not stored since they cannot be disputed
-        let mut account = self.accounts
-            .entry(record.client)
-            .or_insert_with(|| Account::new(record.client));
+        if self.frozen_blocks(&record)? || self.held_back(&record) {
+            return Ok(());
+        }
+
+        // Process transaction with guaranteed ordering for this client
+        match record.transaction_type {
+            TransactionType::Approve => self.handle_approve(record),
+            TransactionType::Reject => self.handle_review_reject(record),
+            _ => self.apply_engine(&record),
+        }?;
+
+        self.enforce_risk_threshold(client_id)
+    }
 
-        if account.withdraw(amount) {
-            self.log(&format!("WITHDRAWAL SUCCESS: client={}, tx={}, amount={}", record.client, record.tx, amount));
+    /// Writes the balances the history samples at this row, while the client is still locked
+    fn sample_balances(&self, row: u64, client: u16, timestamp: Option<DateTime<Utc>>) -> Result<(), ProcessorError> {
+        let Some(history) = &self.balance_history else {
+            return Ok(());
+        };
+        if history.samples_all(row) {
+            history.record_all(row, timestamp, &self.accounts()?)
+        } else if history.follows(client) {
+            history.record(row, timestamp, &self.account(client)?.into_iter().collect::<Vec<_>>())
         } else {
-            self.log(&format!("WITHDRAWAL REJECTED: client={}, tx={}, amount={}, reason=insufficient_funds_or_locked", record.client, record.tx, amount));
+            Ok(())
         }
     }
 
-    fn handle_dispute(&self, record: TransactionInput) {
-        // Referenced transaction must exist
-        let Some(transaction) = self.transactions.get(&record.tx) else {
-            self.log(&format!("DISPUTE REJECTED: client={}, tx={}, reason=transaction_not_found", record.client, record.tx));
-            return;
+    /// Rejects every row of a client whose account was merged into another one, returns whether it did
+    fn merged_away(&self, record: &TransactionInput) -> Result<bool, ProcessorError> {
+        let Some(merged_into) = self.account(record.client)?.and_then(|account| account.merged_into) else {
+            return Ok(false);
         };
 
-        // Verify the transaction belongs to the same client
-        let tx_client_id = transaction.client_id;
-        if tx_client_id != record.client {
-            self.log(&format!("DISPUTE REJECTED: client={}, tx={}, reason=client_mismatch (tx_client={})", record.client, record.tx, tx_client_id));
-            return;
-        }
+        self.reject_with_detail(
+            RejectionReason::AccountMerged,
+            format!("{} REJECTED: client={}, tx={}", record.transaction_type.to_string().to_uppercase(), record.client, record.tx),
+            format!("merged_into={}", merged_into),
+        );
+        Ok(true)
+    }
 
-        // Only deposits can be disputed
-        if transaction.transaction_type != TransactionType::Deposit {
-            self.log(&format!("DISPUTE REJECTED: client={}, tx={}, reason=non_deposit_transaction", record.client, record.tx));
-            return;
+    /// Rejects a row of a frozen account if the freeze policy blocks it, returns whether it did
+    fn frozen_blocks(&self, record: &TransactionInput) -> Result<bool, ProcessorError> {
+        if !self.freeze_policy.blocks(&record.transaction_type) || !self.account(record.client)?.is_some_and(|account| account.frozen) {
+            return Ok(false);
         }
 
-        // Transaction must not already be disputed or charged back
-        let tx_state = transaction.state.clone();
-        if tx_state != TransactionState::Normal {
-            self.log(&format!("DISPUTE REJECTED: client={}, tx={}, reason=invalid_state (state={:?})", record.client, record.tx, tx_state));
-            return;
+        self.reject(RejectionReason::AccountFrozen, format!("{} REJECTED: client={}, tx={}", record.transaction_type.to_string().to_uppercase(), record.client, record.tx));
+        Ok(true)
+    }
+
+    /// Screens deposits and withdrawals and holds back those that may not be applied yet.
+    /// Rows of flagged clients are parked with a review queue and rejected without one, rows
+    /// over the review limit are parked. A screening that fails rejects the row, funds never
+    /// move unscreened. Returns whether the row was held back
+    fn held_back(&self, record: &TransactionInput) -> bool {
+        if !matches!(record.transaction_type, TransactionType::Deposit | TransactionType::Withdrawal) {
+            return false;
         }
 
-        let tx_amount = transaction.amount;
-        drop(transaction);
+        let message = format!("{} REJECTED: client={}, tx={}", record.transaction_type.to_string().to_uppercase(), record.client, record.tx);
+        if let Some(ref screening) = self.screening {
+            match screening.screen(record) {
+                Ok(ScreeningResult::Clear) => {}
+                Ok(ScreeningResult::Hit(reason)) => {
+                    match self.review_queue {
+                        Some(ref queue) => self.park(queue, record, format!("screening_hit: {}", reason)),
+                        None => self.reject_with_detail(RejectionReason::ScreeningHit, message, reason),
+                    }
+                    return true;
+                }
+                Err(err) => {
+                    self.reject_with_detail(RejectionReason::ScreeningUnavailable, message, err.to_string());
+                    return true;
+                }
+            }
+        }
 
-        // Get the account and hold the funds
-        let mut account = match self.accounts.get_mut(&record.client) {
-            Some(acc) => acc,
-            None => {
-                self.log(&format!("DISPUTE REJECTED: client={}, tx={}, reason=account_not_found", record.client, record.tx));
-                return;
+        match self.review_queue {
+            Some(ref queue) if queue.over_limit(record) => {
+                self.park(queue, record, "over_limit".to_string());
+                true
             }
-        };
+            _ => false,
+        }
+    }
 
-        // Mark transaction as under dispute
-        if account.hold_funds(tx_amount) {
-            self.transactions.get_mut(&record.tx).unwrap().state = TransactionState::UnderDispute;
-            self.log(&format!("DISPUTE SUCCESS: client={}, tx={}, amount={} (moved to held)", record.client, record.tx, tx_amount));
-        } else {
-            self.log(&format!("DISPUTE REJECTED: client={}, tx={}, reason=insufficient_available_funds", record.client, record.tx));
+    fn park(&self, queue: &ReviewQueue, record: &TransactionInput, reason: String) {
+        self.log(&format!("{} PARKED: client={}, tx={}, reason={} (waiting for review)", record.transaction_type.to_string().to_uppercase(), record.client, record.tx, reason));
+        queue.park(record, reason);
+        if let Some(ref ack_log) = self.ack_log {
+            ack_log.parked();
         }
     }
 
-    fn handle_resolve(&self, record: TransactionInput) {
-        // Referenced transaction must exist
-        let Some(transaction) = self.transactions.get(&record.tx) else {
-            self.log(&format!("RESOLVE REJECTED: client={}, tx={}, reason=transaction_not_found", record.client, record.tx));
+    /// Flags a row that arrived on another partition than the first row of its client.
+    /// Rows are only ordered within a partition, so a feed that spreads a client over several
+    /// partitions may deliver its rows out of order. The row is still processed.
+    fn check_partition(&self, record: &TransactionInput) {
+        let Some(partition) = record.partition else {
             return;
         };
-
-        // Verify the transaction belongs to the same client
-        let tx_client_id = transaction.client_id;
-        if tx_client_id != record.client {
-            self.log(&format!("RESOLVE REJECTED: client={}, tx={}, reason=client_mismatch (tx_client={})", record.client, record.tx, tx_client_id));
+        let expected = *self.client_partitions.entry(record.client).or_insert(partition);
+        if partition == expected {
             return;
         }
 
-        // Transaction must be under dispute
-        let tx_state = transaction.state.clone();
-        if tx_state != TransactionState::UnderDispute {
-            self.log(&format!("RESOLVE REJECTED: client={}, tx={}, reason=not_under_dispute (state={:?})", record.client, record.tx, tx_state));
-            return;
+        self.partition_violations.fetch_add(1, Ordering::Relaxed);
+        let message = format!("PARTITION MISMATCH: client={}, tx={}, partition={}, expected_partition={}", record.client, record.tx, partition, expected);
+        self.log(&message);
+        if let Some(ref diagnostics) = self.diagnostics {
+            diagnostics.warning(&message);
         }
+    }
 
-        let tx_amount = transaction.amount;
-        drop(transaction); // Release the read lock
+    /// Whether a row was delivered before: a deposit or reserve reusing the id of a stored
+    /// transaction, or, with a de-duplication filter, any row whose idempotency key was seen
+    /// and a withdrawal or adjustment reusing an id. Those two are not stored, so their ids
+    /// are only remembered by the filter.
+    fn is_duplicate(&self, record: &TransactionInput) -> Result<bool, ProcessorError> {
+        let Some(ref dedup) = self.dedup else {
+            return match record.transaction_type {
+                TransactionType::Deposit | TransactionType::Reserve => Ok(self.transactions.get(&record.tx)?.is_some()),
+                _ => Ok(false),
+            };
+        };
 
-        // Get the account and release the held funds
-        let mut account = match self.accounts.get_mut(&record.client) {
-            Some(acc) => acc,
-            None => {
-                self.log(&format!("RESOLVE REJECTED: client={}, tx={}, reason=account_not_found", record.client, record.tx));
-                return;
+        if let Some(ref key) = record.idempotency_key {
+            if dedup.check_and_insert(&format!("key:{}", key), record.timestamp) {
+                return Ok(true);
             }
+        }
+
+        let tx_key = format!("tx:{}", record.tx);
+        let remembered = match record.transaction_type {
+            TransactionType::Deposit | TransactionType::Reserve => dedup.contains(&tx_key),
+            TransactionType::Withdrawal | TransactionType::Adjustment => dedup.check_and_insert(&tx_key, record.timestamp),
+            _ => return Ok(false),
+        };
+        Ok(remembered || self.transactions.get(&record.tx)?.is_some())
+    }
+
+    /// Locks the account once the client's risk score crosses the configured threshold
+    fn enforce_risk_threshold(&self, client_id: u16) -> Result<(), ProcessorError> {
+        let Some(ref risk) = self.risk else {
+            return Ok(());
         };
 
-        // Mark transaction as resolved (back to normal)
-        if account.release_funds(tx_amount) {
-            self.transactions.get_mut(&record.tx).unwrap().state = TransactionState::Normal;
-            self.log(&format!("RESOLVE SUCCESS: client={}, tx={}, amount={} (moved to available)", record.client, record.tx, tx_amount));
-        } else {
-            self.log(&format!("RESOLVE REJECTED: client={}, tx={}, reason=insufficient_held_funds", record.client, record.tx));
+        if risk.should_lock(client_id) {
+            let newly_locked = self.update_account(client_id, |account| !std::mem::replace(&mut account.locked, true))?;
+            if newly_locked == Some(true) {
+                self.log(&format!("RISK LOCK: client={}, score={} (account locked)", client_id, risk.score(client_id)));
+            }
         }
+
+        Ok(())
     }
 
-    fn handle_chargeback(&self, record: TransactionInput) {
-        // Referenced transaction must exist
-        let Some(transaction) = self.transactions.get(&record.tx) else {
-            self.log(&format!("CHARGEBACK REJECTED: client={}, tx={}, reason=transaction_not_found", record.client, record.tx));
-            return;
+    /// Runs `f` on the client's account through the account store.
+    /// Returns None if the client has no account. Stores may call `f` again on a fresh copy
+    /// of the account if a concurrent update wins, so only the last result is kept.
+    fn update_account<R>(&self, client_id: u16, mut f: impl FnMut(&mut Account) -> R) -> Result<Option<R>, ProcessorError> {
+        let mut result = None;
+        self.accounts.update(client_id, &mut |account| result = Some(f(account)))?;
+        Ok(result)
+    }
+
+    /// Applies the row through the engine, logging or rejecting it as the engine decided
+    fn apply_engine(&self, record: &TransactionInput) -> Result<(), ProcessorError> {
+        // Admin operations are the admin's doing, plugins and scripts only check the rows of the input
+        let admin = self.admin_ops.load(Ordering::Relaxed);
+        if !admin && self.plugin_rejects(record)? {
+            return Ok(());
+        }
+        let fee = match (&self.script, admin) {
+            (Some(script), false) => match self.script_fee(script, record)? {
+                Some(fee) => fee,
+                None => return Ok(()),
+            },
+            _ => Decimal::ZERO,
         };
 
-        // Verify the transaction belongs to the same client
-        let tx_client_id = transaction.client_id;
-        if tx_client_id != record.client {
-            self.log(&format!("CHARGEBACK REJECTED: client={}, tx={}, reason=client_mismatch (tx_client={})", record.client, record.tx, tx_client_id));
-            return;
+        // The fee is taken out of the amount: a deposit credits less, a withdrawal debits more
+        let charged;
+        let record = match fee.is_zero() {
+            true => record,
+            false => {
+                charged = TransactionInput {
+                    amount: record.amount.map(|amount| match record.transaction_type {
+                        TransactionType::Deposit => amount - fee,
+                        _ => amount + fee,
+                    }),
+                    ..record.clone()
+                };
+                &charged
+            }
+        };
+
+        let outcome = match admin {
+            true => engine::apply_admin(self, &self.engine_config, record)?,
+            false => engine::apply(self, &self.engine_config, record)?,
+        };
+        if let Some(event) = outcome.risk_event(&record.transaction_type) {
+            self.record_risk(record.client, event);
         }
 
-        // Transaction must be under dispute
-        let tx_state = transaction.state.clone();
-        if tx_state != TransactionState::UnderDispute {
-            self.log(&format!("CHARGEBACK REJECTED: client={}, tx={}, reason=not_under_dispute (state={:?})", record.client, record.tx, tx_state));
-            return;
+        let with_fee = |message: String| match fee.is_zero() {
+            true => message,
+            false => format!("{}, fee={}", message, fee),
+        };
+        match outcome {
+            Outcome::Applied(message) => {
+                if let Some(ref dispute_stats) = self.dispute_stats {
+                    dispute_stats.observe(record);
+                }
+                if let Some(ref dispute_chains) = self.dispute_chains {
+                    dispute_chains.observe(record);
+                }
+                if let Some(ref cohort_stats) = self.cohort_stats {
+                    cohort_stats.applied(record);
+                }
+                self.log(&with_fee(message))
+            }
+            Outcome::Rejected(Rejection { reason, message, detail: None }) => self.reject(reason, with_fee(message)),
+            Outcome::Rejected(Rejection { reason, message, detail: Some(detail) }) => self.reject_with_detail(reason, with_fee(message), detail),
+        }
+        Ok(())
+    }
+
+    /// Rejects the row if a validation plugin rejects it or fails, returns whether one did.
+    /// A plugin that fails rejects like a screening that fails: no row goes unchecked.
+    fn plugin_rejects(&self, record: &TransactionInput) -> Result<bool, ProcessorError> {
+        if self.plugins.is_empty() {
+            return Ok(false);
         }
 
-        let tx_amount = transaction.amount;
-        drop(transaction); // Release the read lock
+        let account = self.account(record.client)?;
+        for plugin in &self.plugins {
+            let (reason, detail) = match plugin.validate(record, account.as_ref())? {
+                Decision::Accept => continue,
+                Decision::Reject(reason) => (RejectionReason::PluginRejected, reason),
+                Decision::Failed(err) => (RejectionReason::PluginFailed, err),
+            };
+            self.reject_with_detail(
+                reason,
+                format!("{} REJECTED: client={}, tx={}", record.transaction_type.to_string().to_uppercase(), record.client, record.tx),
+                format!("{}: {}", plugin.name(), detail),
+            );
+            return Ok(true);
+        }
+        Ok(false)
+    }
 
-        // Get the account and perform chargeback
-        let mut account = match self.accounts.get_mut(&record.client) {
-            Some(acc) => acc,
-            None => {
-                self.log(&format!("CHARGEBACK REJECTED: client={}, tx={}, reason=account_not_found", record.client, record.tx));
-                return;
+    /// Runs the script's `check` and, for deposits and withdrawals, its `fee`. Returns the fee,
+    /// or none if the row was rejected. A script that fails rejects the row like a plugin.
+    fn script_fee(&self, script: &Script, record: &TransactionInput) -> Result<Option<Decimal>, ProcessorError> {
+        let account = self.account(record.client)?;
+        let reject = |reason, detail: String| {
+            self.reject_with_detail(
+                reason,
+                format!("{} REJECTED: client={}, tx={}", record.transaction_type.to_string().to_uppercase(), record.client, record.tx),
+                format!("{}: {}", script.name(), detail),
+            );
+            Ok(None)
+        };
+
+        match script.check(record, account.as_ref()) {
+            Decision::Accept => {}
+            Decision::Reject(reason) => return reject(RejectionReason::ScriptRejected, reason),
+            Decision::Failed(err) => return reject(RejectionReason::ScriptFailed, err),
+        }
+
+        let amount = match (&record.transaction_type, record.amount) {
+            (TransactionType::Deposit | TransactionType::Withdrawal, Some(amount)) if amount > Decimal::ZERO => amount,
+            _ => return Ok(Some(Decimal::ZERO)),
+        };
+        match script.fee(record, account.as_ref()) {
+            Ok(fee) if fee < Decimal::ZERO => reject(RejectionReason::ScriptFailed, format!("fee {} is negative", fee)),
+            Ok(fee) if record.transaction_type == TransactionType::Deposit && fee >= amount => {
+                reject(RejectionReason::ScriptFailed, format!("fee {} is not less than the deposit", fee))
             }
+            Ok(fee) => Ok(Some(fee)),
+            Err(err) => reject(RejectionReason::ScriptFailed, err),
+        }
+    }
+
+    /// Takes the parked row an approve or reject row refers to out of the review queue,
+    /// logging why if there is none
+    fn take_parked(&self, record: &TransactionInput, operation: &str) -> Option<ParkedTransaction> {
+        let message = format!("{} REJECTED: client={}, tx={}", operation, record.client, record.tx);
+        let Some(parked) = self.review_queue.as_ref().and_then(|queue| queue.get(&record.tx)) else {
+            self.reject(RejectionReason::NotInReview, message);
+            return None;
         };
 
-        // Mark transaction as charged back and lock account
-        if account.chargeback(tx_amount) {
-            self.transactions.get_mut(&record.tx).unwrap().state = TransactionState::ChargedBack;
-            self.log(&format!("CHARGEBACK SUCCESS: client={}, tx={}, amount={} (account locked)", record.client, record.tx, tx_amount));
-        } else {
-            self.log(&format!("CHARGEBACK REJECTED: client={}, tx={}, reason=insufficient_held_funds", record.client, record.tx));
+        // Verify the parked row belongs to the same client
+        if parked.client != record.client {
+            self.reject_with_detail(RejectionReason::ClientMismatch, message, format!("tx_client={}", parked.client));
+            return None;
+        }
+
+        self.review_queue.as_ref().and_then(|queue| queue.remove(&record.tx))
+    }
+
+    fn handle_approve(&self, record: TransactionInput) -> Result<(), ProcessorError> {
+        let Some(parked) = self.take_parked(&record, "APPROVE") else {
+            return Ok(());
+        };
+        self.log(&format!("APPROVE SUCCESS: client={}, tx={}, reason={} (released from review)", record.client, record.tx, parked.reason));
+
+        // The approved row skips screening and the review limit, every other check still applies
+        let parked = parked.to_input();
+        if self.frozen_blocks(&parked)? {
+            return Ok(());
+        }
+        self.apply_engine(&parked)
+    }
+
+    fn handle_review_reject(&self, record: TransactionInput) -> Result<(), ProcessorError> {
+        if let Some(parked) = self.take_parked(&record, "REJECT") {
+            self.log(&format!("REJECT SUCCESS: client={}, tx={}, reason={} (discarded after review)", record.client, record.tx, parked.reason));
         }
+        Ok(())
+    }
+
+    fn store_transaction(&self, transaction: Transaction) -> Result<(), ProcessorError> {
+        self.transactions.insert(transaction)?;
+        Ok(())
+    }
+
+    /// Returns the stored transactions of a client, in the order they were processed
+    /// where the store keeps them indexed by client
+    pub fn transactions_for(&self, client_id: u16) -> Result<Vec<Transaction>, ProcessorError> {
+        self.transactions.for_client(client_id)
     }
 
-    pub fn output_accounts(&self) -> Result<(), ProcessorError> {
-        let mut writer = csv::Writer::from_writer(std::io::stdout());
+    /// Retries of the store operations, with batch commits
+    pub fn store_retry_stats(&self) -> Option<RetryStats> {
+        self.batch.as_ref().map(|batch| batch.retry_stats())
+    }
+
+    /// Rows that arrived on another partition than the first row of their client
+    pub fn partition_violations(&self) -> u64 {
+        self.partition_violations.load(Ordering::Relaxed)
+    }
+
+    /// Number of rows handed to the handlers, applied or rejected
+    pub fn rows_processed(&self) -> u64 {
+        self.rows_processed.load(Ordering::Relaxed)
+    }
+
+    /// Number of input bytes read, including the header
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Number of rows handed to the handlers per transaction type, only types that occurred
+    pub fn row_counts(&self) -> BTreeMap<String, u64> {
+        self.rows_by_type
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
 
-        let mut accounts: Vec<_> = self.accounts
+    /// Number of rejected rows per reason, only reasons that occurred
+    pub fn rejection_counts(&self) -> BTreeMap<RejectionReason, u64> {
+        self.rejections
             .iter()
-            .map(|entry| entry.value().clone())
-            .collect();
+            .map(|entry| (*entry.key(), *entry.value()))
+            .collect()
+    }
+
+    /// Returns a copy of a single account, if the client has one
+    pub fn account(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        self.accounts.get(client_id)
+    }
+
+    /// Returns a copy of all accounts ordered by client id
+    pub fn accounts(&self) -> Result<Vec<Account>, ProcessorError> {
+        let mut accounts = self.accounts.iterate()?;
         accounts.sort_by_key(|a| a.client_id);
+        Ok(accounts)
+    }
+
+    /// Returns a copy of all stored transactions ordered by tx id
+    pub fn transactions(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        let mut transactions = self.transactions.iterate()?;
+        transactions.sort_by(|a, b| a.tx_id.cmp(&b.tx_id));
+        Ok(transactions)
+    }
+
+    /// Writes the account report as CSV
+    pub fn output_accounts<W: Write>(&self, output: W) -> Result<(), ProcessorError> {
+        let mut writer = AccountCsvWriter::new(output).with_amount_format(self.amount_format);
+
+        for account in self.accounts()? {
+            self.write_account(&mut writer, &account)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Processes a file whose rows are grouped by client, writing each client's row as soon
+    /// as the rows of the next client start, since nothing later in the file can change it.
+    /// Fails if a client shows up again after its row was written.
+    pub fn output_accounts_streaming<W: Write>(&self, file_path: &str, output: W) -> Result<(), ProcessorError> {
+        let mut writer = AccountCsvWriter::new(output).with_amount_format(self.amount_format);
+        let mut current = None;
+        let mut finished = HashSet::new();
+
+        self.for_each_record(open_reader(file_path)?, Some(file_path), |record| {
+            if current != Some(record.client) {
+                if let Some(client) = current {
+                    self.write_final_account(&mut writer, client)?;
+                    finished.insert(client);
+                }
+                if finished.contains(&record.client) {
+                    return Err(ProcessorError::InvalidArguments(format!(
+                        "client {} appears again at tx {} after its row was written, streaming output needs the rows grouped by client",
+                        record.client, record.tx
+                    )));
+                }
+                current = Some(record.client);
+            }
+            self.process_transaction(record)?;
+            Ok(true)
+        })?;
+
+        if let Some(client) = current {
+            self.write_final_account(&mut writer, client)?;
+        }
+        Ok(())
+    }
+
+    /// Writes and flushes the row of a client, if it has an account
+    fn write_final_account<W: Write>(&self, writer: &mut AccountCsvWriter<W>, client_id: u16) -> Result<(), ProcessorError> {
+        if let Some(account) = self.account(client_id)? {
+            self.write_account(writer, &account)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_account<W: Write>(&self, writer: &mut AccountCsvWriter<W>, account: &Account) -> Result<(), ProcessorError> {
+        writer.write(&self.account_row(account))
+    }
+
+    /// The report row of `account`, with the columns of the output schema and the options and
+    /// amounts in the amount format
+    pub fn account_row(&self, account: &Account) -> AccountRow {
+        let risk_score = self.risk.as_ref().map(|risk| risk.score(account.client_id));
+        let (shortfall, needs_review) = match self.engine_config.shortfall_policy {
+            ShortfallPolicy::Reject => (None, None),
+            _ => (Some(account.shortfall), Some(account.needs_review)),
+        };
+
+        let mut row = match self.output_schema {
+            OutputSchema::V1 => {
+                let mut output = account.to_output();
+                output.risk_score = risk_score;
+                output.shortfall = shortfall;
+                output.needs_review = needs_review;
+                AccountRow::V1(output)
+            }
+            OutputSchema::V2 => {
+                let mut output = account.to_output_v2();
+                output.risk_score = risk_score;
+                output.shortfall = shortfall;
+                output.needs_review = needs_review;
+                AccountRow::V2(output)
+            }
+        };
+        row.format_amounts(self.amount_format);
+        row
+    }
+
+    /// Writes the transactions currently under dispute as CSV, in transaction id order
+    pub fn write_open_disputes(&self, path: &str) -> Result<(), ProcessorError> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(File::create(path)?);
+
+        // Header is written explicitly so an empty report is still a valid CSV
+        writer.write_record(["client", "tx", "amount", "held", "reason_code", "metadata"])?;
+        for transaction in self.transactions()? {
+            if transaction.state == TransactionState::UnderDispute {
+                let mut output = transaction.to_open_dispute();
+                // The account's ledger is authoritative, the transaction only knows what it asked for
+                if let Some(held) = self.account(transaction.client_id)?.and_then(|account| account.holds.get(&transaction.tx_id).copied()) {
+                    output.held = held;
+                }
+                output.format_amounts(self.amount_format);
+                writer.serialize(output)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Checks that the holds ledger of every account adds up to its held funds, warning about
+    /// each account where it does not. Returns the number of such accounts
+    pub fn reconcile_holds(&self) -> Result<u64, ProcessorError> {
+        // The check is about the accounts, not the row read last
+        if let Some(ref diagnostics) = self.diagnostics {
+            diagnostics.clear_row();
+        }
+        let mut mismatches = 0;
+        for account in self.accounts()? {
+            let unattributed = account.unattributed_held();
+            if unattributed.is_zero() {
+                continue;
+            }
 
-        for account in accounts {
-            writer.serialize(account.to_output())?;
+            mismatches += 1;
+            let message = format!("HOLDS MISMATCH: client={}, held={}, ledger={}", account.client_id, account.held, account.held - unattributed);
+            self.log(&message);
+            if let Some(ref diagnostics) = self.diagnostics {
+                diagnostics.warning(&message);
+            }
         }
+        Ok(mismatches)
+    }
+
+    /// Processes the file and writes the closing balances of every account after each day
+    pub fn output_daily_accounts<W: Write>(&self, file_path: &str, output: W) -> Result<(), ProcessorError> {
+        let mut writer = csv::Writer::from_writer(output);
+
+        self.process_file_by_day(file_path, |processor, day| {
+            for account in processor.accounts()? {
+                let mut output = account.to_daily_output(day);
+                output.format_amounts(processor.amount_format);
+                writer.serialize(output)?;
+            }
+            Ok(())
+        })?;
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a single account row, with zero balances if the client has no account
+    pub fn output_account<W: Write>(&self, client_id: u16, output: W) -> Result<(), ProcessorError> {
+        let mut writer = csv::Writer::from_writer(output);
+
+        let account = self.account(client_id)?.unwrap_or_else(|| Account::new(client_id));
+        let mut output = account.to_output();
+        output.format_amounts(self.amount_format);
+        writer.serialize(output)?;
 
         writer.flush()?;
         Ok(())
     }
 }
 
+impl EngineState for TransactionProcessor {
+    fn transaction(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        self.transactions.get(tx_id)
+    }
+
+    fn store_transaction(&self, transaction: Transaction) -> Result<(), ProcessorError> {
+        TransactionProcessor::store_transaction(self, transaction)
+    }
+
+    fn reassign_transactions(&self, from: u16, to: u16) -> Result<usize, ProcessorError> {
+        let transactions = self.transactions_for(from)?;
+        let count = transactions.len();
+        for mut transaction in transactions {
+            transaction.client_id = to;
+            self.transactions.insert(transaction)?;
+        }
+        Ok(count)
+    }
+
+    fn account(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        TransactionProcessor::account(self, client_id)
+    }
+
+    fn update_account(&self, client_id: u16, f: &mut dyn FnMut(&mut Account) -> bool) -> Result<bool, ProcessorError> {
+        Ok(TransactionProcessor::update_account(self, client_id, f)?.unwrap_or(false))
+    }
+
+    fn transition(
+        &self,
+        tx_id: &TxId,
+        event: LifecycleEvent,
+        account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+    ) -> Result<Transition, ProcessorError> {
+        self.transactions.try_transition(self.accounts.as_ref(), tx_id, event, account_op)
+    }
+}
+
 impl Default for TransactionProcessor {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// Opens a transactions CSV, a local file or an http(s) URL, with the reader settings
+/// shared by every command
+pub fn open_reader(file_path: &str) -> Result<csv::Reader<Box<dyn Read + Send>>, ProcessorError> {
+    Ok(csv_reader(input::open(file_path)?))
+}
+
+pub fn csv_reader<R: Read>(input: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input)
+}
@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{self, AccountDelta, AuditTrail, BatchChanges};
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{Transaction, TxId};
+use crate::store::{self, AccountStore, BatchStore, TransactionStore};
+
+/// Changes a run would make to the persistent state, held back until someone runs `apply`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Proposal {
+    pub id: String,
+    pub input_file: String,
+    pub accounts: Vec<AccountDelta>,
+    /// Transactions the run stored or moved to another state, as they are after it
+    pub transactions: Vec<Transaction>,
+    /// Transactions the run removed
+    #[serde(default)]
+    pub removed: Vec<TxId>,
+}
+
+impl Proposal {
+    pub fn from_changes(id: String, input_file: &str, changes: &BatchChanges) -> Self {
+        Proposal {
+            id,
+            input_file: input_file.to_string(),
+            accounts: audit::net_account_deltas([changes]),
+            transactions: changes.transactions
+                .iter()
+                .filter_map(|change| change.after.clone())
+                .collect(),
+            removed: changes.transactions
+                .iter()
+                .filter(|change| change.after.is_none())
+                .map(|change| change.tx.clone())
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ProcessorError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Commits the proposal to the stores as one batch. Deltas are added to the current
+    /// balances, so runs applied since the proposal was made are kept.
+    /// Returns one line per account change.
+    pub fn apply(&self, stores: store::Stores, audit_trail: Option<&AuditTrail>) -> Result<Vec<String>, ProcessorError> {
+        let batch = BatchStore::new(Arc::from(stores.accounts), Arc::from(stores.transactions));
+        let mut entries = Vec::new();
+
+        for transaction in &self.transactions {
+            batch.insert(transaction.clone())?;
+        }
+        for tx_id in &self.removed {
+            batch.remove(tx_id)?;
+        }
+
+        for delta in &self.accounts {
+            batch.ensure(delta.client)?;
+            batch.update(delta.client, &mut |account| delta.apply(account))?;
+            entries.push(format!(
+                "APPLIED ACCOUNT: client={}, available={}, held={}, locked={}",
+                delta.client, delta.available, delta.held, delta.locked_after
+            ));
+        }
+
+        let changes = batch.commit()?;
+        if let Some(audit_trail) = audit_trail {
+            audit_trail.append(&changes)?;
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Applies a proposals file. With an audit directory the apply is recorded as a run,
+/// `apply-<proposal id>` by default, so the same proposal is refused a second time.
+pub fn run_apply(path: &str, store: &str, audit_dir: Option<&str>, run_id: Option<String>) -> Result<(), ProcessorError> {
+    let proposal = Proposal::load(path)?;
+
+    let audit_trail = match audit_dir {
+        Some(dir) => {
+            let run_id = run_id.unwrap_or_else(|| format!("apply-{}", proposal.id));
+            Some(AuditTrail::create(dir, &run_id)?)
+        }
+        None => None,
+    };
+
+    let entries = proposal.apply(store::open_stores(store)?, audit_trail.as_ref())?;
+    for entry in entries {
+        println!("{}", entry);
+    }
+    println!(
+        "Applied proposal {} of '{}': {} account(s), {} transaction(s)",
+        proposal.id, proposal.input_file, proposal.accounts.len(), proposal.transactions.len()
+    );
+
+    Ok(())
+}
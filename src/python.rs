@@ -0,0 +1,125 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use rust_decimal::Decimal;
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::Deserialize;
+
+use crate::model::account::ShortfallPolicy;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType, TxId, TxIdKind};
+use crate::processor;
+
+impl From<ProcessorError> for PyErr {
+    fn from(err: ProcessorError) -> PyErr {
+        match err {
+            ProcessorError::IoError(err) => PyIOError::new_err(err.to_string()),
+            err => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+/// The engine as a Python class. Accounts come back as a list of dicts,
+/// which `pandas.DataFrame` accepts as is.
+#[pyclass(name = "TransactionProcessor")]
+struct PyTransactionProcessor {
+    inner: processor::TransactionProcessor,
+}
+
+#[pymethods]
+impl PyTransactionProcessor {
+    #[new]
+    #[pyo3(signature = (tx_id_type = "u32", allow_adjustments = false, shortfall_policy = "reject", allow_merges = false))]
+    fn new(tx_id_type: &str, allow_adjustments: bool, shortfall_policy: &str, allow_merges: bool) -> PyResult<Self> {
+        let tx_id_kind = TxIdKind::from_name(tx_id_type)
+            .ok_or_else(|| PyValueError::new_err(format!("invalid tx_id_type '{}'", tx_id_type)))?;
+        let shortfall_policy = ShortfallPolicy::from_name(shortfall_policy)
+            .ok_or_else(|| PyValueError::new_err(format!("invalid shortfall_policy '{}'", shortfall_policy)))?;
+
+        Ok(PyTransactionProcessor {
+            inner: processor::TransactionProcessor::new()
+                .with_tx_id_kind(tx_id_kind)
+                .with_adjustments(allow_adjustments)
+                .with_merges(allow_merges)
+                .with_shortfall_policy(shortfall_policy),
+        })
+    }
+
+    fn process_file(&self, path: &str) -> PyResult<()> {
+        Ok(self.inner.process_file(path)?)
+    }
+
+    /// Processes dicts shaped like CSV rows: `type`, `client`, `tx` and optionally
+    /// `amount`, `effective_date`, `timestamp`, `reason_code`, `metadata` (a JSON string), `partition` and `idempotency_key`
+    fn process_records(&self, records: &Bound<'_, PyAny>) -> PyResult<()> {
+        for record in records.try_iter()? {
+            let record = record?;
+            self.inner.process_record(transaction_input(record.downcast::<PyDict>()?)?)?;
+        }
+        Ok(())
+    }
+
+    fn accounts<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let accounts = PyList::empty(py);
+        for account in self.inner.accounts()? {
+            let dict = PyDict::new(py);
+            dict.set_item("client", account.client_id)?;
+            dict.set_item("available", account.available)?;
+            dict.set_item("held", account.held)?;
+            dict.set_item("total", account.total())?;
+            dict.set_item("locked", account.locked)?;
+            dict.set_item("tx_count", account.tx_count)?;
+            dict.set_item("disputes", account.disputes)?;
+            dict.set_item("last_activity", account.last_activity)?;
+            dict.set_item("frozen", account.frozen)?;
+            accounts.append(dict)?;
+        }
+        Ok(accounts)
+    }
+
+    /// Rejected rows by reason
+    fn rejections<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let rejections = PyDict::new(py);
+        for (reason, count) in self.inner.rejection_counts() {
+            rejections.set_item(reason.as_str(), count)?;
+        }
+        Ok(rejections)
+    }
+}
+
+fn transaction_input(record: &Bound<'_, PyDict>) -> PyResult<TransactionInput> {
+    let field = |name: &str| -> PyResult<Bound<'_, PyAny>> {
+        record.get_item(name)?
+            .ok_or_else(|| PyValueError::new_err(format!("record is missing '{}'", name)))
+    };
+    let optional_field = |name: &str| record.get_item(name).map(|value| value.filter(|value| !value.is_none()));
+
+    let name: String = field("type")?.extract()?;
+    let transaction_type = TransactionType::deserialize(StrDeserializer::<ValueError>::new(&name))
+        .map_err(|_| PyValueError::new_err(format!("invalid transaction type '{}'", name)))?;
+
+    Ok(TransactionInput {
+        transaction_type,
+        client: field("client")?.extract()?,
+        tx: TxId::parse(field("tx")?.str()?.to_str()?.trim()),
+        amount: optional_field("amount")?.map(|amount| amount.extract::<Decimal>()).transpose()?,
+        effective_date: optional_field("effective_date")?.map(|date| date.extract::<NaiveDate>()).transpose()?,
+        timestamp: optional_field("timestamp")?.map(|time| time.extract::<DateTime<Utc>>()).transpose()?,
+        reason_code: optional_field("reason_code")?.map(|code| code.extract::<String>()).transpose()?,
+        metadata: optional_field("metadata")?
+            .map(|metadata| -> PyResult<serde_json::Value> {
+                serde_json::from_str(&metadata.extract::<String>()?)
+                    .map_err(|err| PyValueError::new_err(format!("invalid metadata: {}", err)))
+            })
+            .transpose()?,
+        partition: optional_field("partition")?.map(|partition| partition.extract::<u32>()).transpose()?,
+        idempotency_key: optional_field("idempotency_key")?.map(|key| key.extract::<String>()).transpose()?,
+        target_client: optional_field("target_client")?.map(|client| client.extract::<u16>()).transpose()?,
+    })
+}
+
+#[pymodule]
+fn trx_processor(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyTransactionProcessor>()
+}
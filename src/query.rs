@@ -0,0 +1,527 @@
+use std::cmp::Ordering;
+use std::io::Write;
+
+use rust_decimal::Decimal;
+
+use crate::model::account::{AccountRow, AmountFormat};
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionState, TxId};
+use crate::processor::TransactionProcessor;
+
+const ACCOUNT_COLUMNS: [&str; 13] = [
+    "client", "available", "held", "total", "locked", "frozen", "shortfall", "needs_review", "tx_count", "disputes",
+    "last_activity", "risk_score", "merged_into",
+];
+const TRANSACTION_COLUMNS: [&str; 8] = ["tx", "client", "type", "amount", "state", "timestamp", "reason_code", "shortfall"];
+
+/// A query of the `query` subcommand over the state a run left behind
+///
+/// ```text
+/// select <column>, ... | * from accounts | transactions
+///     [where <condition>] [order by <column> [asc|desc], ...] [limit <n>]
+/// ```
+///
+/// A condition compares a column with a literal (`=`, `!=`, `<>`, `<`, `<=`, `>`, `>=`),
+/// tests it with `is [not] null`, and combines these with `and`, `or`, `not` and
+/// parentheses. Literals are numbers, `'text'`, `true`, `false` and `null`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    table: Table,
+    columns: Vec<String>,
+    filter: Option<Expr>,
+    order_by: Vec<(String, bool)>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Table {
+    Accounts,
+    Transactions,
+}
+
+impl Table {
+    fn columns(self) -> &'static [&'static str] {
+        match self {
+            Table::Accounts => &ACCOUNT_COLUMNS,
+            Table::Transactions => &TRANSACTION_COLUMNS,
+        }
+    }
+}
+
+/// A cell of a queried row. Amounts and counts compare as numbers, timestamps as
+/// RFC3339 text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(u64),
+    Amount(Decimal),
+    Text(String),
+}
+
+impl Value {
+    fn number(&self) -> Option<Decimal> {
+        match self {
+            Value::Integer(n) => Some(Decimal::from(*n)),
+            Value::Amount(amount) => Some(*amount),
+            _ => None,
+        }
+    }
+
+    /// Order of the values of different types when sorting, so a column with nulls sorts
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Integer(_) | Value::Amount(_) => 2,
+            Value::Text(_) => 3,
+        }
+    }
+
+    fn sort_cmp(&self, other: &Value) -> Ordering {
+        self.compare(other).unwrap_or_else(|| self.rank().cmp(&other.rank()))
+    }
+
+    /// Only values of the same kind compare, as `'10' < 9` is more likely a mistake than intended
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (a, b) => Some(a.number()?.cmp(&b.number()?)),
+        }
+    }
+
+    fn to_field(&self, amount_format: AmountFormat) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Bool(value) => value.to_string(),
+            Value::Integer(n) => n.to_string(),
+            Value::Amount(amount) => amount_format.format(*amount),
+            Value::Text(text) => text.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Text(text) => write!(f, "'{}'", text),
+            other => write!(f, "{}", other.to_field(AmountFormat::Raw)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Compare(String, Operator, Value),
+    IsNull(String, bool),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn columns(&self) -> Vec<&str> {
+        match self {
+            Expr::Compare(column, _, _) | Expr::IsNull(column, _) => vec![column],
+            Expr::Not(expr) => expr.columns(),
+            Expr::And(a, b) | Expr::Or(a, b) => [a.columns(), b.columns()].concat(),
+        }
+    }
+
+    fn matches(&self, row: &Row) -> Result<bool, ProcessorError> {
+        Ok(match self {
+            Expr::Compare(column, operator, literal) => {
+                let value = row.get(column);
+                // Like SQL, a comparison with a missing value is never true
+                if *value == Value::Null || *literal == Value::Null {
+                    return Ok(false);
+                }
+                let ordering = value.compare(literal).ok_or_else(|| {
+                    query_error(format!("cannot compare {} ({}) with {}", column, value, literal))
+                })?;
+                match operator {
+                    Operator::Eq => ordering == Ordering::Equal,
+                    Operator::Ne => ordering != Ordering::Equal,
+                    Operator::Lt => ordering == Ordering::Less,
+                    Operator::Le => ordering != Ordering::Greater,
+                    Operator::Gt => ordering == Ordering::Greater,
+                    Operator::Ge => ordering != Ordering::Less,
+                }
+            }
+            Expr::IsNull(column, negated) => (*row.get(column) == Value::Null) != *negated,
+            Expr::Not(expr) => !expr.matches(row)?,
+            Expr::And(a, b) => a.matches(row)? && b.matches(row)?,
+            Expr::Or(a, b) => a.matches(row)? || b.matches(row)?,
+        })
+    }
+}
+
+/// A row with a value for every column of its table
+struct Row {
+    columns: &'static [&'static str],
+    values: Vec<Value>,
+}
+
+impl Row {
+    /// Columns were checked when the query was parsed
+    fn get(&self, column: &str) -> &Value {
+        let position = self.columns.iter().position(|name| *name == column).expect("column checked by the parser");
+        &self.values[position]
+    }
+}
+
+fn query_error(message: String) -> ProcessorError {
+    ProcessorError::InvalidArguments(format!("query: {}", message))
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self, ProcessorError> {
+        let mut parser = Parser { tokens: tokenize(text)?, position: 0 };
+        let query = parser.query()?;
+        if let Some(token) = parser.peek() {
+            return Err(query_error(format!("unexpected {}", token)));
+        }
+
+        let known = query.table.columns();
+        let filter_columns = query.filter.as_ref().map(Expr::columns).unwrap_or_default();
+        let order_columns = query.order_by.iter().map(|(column, _)| column.as_str());
+        for column in query.columns.iter().map(String::as_str).chain(filter_columns).chain(order_columns) {
+            if !known.contains(&column) {
+                return Err(query_error(format!("unknown column '{}', expected one of {}", column, known.join(", "))));
+            }
+        }
+        Ok(query)
+    }
+
+    /// Runs the query over the accounts or stored transactions of `processor`
+    pub fn run(&self, processor: &TransactionProcessor) -> Result<(Vec<String>, Vec<Vec<Value>>), ProcessorError> {
+        let mut rows = Vec::new();
+        for row in table_rows(self.table, processor)? {
+            if self.filter.as_ref().map_or(Ok(true), |filter| filter.matches(&row))? {
+                rows.push(row);
+            }
+        }
+        // Stable, so rows equal in every sort column keep their client or tx id order
+        rows.sort_by(|a, b| {
+            self.order_by.iter().fold(Ordering::Equal, |ordering, (column, descending)| {
+                ordering.then_with(|| {
+                    let ordering = a.get(column).sort_cmp(b.get(column));
+                    if *descending { ordering.reverse() } else { ordering }
+                })
+            })
+        });
+        rows.truncate(self.limit.unwrap_or(usize::MAX));
+
+        let values = rows.iter().map(|row| self.columns.iter().map(|column| row.get(column).clone()).collect()).collect();
+        Ok((self.columns.clone(), values))
+    }
+
+    /// Runs the query and writes the result as CSV with a header row
+    pub fn write(&self, processor: &TransactionProcessor, output: &mut dyn Write) -> Result<(), ProcessorError> {
+        let (columns, rows) = self.run(processor)?;
+        let mut writer = csv::Writer::from_writer(output);
+        writer.write_record(&columns)?;
+        for row in rows {
+            writer.write_record(row.iter().map(|value| value.to_field(processor.amount_format())))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn table_rows(table: Table, processor: &TransactionProcessor) -> Result<Vec<Row>, ProcessorError> {
+    let columns = table.columns();
+    let timestamp = |timestamp: Option<chrono::DateTime<chrono::Utc>>| {
+        timestamp.map_or(Value::Null, |timestamp| Value::Text(timestamp.to_rfc3339()))
+    };
+    Ok(match table {
+        Table::Accounts => processor
+            .accounts()?
+            .iter()
+            .map(|account| {
+                let risk_score = match processor.account_row(account) {
+                    AccountRow::V1(row) => row.risk_score,
+                    AccountRow::V2(row) => row.risk_score,
+                };
+                let values = vec![
+                    Value::Integer(account.client_id.into()),
+                    Value::Amount(account.available),
+                    Value::Amount(account.held),
+                    Value::Amount(account.available + account.held),
+                    Value::Bool(account.locked),
+                    Value::Bool(account.frozen),
+                    Value::Amount(account.shortfall),
+                    Value::Bool(account.needs_review),
+                    Value::Integer(account.tx_count),
+                    Value::Integer(account.disputes),
+                    timestamp(account.last_activity),
+                    risk_score.map_or(Value::Null, |score| Value::Integer(score.into())),
+                    account.merged_into.map_or(Value::Null, |target| Value::Integer(target.into())),
+                ];
+                Row { columns, values }
+            })
+            .collect(),
+        Table::Transactions => processor
+            .transactions()?
+            .into_iter()
+            .map(|tx| {
+                let values = vec![
+                    match tx.tx_id {
+                        TxId::Numeric(n) => Value::Integer(n),
+                        TxId::Text(text) => Value::Text(text),
+                    },
+                    Value::Integer(tx.client_id.into()),
+                    Value::Text(tx.transaction_type.to_string()),
+                    Value::Amount(tx.amount),
+                    Value::Text(state_name(&tx.state).to_string()),
+                    timestamp(tx.timestamp),
+                    tx.reason_code.map_or(Value::Null, Value::Text),
+                    tx.shortfall.map_or(Value::Null, Value::Amount),
+                ];
+                Row { columns, values }
+            })
+            .collect(),
+    })
+}
+
+fn state_name(state: &TransactionState) -> &'static str {
+    match state {
+        TransactionState::Normal => "normal",
+        TransactionState::UnderDispute => "under_dispute",
+        TransactionState::ChargedBack => "charged_back",
+        TransactionState::Reserved => "reserved",
+        TransactionState::Captured => "captured",
+        TransactionState::Cancelled => "cancelled",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(Decimal),
+    Text(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Number(n) => write!(f, "'{}'", n),
+            Token::Text(text) => write!(f, "'{}'", text),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ProcessorError> {
+    const SYMBOLS: [&str; 11] = ["<=", ">=", "!=", "<>", "=", "<", ">", ",", "*", "(", ")"];
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if c == '\'' {
+            // '' inside a string is a quote, as in SQL
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '\'')) if rest[1 + i + 1..].starts_with('\'') => {
+                        value.push('\'');
+                        chars.next();
+                    }
+                    Some((i, '\'')) => break 1 + i + 1,
+                    Some((_, c)) => value.push(c),
+                    None => return Err(query_error("unterminated string".to_string())),
+                }
+            };
+            tokens.push(Token::Text(value));
+            rest = &rest[end..];
+        } else if c.is_ascii_digit() || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let end = rest[1..].find(|c: char| !c.is_ascii_digit() && c != '.').map_or(rest.len(), |i| i + 1);
+            let number = rest[..end].parse().map_err(|_| query_error(format!("invalid number '{}'", &rest[..end])))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_ascii_lowercase()));
+            rest = &rest[end..];
+        } else {
+            return Err(query_error(format!("unexpected '{}'", c)));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, `or` binding weaker than `and` and `and` weaker than `not`
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, ProcessorError> {
+        let token = self.peek().cloned().ok_or_else(|| query_error("unexpected end of query".to_string()))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(word)) if word == keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(found)) if *found == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ProcessorError> {
+        if self.keyword(keyword) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(token) => Err(query_error(format!("expected '{}', found {}", keyword, token))),
+            None => Err(query_error(format!("expected '{}'", keyword))),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, ProcessorError> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            token => Err(query_error(format!("expected a column, found {}", token))),
+        }
+    }
+
+    fn query(&mut self) -> Result<Query, ProcessorError> {
+        self.expect_keyword("select")?;
+        let columns = if self.symbol("*") {
+            Vec::new()
+        } else {
+            let mut columns = vec![self.identifier()?];
+            while self.symbol(",") {
+                columns.push(self.identifier()?);
+            }
+            columns
+        };
+
+        self.expect_keyword("from")?;
+        let table = match self.next()? {
+            Token::Word(word) if word == "accounts" => Table::Accounts,
+            Token::Word(word) if word == "transactions" => Table::Transactions,
+            token => return Err(query_error(format!("unknown table {}, expected accounts or transactions", token))),
+        };
+        let columns = if columns.is_empty() { table.columns().iter().map(|column| column.to_string()).collect() } else { columns };
+
+        let filter = if self.keyword("where") { Some(self.or()?) } else { None };
+
+        let mut order_by = Vec::new();
+        if self.keyword("order") {
+            self.expect_keyword("by")?;
+            loop {
+                let column = self.identifier()?;
+                let descending = self.keyword("desc");
+                if !descending {
+                    self.keyword("asc");
+                }
+                order_by.push((column, descending));
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        let limit = if self.keyword("limit") {
+            match self.next()? {
+                Token::Number(n) if n.fract().is_zero() && n.is_sign_positive() => {
+                    Some(n.try_into().map_err(|_| query_error(format!("invalid limit {}", n)))?)
+                }
+                token => return Err(query_error(format!("expected a row count after 'limit', found {}", token))),
+            }
+        } else {
+            None
+        };
+
+        Ok(Query { table, columns, filter, order_by, limit })
+    }
+
+    fn or(&mut self) -> Result<Expr, ProcessorError> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ProcessorError> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, ProcessorError> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.symbol("(") {
+            let expr = self.or()?;
+            if !self.symbol(")") {
+                return Err(query_error("missing ')'".to_string()));
+            }
+            return Ok(expr);
+        }
+
+        let column = self.identifier()?;
+        if self.keyword("is") {
+            let negated = self.keyword("not");
+            self.expect_keyword("null")?;
+            return Ok(Expr::IsNull(column, negated));
+        }
+        let operator = match self.next()? {
+            Token::Symbol("=") => Operator::Eq,
+            Token::Symbol("!=") | Token::Symbol("<>") => Operator::Ne,
+            Token::Symbol("<") => Operator::Lt,
+            Token::Symbol("<=") => Operator::Le,
+            Token::Symbol(">") => Operator::Gt,
+            Token::Symbol(">=") => Operator::Ge,
+            token => return Err(query_error(format!("expected a comparison after {}, found {}", column, token))),
+        };
+        let literal = match self.next()? {
+            Token::Number(n) => Value::Amount(n),
+            Token::Text(text) => Value::Text(text),
+            Token::Word(word) if word == "true" => Value::Bool(true),
+            Token::Word(word) if word == "false" => Value::Bool(false),
+            Token::Word(word) if word == "null" => Value::Null,
+            token => return Err(query_error(format!("expected a value after {}, found {}", column, token))),
+        };
+        Ok(Expr::Compare(column, operator, literal))
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::BTreeSet;
+use std::fs;
+
+use crate::model::error::ProcessorError;
+
+/// Visa and Mastercard chargeback reason codes accepted when no `--reason-codes` list is given
+const CARD_NETWORK_CODES: [&str; 38] = [
+    // Visa: fraud, authorization, processing errors and consumer disputes
+    "10.1", "10.2", "10.3", "10.4", "10.5",
+    "11.1", "11.2", "11.3",
+    "12.1", "12.2", "12.3", "12.4", "12.5", "12.6", "12.7",
+    "13.1", "13.2", "13.3", "13.4", "13.5", "13.6", "13.7", "13.8", "13.9",
+    // Mastercard
+    "4808", "4812", "4831", "4834", "4837", "4840", "4849", "4853", "4855", "4859", "4860", "4863", "4870", "4871",
+];
+
+/// Codes accepted in the `reason_code` column of dispute and chargeback rows
+#[derive(Debug, Clone)]
+pub struct ReasonCodes {
+    codes: BTreeSet<String>,
+}
+
+impl Default for ReasonCodes {
+    fn default() -> Self {
+        ReasonCodes {
+            codes: CARD_NETWORK_CODES.iter().map(|code| code.to_string()).collect(),
+        }
+    }
+}
+
+impl ReasonCodes {
+    /// Loads a code list with one code per line. Blank lines and `#` comments are skipped.
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let codes = fs::read_to_string(path)?
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|code| !code.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(ReasonCodes { codes })
+    }
+
+    pub fn contains(&self, code: &str) -> bool {
+        self.codes.contains(code)
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rust_decimal::Decimal;
+
+use crate::model::transaction::{TransactionInput, TransactionType, TxId};
+
+/// Balances of one client in the reference engine
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReferenceAccount {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DepositState {
+    Normal,
+    UnderDispute,
+    ChargedBack,
+}
+
+#[derive(Debug)]
+struct Deposit {
+    client: u16,
+    amount: Decimal,
+    state: DepositState,
+}
+
+/// Deliberately simple, single-threaded engine used as the oracle in differential tests.
+/// It covers deposits, withdrawals, disputes, resolves and chargebacks with the default
+/// options and skips every other transaction type.
+#[derive(Debug, Default)]
+pub struct ReferenceEngine {
+    accounts: BTreeMap<u16, ReferenceAccount>,
+    deposits: HashMap<TxId, Deposit>,
+}
+
+impl ReferenceEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(&mut self, record: &TransactionInput) {
+        let account = self.accounts.entry(record.client).or_default();
+
+        match record.transaction_type {
+            TransactionType::Deposit => {
+                let Some(amount) = record.amount.filter(|amount| *amount > Decimal::ZERO) else {
+                    return;
+                };
+                if !account.locked {
+                    account.available += amount;
+                    self.deposits.insert(record.tx.clone(), Deposit {
+                        client: record.client,
+                        amount,
+                        state: DepositState::Normal,
+                    });
+                }
+            }
+            TransactionType::Withdrawal => {
+                let Some(amount) = record.amount.filter(|amount| *amount > Decimal::ZERO) else {
+                    return;
+                };
+                if !account.locked && account.available >= amount {
+                    account.available -= amount;
+                }
+            }
+            TransactionType::Dispute => {
+                let Some(deposit) = self.deposits.get_mut(&record.tx) else {
+                    return;
+                };
+                if deposit.client == record.client
+                    && deposit.state == DepositState::Normal
+                    && account.available >= deposit.amount
+                {
+                    account.available -= deposit.amount;
+                    account.held += deposit.amount;
+                    deposit.state = DepositState::UnderDispute;
+                }
+            }
+            TransactionType::Resolve => {
+                let Some(deposit) = self.deposits.get_mut(&record.tx) else {
+                    return;
+                };
+                if deposit.client == record.client
+                    && deposit.state == DepositState::UnderDispute
+                    && account.held >= deposit.amount
+                {
+                    account.held -= deposit.amount;
+                    account.available += deposit.amount;
+                    deposit.state = DepositState::Normal;
+                }
+            }
+            TransactionType::Chargeback => {
+                let Some(deposit) = self.deposits.get_mut(&record.tx) else {
+                    return;
+                };
+                if deposit.client == record.client
+                    && deposit.state == DepositState::UnderDispute
+                    && account.held >= deposit.amount
+                {
+                    account.held -= deposit.amount;
+                    account.locked = true;
+                    deposit.state = DepositState::ChargedBack;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Accounts by client id
+    pub fn accounts(&self) -> &BTreeMap<u16, ReferenceAccount> {
+        &self.accounts
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Instant;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::clock::Clock;
+use crate::model::error::ProcessorError;
+use crate::processor::open_reader;
+
+/// Parses a `--speed` factor such as `10x`, `0.5x` or `10`
+pub fn parse_speed(value: &str) -> Option<f64> {
+    let speed: f64 = value.strip_suffix('x').unwrap_or(value).parse().ok()?;
+    (speed.is_finite() && speed > 0.0).then_some(speed)
+}
+
+/// Writes the rows of a timestamped file to stdout at the pace they were recorded,
+/// sped up by `speed`, so streaming consumers see realistic inter-arrival times.
+/// Rows without a timestamp follow the previous row immediately. With `restamp`, the
+/// timestamp of every row is replaced by the time of `clock` it was replayed at.
+pub fn run_replay(input_file: &str, speed: f64, restamp: bool, clock: &dyn Clock) -> Result<(), ProcessorError> {
+    replay(open_reader(input_file)?, io::stdout().lock(), speed, restamp, clock)
+}
+
+/// `run_replay` of the rows of `reader` to `output`
+pub fn replay<R: Read, W: Write>(
+    mut reader: csv::Reader<R>,
+    output: W,
+    speed: f64,
+    restamp: bool,
+    clock: &dyn Clock,
+) -> Result<(), ProcessorError> {
+    let headers = reader.headers()?.clone();
+    let Some(column) = headers.iter().position(|header| header == "timestamp") else {
+        return Err(ProcessorError::InvalidArguments("replaying requires a timestamp column".to_string()));
+    };
+
+    let mut writer = csv::Writer::from_writer(output);
+    writer.write_record(&headers)?;
+    writer.flush()?;
+
+    let started = Instant::now();
+    let mut first = None;
+    for record in reader.records() {
+        let mut record = record?;
+
+        if let Some(at) = record.get(column).and_then(|cell| cell.parse::<DateTime<Utc>>().ok()) {
+            let first = *first.get_or_insert(at);
+            // Rows stamped before the first one are replayed right away
+            let offset = (at - first).to_std().unwrap_or_default().div_f64(speed);
+            if let Some(wait) = offset.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+
+        if restamp {
+            let now = clock.now().to_rfc3339_opts(SecondsFormat::Millis, true);
+            record = record
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| if i == column { now.as_str() } else { cell })
+                .collect();
+        }
+
+        // Flushed per row so a consumer on the other end of a pipe sees it right away
+        writer.write_record(&record)?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
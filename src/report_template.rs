@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::DateTime;
+use serde::Serialize;
+use tera::{Context, Tera, Value};
+
+use crate::input;
+use crate::model::account::AccountRow;
+use crate::model::error::ProcessorError;
+use crate::processor::TransactionProcessor;
+use crate::summary::RunSummary;
+
+/// Tera template the account report is rendered with, selected with `--report-template`.
+/// It sees:
+///
+/// - `accounts`: the report rows, with the columns of the output schema and amounts in
+///   the `--amount-format`
+/// - `summary`: the run summary, as in the JSON run report
+/// - `input_file`: the name of the input file
+/// - `generated_at`: when the report was rendered, RFC3339 in UTC
+/// - `transaction_mix` and `rejection_reasons`: chart bars of the rows per transaction
+///   type and per rejection reason, each a `label`, a `count` and a `percent` of all rows
+///
+/// Besides the Tera built-ins, the `pad_left`, `pad_right` and `date` filters help with
+/// fixed-width layouts. Templates whose name ends in `.html`, `.htm` or `.xml` have their
+/// values escaped.
+pub struct ReportTemplate {
+    tera: Tera,
+    name: String,
+}
+
+/// One bar of a chart
+#[derive(Debug, Serialize)]
+struct Bar {
+    label: String,
+    count: u64,
+    percent: f64,
+}
+
+impl ReportTemplate {
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let name = Path::new(path).file_name().map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+        let mut tera = Self::engine();
+        tera.add_template_file(path, Some(&name)).map_err(template_error)?;
+        Ok(ReportTemplate { tera, name })
+    }
+
+    /// The single-file HTML dashboard of `--output-format html`
+    pub fn dashboard() -> Result<Self, ProcessorError> {
+        let name = "dashboard.html".to_string();
+        let mut tera = Self::engine();
+        tera.add_raw_template(&name, include_str!("dashboard.html")).map_err(template_error)?;
+        Ok(ReportTemplate { tera, name })
+    }
+
+    fn engine() -> Tera {
+        let mut tera = Tera::default();
+        tera.register_filter("pad_left", pad_left);
+        tera.register_filter("pad_right", pad_right);
+        tera.register_filter("date", date);
+        tera
+    }
+
+    pub fn render<W: Write>(
+        &self,
+        processor: &TransactionProcessor,
+        summary: &RunSummary,
+        input_file: &str,
+        mut output: W,
+    ) -> Result<(), ProcessorError> {
+        let accounts: Vec<AccountRow> = processor.accounts()?.iter().map(|account| processor.account_row(account)).collect();
+        let input_file = input::display_name(input_file);
+
+        let mut context = Context::new();
+        context.insert("accounts", &accounts);
+        context.insert("summary", summary);
+        context.insert("input_file", Path::new(input_file).file_name().map_or(input_file.into(), |name| name.to_string_lossy()).as_ref());
+        context.insert("generated_at", &processor.clock().now());
+        let bars = |counts: Vec<(String, u64)>| -> Vec<Bar> {
+            let rows = summary.rows_processed.max(1) as f64;
+            counts
+                .into_iter()
+                .map(|(label, count)| Bar { label, count, percent: (count as f64 * 1000.0 / rows).round() / 10.0 })
+                .collect()
+        };
+        context.insert("transaction_mix", &bars(summary.rows_by_type.clone().into_iter().collect()));
+        context.insert(
+            "rejection_reasons",
+            &bars(summary.rejections.iter().map(|(reason, count)| (reason.to_string(), *count)).collect()),
+        );
+        // Rendered in full first, so a failing template leaves no half report behind
+        let report = self.tera.render(&self.name, &context).map_err(template_error)?;
+        output.write_all(report.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Tera errors keep the reason in their source chain, e.g. the line of a syntax error
+fn template_error(err: tera::Error) -> ProcessorError {
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(&err);
+    while let Some(err) = source {
+        message.push_str(&format!(": {}", err));
+        source = err.source();
+    }
+    ProcessorError::InvalidArguments(format!("report template: {}", message))
+}
+
+/// `{{ value | pad_left(width=12, fill="0") }}` right-aligns the value in `width`
+/// characters, as fixed-width layouts want amounts. Longer values are left as they are.
+fn pad_left(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let (text, padding) = padding(value, args)?;
+    Ok(Value::String(padding + text.as_str()))
+}
+
+/// `{{ value | pad_right(width=20) }}` left-aligns the value in `width` characters
+fn pad_right(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let (text, padding) = padding(value, args)?;
+    Ok(Value::String(text + padding.as_str()))
+}
+
+/// `{{ generated_at | date(format="%Y%m%d") }}` formats an RFC3339 timestamp with a
+/// strftime pattern, `%Y-%m-%d` by default. Empty values, e.g. an account without
+/// activity, stay empty.
+fn date(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let format = args.get("format").and_then(Value::as_str).unwrap_or("%Y-%m-%d");
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(tera::Error::msg(format!("invalid date format '{}'", format)));
+    }
+    match value {
+        Value::Null => Ok(Value::String(String::new())),
+        Value::String(timestamp) => {
+            let timestamp = DateTime::parse_from_rfc3339(timestamp)
+                .map_err(|_| tera::Error::msg(format!("'{}' is not an RFC3339 timestamp", timestamp)))?;
+            Ok(Value::String(timestamp.format(format).to_string()))
+        }
+        other => Err(tera::Error::msg(format!("cannot format {} as a date", other))),
+    }
+}
+
+fn padding(value: &Value, args: &HashMap<String, Value>) -> tera::Result<(String, String)> {
+    let width = args
+        .get("width")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| tera::Error::msg("padding needs a width, e.g. pad_left(width=10)"))? as usize;
+    let fill = match args.get("fill") {
+        Some(Value::String(fill)) if fill.chars().count() == 1 => fill.clone(),
+        Some(_) => return Err(tera::Error::msg("the fill of a padding must be a single character")),
+        None => " ".to_string(),
+    };
+    let text = match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    let padding = fill.repeat(width.saturating_sub(text.chars().count()));
+    Ok((text, padding))
+}
@@ -0,0 +1,52 @@
+use std::fs;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Clock ticks per second of the CPU times in /proc, fixed at 100 on Linux
+const USER_HZ: u64 = 100;
+
+/// What a run cost, for capacity planning. Peak RSS and CPU time are read from /proc
+/// and are None on platforms without it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub wall_time_secs: f64,
+    pub cpu_time_secs: Option<f64>,
+    pub peak_rss_bytes: Option<u64>,
+    pub rows_per_sec: f64,
+    pub bytes_per_sec: f64,
+}
+
+impl ResourceUsage {
+    /// Usage of this process so far, `elapsed` being the wall time the `rows` and `bytes` took
+    pub fn measure(elapsed: Duration, rows: u64, bytes: u64) -> Self {
+        let seconds = elapsed.as_secs_f64();
+        let rate = |count: u64| if seconds > 0.0 { count as f64 / seconds } else { 0.0 };
+
+        ResourceUsage {
+            wall_time_secs: seconds,
+            cpu_time_secs: cpu_time().map(|cpu_time| cpu_time.as_secs_f64()),
+            peak_rss_bytes: peak_rss(),
+            rows_per_sec: rate(rows),
+            bytes_per_sec: rate(bytes),
+        }
+    }
+}
+
+/// User plus system time, fields 14 and 15 of /proc/self/stat
+fn cpu_time() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name in parentheses may contain spaces, the fields after it are the third onwards
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(Duration::from_millis((utime + stime) * 1_000 / USER_HZ))
+}
+
+/// High water mark of the resident set, `VmHWM` of /proc/self/status
+pub fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find_map(|line| line.strip_prefix("VmHWM:"))?;
+    let kilobytes: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kilobytes * 1024)
+}
@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::chaos::Lcg;
+use crate::model::error::ProcessorError;
+
+/// Operations failing in a row, each after all its attempts, that open the circuit
+const BREAKER_FAILURES: u32 = 5;
+/// How long an open circuit fails operations without trying the store
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// How often and how patiently a failing store operation is tried, set with
+/// `--store-retries` and `--store-backoff`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in all, the first one included
+    pub attempts: u32,
+    /// Longest wait before the first retry, doubled for every retry after it
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { attempts: 3, backoff: Duration::from_millis(50), max_backoff: Duration::from_secs(2) }
+    }
+}
+
+/// Whether trying again may help: store and I/O errors, and conflicts with other writers that
+/// the operation resolves before its next attempt, see `BatchStore::commit`. Not bad data.
+fn is_transient(err: &ProcessorError) -> bool {
+    matches!(err, ProcessorError::StoreError(_) | ProcessorError::IoError(_) | ProcessorError::StoreConflict(_))
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    /// Operations that failed in a row
+    failures: u32,
+    /// Until when the circuit is open
+    open_until: Option<Instant>,
+}
+
+/// Retry counts of a run, for the summary and the metrics
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetryStats {
+    /// Attempts that failed and were tried again
+    pub retries: u64,
+    /// Operations that failed through every attempt or with the circuit open
+    pub failures: u64,
+    /// Times the circuit opened
+    pub circuit_opened: u64,
+}
+
+/// Runs store operations under a `RetryPolicy`: transient failures are retried after an
+/// exponential backoff with full jitter, so that instances retrying together spread out.
+/// Once `BREAKER_FAILURES` operations failed in a row the circuit opens, and for
+/// `BREAKER_COOLDOWN` every operation fails at once rather than waiting on a store that
+/// is down. The first operation after that tries the store again.
+pub struct Retrier {
+    policy: RetryPolicy,
+    breaker: Mutex<Breaker>,
+    jitter: Mutex<Lcg>,
+    retries: AtomicU64,
+    failures: AtomicU64,
+    circuit_opened: AtomicU64,
+}
+
+impl Retrier {
+    pub fn new(policy: RetryPolicy) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_nanos() as u64);
+        Retrier {
+            policy,
+            breaker: Mutex::new(Breaker::default()),
+            jitter: Mutex::new(Lcg(seed ^ std::process::id() as u64)),
+            retries: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            circuit_opened: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error retrying cannot help, or runs
+    /// out of attempts. Transient errors it gives up on come back as `StoreUnavailable`.
+    pub fn run<T>(&self, mut operation: impl FnMut() -> Result<T, ProcessorError>) -> Result<T, ProcessorError> {
+        if let Some(open_until) = self.breaker.lock().open_until {
+            if Instant::now() < open_until {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(ProcessorError::StoreUnavailable("circuit open after repeated store failures".to_string()));
+            }
+        }
+
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Ok(value) => {
+                    *self.breaker.lock() = Breaker::default();
+                    return Ok(value);
+                }
+                Err(err) if !is_transient(&err) => return Err(err),
+                Err(_) if attempt < self.policy.attempts => {
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    thread::sleep(self.backoff(attempt));
+                    attempt += 1;
+                }
+                Err(err) => {
+                    self.failed();
+                    return Err(ProcessorError::StoreUnavailable(format!("{} attempt(s) failed, the last with: {}", attempt, err)));
+                }
+            }
+        }
+    }
+
+    /// A random wait of up to the backoff of the retry, which doubles with every attempt
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self.policy.backoff.saturating_mul(1 << (attempt - 1).min(16)).min(self.policy.max_backoff);
+        let micros = ceiling.as_micros() as u64;
+        Duration::from_micros(self.jitter.lock().next(micros + 1))
+    }
+
+    fn failed(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let mut breaker = self.breaker.lock();
+        breaker.failures += 1;
+        // A failure right after the cool-down opens the circuit again at once
+        if breaker.failures >= BREAKER_FAILURES || breaker.open_until.is_some() {
+            breaker.failures = 0;
+            breaker.open_until = Some(Instant::now() + BREAKER_COOLDOWN);
+            self.circuit_opened.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> RetryStats {
+        RetryStats {
+            retries: self.retries.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            circuit_opened: self.circuit_opened.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType, TxId};
+
+/// A row held back for manual approval, with why it was held
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ParkedTransaction {
+    pub client: u16,
+    pub tx: TxId,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub amount: Option<Decimal>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub reason: String,
+}
+
+impl ParkedTransaction {
+    /// The row as it is applied once approved
+    pub fn to_input(&self) -> TransactionInput {
+        TransactionInput {
+            transaction_type: self.transaction_type.clone(),
+            client: self.client,
+            tx: self.tx.clone(),
+            amount: self.amount,
+            effective_date: None,
+            timestamp: self.timestamp,
+            reason_code: None,
+            metadata: None,
+            partition: None,
+            idempotency_key: None,
+            target_client: None,
+        }
+    }
+}
+
+/// Deposits and withdrawals waiting for an `approve` or `reject` row, kept in a JSON file
+/// given with `--review-queue` so they can be decided in a later run
+pub struct ReviewQueue {
+    path: String,
+    /// Amount above which deposits and withdrawals are parked, set with `--review-over`
+    limit: Option<Decimal>,
+    parked: Mutex<BTreeMap<TxId, ParkedTransaction>>,
+}
+
+impl ReviewQueue {
+    /// Loads the queue left by earlier runs, an absent file is an empty queue
+    pub fn open(path: &str) -> Result<Self, ProcessorError> {
+        let parked: Vec<ParkedTransaction> = match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(ReviewQueue {
+            path: path.to_string(),
+            limit: None,
+            parked: Mutex::new(parked.into_iter().map(|parked| (parked.tx.clone(), parked)).collect()),
+        })
+    }
+
+    pub fn with_limit(mut self, limit: Option<Decimal>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn over_limit(&self, record: &TransactionInput) -> bool {
+        matches!(record.transaction_type, TransactionType::Deposit | TransactionType::Withdrawal)
+            && matches!((record.amount, self.limit), (Some(amount), Some(limit)) if amount > limit)
+    }
+
+    /// Parks a row, replacing an earlier delivery of the same tx id
+    pub fn park(&self, record: &TransactionInput, reason: String) {
+        self.parked.lock().insert(record.tx.clone(), ParkedTransaction {
+            client: record.client,
+            tx: record.tx.clone(),
+            transaction_type: record.transaction_type.clone(),
+            amount: record.amount,
+            timestamp: record.timestamp,
+            reason,
+        });
+    }
+
+    pub fn get(&self, tx: &TxId) -> Option<ParkedTransaction> {
+        self.parked.lock().get(tx).cloned()
+    }
+
+    pub fn remove(&self, tx: &TxId) -> Option<ParkedTransaction> {
+        self.parked.lock().remove(tx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.parked.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Written to a temporary file and renamed, so a crash never loses the queue
+    pub fn save(&self) -> Result<(), ProcessorError> {
+        let parked: Vec<ParkedTransaction> = self.parked.lock().values().cloned().collect();
+        let tmp_path = format!("{}.tmp", self.path);
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&parked)?)?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+}
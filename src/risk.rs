@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+
+const FAILED_WITHDRAWAL_WEIGHT: u32 = 5;
+const DISPUTE_WEIGHT: u32 = 10;
+const CHARGEBACK_WEIGHT: u32 = 50;
+const VELOCITY_WEIGHT: u32 = 2;
+
+/// Transactions allowed within the velocity window before each extra one adds to the score
+const VELOCITY_LIMIT: usize = 10;
+const VELOCITY_WINDOW_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RiskEvent {
+    FailedWithdrawal,
+    Dispute,
+    Chargeback,
+}
+
+#[derive(Debug, Default, Clone)]
+struct ClientRisk {
+    failed_withdrawals: u32,
+    disputes: u32,
+    chargebacks: u32,
+    velocity_breaches: u32,
+    recent_activity: VecDeque<DateTime<Utc>>,
+}
+
+impl ClientRisk {
+    fn score(&self) -> u32 {
+        self.failed_withdrawals * FAILED_WITHDRAWAL_WEIGHT
+            + self.disputes * DISPUTE_WEIGHT
+            + self.chargebacks * CHARGEBACK_WEIGHT
+            + self.velocity_breaches * VELOCITY_WEIGHT
+    }
+}
+
+/// Streaming per-client risk score, updated as transactions are processed
+#[derive(Default)]
+pub struct RiskEngine {
+    clients: DashMap<u16, ClientRisk>,
+    lock_threshold: Option<u32>,
+}
+
+impl RiskEngine {
+    pub fn new(lock_threshold: Option<u32>) -> Self {
+        RiskEngine {
+            clients: DashMap::new(),
+            lock_threshold,
+        }
+    }
+
+    pub fn record(&self, client_id: u16, event: RiskEvent) {
+        let mut risk = self.clients.entry(client_id).or_default();
+        match event {
+            RiskEvent::FailedWithdrawal => risk.failed_withdrawals += 1,
+            RiskEvent::Dispute => risk.disputes += 1,
+            RiskEvent::Chargeback => risk.chargebacks += 1,
+        }
+    }
+
+    /// Tracks transaction velocity, only timestamped transactions count
+    pub fn record_activity(&self, client_id: u16, timestamp: DateTime<Utc>) {
+        let mut risk = self.clients.entry(client_id).or_default();
+
+        let window_start = timestamp - Duration::minutes(VELOCITY_WINDOW_MINUTES);
+        while risk.recent_activity.front().is_some_and(|t| *t <= window_start) {
+            risk.recent_activity.pop_front();
+        }
+        risk.recent_activity.push_back(timestamp);
+
+        if risk.recent_activity.len() > VELOCITY_LIMIT {
+            risk.velocity_breaches += 1;
+        }
+    }
+
+    pub fn score(&self, client_id: u16) -> u32 {
+        self.clients.get(&client_id).map(|risk| risk.score()).unwrap_or(0)
+    }
+
+    /// Returns true if the client's score has reached the configured lock threshold
+    pub fn should_lock(&self, client_id: u16) -> bool {
+        self.lock_threshold.is_some_and(|threshold| self.score(client_id) >= threshold)
+    }
+}
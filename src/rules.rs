@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{Transaction, TransactionType};
+
+const DEFAULT_TIER: &str = "standard";
+
+/// A composable condition over a disputed transaction, written in JSON as e.g.
+/// `{"all": [{"transaction_type": ["deposit"]}, {"max_age_days": 120}]}`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    TransactionType(Vec<TransactionType>),
+    MinAmount(Decimal),
+    MaxAmount(Decimal),
+    /// Days between the transaction and the dispute; fails if either lacks a timestamp
+    MaxAgeDays(i64),
+    ClientTier(Vec<String>),
+}
+
+/// Dispute eligibility configuration loaded with `--dispute-rules`
+#[derive(Debug, Deserialize, Default)]
+pub struct RulesConfig {
+    /// Client tiers keyed by client id, clients not listed are "standard"
+    #[serde(default)]
+    pub tiers: HashMap<u16, String>,
+    /// Condition a transaction must satisfy to be disputed, everything is eligible if absent
+    #[serde(default)]
+    pub dispute: Option<Condition>,
+}
+
+/// What a condition is evaluated against
+pub struct DisputeContext<'a> {
+    pub transaction: &'a Transaction,
+    pub disputed_at: Option<DateTime<Utc>>,
+    pub tier: &'a str,
+}
+
+impl RulesConfig {
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn tier(&self, client_id: u16) -> &str {
+        self.tiers.get(&client_id).map(String::as_str).unwrap_or(DEFAULT_TIER)
+    }
+
+    pub fn dispute_allowed(&self, transaction: &Transaction, disputed_at: Option<DateTime<Utc>>) -> bool {
+        let Some(condition) = &self.dispute else {
+            return true;
+        };
+
+        condition.matches(&DisputeContext {
+            transaction,
+            disputed_at,
+            tier: self.tier(transaction.client_id),
+        })
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, ctx: &DisputeContext) -> bool {
+        match self {
+            Condition::All(conditions) => conditions.iter().all(|c| c.matches(ctx)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.matches(ctx)),
+            Condition::Not(condition) => !condition.matches(ctx),
+            Condition::TransactionType(types) => types.contains(&ctx.transaction.transaction_type),
+            Condition::MinAmount(min) => ctx.transaction.amount >= *min,
+            Condition::MaxAmount(max) => ctx.transaction.amount <= *max,
+            Condition::MaxAgeDays(days) => match (ctx.transaction.timestamp, ctx.disputed_at) {
+                (Some(created), Some(disputed)) => (disputed - created).num_days() <= *days,
+                _ => false,
+            },
+            Condition::ClientTier(tiers) => tiers.iter().any(|tier| tier == ctx.tier),
+        }
+    }
+}
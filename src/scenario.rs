@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionInput, TransactionType, TxId};
+use crate::processor::TransactionProcessor;
+use crate::store::{AccountStore, MemoryAccountStore};
+
+/// A QA test case: seed accounts, transactions to apply and the balances expected afterwards
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub allow_adjustments: bool,
+    #[serde(default)]
+    pub allow_merges: bool,
+    #[serde(default)]
+    pub accounts: Vec<SeedAccount>,
+    #[serde(default)]
+    pub transactions: Vec<ScenarioTransaction>,
+    pub expected: Vec<ExpectedAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedAccount {
+    pub client: u16,
+    #[serde(default)]
+    pub available: Decimal,
+    #[serde(default)]
+    pub held: Decimal,
+    #[serde(default)]
+    pub locked: bool,
+}
+
+/// Numbers and strings are both accepted, as in a CSV cell
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ScenarioTxId {
+    Numeric(u64),
+    Text(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioTransaction {
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub client: u16,
+    pub tx: ScenarioTxId,
+    pub amount: Option<Decimal>,
+    pub effective_date: Option<NaiveDate>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub reason_code: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub target_client: Option<u16>,
+}
+
+/// Only the listed fields are checked
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedAccount {
+    pub client: u16,
+    pub available: Option<Decimal>,
+    pub held: Option<Decimal>,
+    pub total: Option<Decimal>,
+    pub locked: Option<bool>,
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_yaml::from_reader(reader)?)
+    }
+
+    /// Runs the scenario on a fresh in-memory processor.
+    /// Returns one line per mismatch between the expected and the actual accounts.
+    pub fn run(&self) -> Result<Vec<String>, ProcessorError> {
+        let seed: Vec<Account> = self.accounts
+            .iter()
+            .map(|seed| Account {
+                available: seed.available,
+                held: seed.held,
+                locked: seed.locked,
+                ..Account::new(seed.client)
+            })
+            .collect();
+        let accounts = MemoryAccountStore::new();
+        accounts.write_batch(&seed)?;
+
+        let processor = TransactionProcessor::new()
+            .with_account_store(Box::new(accounts))
+            .with_adjustments(self.allow_adjustments)
+            .with_merges(self.allow_merges);
+        for transaction in &self.transactions {
+            processor.process_record(transaction.to_input())?;
+        }
+
+        let mut mismatches = Vec::new();
+        for expected in &self.expected {
+            let Some(account) = processor.account(expected.client)? else {
+                mismatches.push(format!("client {}: account does not exist", expected.client));
+                continue;
+            };
+
+            let mut check = |field: &str, expected: Option<String>, actual: String| {
+                if let Some(expected) = expected.filter(|expected| *expected != actual) {
+                    mismatches.push(format!(
+                        "client {} {}: expected {}, got {}",
+                        account.client_id, field, expected, actual
+                    ));
+                }
+            };
+            check("available", expected.available.map(|d| d.normalize().to_string()), account.available.normalize().to_string());
+            check("held", expected.held.map(|d| d.normalize().to_string()), account.held.normalize().to_string());
+            check("total", expected.total.map(|d| d.normalize().to_string()), account.total().normalize().to_string());
+            check("locked", expected.locked.map(|locked| locked.to_string()), account.locked.to_string());
+        }
+
+        Ok(mismatches)
+    }
+}
+
+impl ScenarioTransaction {
+    fn to_input(&self) -> TransactionInput {
+        TransactionInput {
+            transaction_type: self.transaction_type.clone(),
+            client: self.client,
+            tx: match self.tx {
+                ScenarioTxId::Numeric(n) => TxId::Numeric(n),
+                ScenarioTxId::Text(ref text) => TxId::parse(text.trim()),
+            },
+            amount: self.amount,
+            effective_date: self.effective_date,
+            timestamp: self.timestamp,
+            reason_code: self.reason_code.clone(),
+            metadata: self.metadata.clone(),
+            partition: None,
+            idempotency_key: None,
+            target_client: self.target_client,
+        }
+    }
+}
+
+/// Runs each scenario file, printing PASS or FAIL with the mismatches.
+/// Fails if any scenario did not pass.
+pub fn run_scenarios(paths: &[String]) -> Result<(), ProcessorError> {
+    let mut failed = 0;
+
+    for path in paths {
+        let scenario = Scenario::load(path)?;
+        let name = scenario.name.as_deref().unwrap_or(path);
+        let mismatches = scenario.run()?;
+
+        if mismatches.is_empty() {
+            println!("PASS {}", name);
+        } else {
+            failed += 1;
+            println!("FAIL {}", name);
+            for mismatch in mismatches {
+                println!("  {}", mismatch);
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(ProcessorError::ScenarioFailed(format!("{} of {} scenario(s) failed", failed, paths.len()))),
+    }
+}
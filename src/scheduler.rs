@@ -0,0 +1,134 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::TransactionInput;
+
+/// Rows queued at most, beyond which reading waits for the workers
+const QUEUED_ROWS: usize = 100_000;
+
+#[derive(Default)]
+struct State {
+    queues: HashMap<u16, VecDeque<TransactionInput>>,
+    /// Clients with queued rows that no worker is on, in turn
+    ready: VecDeque<u16>,
+    /// Clients a worker is on; rows keep queuing for them, but they wait for their next turn
+    busy: HashSet<u16>,
+    queued: usize,
+    /// No more rows are coming
+    closed: bool,
+    /// First error of a worker, which stops every worker
+    error: Option<ProcessorError>,
+}
+
+/// Rows queued per client and handed to workers round-robin, each client at most `budget`
+/// rows per turn, so one client with millions of rows cannot keep the workers from the
+/// others. A client is on one worker at a time, so its rows are applied in order; rows of
+/// different clients may be applied in another order than they were pushed in.
+pub struct FairScheduler {
+    state: Mutex<State>,
+    work: Condvar,
+    space: Condvar,
+    budget: usize,
+}
+
+impl FairScheduler {
+    /// Rows per turn of `--client-budget`
+    pub const DEFAULT_BUDGET: usize = 64;
+
+    pub fn new(budget: usize) -> Self {
+        FairScheduler { state: Mutex::new(State::default()), work: Condvar::new(), space: Condvar::new(), budget: budget.max(1) }
+    }
+
+    /// Queues the row, waiting while the queue is full. Returns false once a worker failed,
+    /// after which rows are not taken anymore.
+    pub fn push(&self, record: TransactionInput) -> bool {
+        let mut state = self.state.lock();
+        while state.queued >= QUEUED_ROWS && state.error.is_none() {
+            self.space.wait(&mut state);
+        }
+        if state.error.is_some() {
+            return false;
+        }
+
+        let client = record.client;
+        let state = &mut *state;
+        let queue = state.queues.entry(client).or_default();
+        queue.push_back(record);
+        state.queued += 1;
+        if queue.len() == 1 && !state.busy.contains(&client) {
+            state.ready.push_back(client);
+            self.work.notify_one();
+        }
+        true
+    }
+
+    /// Tells the workers that no more rows are coming, they return once the queue is empty
+    pub fn close(&self) {
+        self.state.lock().closed = true;
+        self.work.notify_all();
+    }
+
+    /// Applies rows with `apply` until the scheduler is closed and empty, or a worker failed.
+    /// Run it on every worker thread.
+    pub fn work(&self, apply: impl Fn(TransactionInput) -> Result<(), ProcessorError>) {
+        loop {
+            let (client, turn) = {
+                let mut state = self.state.lock();
+                loop {
+                    if state.error.is_some() || (state.closed && state.queued == 0) {
+                        return;
+                    }
+                    if let Some(client) = state.ready.pop_front() {
+                        let queue = state.queues.get_mut(&client).expect("ready clients have queued rows");
+                        let turn: Vec<_> = queue.drain(..self.budget.min(queue.len())).collect();
+                        state.busy.insert(client);
+                        break (client, turn);
+                    }
+                    self.work.wait(&mut state);
+                }
+            };
+
+            let rows = turn.len();
+            let mut failure = None;
+            for record in turn {
+                if let Err(err) = apply(record) {
+                    failure = Some(err);
+                    break;
+                }
+            }
+
+            let mut state = self.state.lock();
+            state.busy.remove(&client);
+            state.queued -= rows;
+            if let Some(err) = failure {
+                state.error.get_or_insert(err);
+                drop(state);
+                self.work.notify_all();
+                self.space.notify_all();
+                return;
+            }
+            // The client goes to the back of the line if it has more rows
+            match state.queues.entry(client) {
+                Entry::Occupied(queue) if queue.get().is_empty() => {
+                    queue.remove();
+                }
+                Entry::Occupied(_) => state.ready.push_back(client),
+                Entry::Vacant(_) => {}
+            }
+            drop(state);
+            self.work.notify_all();
+            self.space.notify_all();
+        }
+    }
+
+    /// The error a worker stopped on, if any
+    pub fn finish(self) -> Result<(), ProcessorError> {
+        match self.state.into_inner().error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use rust_decimal::Decimal;
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{AmountParsing, TransactionInput, TransactionType, TxId, TxIdKind};
+use crate::processor::open_reader;
+
+/// Rows failing to parse that are listed, the others are only counted
+const LISTED_FAILURES: usize = 20;
+
+/// What the cells of a column must hold
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    TransactionType,
+    Client,
+    TxId,
+    Amount,
+    Date,
+    Timestamp,
+    Text,
+    Json,
+    Partition,
+}
+
+/// Columns of the transaction format and whether a file must have them
+const COLUMNS: [(&str, ColumnType, bool); 11] = [
+    ("type", ColumnType::TransactionType, true),
+    ("client", ColumnType::Client, true),
+    ("tx", ColumnType::TxId, true),
+    ("amount", ColumnType::Amount, true),
+    ("effective_date", ColumnType::Date, false),
+    ("timestamp", ColumnType::Timestamp, false),
+    ("reason_code", ColumnType::Text, false),
+    ("metadata", ColumnType::Json, false),
+    ("partition", ColumnType::Partition, false),
+    ("idempotency_key", ColumnType::Text, false),
+    ("target_client", ColumnType::Client, false),
+];
+
+/// Smallest and largest value seen
+#[derive(Debug, Clone, Copy)]
+struct Range<T>(T, T);
+
+impl<T: PartialOrd + Copy> Range<T> {
+    fn widen(range: &mut Option<Range<T>>, value: T) {
+        match range {
+            Some(Range(min, max)) => {
+                if value < *min {
+                    *min = value;
+                }
+                if value > *max {
+                    *max = value;
+                }
+            }
+            None => *range = Some(Range(value, value)),
+        }
+    }
+}
+
+impl<T: Display> Display for Range<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} to {}", self.0, self.1)
+    }
+}
+
+#[derive(Debug)]
+struct ColumnStats {
+    name: String,
+    column_type: ColumnType,
+    empty: u64,
+    invalid: u64,
+    first_invalid: Option<String>,
+    /// Rows per transaction type, for the type column
+    values: BTreeMap<String, u64>,
+    numbers: Option<Range<Decimal>>,
+    dates: Option<Range<NaiveDate>>,
+    timestamps: Option<Range<DateTime<Utc>>>,
+}
+
+impl ColumnStats {
+    fn new(name: &str, column_type: ColumnType) -> Self {
+        ColumnStats {
+            name: name.to_string(),
+            column_type,
+            empty: 0,
+            invalid: 0,
+            first_invalid: None,
+            values: BTreeMap::new(),
+            numbers: None,
+            dates: None,
+            timestamps: None,
+        }
+    }
+
+    fn observe(&mut self, cell: &str, tx_id_kind: TxIdKind) {
+        if cell.is_empty() {
+            self.empty += 1;
+            return;
+        }
+
+        let valid = match self.column_type {
+            ColumnType::TransactionType => {
+                *self.values.entry(cell.to_string()).or_default() += 1;
+                TransactionType::from_name(cell).is_some()
+            }
+            ColumnType::Client => cell.parse::<u16>().map(|client| Range::widen(&mut self.numbers, client.into())).is_ok(),
+            ColumnType::TxId => tx_id_kind.normalize(TxId::parse(cell)).is_some(),
+            ColumnType::Amount => AmountParsing::current().parse(cell).map(|amount| Range::widen(&mut self.numbers, amount)).is_ok(),
+            ColumnType::Date => cell.parse::<NaiveDate>().map(|date| Range::widen(&mut self.dates, date)).is_ok(),
+            ColumnType::Timestamp => cell.parse::<DateTime<Utc>>().map(|at| Range::widen(&mut self.timestamps, at)).is_ok(),
+            ColumnType::Text => true,
+            ColumnType::Json => serde_json::from_str::<serde_json::Value>(cell).is_ok(),
+            ColumnType::Partition => cell.parse::<u32>().map(|partition| Range::widen(&mut self.numbers, partition.into())).is_ok(),
+        };
+        if !valid {
+            self.invalid += 1;
+            self.first_invalid.get_or_insert_with(|| cell.to_string());
+        }
+    }
+
+    fn range(&self) -> Option<String> {
+        let numbers = self.numbers.map(|range| range.to_string());
+        let dates = self.dates.map(|range| range.to_string());
+        let timestamps = self.timestamps.map(|Range(min, max)| {
+            Range(min.to_rfc3339_opts(SecondsFormat::AutoSi, true), max.to_rfc3339_opts(SecondsFormat::AutoSi, true)).to_string()
+        });
+        numbers.or(dates).or(timestamps)
+    }
+}
+
+/// A row that would fail parsing
+#[derive(Debug)]
+struct Failure {
+    line: Option<u64>,
+    error: String,
+}
+
+/// How well a transactions file fits the input format, printed by the `schema` subcommand:
+/// which columns it has, how many cells of each are empty or not of the column's type, the
+/// range of their values, and which rows a run would fail on or dead-letter.
+#[derive(Debug)]
+pub struct SchemaReport {
+    rows: u64,
+    columns: Vec<ColumnStats>,
+    missing: Vec<&'static str>,
+    /// Columns the processor ignores
+    unknown: Vec<String>,
+    failing_rows: u64,
+    failures: Vec<Failure>,
+}
+
+impl SchemaReport {
+    /// Transaction ids are checked against `tx_id_kind`, as `--tx-id-type` would, and
+    /// amounts by the installed `AmountParsing`
+    pub fn from_file(file_path: &str, tx_id_kind: TxIdKind) -> Result<Self, ProcessorError> {
+        let mut reader = open_reader(file_path)?;
+        let headers = reader.headers()?.clone();
+
+        let mut columns: Vec<(usize, ColumnStats)> = Vec::new();
+        let mut missing = Vec::new();
+        for (name, column_type, required) in COLUMNS {
+            match headers.iter().position(|header| header == name) {
+                Some(index) => columns.push((index, ColumnStats::new(name, column_type))),
+                None if required => missing.push(name),
+                None => {}
+            }
+        }
+        let unknown = headers
+            .iter()
+            .filter(|header| !COLUMNS.iter().any(|(name, _, _)| name == header))
+            .map(str::to_string)
+            .collect();
+
+        let mut report = SchemaReport { rows: 0, columns: Vec::new(), missing, unknown, failing_rows: 0, failures: Vec::new() };
+        for result in reader.records() {
+            report.rows += 1;
+            let record = match result {
+                Ok(record) => record,
+                Err(err) => {
+                    report.fail(err.position().map(|position| position.line()), err.to_string());
+                    continue;
+                }
+            };
+            for (index, stats) in columns.iter_mut() {
+                stats.observe(record.get(*index).unwrap_or_default(), tx_id_kind);
+            }
+
+            let line = record.position().map(|position| position.line());
+            match record.deserialize::<TransactionInput>(Some(&headers)) {
+                Ok(input) if tx_id_kind.normalize(input.tx.clone()).is_none() => {
+                    report.fail(line, ProcessorError::InvalidTransactionId(input.tx.to_string()).to_string())
+                }
+                Ok(_) => {}
+                Err(err) => report.fail(line, ProcessorError::from(err).to_string()),
+            }
+        }
+        report.columns = columns.into_iter().map(|(_, stats)| stats).collect();
+        Ok(report)
+    }
+
+    fn fail(&mut self, line: Option<u64>, error: String) {
+        self.failing_rows += 1;
+        if self.failures.len() < LISTED_FAILURES {
+            self.failures.push(Failure { line, error });
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Rows: {} ({} failing to parse)", self.rows, self.failing_rows);
+
+        println!("Columns:");
+        for stats in &self.columns {
+            let mut line = format!(
+                "  {}: {} empty ({}), {} invalid",
+                stats.name,
+                stats.empty,
+                percentage(stats.empty, self.rows),
+                stats.invalid
+            );
+            if let Some(ref value) = stats.first_invalid {
+                line.push_str(&format!(" (first: '{}')", value));
+            }
+            if let Some(range) = stats.range() {
+                line.push_str(&format!(", range {}", range));
+            }
+            if !stats.values.is_empty() {
+                let values: Vec<String> = stats.values.iter().map(|(value, rows)| format!("{}={}", value, rows)).collect();
+                line.push_str(&format!(", values {}", values.join(" ")));
+            }
+            println!("{}", line);
+        }
+        for name in &self.missing {
+            println!("  {}: missing, required", name);
+        }
+        for name in &self.unknown {
+            println!("  {}: not used", name);
+        }
+
+        if !self.failures.is_empty() {
+            println!("Rows failing to parse:");
+            for failure in &self.failures {
+                match failure.line {
+                    Some(line) => println!("  line {}: {}", line, failure.error),
+                    None => println!("  {}", failure.error),
+                }
+            }
+            if self.failing_rows > self.failures.len() as u64 {
+                println!("  ... and {} more", self.failing_rows - self.failures.len() as u64);
+            }
+        }
+    }
+}
+
+fn percentage(part: u64, whole: u64) -> String {
+    match whole {
+        0 => "n/a".to_string(),
+        _ => format!("{:.2}%", part as f64 / whole as f64 * 100.0),
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::http;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::TransactionInput;
+use crate::processor::open_reader;
+
+const DEFAULT_PORT: u16 = 80;
+
+/// Outcome of screening a row
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScreeningResult {
+    Clear,
+    /// The client is flagged, with the reason given by the screening source
+    Hit(String),
+}
+
+/// Sanctions or denylist check run before every deposit and withdrawal is applied.
+/// An error fails the screening closed: the row is rejected as if the client was flagged.
+pub trait Screening: Send + Sync {
+    fn screen(&self, record: &TransactionInput) -> Result<ScreeningResult, ProcessorError>;
+}
+
+#[derive(Deserialize)]
+struct DenylistEntry {
+    client: u16,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Clients listed in a CSV file with a `client` and an optional `reason` column,
+/// loaded with `--screening-denylist`
+pub struct Denylist {
+    clients: HashMap<u16, String>,
+}
+
+impl Denylist {
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let mut clients = HashMap::new();
+        for entry in open_reader(path)?.deserialize() {
+            let entry: DenylistEntry = entry?;
+            clients.insert(entry.client, entry.reason.filter(|reason| !reason.is_empty()).unwrap_or_else(|| "denylisted".to_string()));
+        }
+        Ok(Denylist { clients })
+    }
+}
+
+impl Screening for Denylist {
+    fn screen(&self, record: &TransactionInput) -> Result<ScreeningResult, ProcessorError> {
+        Ok(match self.clients.get(&record.client) {
+            Some(reason) => ScreeningResult::Hit(reason.clone()),
+            None => ScreeningResult::Clear,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct CalloutAnswer {
+    hit: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Screening service called for every row with `--screening-url`. It is posted
+/// `{"client": 1, "tx": "7", "type": "deposit", "amount": "100"}` and answers
+/// `{"hit": true, "reason": "ofac"}` or `{"hit": false}`.
+pub struct HttpScreening {
+    address: String,
+    host: String,
+    path: String,
+}
+
+impl HttpScreening {
+    /// Calls `http://host[:port][/path]`
+    pub fn new(url: &str) -> Result<Self, ProcessorError> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(ProcessorError::InvalidArguments(format!(
+                "unsupported screening URL {}, expected http://<host>[:port][/path]",
+                url
+            )));
+        };
+        let (host, path) = match rest.find('/') {
+            Some(position) => rest.split_at(position),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(ProcessorError::InvalidArguments(format!("screening URL {} has no host", url)));
+        }
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+
+        Ok(HttpScreening { address, host: host.to_string(), path: path.to_string() })
+    }
+}
+
+impl Screening for HttpScreening {
+    fn screen(&self, record: &TransactionInput) -> Result<ScreeningResult, ProcessorError> {
+        let body = serde_json::to_vec(&json!({
+            "client": record.client,
+            "tx": record.tx,
+            "type": record.transaction_type,
+            "amount": record.amount,
+        }))?;
+
+        let response = http::post_json(&self.address, &self.host, &self.path, &body)?;
+        if response.status != 200 {
+            return Err(std::io::Error::other(format!("screening service answered {}", response.status)).into());
+        }
+        let answer: CalloutAnswer = serde_json::from_slice(&response.body)?;
+        Ok(match answer.hit {
+            true => ScreeningResult::Hit(answer.reason.unwrap_or_else(|| "screening hit".to_string())),
+            false => ScreeningResult::Clear,
+        })
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::Path;
+
+/// Name of a script in errors, its file name
+fn script_name(path: &str) -> String {
+    Path::new(path).file_name().map_or_else(|| path.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+#[cfg(feature = "scripting")]
+mod host {
+    use rhai::module_resolvers::DummyModuleResolver;
+    use rhai::{Dynamic, Engine, Map, Scope, AST};
+    use rust_decimal::Decimal;
+
+    use super::script_name;
+    use crate::model::account::Account;
+    use crate::model::error::ProcessorError;
+    use crate::model::transaction::TransactionInput;
+    use crate::plugin::Decision;
+
+    /// Operations a call may run before it is stopped, far more than any rule needs
+    const MAX_OPERATIONS: u64 = 1_000_000;
+
+    /// Fee and limit rules loaded with `--script`, a [Rhai](https://rhai.rs) script defining
+    /// either or both of
+    ///
+    /// - `fee(tx, account)`: the fee of a deposit or withdrawal, `()` or `0` for none
+    /// - `check(tx, account)`: a reason to reject the row, `()` to let it through
+    ///
+    /// `tx` is a map of `type`, `client`, `tx`, `amount`, `timestamp` and `reason_code`,
+    /// `account` one of `available`, `held`, `total`, `locked` and `frozen`, or `()` before the
+    /// client's first row. Amounts are decimals, as are the number literals of the script.
+    /// The script cannot import modules, and what it prints goes to stderr.
+    pub struct Script {
+        name: String,
+        engine: Engine,
+        ast: AST,
+        has_fee: bool,
+        has_check: bool,
+    }
+
+    impl Script {
+        pub fn load(path: &str) -> Result<Self, ProcessorError> {
+            let name = script_name(path);
+            let source = std::fs::read_to_string(path)?;
+
+            let mut engine = Engine::new();
+            engine.set_max_operations(MAX_OPERATIONS);
+            engine.set_module_resolver(DummyModuleResolver::new());
+            // stdout carries the account CSV
+            let print_name = name.clone();
+            engine.on_print(move |text| eprintln!("{}: {}", print_name, text));
+            let debug_name = name.clone();
+            engine.on_debug(move |text, _, pos| eprintln!("{} {}: {}", debug_name, pos, text));
+
+            let ast = engine.compile(&source).map_err(|err| ProcessorError::InvalidArguments(format!("script {}: {}", path, err)))?;
+            let defines = |function: &str| ast.iter_functions().any(|f| f.name == function && f.params.len() == 2);
+            let (has_fee, has_check) = (defines("fee"), defines("check"));
+            if !has_fee && !has_check {
+                return Err(ProcessorError::InvalidArguments(format!(
+                    "script {} defines neither fee(tx, account) nor check(tx, account)",
+                    path
+                )));
+            }
+
+            Ok(Script { name, engine, ast, has_fee, has_check })
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        /// What `check` decides about the row, accepted if the script has no `check`
+        pub fn check(&self, record: &TransactionInput, account: Option<&Account>) -> Decision {
+            if !self.has_check {
+                return Decision::Accept;
+            }
+            match self.call("check", record, account) {
+                Ok(result) if result.is_unit() => Decision::Accept,
+                Ok(result) => match result.into_string() {
+                    Ok(reason) => Decision::Reject(reason),
+                    Err(kind) => Decision::Failed(format!("check returned {} instead of a string or ()", kind)),
+                },
+                Err(err) => Decision::Failed(err),
+            }
+        }
+
+        /// The fee `fee` charges for the row, zero if the script has no `fee`
+        pub fn fee(&self, record: &TransactionInput, account: Option<&Account>) -> Result<Decimal, String> {
+            if !self.has_fee {
+                return Ok(Decimal::ZERO);
+            }
+            let result = self.call("fee", record, account)?;
+            if result.is_unit() {
+                return Ok(Decimal::ZERO);
+            }
+            match (result.as_decimal(), result.as_int()) {
+                (Ok(fee), _) => Ok(fee),
+                (_, Ok(fee)) => Ok(Decimal::from(fee)),
+                _ => Err(format!("fee returned {} instead of a number or ()", result.type_name())),
+            }
+        }
+
+        fn call(&self, function: &str, record: &TransactionInput, account: Option<&Account>) -> Result<Dynamic, String> {
+            let args = (transaction_map(record), account.map_or(Dynamic::UNIT, |account| account_map(account).into()));
+            self.engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, function, args)
+                .map_err(|err| err.to_string())
+        }
+    }
+
+    fn optional<T: Into<Dynamic>>(value: Option<T>) -> Dynamic {
+        value.map_or(Dynamic::UNIT, Into::into)
+    }
+
+    fn transaction_map(record: &TransactionInput) -> Map {
+        let mut map = Map::new();
+        map.insert("type".into(), record.transaction_type.to_string().into());
+        map.insert("client".into(), (record.client as i64).into());
+        map.insert("tx".into(), record.tx.to_string().into());
+        map.insert("amount".into(), optional(record.amount));
+        map.insert("timestamp".into(), optional(record.timestamp.map(|timestamp| timestamp.to_rfc3339())));
+        map.insert("reason_code".into(), optional(record.reason_code.clone()));
+        map
+    }
+
+    fn account_map(account: &Account) -> Map {
+        let mut map = Map::new();
+        map.insert("available".into(), account.available.into());
+        map.insert("held".into(), account.held.into());
+        map.insert("total".into(), account.total().into());
+        map.insert("locked".into(), account.locked.into());
+        map.insert("frozen".into(), account.frozen.into());
+        map
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod host {
+    use rust_decimal::Decimal;
+
+    use super::script_name;
+    use crate::model::account::Account;
+    use crate::model::error::ProcessorError;
+    use crate::model::transaction::TransactionInput;
+    use crate::plugin::Decision;
+
+    /// Without the `scripting` feature no script can be loaded
+    pub struct Script {
+        name: String,
+    }
+
+    impl Script {
+        pub fn load(path: &str) -> Result<Self, ProcessorError> {
+            Err(ProcessorError::InvalidArguments(format!(
+                "cannot load script {}, built without the scripting feature",
+                script_name(path)
+            )))
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub fn check(&self, _record: &TransactionInput, _account: Option<&Account>) -> Decision {
+            Decision::Accept
+        }
+
+        pub fn fee(&self, _record: &TransactionInput, _account: Option<&Account>) -> Result<Decimal, String> {
+            Ok(Decimal::ZERO)
+        }
+    }
+}
+
+pub use host::Script;
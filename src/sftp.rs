@@ -0,0 +1,143 @@
+use std::io::Read;
+
+use crate::model::error::ProcessorError;
+
+/// Environment variable holding the path of the private key to log in with. Without it,
+/// the keys of the running ssh-agent are tried.
+pub const KEY_ENV: &str = "TRX_SFTP_KEY";
+/// Environment variable holding the passphrase of the private key, if it has one
+pub const PASSPHRASE_ENV: &str = "TRX_SFTP_KEY_PASSPHRASE";
+/// Environment variable overriding the known hosts file, `~/.ssh/known_hosts` by default
+pub const KNOWN_HOSTS_ENV: &str = "TRX_SFTP_KNOWN_HOSTS";
+
+/// A file on an SFTP server, `sftp://[user@]host[:port]/path`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SftpLocation {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl SftpLocation {
+    /// The user defaults to `$USER`, the port to 22
+    pub fn parse(url: &str) -> Result<Self, ProcessorError> {
+        let invalid = || ProcessorError::InvalidArguments(format!("invalid SFTP location {}, expected sftp://[user@]host[:port]/path", url));
+        let rest = url.strip_prefix("sftp://").ok_or_else(invalid)?;
+        let (authority, path) = rest.split_once('/').ok_or_else(invalid)?;
+        let (user, address) = match authority.split_once('@') {
+            Some((user, address)) => (user.to_string(), address),
+            None => (std::env::var("USER").map_err(|_| invalid())?, authority),
+        };
+        let (host, port) = match address.split_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (address, 22),
+        };
+        if user.is_empty() || host.is_empty() || path.is_empty() {
+            return Err(invalid());
+        }
+        Ok(SftpLocation { user, host: host.to_string(), port, path: format!("/{}", path) })
+    }
+}
+
+impl std::fmt::Display for SftpLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "sftp://{}@{}:{}{}", self.user, self.host, self.port, self.path)
+    }
+}
+
+#[cfg(feature = "sftp")]
+mod client {
+    use std::io::{self, Read, Write};
+    use std::net::TcpStream;
+    use std::path::{Path, PathBuf};
+
+    use ssh2::{CheckResult, KnownHostFileKind, RenameFlags, Session, Sftp};
+
+    use super::{SftpLocation, KEY_ENV, KNOWN_HOSTS_ENV, PASSPHRASE_ENV};
+    use crate::model::error::ProcessorError;
+
+    fn error(location: &SftpLocation, err: impl std::fmt::Display) -> ProcessorError {
+        io::Error::other(format!("{}: {}", location, err)).into()
+    }
+
+    /// Logs in with a key after checking the server against the known hosts file, so a
+    /// settlement file is never fetched from or uploaded to an impostor
+    fn connect(location: &SftpLocation) -> Result<Sftp, ProcessorError> {
+        let mut session = Session::new().map_err(|err| error(location, err))?;
+        let stream = TcpStream::connect((location.host.as_str(), location.port)).map_err(|err| error(location, err))?;
+        session.set_tcp_stream(stream);
+        session.handshake().map_err(|err| error(location, err))?;
+
+        let known_hosts_path = match std::env::var(KNOWN_HOSTS_ENV) {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".ssh/known_hosts"),
+        };
+        let mut known_hosts = session.known_hosts().map_err(|err| error(location, err))?;
+        known_hosts
+            .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+            .map_err(|err| error(location, format!("cannot read {}: {}", known_hosts_path.display(), err)))?;
+        let (key, _) = session.host_key().ok_or_else(|| error(location, "no host key"))?;
+        match known_hosts.check_port(&location.host, location.port, key) {
+            CheckResult::Match => {}
+            CheckResult::Mismatch => return Err(error(location, "host key does not match the known hosts file")),
+            CheckResult::NotFound => return Err(error(location, format!("host is not in {}", known_hosts_path.display()))),
+            CheckResult::Failure => return Err(error(location, "host key check failed")),
+        }
+
+        match std::env::var(KEY_ENV) {
+            Ok(key) => session.userauth_pubkey_file(&location.user, None, Path::new(&key), std::env::var(PASSPHRASE_ENV).ok().as_deref()),
+            Err(_) => session.userauth_agent(&location.user),
+        }
+        .map_err(|err| error(location, format!("key authentication failed: {}", err)))?;
+
+        session.sftp().map_err(|err| error(location, err))
+    }
+
+    pub fn open(location: &SftpLocation) -> Result<Box<dyn Read + Send>, ProcessorError> {
+        let sftp = connect(location)?;
+        Ok(Box::new(sftp.open(Path::new(&location.path)).map_err(|err| error(location, err))?))
+    }
+
+    /// Written next to the destination and renamed, so the bank never picks up half a file
+    pub fn upload(location: &SftpLocation, contents: &[u8]) -> Result<(), ProcessorError> {
+        let sftp = connect(location)?;
+        let path = Path::new(&location.path);
+        let part_path = PathBuf::from(format!("{}.part", location.path));
+        let mut file = sftp.create(&part_path).map_err(|err| error(location, err))?;
+        file.write_all(contents)?;
+        file.close().map_err(|err| error(location, err))?;
+        sftp.rename(&part_path, path, Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE))
+            .map_err(|err| error(location, err))
+    }
+}
+
+#[cfg(not(feature = "sftp"))]
+mod client {
+    use std::io::Read;
+
+    use super::SftpLocation;
+    use crate::model::error::ProcessorError;
+
+    fn unsupported(location: &SftpLocation) -> ProcessorError {
+        ProcessorError::InvalidArguments(format!("cannot reach {}, built without the sftp feature", location))
+    }
+
+    pub fn open(location: &SftpLocation) -> Result<Box<dyn Read + Send>, ProcessorError> {
+        Err(unsupported(location))
+    }
+
+    pub fn upload(location: &SftpLocation, _contents: &[u8]) -> Result<(), ProcessorError> {
+        Err(unsupported(location))
+    }
+}
+
+/// Streams the file at `location` as it is read
+pub fn open(location: &SftpLocation) -> Result<Box<dyn Read + Send>, ProcessorError> {
+    client::open(location)
+}
+
+/// Uploads `contents` to `location`, replacing the file there
+pub fn upload(location: &SftpLocation, contents: &[u8]) -> Result<(), ProcessorError> {
+    client::upload(location, contents)
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, BufWriter, Read, Write};
+use std::process::{self, Stdio};
+
+use crate::input;
+use crate::model::error::ProcessorError;
+use crate::processor::{csv_reader, open_reader};
+
+/// The slice of clients one instance processes with `--shard <index>/<count>`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    /// Parses `i/N`, with shards numbered from 0
+    pub fn parse(value: &str) -> Option<Self> {
+        let (index, count) = value.split_once('/')?;
+        let shard = Shard { index: index.parse().ok()?, count: count.parse().ok()? };
+        (shard.index < shard.count).then_some(shard)
+    }
+
+    /// Shard a client belongs to. The hash is fixed so that every machine agrees, and
+    /// spreads neighbouring client ids, which often come from the same provider, over shards.
+    pub fn of(client: u16, count: u32) -> u32 {
+        ((u64::from(client).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) % u64::from(count)) as u32
+    }
+
+    pub fn contains(&self, client: u16) -> bool {
+        Shard::of(client, self.count) == self.index
+    }
+}
+
+/// Combines the account reports of the shards of a run into one report on stdout
+pub fn run_merge_reports(files: &[String]) -> Result<(), ProcessorError> {
+    let mut reports = Vec::new();
+    for file in files {
+        reports.push((file.clone(), open_reader(file)?));
+    }
+    merge_reports(reports, io::stdout().lock())
+}
+
+/// Program a worker runs on each of the `--hosts` of `coordinate`, found on the host's PATH
+const REMOTE_EXECUTABLE: &str = "trx_processor";
+
+/// A worker of `coordinate`, fed its rows on stdin
+struct Worker {
+    name: String,
+    child: process::Child,
+    /// None once the input is done, or the worker stopped reading
+    stdin: Option<BufWriter<process::ChildStdin>>,
+    stopped_reading: bool,
+}
+
+impl Worker {
+    fn send(&mut self, record: &[u8]) {
+        let Some(ref mut stdin) = self.stdin else {
+            return;
+        };
+        if stdin.write_all(record).is_err() {
+            self.stdin = None;
+            self.stopped_reading = true;
+        }
+    }
+}
+
+/// Runs `workers` processes of this executable with `worker_args`, or of `REMOTE_EXECUTABLE`
+/// over ssh on `hosts` in turn, and writes the merged report of all of them to stdout.
+///
+/// The input is read once, here: the header goes to every worker and each row to the worker
+/// of its client's shard, on the worker's stdin, so a worker reads only its share of the file.
+/// A row without a readable client goes to worker 0, which rejects it as a single run would.
+/// Workers report their warnings on the shared stderr, with the lines of their share.
+pub fn run_coordinate(input_file: &str, workers: u32, hosts: &[String], worker_args: &[String]) -> Result<(), ProcessorError> {
+    let executable = env::current_exe()?;
+    let mut children = Vec::new();
+    for index in 0..workers {
+        let mut command = match hosts {
+            [] => {
+                let mut command = process::Command::new(&executable);
+                command.arg(input::STDIN).args(worker_args);
+                command
+            }
+            hosts => {
+                let remote: Vec<_> = [REMOTE_EXECUTABLE, input::STDIN]
+                    .into_iter()
+                    .map(str::to_string)
+                    .chain(worker_args.iter().map(|arg| shell_quote(arg)))
+                    .collect();
+                let mut command = process::Command::new("ssh");
+                command.arg(&hosts[index as usize % hosts.len()]).arg(remote.join(" "));
+                command
+            }
+        };
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take().map(BufWriter::new);
+        children.push(Worker { name: format!("worker {}/{}", index, workers), child, stdin, stopped_reading: false });
+    }
+
+    let split = split_input(input_file, &mut children);
+    for worker in &mut children {
+        if let Some(mut stdin) = worker.stdin.take() {
+            worker.stopped_reading |= stdin.flush().is_err();
+        }
+    }
+
+    // Every worker is waited for, even after one failed
+    let mut reports = Vec::new();
+    let mut failed = Vec::new();
+    for worker in children {
+        let output = worker.child.wait_with_output()?;
+        if !output.status.success() {
+            failed.push(format!("{} failed with {}", worker.name, output.status));
+        } else if worker.stopped_reading {
+            failed.push(format!("{} stopped reading its rows", worker.name));
+        } else {
+            reports.push((worker.name, output.stdout));
+        }
+    }
+    split?;
+    if !failed.is_empty() {
+        return Err(io::Error::other(failed.join(", ")).into());
+    }
+
+    let reports = reports.iter().map(|(name, report)| (name.clone(), csv_reader(report.as_slice()))).collect();
+    merge_reports(reports, io::stdout().lock())
+}
+
+/// Sends the header of the input to every worker and each row to the worker of its shard
+fn split_input(input_file: &str, workers: &mut [Worker]) -> Result<(), ProcessorError> {
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(input::open(input_file)?);
+    let mut record = csv::ByteRecord::new();
+    let mut client_column = None;
+    let mut header = true;
+
+    while reader.read_byte_record(&mut record)? {
+        let encoded = encode_record(&record);
+        if header {
+            header = false;
+            client_column = record.iter().position(|field| field.trim_ascii() == b"client");
+            for worker in workers.iter_mut() {
+                worker.send(&encoded);
+            }
+            continue;
+        }
+
+        let index = client_column
+            .and_then(|column| record.get(column))
+            .and_then(|client| std::str::from_utf8(client).ok())
+            .and_then(|client| client.trim().parse().ok())
+            .map_or(0, |client| Shard::of(client, workers.len() as u32));
+        workers[index as usize].send(&encoded);
+    }
+    Ok(())
+}
+
+/// The record as a CSV line, quoting the fields that need it
+fn encode_record(record: &csv::ByteRecord) -> Vec<u8> {
+    let mut line = Vec::new();
+    for (index, field) in record.iter().enumerate() {
+        if index > 0 {
+            line.push(b',');
+        }
+        if field.iter().any(|byte| matches!(byte, b',' | b'"' | b'\n' | b'\r')) {
+            line.push(b'"');
+            for &byte in field {
+                if byte == b'"' {
+                    line.push(b'"');
+                }
+                line.push(byte);
+            }
+            line.push(b'"');
+        } else {
+            line.extend_from_slice(field);
+        }
+    }
+    line.push(b'\n');
+    line
+}
+
+/// Quotes an argument for the shell that runs the command ssh is given
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Writes the rows of all reports ordered by client. The reports must have the same
+/// columns, and no client may be in two of them.
+fn merge_reports<R: Read, W: Write>(reports: Vec<(String, csv::Reader<R>)>, output: W) -> Result<(), ProcessorError> {
+    let mut headers: Option<csv::StringRecord> = None;
+    let mut rows = BTreeMap::new();
+
+    for (file, mut reader) in reports {
+        let file_headers = reader.headers()?.clone();
+        // A shard without any client writes an empty report, not even a header
+        if file_headers.is_empty() {
+            continue;
+        }
+        match headers {
+            Some(ref headers) if *headers != file_headers => {
+                return Err(ProcessorError::InvalidArguments(format!(
+                    "{} has columns {:?}, expected {:?}",
+                    file,
+                    file_headers.iter().collect::<Vec<_>>(),
+                    headers.iter().collect::<Vec<_>>()
+                )));
+            }
+            Some(_) => {}
+            None => headers = Some(file_headers),
+        }
+
+        for record in reader.records() {
+            let record = record?;
+            let client: u16 = record
+                .get(0)
+                .and_then(|client| client.parse().ok())
+                .ok_or_else(|| ProcessorError::InvalidArguments(format!("{}: row without a client id", file)))?;
+            if let Some((other, _)) = rows.insert(client, (file.clone(), record)) {
+                return Err(ProcessorError::InvalidArguments(format!(
+                    "client {} is in both {} and {}, the reports overlap",
+                    client, other, file
+                )));
+            }
+        }
+    }
+
+    let mut writer = csv::Writer::from_writer(output);
+    if let Some(headers) = headers {
+        writer.write_record(&headers)?;
+    }
+    for (_, record) in rows.values() {
+        writer.write_record(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
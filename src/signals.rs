@@ -0,0 +1,25 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the SIGUSR1 handler, taken by the read loop
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn request_dump(_signal: libc::c_int) {
+    // Storing to an atomic is all a signal handler may safely do here
+    DUMP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Makes SIGUSR1 request a state dump instead of terminating the process, see
+/// `TransactionProcessor::with_state_dumps`. Does nothing on platforms without signals.
+pub fn install_dump_handler() {
+    #[cfg(unix)]
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGUSR1, request_dump as *const () as libc::sighandler_t);
+    }
+}
+
+/// Whether a dump was requested since the last call
+pub fn take_dump_request() -> bool {
+    DUMP_REQUESTED.swap(false, Ordering::Relaxed)
+}
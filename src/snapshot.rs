@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{TransactionState, TransactionType, TxId};
+use crate::processor::TransactionProcessor;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccountSnapshot {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransactionSnapshot {
+    pub tx: TxId,
+    pub client: u16,
+    pub transaction_type: TransactionType,
+    pub amount: Decimal,
+    pub state: TransactionState,
+}
+
+/// Point-in-time copy of the processor state
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Snapshot {
+    pub accounts: Vec<AccountSnapshot>,
+    pub transactions: Vec<TransactionSnapshot>,
+}
+
+impl Snapshot {
+    pub fn capture(processor: &TransactionProcessor) -> Result<Self, ProcessorError> {
+        let accounts = processor
+            .accounts()?
+            .into_iter()
+            .map(|account| AccountSnapshot {
+                client: account.client_id,
+                available: account.available,
+                held: account.held,
+                locked: account.locked,
+            })
+            .collect();
+
+        let transactions = processor
+            .transactions()?
+            .into_iter()
+            .map(|tx| TransactionSnapshot {
+                tx: tx.tx_id,
+                client: tx.client_id,
+                transaction_type: tx.transaction_type,
+                amount: tx.amount,
+                state: tx.state,
+            })
+            .collect();
+
+        Ok(Snapshot { accounts, transactions })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ProcessorError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, ProcessorError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// Differences between two snapshots, one human readable line per change
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    let before_accounts: BTreeMap<u16, &AccountSnapshot> =
+        before.accounts.iter().map(|a| (a.client, a)).collect();
+
+    for account in &after.accounts {
+        let Some(previous) = before_accounts.get(&account.client) else {
+            changes.push(format!(
+                "NEW ACCOUNT: client={}, available={}, held={}, locked={}",
+                account.client, account.available, account.held, account.locked
+            ));
+            continue;
+        };
+
+        if previous.available != account.available || previous.held != account.held {
+            changes.push(format!(
+                "BALANCE CHANGED: client={}, available={} -> {}, held={} -> {}",
+                account.client, previous.available, account.available, previous.held, account.held
+            ));
+        }
+
+        if !previous.locked && account.locked {
+            changes.push(format!("NEWLY LOCKED: client={}", account.client));
+        }
+    }
+
+    let before_states: BTreeMap<&TxId, &TransactionState> =
+        before.transactions.iter().map(|tx| (&tx.tx, &tx.state)).collect();
+
+    for tx in &after.transactions {
+        if let Some(previous) = before_states.get(&tx.tx) {
+            if **previous != tx.state {
+                changes.push(format!(
+                    "DISPUTE STATE CHANGED: client={}, tx={}, state={:?} -> {:?}",
+                    tx.client, tx.tx, previous, tx.state
+                ));
+            }
+        }
+    }
+
+    changes
+}
+
+pub fn run_diff(before_path: &str, after_path: &str) -> Result<(), ProcessorError> {
+    let before = Snapshot::load(before_path)?;
+    let after = Snapshot::load(after_path)?;
+
+    let changes = diff(&before, &after);
+    if changes.is_empty() {
+        println!("No differences");
+    }
+    for change in changes {
+        println!("{}", change);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,350 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use rust_decimal::Decimal;
+
+use crate::audit::{self, AccountChange, BatchChanges, TransactionChange};
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{Transaction, TransactionState, TxId};
+use crate::retry::{Retrier, RetryPolicy, RetryStats};
+use crate::store::{AccountStore, TransactionStore};
+
+/// Stages every mutation in memory on top of persistent stores until `commit`,
+/// so a failure mid-batch leaves the persistent state untouched.
+///
+/// Reads see staged values first and fall back to the backing stores. Over a cacheable
+/// account store, committed and read accounts are also kept in memory, so a client is
+/// read from the store once per run rather than once per batch.
+///
+/// The image a key had when the batch first changed it is kept as its before image, which
+/// stores shared with other writers check at commit, see `TransactionStore::commit_batch`.
+///
+/// Every read of and write to the backing stores goes through a `Retrier`.
+pub struct BatchStore {
+    accounts: Arc<dyn AccountStore>,
+    transactions: Arc<dyn TransactionStore>,
+    staged_accounts: DashMap<u16, Account>,
+    /// Before images of the staged accounts, None for accounts the batch creates
+    read_accounts: DashMap<u16, Option<Account>>,
+    /// Accounts as they are in the backing store, None if it is not cacheable
+    cached_accounts: Option<DashMap<u16, Account>>,
+    /// None marks a transaction removed by the batch
+    staged_transactions: DashMap<TxId, Option<Transaction>>,
+    /// Before images of the staged transactions, None for transactions the batch stores first
+    read_transactions: DashMap<TxId, Option<Transaction>>,
+    retrier: Retrier,
+}
+
+impl BatchStore {
+    pub fn new(accounts: Arc<dyn AccountStore>, transactions: Arc<dyn TransactionStore>) -> Self {
+        let cached_accounts = accounts.cacheable().then(DashMap::new);
+        BatchStore {
+            accounts,
+            transactions,
+            staged_accounts: DashMap::new(),
+            read_accounts: DashMap::new(),
+            cached_accounts,
+            staged_transactions: DashMap::new(),
+            read_transactions: DashMap::new(),
+            retrier: Retrier::new(RetryPolicy::default()),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retrier = Retrier::new(policy);
+        self
+    }
+
+    pub fn retry_stats(&self) -> RetryStats {
+        self.retrier.stats()
+    }
+
+    fn backing_account(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        self.retrier.run(|| self.accounts.get(client_id))
+    }
+
+    fn backing_transaction(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        self.retrier.run(|| self.transactions.get(tx_id))
+    }
+
+    /// The account as it is in the backing store
+    fn stored_account(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        let Some(ref cache) = self.cached_accounts else {
+            return self.backing_account(client_id);
+        };
+        if let Some(account) = cache.get(&client_id) {
+            return Ok(Some(account.clone()));
+        }
+
+        let account = self.backing_account(client_id)?;
+        if let Some(ref account) = account {
+            cache.insert(client_id, account.clone());
+        }
+        Ok(account)
+    }
+
+    /// Stages the account, keeping `read` as its before image on the batch's first change.
+    /// `read` is only called then.
+    fn stage_account(
+        &self,
+        account: Account,
+        read: impl FnOnce() -> Result<Option<Account>, ProcessorError>,
+    ) -> Result<(), ProcessorError> {
+        if !self.read_accounts.contains_key(&account.client_id) {
+            self.read_accounts.insert(account.client_id, read()?);
+        }
+        self.staged_accounts.insert(account.client_id, account);
+        Ok(())
+    }
+
+    /// Stages the transaction, None to remove it, like `stage_account`
+    fn stage_transaction(
+        &self,
+        tx_id: &TxId,
+        transaction: Option<Transaction>,
+        read: impl FnOnce() -> Result<Option<Transaction>, ProcessorError>,
+    ) -> Result<(), ProcessorError> {
+        if !self.read_transactions.contains_key(tx_id) {
+            self.read_transactions.insert(tx_id.clone(), read()?);
+        }
+        self.staged_transactions.insert(tx_id.clone(), transaction);
+        Ok(())
+    }
+
+    /// Before and after images of everything the staged batch changes
+    pub fn changes(&self) -> Result<BatchChanges, ProcessorError> {
+        let mut changes = BatchChanges::default();
+        for entry in self.staged_transactions.iter() {
+            let before = match self.read_transactions.get(entry.key()) {
+                Some(before) => before.clone(),
+                None => self.backing_transaction(entry.key())?,
+            };
+            changes.transactions.push(TransactionChange { tx: entry.key().clone(), before, after: entry.value().clone() });
+        }
+        for entry in self.staged_accounts.iter() {
+            let before = match self.read_accounts.get(entry.key()) {
+                Some(before) => before.clone(),
+                None => self.stored_account(*entry.key())?,
+            };
+            changes.accounts.push(AccountChange { before, after: entry.value().clone() });
+        }
+        Ok(changes)
+    }
+
+    /// Writes the staged batch to the backing stores and starts a new one.
+    /// Store errors are retried, writing full images again is harmless. So are conflicts with
+    /// another writer, after the batch was rebased on what that writer committed, see `rebase`.
+    /// Returns the before and after images of everything the batch changed.
+    pub fn commit(&self) -> Result<BatchChanges, ProcessorError> {
+        let mut changes = self.changes()?;
+        if !changes.is_empty() {
+            self.retrier.run(|| match self.transactions.commit_batch(self.accounts.as_ref(), &changes) {
+                Err(err @ ProcessorError::StoreConflict(_)) => {
+                    changes = self.rebase(&changes)?;
+                    Err(err)
+                }
+                result => result,
+            })?;
+        }
+
+        if let Some(ref cache) = self.cached_accounts {
+            for change in &changes.accounts {
+                cache.insert(change.after.client_id, change.after.clone());
+            }
+        }
+        self.rollback();
+        Ok(changes)
+    }
+
+    /// The batch's changes made again on what other writers committed since the batch read its
+    /// keys: the batch's net change of an account someone else changed is applied to the account
+    /// as it is now, like `AccountDelta::apply` does on undo. An account the other writer locked,
+    /// froze or merged, one the change would overdraw, and a transaction the other writer
+    /// changed cannot be rebased, the rows behind them were decided on what no longer holds.
+    fn rebase(&self, changes: &BatchChanges) -> Result<BatchChanges, ProcessorError> {
+        for change in &changes.transactions {
+            let current = self.transactions.get(&change.tx)?;
+            if serde_json::to_value(&current)? != serde_json::to_value(&change.before)? {
+                return Err(ProcessorError::StoreConflict(format!(
+                    "transaction {} was changed by another writer since the batch read it",
+                    change.tx
+                )));
+            }
+        }
+
+        let mut accounts = Vec::with_capacity(changes.accounts.len());
+        for change in &changes.accounts {
+            let client_id = change.after.client_id;
+            let current = self.accounts.get(client_id)?;
+            if serde_json::to_value(&current)? == serde_json::to_value(&change.before)? {
+                accounts.push(change.clone());
+                continue;
+            }
+
+            let before = change.before.clone().unwrap_or_else(|| Account::new(client_id));
+            let mut after = current.clone().unwrap_or_else(|| Account::new(client_id));
+            if (after.locked, after.frozen, after.merged_into) != (before.locked, before.frozen, before.merged_into) {
+                return Err(ProcessorError::StoreConflict(format!(
+                    "client {} was locked, frozen or merged by another writer since the batch read it",
+                    client_id
+                )));
+            }
+            let batch = BatchChanges { accounts: vec![change.clone()], ..BatchChanges::default() };
+            for delta in audit::net_account_deltas([&batch]) {
+                delta.apply(&mut after);
+            }
+            let overdrawn = |account: &Account| account.available < Decimal::ZERO || account.held < Decimal::ZERO;
+            if overdrawn(&after) && !overdrawn(&change.after) {
+                return Err(ProcessorError::StoreConflict(format!(
+                    "the batch would overdraw client {}, which another writer changed since the batch read it",
+                    client_id
+                )));
+            }
+            accounts.push(AccountChange { before: current, after });
+        }
+
+        Ok(BatchChanges { accounts, transactions: changes.transactions.clone(), section: changes.section.clone() })
+    }
+
+    /// Discards the staged batch
+    pub fn rollback(&self) {
+        self.staged_accounts.clear();
+        self.read_accounts.clear();
+        self.staged_transactions.clear();
+        self.read_transactions.clear();
+    }
+}
+
+impl AccountStore for BatchStore {
+    fn get(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        match self.staged_accounts.get(&client_id) {
+            Some(account) => Ok(Some(account.clone())),
+            None => self.stored_account(client_id),
+        }
+    }
+
+    fn ensure(&self, client_id: u16) -> Result<(), ProcessorError> {
+        if !self.staged_accounts.contains_key(&client_id) && self.stored_account(client_id)?.is_none() {
+            self.stage_account(Account::new(client_id), || Ok(None))?;
+        }
+        Ok(())
+    }
+
+    fn update(&self, client_id: u16, f: &mut dyn FnMut(&mut Account)) -> Result<bool, ProcessorError> {
+        let staged = self.staged_accounts.get(&client_id).map(|account| account.clone());
+        let (mut account, read) = match staged {
+            Some(account) => (account, None),
+            None => match self.stored_account(client_id)? {
+                Some(account) => (account.clone(), Some(account)),
+                None => return Ok(false),
+            },
+        };
+
+        f(&mut account);
+        self.stage_account(account, || Ok(read))?;
+        Ok(true)
+    }
+
+    fn iterate(&self) -> Result<Vec<Account>, ProcessorError> {
+        let mut accounts: Vec<_> = self.retrier
+            .run(|| self.accounts.iterate())?
+            .into_iter()
+            .filter(|account| !self.staged_accounts.contains_key(&account.client_id))
+            .collect();
+        accounts.extend(self.staged_accounts.iter().map(|entry| entry.value().clone()));
+        Ok(accounts)
+    }
+
+    fn write_batch(&self, accounts: &[Account]) -> Result<(), ProcessorError> {
+        for account in accounts {
+            self.stage_account(account.clone(), || self.stored_account(account.client_id))?;
+        }
+        Ok(())
+    }
+}
+
+impl TransactionStore for BatchStore {
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        match self.staged_transactions.get(tx_id) {
+            Some(transaction) => Ok(transaction.clone()),
+            None => self.backing_transaction(tx_id),
+        }
+    }
+
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError> {
+        let tx_id = transaction.tx_id.clone();
+        let staged = self.staged_transactions.get(&tx_id).map(|staged| staged.is_some());
+        let (is_new, read) = match staged {
+            Some(stored) => (!stored, None),
+            None => {
+                let read = self.backing_transaction(&tx_id)?;
+                (read.is_none(), Some(read))
+            }
+        };
+        self.stage_transaction(&tx_id, Some(transaction), || match read {
+            Some(read) => Ok(read),
+            None => self.backing_transaction(&tx_id),
+        })?;
+        Ok(is_new)
+    }
+
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError> {
+        let staged = self.staged_transactions.get(tx_id).map(|staged| staged.clone());
+        let (transaction, read) = match staged {
+            Some(transaction) => (transaction, None),
+            None => {
+                let read = self.backing_transaction(tx_id)?;
+                (read.clone(), Some(read))
+            }
+        };
+        if let Some(mut transaction) = transaction {
+            transaction.state = state;
+            self.stage_transaction(tx_id, Some(transaction), || match read {
+                Some(read) => Ok(read),
+                None => self.backing_transaction(tx_id),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        self.stage_transaction(tx_id, None, || self.backing_transaction(tx_id))
+    }
+
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        let mut transactions: Vec<_> = self.retrier
+            .run(|| self.transactions.iterate())?
+            .into_iter()
+            .filter(|transaction| !self.staged_transactions.contains_key(&transaction.tx_id))
+            .collect();
+        transactions.extend(self.staged_transactions.iter().filter_map(|entry| entry.value().clone()));
+        Ok(transactions)
+    }
+
+    fn for_client(&self, client_id: u16) -> Result<Vec<Transaction>, ProcessorError> {
+        // A staged transaction may have been stored for another client, so all are checked
+        let mut transactions: Vec<_> = self.retrier
+            .run(|| self.transactions.for_client(client_id))?
+            .into_iter()
+            .filter(|transaction| !self.staged_transactions.contains_key(&transaction.tx_id))
+            .collect();
+        transactions.extend(
+            self.staged_transactions
+                .iter()
+                .filter_map(|entry| entry.value().clone())
+                .filter(|transaction| transaction.client_id == client_id),
+        );
+        Ok(transactions)
+    }
+
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+        for transaction in transactions {
+            self.stage_transaction(&transaction.tx_id, Some(transaction.clone()), || self.backing_transaction(&transaction.tx_id))?;
+        }
+        for tx_id in removed {
+            self.stage_transaction(tx_id, None, || self.backing_transaction(tx_id))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use parking_lot::RwLock;
+
+use crate::audit::BatchChanges;
+use crate::bloom::Bloom;
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{LifecycleEvent, Transaction, TransactionState, TxId};
+use crate::store::{AccountStore, Stores, TransactionStore, Transition};
+
+/// Transaction store that remembers every id it stored in a bloom filter, so that looking
+/// up an id it never saw, e.g. the tx of a bogus dispute, does not reach the backing store.
+///
+/// Ids stored by anyone else after it was opened are missed, so it must be the only writer.
+/// Removed ids stay in the filter, which only costs a lookup.
+pub struct BloomTransactionStore {
+    inner: Box<dyn TransactionStore>,
+    stored: RwLock<Bloom>,
+}
+
+impl BloomTransactionStore {
+    /// Fills the filter with the transactions already in the store
+    pub fn new(inner: Box<dyn TransactionStore>, capacity: usize) -> Result<Self, ProcessorError> {
+        let mut stored = Bloom::new(capacity);
+        for transaction in inner.iterate()? {
+            stored.insert(&transaction.tx_id.to_string());
+        }
+        Ok(BloomTransactionStore { inner, stored: RwLock::new(stored) })
+    }
+
+    fn may_contain(&self, tx_id: &TxId) -> bool {
+        self.stored.read().contains(&tx_id.to_string())
+    }
+
+    fn remember(&self, tx_id: &TxId) {
+        self.stored.write().insert(&tx_id.to_string());
+    }
+}
+
+impl Stores {
+    pub fn with_tx_bloom(self, capacity: usize) -> Result<Stores, ProcessorError> {
+        Ok(Stores {
+            accounts: self.accounts,
+            transactions: Box::new(BloomTransactionStore::new(self.transactions, capacity)?),
+        })
+    }
+}
+
+impl TransactionStore for BloomTransactionStore {
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        if !self.may_contain(tx_id) {
+            return Ok(None);
+        }
+        self.inner.get(tx_id)
+    }
+
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError> {
+        // Remembered first, so the id is never missed while the insert is under way
+        self.remember(&transaction.tx_id);
+        self.inner.insert(transaction)
+    }
+
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError> {
+        self.inner.set_state(tx_id, state)
+    }
+
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        self.inner.remove(tx_id)
+    }
+
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        self.inner.iterate()
+    }
+
+    fn for_client(&self, client_id: u16) -> Result<Vec<Transaction>, ProcessorError> {
+        self.inner.for_client(client_id)
+    }
+
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+        for transaction in transactions {
+            self.remember(&transaction.tx_id);
+        }
+        self.inner.write_batch(transactions, removed)
+    }
+
+    fn commit_batch(&self, accounts: &dyn AccountStore, changes: &BatchChanges) -> Result<(), ProcessorError> {
+        for transaction in changes.transactions.iter().filter_map(|change| change.after.as_ref()) {
+            self.remember(&transaction.tx_id);
+        }
+        self.inner.commit_batch(accounts, changes)
+    }
+
+    fn try_transition(
+        &self,
+        accounts: &dyn AccountStore,
+        tx_id: &TxId,
+        event: LifecycleEvent,
+        account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+    ) -> Result<Transition, ProcessorError> {
+        if !self.may_contain(tx_id) {
+            return Ok(Transition::TransactionNotFound);
+        }
+        self.inner.try_transition(accounts, tx_id, event, account_op)
+    }
+}
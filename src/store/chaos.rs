@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use crate::audit::BatchChanges;
+use crate::chaos::Chaos;
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{Transaction, TransactionState, TxId};
+use crate::store::{AccountStore, Stores, TransactionStore};
+
+/// Account store whose batch writes fail now and then, see `Chaos`
+pub struct ChaosAccountStore {
+    inner: Box<dyn AccountStore>,
+    chaos: Arc<Chaos>,
+}
+
+/// Transaction store whose batch writes fail now and then, see `Chaos`
+pub struct ChaosTransactionStore {
+    inner: Box<dyn TransactionStore>,
+    chaos: Arc<Chaos>,
+}
+
+impl Stores {
+    pub fn with_chaos(self, chaos: Arc<Chaos>) -> Stores {
+        Stores {
+            accounts: Box::new(ChaosAccountStore { inner: self.accounts, chaos: chaos.clone() }),
+            transactions: Box::new(ChaosTransactionStore { inner: self.transactions, chaos }),
+        }
+    }
+}
+
+impl AccountStore for ChaosAccountStore {
+    fn get(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        self.inner.get(client_id)
+    }
+
+    fn ensure(&self, client_id: u16) -> Result<(), ProcessorError> {
+        self.inner.ensure(client_id)
+    }
+
+    fn update(&self, client_id: u16, f: &mut dyn FnMut(&mut Account)) -> Result<bool, ProcessorError> {
+        self.inner.update(client_id, f)
+    }
+
+    fn iterate(&self) -> Result<Vec<Account>, ProcessorError> {
+        self.inner.iterate()
+    }
+
+    fn write_batch(&self, accounts: &[Account]) -> Result<(), ProcessorError> {
+        self.chaos.store_write("accounts")?;
+        self.inner.write_batch(accounts)
+    }
+
+    fn cacheable(&self) -> bool {
+        self.inner.cacheable()
+    }
+}
+
+impl TransactionStore for ChaosTransactionStore {
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        self.inner.get(tx_id)
+    }
+
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError> {
+        self.inner.insert(transaction)
+    }
+
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError> {
+        self.inner.set_state(tx_id, state)
+    }
+
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        self.inner.remove(tx_id)
+    }
+
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        self.inner.iterate()
+    }
+
+    fn for_client(&self, client_id: u16) -> Result<Vec<Transaction>, ProcessorError> {
+        self.inner.for_client(client_id)
+    }
+
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+        self.chaos.store_write("transactions")?;
+        self.inner.write_batch(transactions, removed)
+    }
+
+    fn commit_batch(&self, accounts: &dyn AccountStore, changes: &BatchChanges) -> Result<(), ProcessorError> {
+        self.chaos.store_write("transactions")?;
+        self.inner.commit_batch(accounts, changes)
+    }
+}
@@ -0,0 +1,326 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::BatchChanges;
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{Transaction, TransactionState, TxId};
+use crate::store::{AccountStore, MemoryTransactionStore, TransactionStore};
+
+const JOURNAL_FILE: &str = "batch.journal";
+const TRANSACTIONS_FILE: &str = "transactions.jsonl";
+
+/// A batch as written to the journal before any of it is applied
+#[derive(Default, Serialize, Deserialize)]
+struct Journal {
+    accounts: Vec<Account>,
+    #[serde(default)]
+    transactions: Vec<Transaction>,
+    #[serde(default)]
+    removed: Vec<TxId>,
+}
+
+/// A line of the transaction log, None once the transaction was removed
+#[derive(Serialize, Deserialize)]
+struct LogEntry {
+    tx: TxId,
+    transaction: Option<Transaction>,
+}
+
+/// Disk-backed account store keeping one JSON file per client in a directory,
+/// so balances survive across runs.
+///
+/// Batches are first written to a journal which is replayed on open, so a crash while
+/// applying a batch never leaves only part of it on disk. The journal is shared with the
+/// `FileTransactionStore` of the same directory.
+#[derive(Clone)]
+pub struct FileAccountStore {
+    dir: PathBuf,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl FileAccountStore {
+    pub fn open(dir: &str) -> Result<Self, ProcessorError> {
+        fs::create_dir_all(dir)?;
+        let store = FileAccountStore {
+            dir: PathBuf::from(dir),
+            write_lock: Arc::new(Mutex::new(())),
+        };
+        store.replay_journal()?;
+        Ok(store)
+    }
+
+    /// Finishes applying a batch interrupted by a crash, transactions included
+    fn replay_journal(&self) -> Result<(), ProcessorError> {
+        let path = self.dir.join(JOURNAL_FILE);
+        let journal = match fs::read(&path) {
+            // Journals written before transactions were kept on disk only hold accounts
+            Ok(bytes) => serde_json::from_slice(&bytes).or_else(|_| {
+                serde_json::from_slice(&bytes).map(|accounts| Journal { accounts, ..Journal::default() })
+            })?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        truncate_torn_line(&self.dir.join(TRANSACTIONS_FILE))?;
+        self.apply(&journal)?;
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Writes a batch to the journal, then applies it and drops the journal.
+    /// The caller holds the write lock.
+    fn commit(&self, journal: &Journal) -> Result<(), ProcessorError> {
+        // The rename makes the journal appear complete or not at all
+        let path = self.dir.join(JOURNAL_FILE);
+        let tmp_path = path.with_extension("journal.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(journal)?)?;
+        fs::rename(&tmp_path, &path)?;
+
+        self.apply(journal)?;
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Applies a journaled batch, transactions before the accounts whose balances they explain.
+    /// Applying it again after a crash gives the same result.
+    fn apply(&self, journal: &Journal) -> Result<(), ProcessorError> {
+        if !journal.transactions.is_empty() || !journal.removed.is_empty() {
+            let mut log = OpenOptions::new().create(true).append(true).open(self.dir.join(TRANSACTIONS_FILE))?;
+            append_log(&mut log, &journal.transactions, &journal.removed)?;
+        }
+        for account in &journal.accounts {
+            self.write(account)?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, client_id: u16) -> PathBuf {
+        self.dir.join(format!("{}.json", client_id))
+    }
+
+    fn read(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        match fs::read(self.path(client_id)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn write(&self, account: &Account) -> Result<(), ProcessorError> {
+        // Write to a temporary file first so a crash never leaves a truncated account
+        let path = self.path(account.client_id);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(account)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+impl AccountStore for FileAccountStore {
+    fn get(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        self.read(client_id)
+    }
+
+    fn ensure(&self, client_id: u16) -> Result<(), ProcessorError> {
+        let _guard = self.write_lock.lock();
+        if self.read(client_id)?.is_none() {
+            self.write(&Account::new(client_id))?;
+        }
+        Ok(())
+    }
+
+    fn update(&self, client_id: u16, f: &mut dyn FnMut(&mut Account)) -> Result<bool, ProcessorError> {
+        let _guard = self.write_lock.lock();
+        let Some(mut account) = self.read(client_id)? else {
+            return Ok(false);
+        };
+
+        f(&mut account);
+        self.write(&account)?;
+        Ok(true)
+    }
+
+    fn iterate(&self) -> Result<Vec<Account>, ProcessorError> {
+        let mut accounts = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let client_id = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|id| id.parse().ok());
+
+            // Anything that is not a client file (e.g. leftover temporary files) is skipped
+            if let Some(account) = client_id.map(|id| self.read(id)).transpose()?.flatten() {
+                accounts.push(account);
+            }
+        }
+        Ok(accounts)
+    }
+
+    fn write_batch(&self, accounts: &[Account]) -> Result<(), ProcessorError> {
+        let _guard = self.write_lock.lock();
+        self.commit(&Journal { accounts: accounts.to_vec(), ..Journal::default() })
+    }
+
+    /// The directory belongs to the run, as concurrent runs would overwrite each other anyway
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Disk-backed transaction store, so disputes opened in one run can be resolved or charged
+/// back in a later one. Transactions are appended to a log in the directory of a
+/// `FileAccountStore` and all kept in memory; the log is compacted when opened.
+///
+/// Batches go through the journal of the account store, so a crash never leaves the
+/// transactions of a batch on disk without its balances or the other way round.
+pub struct FileTransactionStore {
+    accounts: FileAccountStore,
+    transactions: MemoryTransactionStore,
+}
+
+impl FileTransactionStore {
+    /// Opens the transaction log of `dir`, finishing any interrupted batch first
+    pub fn open(dir: &str) -> Result<Self, ProcessorError> {
+        let accounts = FileAccountStore::open(dir)?;
+        let transactions = MemoryTransactionStore::new();
+        let path = accounts.dir.join(TRANSACTIONS_FILE);
+
+        let mut entries = 0;
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let entry: LogEntry = serde_json::from_str(&line?)?;
+                    entries += 1;
+                    match entry.transaction {
+                        Some(transaction) => transactions.write_batch(&[transaction], &[])?,
+                        None => transactions.write_batch(&[], &[entry.tx])?,
+                    }
+                }
+            }
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            Err(_) => {}
+        }
+
+        let stored = transactions.iterate()?;
+        if entries > stored.len() {
+            // Rewrites only the latest state of each transaction
+            let tmp_path = path.with_extension("jsonl.tmp");
+            append_log(&mut File::create(&tmp_path)?, &stored, &[])?;
+            fs::rename(tmp_path, &path)?;
+        }
+        Ok(FileTransactionStore { accounts, transactions })
+    }
+
+    /// The account store of the same directory, sharing its journal
+    pub fn accounts(&self) -> FileAccountStore {
+        self.accounts.clone()
+    }
+
+    /// Journals and applies a batch, keeping it in memory once it is on disk
+    fn commit(&self, journal: Journal) -> Result<(), ProcessorError> {
+        let _guard = self.accounts.write_lock.lock();
+        self.accounts.commit(&journal)?;
+        self.transactions.write_batch(&journal.transactions, &journal.removed)
+    }
+}
+
+impl TransactionStore for FileTransactionStore {
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        self.transactions.get(tx_id)
+    }
+
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError> {
+        let is_new = self.transactions.get(&transaction.tx_id)?.is_none();
+        self.commit(Journal { transactions: vec![transaction], ..Journal::default() })?;
+        Ok(is_new)
+    }
+
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError> {
+        if let Some(mut transaction) = self.transactions.get(tx_id)? {
+            transaction.state = state;
+            self.commit(Journal { transactions: vec![transaction], ..Journal::default() })?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        if self.transactions.get(tx_id)?.is_some() {
+            self.commit(Journal { removed: vec![tx_id.clone()], ..Journal::default() })?;
+        }
+        Ok(())
+    }
+
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        self.transactions.iterate()
+    }
+
+    fn for_client(&self, client_id: u16) -> Result<Vec<Transaction>, ProcessorError> {
+        self.transactions.for_client(client_id)
+    }
+
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+        self.commit(Journal { accounts: Vec::new(), transactions: transactions.to_vec(), removed: removed.to_vec() })
+    }
+
+    /// Writes the accounts to this store's directory as part of the same journaled batch
+    fn commit_batch(&self, _accounts: &dyn AccountStore, changes: &BatchChanges) -> Result<(), ProcessorError> {
+        let mut journal = Journal::default();
+        for change in &changes.transactions {
+            match &change.after {
+                Some(transaction) => journal.transactions.push(transaction.clone()),
+                None => journal.removed.push(change.tx.clone()),
+            }
+        }
+        journal.accounts = changes.accounts.iter().map(|change| change.after.clone()).collect();
+        self.commit(journal)
+    }
+}
+
+/// Appends a line per stored and removed transaction, synced before it returns
+fn append_log(log: &mut File, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+    let mut lines = Vec::new();
+    for transaction in transactions {
+        serde_json::to_writer(&mut lines, &LogEntry { tx: transaction.tx_id.clone(), transaction: Some(transaction.clone()) })?;
+        lines.push(b'\n');
+    }
+    for tx_id in removed {
+        serde_json::to_writer(&mut lines, &LogEntry { tx: tx_id.clone(), transaction: None })?;
+        lines.push(b'\n');
+    }
+    log.write_all(&lines)?;
+    log.sync_data()?;
+    Ok(())
+}
+
+/// Cuts a line torn by a crash off the end of the log, so the batch replayed from the
+/// journal starts on a line of its own
+fn truncate_torn_line(path: &Path) -> Result<(), ProcessorError> {
+    let mut log = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(log) => log,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut end = log.metadata()?.len();
+    let mut block = [0; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(block.len() as u64);
+        let block = &mut block[..(end - start) as usize];
+        log.seek(SeekFrom::Start(start))?;
+        log.read_exact(block)?;
+        if let Some(newline) = block.iter().rposition(|&byte| byte == b'\n') {
+            log.set_len(start + newline as u64 + 1)?;
+            return Ok(());
+        }
+        end = start;
+    }
+    log.set_len(0)?;
+    Ok(())
+}
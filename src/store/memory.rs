@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::num::NonZeroUsize;
+use std::thread;
+
+use dashmap::DashMap;
+use parking_lot::RwLock;
+
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{LifecycleEvent, Transaction, TransactionState, TxId};
+use crate::store::{self, AccountStore, Stores, TransactionStore, Transition};
+
+/// Default in-process account store
+#[derive(Default)]
+pub struct MemoryAccountStore {
+    accounts: DashMap<u16, Account>,
+}
+
+impl MemoryAccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn get(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        Ok(self.accounts.get(&client_id).map(|account| account.clone()))
+    }
+
+    fn ensure(&self, client_id: u16) -> Result<(), ProcessorError> {
+        self.accounts
+            .entry(client_id)
+            .or_insert_with(|| Account::new(client_id));
+        Ok(())
+    }
+
+    fn update(&self, client_id: u16, f: &mut dyn FnMut(&mut Account)) -> Result<bool, ProcessorError> {
+        match self.accounts.get_mut(&client_id) {
+            Some(mut account) => {
+                f(&mut account);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn iterate(&self) -> Result<Vec<Account>, ProcessorError> {
+        Ok(self.accounts
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect())
+    }
+
+    fn write_batch(&self, accounts: &[Account]) -> Result<(), ProcessorError> {
+        for account in accounts {
+            self.accounts.insert(account.client_id, account.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Slot of a stored transaction: the slab of its shard and its index there
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Slot {
+    shard: u32,
+    index: u32,
+}
+
+/// Transactions of one shard of the store, each in a slot of `slots` found through `index`.
+/// Removing a transaction frees its slot for the next one rather than shrinking anything.
+#[derive(Default)]
+struct Slab {
+    index: HashMap<TxId, u32>,
+    slots: Vec<Option<Transaction>>,
+    free: Vec<u32>,
+}
+
+impl Slab {
+    fn with_capacity(transactions: usize) -> Self {
+        Slab {
+            index: HashMap::with_capacity(transactions),
+            slots: Vec::with_capacity(transactions),
+            free: Vec::new(),
+        }
+    }
+
+    fn get(&self, tx_id: &TxId) -> Option<&Transaction> {
+        self.index.get(tx_id).and_then(|&index| self.slots[index as usize].as_ref())
+    }
+
+    fn get_mut(&mut self, tx_id: &TxId) -> Option<&mut Transaction> {
+        self.index.get(tx_id).and_then(|&index| self.slots[index as usize].as_mut())
+    }
+
+    /// Stores the transaction in its slot, or a free one if it is new. Returns the slot and
+    /// the transaction it replaced.
+    fn insert(&mut self, transaction: Transaction) -> (u32, Option<Transaction>) {
+        if let Some(&index) = self.index.get(&transaction.tx_id) {
+            return (index, self.slots[index as usize].replace(transaction));
+        }
+        let tx_id = transaction.tx_id.clone();
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize] = Some(transaction);
+                index
+            }
+            None => {
+                self.slots.push(Some(transaction));
+                (self.slots.len() - 1) as u32
+            }
+        };
+        self.index.insert(tx_id, index);
+        (index, None)
+    }
+
+    fn remove(&mut self, tx_id: &TxId) -> Option<(u32, Transaction)> {
+        let index = self.index.remove(tx_id)?;
+        self.free.push(index);
+        self.slots[index as usize].take().map(|transaction| (index, transaction))
+    }
+}
+
+/// Default in-process transaction store. Transactions are kept in slabs, one per shard of
+/// the tx ids, so storing one allocates nothing beyond its text fields, and the id index
+/// of a shard holds slot numbers rather than the transactions themselves. Growing the index
+/// thus moves small entries only, and a slab grows in place where the allocator can. The
+/// client index refers to slots as well, rather than holding a copy of every id.
+pub struct MemoryTransactionStore {
+    slabs: Box<[RwLock<Slab>]>,
+    hasher: RandomState,
+    /// Slots of every stored transaction by client, in the order stored
+    by_client: DashMap<u16, Vec<Slot>>,
+}
+
+impl Default for MemoryTransactionStore {
+    fn default() -> Self {
+        MemoryTransactionStore::with_capacity(0)
+    }
+}
+
+impl MemoryTransactionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sized up front for `transactions`, so no slab or index grows up to that many
+    pub fn with_capacity(transactions: usize) -> Self {
+        // As many shards as `DashMap` has, for as little lock contention between workers
+        let shards = (thread::available_parallelism().map_or(1, NonZeroUsize::get) * 4).next_power_of_two();
+        // Room for the shards the hash fills above the average
+        let per_shard = transactions.div_ceil(shards);
+        let per_shard = per_shard + per_shard / 8;
+        MemoryTransactionStore {
+            slabs: (0..shards).map(|_| RwLock::new(Slab::with_capacity(per_shard))).collect(),
+            hasher: RandomState::new(),
+            by_client: DashMap::new(),
+        }
+    }
+
+    fn shard_of(&self, tx_id: &TxId) -> u32 {
+        (self.hasher.hash_one(tx_id) as usize & (self.slabs.len() - 1)) as u32
+    }
+
+    fn slab(&self, tx_id: &TxId) -> (u32, &RwLock<Slab>) {
+        let shard = self.shard_of(tx_id);
+        (shard, &self.slabs[shard as usize])
+    }
+
+    /// Moves the slot in the client index after its transaction was stored for `to`, or
+    /// removed with None, having been stored for `from` before
+    fn reindex(&self, slot: Slot, from: Option<u16>, to: Option<u16>) {
+        if from == to {
+            return;
+        }
+        if let Some(from) = from {
+            if let Some(mut slots) = self.by_client.get_mut(&from) {
+                slots.retain(|other| *other != slot);
+            }
+            self.by_client.remove_if(&from, |_, slots| slots.is_empty());
+        }
+        if let Some(to) = to {
+            self.by_client.entry(to).or_default().push(slot);
+        }
+    }
+}
+
+impl Stores {
+    /// Replaces the in-memory transaction store with one sized for `transactions`
+    pub fn with_transaction_capacity(self, transactions: usize) -> Stores {
+        Stores {
+            accounts: self.accounts,
+            transactions: Box::new(MemoryTransactionStore::with_capacity(transactions)),
+        }
+    }
+}
+
+impl TransactionStore for MemoryTransactionStore {
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        Ok(self.slab(tx_id).1.read().get(tx_id).cloned())
+    }
+
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError> {
+        let (shard, slab) = self.slab(&transaction.tx_id);
+        let client_id = transaction.client_id;
+        let (index, previous) = slab.write().insert(transaction);
+        self.reindex(Slot { shard, index }, previous.as_ref().map(|previous| previous.client_id), Some(client_id));
+        Ok(previous.is_none())
+    }
+
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError> {
+        if let Some(transaction) = self.slab(tx_id).1.write().get_mut(tx_id) {
+            transaction.state = state;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        let (shard, slab) = self.slab(tx_id);
+        let removed = slab.write().remove(tx_id);
+        if let Some((index, removed)) = removed {
+            self.reindex(Slot { shard, index }, Some(removed.client_id), None);
+        }
+        Ok(())
+    }
+
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        let mut transactions = Vec::new();
+        for slab in self.slabs.iter() {
+            transactions.extend(slab.read().slots.iter().flatten().cloned());
+        }
+        Ok(transactions)
+    }
+
+    fn for_client(&self, client_id: u16) -> Result<Vec<Transaction>, ProcessorError> {
+        let slots = self.by_client.get(&client_id).map(|slots| slots.clone()).unwrap_or_default();
+        Ok(slots
+            .iter()
+            .filter_map(|slot| self.slabs[slot.shard as usize].read().slots[slot.index as usize].clone())
+            .collect())
+    }
+
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+        for transaction in transactions {
+            self.insert(transaction.clone())?;
+        }
+        for tx_id in removed {
+            self.remove(tx_id)?;
+        }
+        Ok(())
+    }
+
+    fn try_transition(
+        &self,
+        accounts: &dyn AccountStore,
+        tx_id: &TxId,
+        event: LifecycleEvent,
+        account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+    ) -> Result<Transition, ProcessorError> {
+        // The slab stays locked while the account is updated, so no other transition
+        // of the same transaction can slip in between the state check and the update
+        let mut slab = self.slab(tx_id).1.write();
+        let Some(transaction) = slab.get_mut(tx_id) else {
+            return Ok(Transition::TransactionNotFound);
+        };
+        let Some(new) = transaction.state.next(event) else {
+            return Ok(Transition::WrongState(transaction.state.clone()));
+        };
+
+        match store::apply_account_op(accounts, transaction, account_op)? {
+            None => Ok(Transition::AccountNotFound),
+            Some((false, _)) => Ok(Transition::Declined),
+            Some((true, mut amended)) => {
+                amended.state = new;
+                *transaction = amended;
+                Ok(Transition::Applied)
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+pub mod batch;
+pub mod bloom;
+pub mod chaos;
+pub mod file;
+pub mod memory;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod redis;
+pub mod tiered;
+
+use std::time::Duration;
+
+use crate::audit::BatchChanges;
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{LifecycleEvent, Transaction, TransactionState, TxId};
+
+pub use batch::BatchStore;
+pub use bloom::BloomTransactionStore;
+pub use file::{FileAccountStore, FileTransactionStore};
+pub use memory::{MemoryAccountStore, MemoryTransactionStore};
+#[cfg(not(target_arch = "wasm32"))]
+pub use redis::{RedisAccountStore, RedisTransactionStore};
+pub use tiered::TieredTransactionStore;
+
+/// Storage backend for account state.
+///
+/// Callers serialize access per client, so implementations only need to keep concurrent
+/// operations on different clients from corrupting each other.
+pub trait AccountStore: Send + Sync {
+    /// Returns a copy of the client's account
+    fn get(&self, client_id: u16) -> Result<Option<Account>, ProcessorError>;
+
+    /// Creates an empty account for the client if it does not exist yet
+    fn ensure(&self, client_id: u16) -> Result<(), ProcessorError>;
+
+    /// Applies `f` to the client's account and persists the result.
+    /// Returns false if the client has no account. Implementations with optimistic
+    /// concurrency may call `f` more than once, each time on a freshly read account.
+    fn update(&self, client_id: u16, f: &mut dyn FnMut(&mut Account)) -> Result<bool, ProcessorError>;
+
+    /// Returns copies of all accounts, in no particular order
+    fn iterate(&self) -> Result<Vec<Account>, ProcessorError>;
+
+    /// Creates or replaces all the accounts as one atomic unit
+    fn write_batch(&self, accounts: &[Account]) -> Result<(), ProcessorError>;
+
+    /// Whether no one else writes the store during a run, so that `BatchStore` may keep
+    /// the accounts it committed in memory instead of reading them back
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Storage backend for disputable transactions
+pub trait TransactionStore: Send + Sync {
+    /// Returns a copy of the transaction
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError>;
+
+    /// Stores the transaction, replacing any with the same id.
+    /// Returns true if the id was not stored before.
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError>;
+
+    /// Moves a stored transaction to a new dispute state
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError>;
+
+    /// Forgets a stored transaction, doing nothing if it is not stored
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError>;
+
+    /// Returns copies of all transactions, in no particular order
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError>;
+
+    /// Returns copies of the client's transactions. This default scans every transaction;
+    /// stores that index them by client override it and return them in the order stored.
+    fn for_client(&self, client_id: u16) -> Result<Vec<Transaction>, ProcessorError> {
+        Ok(self.iterate()?.into_iter().filter(|transaction| transaction.client_id == client_id).collect())
+    }
+
+    /// Creates or replaces all the transactions and removes the `removed` ids as one atomic unit
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError>;
+
+    /// Writes the after images of a batch, see `BatchStore::commit`: the transactions first,
+    /// then the accounts whose balances they explain. Stores shared with other writers
+    /// override it to write both as one unit, and only if every key still holds its before
+    /// image, failing with `StoreConflict` otherwise.
+    fn commit_batch(&self, accounts: &dyn AccountStore, changes: &BatchChanges) -> Result<(), ProcessorError> {
+        let transactions: Vec<_> = changes.transactions
+            .iter()
+            .filter_map(|change| change.after.clone())
+            .collect();
+        let removed: Vec<_> = changes.transactions
+            .iter()
+            .filter(|change| change.after.is_none())
+            .map(|change| change.tx.clone())
+            .collect();
+        let after: Vec<_> = changes.accounts
+            .iter()
+            .map(|change| change.after.clone())
+            .collect();
+
+        if !transactions.is_empty() || !removed.is_empty() {
+            self.write_batch(&transactions, &removed)?;
+        }
+        if !after.is_empty() {
+            accounts.write_batch(&after)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the transaction to the state `event` leads to and applies `account_op` to the
+    /// account of its client, or does neither. `account_op` may amend the transaction and
+    /// returns false, leaving the account untouched, to decline.
+    ///
+    /// This default is atomic only while the caller holds the client's ordering lock;
+    /// stores that can check and update in one step override it.
+    fn try_transition(
+        &self,
+        accounts: &dyn AccountStore,
+        tx_id: &TxId,
+        event: LifecycleEvent,
+        account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+    ) -> Result<Transition, ProcessorError> {
+        let Some(transaction) = self.get(tx_id)? else {
+            return Ok(Transition::TransactionNotFound);
+        };
+        let Some(new) = transaction.state.next(event) else {
+            return Ok(Transition::WrongState(transaction.state));
+        };
+
+        match apply_account_op(accounts, &transaction, account_op)? {
+            None => Ok(Transition::AccountNotFound),
+            Some((false, _)) => Ok(Transition::Declined),
+            Some((true, mut amended)) => {
+                amended.state = new;
+                self.insert(amended)?;
+                Ok(Transition::Applied)
+            }
+        }
+    }
+}
+
+/// Outcome of `TransactionStore::try_transition`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transition {
+    /// The state changed and the account operation was applied
+    Applied,
+    TransactionNotFound,
+    /// The event is not allowed in the transaction's state, nothing changed
+    WrongState(TransactionState),
+    AccountNotFound,
+    /// The account operation declined, nothing changed
+    Declined,
+}
+
+/// Runs `account_op` of a transition on the account of the transaction's client.
+/// Returns whether it applied with the amended transaction, or None if there is no account.
+pub(crate) fn apply_account_op(
+    accounts: &dyn AccountStore,
+    transaction: &Transaction,
+    account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+) -> Result<Option<(bool, Transaction)>, ProcessorError> {
+    let mut outcome = None;
+    let found = accounts.update(transaction.client_id, &mut |account| {
+        // Stores may retry on a fresh account, so every attempt starts from the original
+        let mut amended = transaction.clone();
+        let applied = account_op(&mut amended, account);
+        outcome = Some((applied, amended));
+    })?;
+
+    Ok(outcome.filter(|_| found))
+}
+
+/// Longest wait to connect to a redis store and for each read and write, unless set with
+/// `--store-timeout`
+pub const DEFAULT_STORE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Account and transaction stores selected by `--store`
+pub struct Stores {
+    pub accounts: Box<dyn AccountStore>,
+    pub transactions: Box<dyn TransactionStore>,
+}
+
+/// Opens the stores for a `--store` location
+pub fn open_stores(location: &str) -> Result<Stores, ProcessorError> {
+    open_stores_with_timeout(location, DEFAULT_STORE_TIMEOUT)
+}
+
+/// Opens the stores for a `--store` location, waiting at most `timeout` on each read from and
+/// write to a redis store, see `--store-timeout`
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+pub fn open_stores_with_timeout(location: &str, timeout: Duration) -> Result<Stores, ProcessorError> {
+    match location.split_once("://") {
+        Some(("memory", _)) => Ok(Stores {
+            accounts: Box::new(MemoryAccountStore::new()),
+            transactions: Box::new(MemoryTransactionStore::new()),
+        }),
+        Some(("file", path)) => {
+            let transactions = FileTransactionStore::open(path)?;
+            Ok(Stores {
+                accounts: Box::new(transactions.accounts()),
+                transactions: Box::new(transactions),
+            })
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Some(("redis", _)) => Ok(Stores {
+            accounts: Box::new(RedisAccountStore::open(location, timeout)?),
+            transactions: Box::new(RedisTransactionStore::open(location, timeout)?),
+        }),
+        _ => Err(ProcessorError::InvalidArguments(format!(
+            "unsupported store '{}', expected memory://, file://<dir> or redis://<host>[:port][/db]",
+            location
+        ))),
+    }
+}
@@ -0,0 +1,462 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use parking_lot::{Mutex, MutexGuard};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::audit::BatchChanges;
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{Transaction, TransactionState, TxId};
+use crate::store::{AccountStore, TransactionStore};
+
+const DEFAULT_PORT: u16 = 6379;
+const KEY_PREFIX: &str = "trx";
+/// Attempts of a WATCH/MULTI/EXEC update before giving up on a contended key
+const MAX_OPTIMISTIC_RETRIES: usize = 16;
+
+#[derive(Debug)]
+enum Reply {
+    Status,
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+    /// An error reply, read in full so the connection stays in step
+    Error(String),
+}
+
+impl Reply {
+    /// The reply, or the first error reply in it, e.g. a failed command in the reply to EXEC
+    fn into_result(self) -> Result<Reply, ProcessorError> {
+        match self {
+            Reply::Error(message) => Err(ProcessorError::StoreError(message)),
+            Reply::Array(Some(items)) => Ok(Reply::Array(Some(items.into_iter().map(Reply::into_result).collect::<Result<_, _>>()?))),
+            reply => Ok(reply),
+        }
+    }
+}
+
+/// Both halves of a connection to redis
+struct RespStream {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl RespStream {
+    fn connect(address: &str, timeout: Duration) -> Result<Self, ProcessorError> {
+        let mut last_err = None;
+        for address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(timeout))?;
+                    stream.set_write_timeout(Some(timeout))?;
+                    return Ok(RespStream { reader: BufReader::new(stream.try_clone()?), writer: stream });
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(match last_err {
+            Some(err) => err.into(),
+            None => ProcessorError::StoreError(format!("'{}' did not resolve to an address", address)),
+        })
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<Reply, ProcessorError> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg.as_bytes());
+            request.extend_from_slice(b"\r\n");
+        }
+        self.writer.write_all(&request)?;
+        self.read_reply()
+    }
+
+    fn read_reply(&mut self) -> Result<Reply, ProcessorError> {
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        let line = line.trim_end_matches("\r\n");
+        if line.is_empty() {
+            return Err(ProcessorError::StoreError("connection closed by redis".to_string()));
+        }
+
+        let (kind, payload) = line.split_at(1);
+        match kind {
+            "+" => Ok(Reply::Status),
+            "-" => Ok(Reply::Error(payload.to_string())),
+            ":" => Ok(Reply::Integer(parse_length(payload)?)),
+            "$" => {
+                let len = parse_length(payload)?;
+                if len < 0 {
+                    return Ok(Reply::Bulk(None));
+                }
+                let mut data = vec![0; len as usize + 2];
+                self.reader.read_exact(&mut data)?;
+                data.truncate(len as usize);
+                Ok(Reply::Bulk(Some(data)))
+            }
+            "*" => {
+                let len = parse_length(payload)?;
+                if len < 0 {
+                    return Ok(Reply::Array(None));
+                }
+                let items = (0..len).map(|_| self.read_reply()).collect::<Result<_, _>>()?;
+                Ok(Reply::Array(Some(items)))
+            }
+            _ => Err(ProcessorError::StoreError(format!("unexpected redis reply '{}'", line))),
+        }
+    }
+}
+
+/// Minimal RESP client, just enough for the commands the stores need.
+///
+/// Every read and write waits at most the connection's timeout, `--store-timeout`. A command
+/// that failed other than with an error reply, a timeout included, may leave a reply unread,
+/// so the connection is dropped and the commands after it in the same store operation fail.
+/// The next operation, e.g. the retry, connects again.
+struct RedisConnection {
+    address: String,
+    db: Option<String>,
+    timeout: Duration,
+    /// None once dropped, until `reconnect`
+    stream: Option<RespStream>,
+}
+
+impl RedisConnection {
+    /// Connects to `redis://host[:port][/db]`
+    fn open(url: &str, timeout: Duration) -> Result<Self, ProcessorError> {
+        let rest = url.strip_prefix("redis://").unwrap_or(url);
+        let (address, db) = match rest.split_once('/') {
+            Some((address, db)) if !db.is_empty() => (address, Some(db.to_string())),
+            Some((address, _)) => (address, None),
+            None => (rest, None),
+        };
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:{}", address, DEFAULT_PORT)
+        };
+
+        let mut connection = RedisConnection { address, db, timeout, stream: None };
+        connection.reconnect()?;
+        Ok(connection)
+    }
+
+    /// Connects again if the connection was dropped
+    fn reconnect(&mut self) -> Result<(), ProcessorError> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+        self.stream = Some(RespStream::connect(&self.address, self.timeout)?);
+        if let Some(db) = self.db.clone() {
+            self.command(&["SELECT", &db])?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<Reply, ProcessorError> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Err(ProcessorError::StoreError("the connection to redis was dropped after an earlier error".to_string()));
+        };
+        match stream.command(args) {
+            Ok(reply) => reply.into_result(),
+            Err(err) => {
+                self.stream = None;
+                Err(err)
+            }
+        }
+    }
+
+    fn get_json<T: DeserializeOwned>(&mut self, key: &str) -> Result<Option<T>, ProcessorError> {
+        match self.command(&["GET", key])? {
+            Reply::Bulk(Some(data)) => Ok(Some(serde_json::from_slice(&data)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn members(&mut self, set: &str) -> Result<Vec<String>, ProcessorError> {
+        match self.command(&["SMEMBERS", set])? {
+            Reply::Array(Some(items)) => Ok(items
+                .into_iter()
+                .filter_map(|item| match item {
+                    Reply::Bulk(Some(data)) => String::from_utf8(data).ok(),
+                    _ => None,
+                })
+                .collect()),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Read-modify-write of a JSON value guarded by WATCH, retried when another
+    /// writer changes the key between the read and the EXEC. Returns false if the key is missing.
+    fn update_json<T, F>(&mut self, key: &str, mut f: F) -> Result<bool, ProcessorError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnMut(&mut T),
+    {
+        for _ in 0..MAX_OPTIMISTIC_RETRIES {
+            self.command(&["WATCH", key])?;
+            let Some(mut value) = self.get_json::<T>(key)? else {
+                self.command(&["UNWATCH"])?;
+                return Ok(false);
+            };
+
+            f(&mut value);
+            let json = serde_json::to_string(&value)?;
+
+            if self.exec(|connection| connection.command(&["SET", key, &json]).map(|_| ()))? {
+                return Ok(true);
+            }
+        }
+
+        Err(ProcessorError::StoreError(format!("too many concurrent updates of '{}'", key)))
+    }
+
+    /// Runs the commands `queue` sends as one MULTI/EXEC. Returns false if EXEC was refused
+    /// because a watched key changed. If queueing fails the transaction is discarded, so the
+    /// connection is not left inside it.
+    fn exec(&mut self, queue: impl FnOnce(&mut Self) -> Result<(), ProcessorError>) -> Result<bool, ProcessorError> {
+        self.command(&["MULTI"])?;
+        if let Err(err) = queue(self) {
+            // Fails too if the connection itself failed, which the first error already says
+            let _ = self.command(&["DISCARD"]);
+            return Err(err);
+        }
+        match self.command(&["EXEC"])? {
+            Reply::Array(Some(_)) => Ok(true),
+            Reply::Array(None) => Ok(false),
+            reply => Err(ProcessorError::StoreError(format!("unexpected reply to EXEC: {:?}", reply))),
+        }
+    }
+
+    /// The value of `key` as `T` would serialize it, so images written by a version with
+    /// fewer fields still compare equal
+    fn get_image<T: Serialize + DeserializeOwned>(&mut self, key: &str) -> Result<Option<serde_json::Value>, ProcessorError> {
+        Ok(self.get_json::<T>(key)?.map(|value| serde_json::to_value(value)).transpose()?)
+    }
+
+    /// Writes a batch as one MULTI/EXEC, guarded by WATCH on every key it changes. Fails
+    /// with `StoreConflict` if a key no longer holds the image the batch read; a key that
+    /// already holds its after image was written by an earlier attempt whose reply was lost.
+    fn commit_batch(&mut self, changes: &BatchChanges) -> Result<(), ProcessorError> {
+        let mut images = Vec::new();
+        for change in &changes.transactions {
+            images.push((transaction_key(&change.tx), serde_json::to_value(&change.before)?, serde_json::to_value(&change.after)?, true));
+        }
+        for change in &changes.accounts {
+            images.push((account_key(change.after.client_id), serde_json::to_value(&change.before)?, serde_json::to_value(Some(&change.after))?, false));
+        }
+
+        for _ in 0..MAX_OPTIMISTIC_RETRIES {
+            let mut watch = vec!["WATCH"];
+            watch.extend(images.iter().map(|(key, ..)| key.as_str()));
+            self.command(&watch)?;
+
+            for (key, before, after, transaction) in &images {
+                let current = match transaction {
+                    true => self.get_image::<Transaction>(key)?,
+                    false => self.get_image::<Account>(key)?,
+                };
+                let current = current.unwrap_or(serde_json::Value::Null);
+                if current != *before && current != *after {
+                    self.command(&["UNWATCH"])?;
+                    return Err(ProcessorError::StoreConflict(format!("'{}' was changed by another writer since the batch read it", key)));
+                }
+            }
+
+            // Refused if a watched key changed after the check, which is made again
+            if self.exec(|connection| connection.queue_batch(changes))? {
+                return Ok(());
+            }
+        }
+
+        Err(ProcessorError::StoreError("too many concurrent updates of the batch's keys".to_string()))
+    }
+
+    /// Queues the writes of a batch inside MULTI, transactions before accounts
+    fn queue_batch(&mut self, changes: &BatchChanges) -> Result<(), ProcessorError> {
+        for change in &changes.transactions {
+            match &change.after {
+                Some(transaction) => {
+                    let json = serde_json::to_string(transaction)?;
+                    self.command(&["SET", &transaction_key(&change.tx), &json])?;
+                    self.command(&["SADD", &transactions_set(), &change.tx.to_string()])?;
+                }
+                None => {
+                    self.command(&["DEL", &transaction_key(&change.tx)])?;
+                    self.command(&["SREM", &transactions_set(), &change.tx.to_string()])?;
+                }
+            }
+        }
+        for change in &changes.accounts {
+            let json = serde_json::to_string(&change.after)?;
+            self.command(&["SET", &account_key(change.after.client_id), &json])?;
+            self.command(&["SADD", &accounts_set(), &change.after.client_id.to_string()])?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_length(payload: &str) -> Result<i64, ProcessorError> {
+    payload
+        .parse()
+        .map_err(|_| ProcessorError::StoreError(format!("invalid redis length '{}'", payload)))
+}
+
+fn account_key(client_id: u16) -> String {
+    format!("{}:account:{}", KEY_PREFIX, client_id)
+}
+
+fn accounts_set() -> String {
+    format!("{}:accounts", KEY_PREFIX)
+}
+
+fn transaction_key(tx_id: &TxId) -> String {
+    format!("{}:tx:{}", KEY_PREFIX, tx_id)
+}
+
+fn transactions_set() -> String {
+    format!("{}:transactions", KEY_PREFIX)
+}
+
+/// Locks the connection for one store operation, connecting again if an earlier one dropped it
+fn lock(connection: &Mutex<RedisConnection>) -> Result<MutexGuard<'_, RedisConnection>, ProcessorError> {
+    let mut connection = connection.lock();
+    connection.reconnect()?;
+    Ok(connection)
+}
+
+/// Account store shared by every processor instance pointed at the same Redis
+pub struct RedisAccountStore {
+    connection: Mutex<RedisConnection>,
+}
+
+impl RedisAccountStore {
+    pub fn open(url: &str, timeout: Duration) -> Result<Self, ProcessorError> {
+        Ok(RedisAccountStore {
+            connection: Mutex::new(RedisConnection::open(url, timeout)?),
+        })
+    }
+}
+
+impl AccountStore for RedisAccountStore {
+    fn get(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        lock(&self.connection)?.get_json(&account_key(client_id))
+    }
+
+    fn ensure(&self, client_id: u16) -> Result<(), ProcessorError> {
+        let json = serde_json::to_string(&Account::new(client_id))?;
+        let mut connection = lock(&self.connection)?;
+        connection.command(&["SET", &account_key(client_id), &json, "NX"])?;
+        connection.command(&["SADD", &accounts_set(), &client_id.to_string()])?;
+        Ok(())
+    }
+
+    fn update(&self, client_id: u16, f: &mut dyn FnMut(&mut Account)) -> Result<bool, ProcessorError> {
+        lock(&self.connection)?.update_json(&account_key(client_id), f)
+    }
+
+    fn iterate(&self) -> Result<Vec<Account>, ProcessorError> {
+        let mut connection = lock(&self.connection)?;
+        let mut accounts = Vec::new();
+        for member in connection.members(&accounts_set())? {
+            let Ok(client_id) = member.parse() else {
+                continue;
+            };
+            if let Some(account) = connection.get_json(&account_key(client_id))? {
+                accounts.push(account);
+            }
+        }
+        Ok(accounts)
+    }
+
+    fn write_batch(&self, accounts: &[Account]) -> Result<(), ProcessorError> {
+        lock(&self.connection)?.exec(|connection| {
+            for account in accounts {
+                let json = serde_json::to_string(account)?;
+                connection.command(&["SET", &account_key(account.client_id), &json])?;
+                connection.command(&["SADD", &accounts_set(), &account.client_id.to_string()])?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+}
+
+/// Transaction store shared by every processor instance pointed at the same Redis
+pub struct RedisTransactionStore {
+    connection: Mutex<RedisConnection>,
+}
+
+impl RedisTransactionStore {
+    pub fn open(url: &str, timeout: Duration) -> Result<Self, ProcessorError> {
+        Ok(RedisTransactionStore {
+            connection: Mutex::new(RedisConnection::open(url, timeout)?),
+        })
+    }
+}
+
+impl TransactionStore for RedisTransactionStore {
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        lock(&self.connection)?.get_json(&transaction_key(tx_id))
+    }
+
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError> {
+        let json = serde_json::to_string(&transaction)?;
+        let mut connection = lock(&self.connection)?;
+        connection.command(&["SET", &transaction_key(&transaction.tx_id), &json])?;
+        let added = connection.command(&["SADD", &transactions_set(), &transaction.tx_id.to_string()])?;
+        Ok(matches!(added, Reply::Integer(1)))
+    }
+
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError> {
+        self.connection
+            .lock()
+            .update_json(&transaction_key(tx_id), |transaction: &mut Transaction| {
+                transaction.state = state.clone();
+            })?;
+        Ok(())
+    }
+
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        let mut connection = lock(&self.connection)?;
+        connection.command(&["DEL", &transaction_key(tx_id)])?;
+        connection.command(&["SREM", &transactions_set(), &tx_id.to_string()])?;
+        Ok(())
+    }
+
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        let mut connection = lock(&self.connection)?;
+        let mut transactions = Vec::new();
+        for member in connection.members(&transactions_set())? {
+            if let Some(transaction) = connection.get_json(&format!("{}:tx:{}", KEY_PREFIX, member))? {
+                transactions.push(transaction);
+            }
+        }
+        Ok(transactions)
+    }
+
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+        lock(&self.connection)?.exec(|connection| {
+            for transaction in transactions {
+                let json = serde_json::to_string(transaction)?;
+                connection.command(&["SET", &transaction_key(&transaction.tx_id), &json])?;
+                connection.command(&["SADD", &transactions_set(), &transaction.tx_id.to_string()])?;
+            }
+            for tx_id in removed {
+                connection.command(&["DEL", &transaction_key(tx_id)])?;
+                connection.command(&["SREM", &transactions_set(), &tx_id.to_string()])?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Writes the accounts too, through this store's connection, as the keys of both stores
+    /// live in the same database
+    fn commit_batch(&self, _accounts: &dyn AccountStore, changes: &BatchChanges) -> Result<(), ProcessorError> {
+        lock(&self.connection)?.commit_batch(changes)
+    }
+}
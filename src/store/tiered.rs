@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::mem;
+
+use dashmap::mapref::one::RefMut;
+use dashmap::DashMap;
+use parking_lot::RwLock;
+
+use crate::model::account::Account;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::{LifecycleEvent, Transaction, TransactionState, TxId};
+use crate::store::{self, AccountStore, Stores, TransactionStore, Transition};
+
+/// Transactions per compressed block, all of which a lookup of one cold transaction decompresses
+const BLOCK_TRANSACTIONS: usize = 4096;
+
+/// Compressed JSON of transactions moved to the cold tier
+struct ColdBlock {
+    data: Vec<u8>,
+    /// Transactions of the block still cold, its data is freed when none are left
+    live: usize,
+}
+
+impl ColdBlock {
+    fn compress(transactions: &[Transaction]) -> Result<Self, ProcessorError> {
+        let data = lz4_flex::compress_prepend_size(&serde_json::to_vec(transactions)?);
+        Ok(ColdBlock { data, live: transactions.len() })
+    }
+
+    fn decompress(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        let json = lz4_flex::decompress_size_prepended(&self.data)
+            .map_err(|err| ProcessorError::StoreError(format!("corrupt cold block: {}", err)))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+#[derive(Default)]
+struct Tiers {
+    /// Recently stored or touched transactions, moved to `previous` once full
+    current: DashMap<TxId, Transaction>,
+    previous: DashMap<TxId, Transaction>,
+    /// Block of every cold transaction. Blocks keep the stale copies of transactions moved
+    /// back to the hot tier, this index is what says which copy counts.
+    cold_index: HashMap<TxId, usize>,
+    cold: Vec<ColdBlock>,
+}
+
+impl Tiers {
+    fn hot(&self, tx_id: &TxId) -> Option<Transaction> {
+        self.current.get(tx_id).or_else(|| self.previous.get(tx_id)).map(|transaction| transaction.clone())
+    }
+
+    fn hot_mut(&self, tx_id: &TxId) -> Option<RefMut<'_, TxId, Transaction>> {
+        self.current.get_mut(tx_id).or_else(|| self.previous.get_mut(tx_id))
+    }
+
+    fn is_cold(&self, tx_id: &TxId) -> bool {
+        self.cold_index.contains_key(tx_id)
+    }
+
+    fn cold(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        let Some(&block) = self.cold_index.get(tx_id) else {
+            return Ok(None);
+        };
+        Ok(self.cold[block].decompress()?.into_iter().find(|transaction| transaction.tx_id == *tx_id))
+    }
+
+    /// Moves a cold transaction back to the hot tier
+    fn thaw(&mut self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        let Some(transaction) = self.cold(tx_id)? else {
+            return Ok(());
+        };
+        if let Some(block) = self.cold_index.remove(tx_id) {
+            let block = &mut self.cold[block];
+            block.live -= 1;
+            if block.live == 0 {
+                block.data = Vec::new();
+            }
+        }
+        self.current.insert(tx_id.clone(), transaction);
+        Ok(())
+    }
+
+    /// Compresses the previous generation into the cold tier and starts a new current one
+    fn rotate(&mut self) -> Result<(), ProcessorError> {
+        let previous = mem::replace(&mut self.previous, mem::take(&mut self.current));
+        let transactions: Vec<Transaction> = previous.into_iter().map(|(_, transaction)| transaction).collect();
+        for chunk in transactions.chunks(BLOCK_TRANSACTIONS) {
+            let block = self.cold.len();
+            self.cold.push(ColdBlock::compress(chunk)?);
+            for transaction in chunk {
+                self.cold_index.insert(transaction.tx_id.clone(), block);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// In-memory transaction store that compresses transactions nobody touched for a while.
+///
+/// Transactions live in two hot generations of up to `hot_transactions` each. When the
+/// current one is full, the previous one is compressed into blocks of the cold tier, so a
+/// transaction stays hot for at least `hot_transactions` more stores. A cold transaction is
+/// decompressed to be read, and moved back to the hot tier to be changed.
+pub struct TieredTransactionStore {
+    tiers: RwLock<Tiers>,
+    hot_transactions: usize,
+}
+
+impl TieredTransactionStore {
+    pub fn new(hot_transactions: usize) -> Self {
+        TieredTransactionStore { tiers: RwLock::new(Tiers::default()), hot_transactions }
+    }
+
+    /// Runs `f` with the transaction, if stored, in the hot tier. Cold transactions are
+    /// thawed and `f` run under the write lock, so no rotation can move them back meanwhile.
+    fn with_hot<T>(&self, tx_id: &TxId, f: impl FnOnce(&Tiers) -> T) -> Result<T, ProcessorError> {
+        {
+            let tiers = self.tiers.read();
+            if !tiers.is_cold(tx_id) {
+                return Ok(f(&tiers));
+            }
+        }
+        let mut tiers = self.tiers.write();
+        tiers.thaw(tx_id)?;
+        Ok(f(&tiers))
+    }
+
+    fn rotate_if_full(&self) -> Result<(), ProcessorError> {
+        if self.tiers.read().current.len() < self.hot_transactions {
+            return Ok(());
+        }
+        let mut tiers = self.tiers.write();
+        if tiers.current.len() < self.hot_transactions {
+            return Ok(());
+        }
+        tiers.rotate()
+    }
+}
+
+impl Stores {
+    /// Replaces the in-memory transaction store with a tiered one, see `TieredTransactionStore`
+    pub fn with_cold_tier(self, hot_transactions: usize) -> Stores {
+        Stores {
+            accounts: self.accounts,
+            transactions: Box::new(TieredTransactionStore::new(hot_transactions)),
+        }
+    }
+}
+
+impl TransactionStore for TieredTransactionStore {
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        let tiers = self.tiers.read();
+        match tiers.hot(tx_id) {
+            Some(transaction) => Ok(Some(transaction)),
+            None => tiers.cold(tx_id),
+        }
+    }
+
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError> {
+        let tx_id = transaction.tx_id.clone();
+        let is_new = self.with_hot(&tx_id, |tiers| {
+            let replaced = tiers.previous.remove(&tx_id).is_some();
+            tiers.current.insert(tx_id.clone(), transaction).is_none() && !replaced
+        })?;
+        self.rotate_if_full()?;
+        Ok(is_new)
+    }
+
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError> {
+        self.with_hot(tx_id, |tiers| {
+            if let Some(mut transaction) = tiers.hot_mut(tx_id) {
+                transaction.state = state;
+            }
+        })
+    }
+
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        self.with_hot(tx_id, |tiers| {
+            tiers.current.remove(tx_id);
+            tiers.previous.remove(tx_id);
+        })
+    }
+
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        let tiers = self.tiers.read();
+        let mut transactions: Vec<Transaction> = tiers
+            .current
+            .iter()
+            .chain(tiers.previous.iter())
+            .map(|entry| entry.value().clone())
+            .collect();
+        for (index, block) in tiers.cold.iter().enumerate().filter(|(_, block)| block.live > 0) {
+            let cold = block.decompress()?.into_iter();
+            transactions.extend(cold.filter(|transaction| tiers.cold_index.get(&transaction.tx_id) == Some(&index)));
+        }
+        Ok(transactions)
+    }
+
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+        for transaction in transactions {
+            self.insert(transaction.clone())?;
+        }
+        for tx_id in removed {
+            self.remove(tx_id)?;
+        }
+        Ok(())
+    }
+
+    fn try_transition(
+        &self,
+        accounts: &dyn AccountStore,
+        tx_id: &TxId,
+        event: LifecycleEvent,
+        account_op: &mut dyn FnMut(&mut Transaction, &mut Account) -> bool,
+    ) -> Result<Transition, ProcessorError> {
+        self.with_hot(tx_id, |tiers| {
+            // As in the memory store, the entry stays locked while the account is updated
+            let Some(mut transaction) = tiers.hot_mut(tx_id) else {
+                return Ok(Transition::TransactionNotFound);
+            };
+            let Some(new) = transaction.state.next(event) else {
+                return Ok(Transition::WrongState(transaction.state.clone()));
+            };
+
+            match store::apply_account_op(accounts, &transaction, account_op)? {
+                None => Ok(Transition::AccountNotFound),
+                Some((false, _)) => Ok(Transition::Declined),
+                Some((true, mut amended)) => {
+                    amended.state = new;
+                    *transaction = amended;
+                    Ok(Transition::Applied)
+                }
+            }
+        })?
+    }
+}
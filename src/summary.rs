@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cohort::{CohortFigures, CohortStats};
+use crate::latency::LatencySummary;
+use crate::model::error::ProcessorError;
+use crate::model::rejection::RejectionReason;
+use crate::processor::TransactionProcessor;
+use crate::resources::ResourceUsage;
+use crate::retry::RetryStats;
+
+/// Counts describing one processing run
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub rows_processed: u64,
+    /// Rows processed per transaction type
+    #[serde(default)]
+    pub rows_by_type: BTreeMap<String, u64>,
+    pub rows_rejected: u64,
+    pub rejections: BTreeMap<RejectionReason, u64>,
+    /// Rows of the types disabled with `--disable`, per type
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rows_disabled: BTreeMap<String, u64>,
+    /// Rows of a client that arrived on another partition than its first row
+    pub partition_violations: u64,
+    /// Figures per cohort of `--cohorts`, per dimension of the mapping file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cohorts: BTreeMap<String, BTreeMap<String, CohortFigures>>,
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    /// Retries of the store operations, with `--store`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_retries: Option<RetryStats>,
+    /// Time spent processing rows
+    pub latency: LatencySummary,
+    pub resources: ResourceUsage,
+}
+
+impl RunSummary {
+    /// `elapsed` is the wall time of the run so far
+    pub fn collect(processor: &TransactionProcessor, elapsed: Duration) -> Result<Self, ProcessorError> {
+        let rejections = processor.rejection_counts();
+        let accounts = processor.accounts()?;
+        let rows_by_type = processor.row_counts();
+        // Every row of a disabled type is rejected, so its count is the count of its type
+        let rows_disabled = processor
+            .disabled_types()
+            .iter()
+            .map(|transaction_type| transaction_type.to_string())
+            .map(|name| (name.clone(), rows_by_type.get(&name).copied().unwrap_or(0)))
+            .collect();
+
+        Ok(RunSummary {
+            rows_processed: processor.rows_processed(),
+            rows_by_type,
+            rows_rejected: rejections.values().sum(),
+            rejections,
+            rows_disabled,
+            partition_violations: processor.partition_violations(),
+            cohorts: processor.cohort_stats().map(CohortStats::summary).unwrap_or_default(),
+            accounts: accounts.len(),
+            locked_accounts: accounts.iter().filter(|account| account.locked).count(),
+            store_retries: processor.store_retry_stats(),
+            latency: processor.latency().summary(),
+            resources: ResourceUsage::measure(elapsed, processor.rows_processed(), processor.bytes_read()),
+        })
+    }
+
+    /// Human readable summary on stderr, stdout carries the account CSV
+    pub fn print(&self) {
+        eprintln!("Rows processed: {}", self.rows_processed);
+        eprintln!("Rows rejected: {}", self.rows_rejected);
+        for (reason, count) in &self.rejections {
+            eprintln!("  {}: {}", reason, count);
+        }
+        if !self.rows_disabled.is_empty() {
+            eprintln!("Rows disabled: {}", self.rows_disabled.values().sum::<u64>());
+            for (transaction_type, count) in &self.rows_disabled {
+                eprintln!("  {}: {}", transaction_type, count);
+            }
+        }
+        if self.partition_violations > 0 {
+            eprintln!("Partition violations: {}", self.partition_violations);
+        }
+        for (dimension, cohorts) in &self.cohorts {
+            eprintln!("By {}:", dimension);
+            for (cohort, figures) in cohorts {
+                eprintln!(
+                    "  {}: {} client(s), {} rows ({} rejected), deposits {}, withdrawals {}, chargebacks {}",
+                    cohort, figures.clients, figures.rows, figures.rows_rejected, figures.deposits, figures.withdrawals, figures.chargebacks
+                );
+            }
+        }
+        eprintln!("Accounts: {} ({} locked)", self.accounts, self.locked_accounts);
+        if let Some(retries) = self.store_retries {
+            eprintln!(
+                "Store retries: {} ({} failed operations, circuit opened {} times)",
+                retries.retries, retries.failures, retries.circuit_opened
+            );
+        }
+
+        let latency = &self.latency;
+        eprintln!("Processing time: {:.1?}", Duration::from_micros(latency.total_us));
+        for bucket in latency.buckets.iter().filter(|bucket| bucket.rows > 0) {
+            match bucket.le_us {
+                Some(le_us) => eprintln!("  <= {:?}: {}", Duration::from_micros(le_us), bucket.rows),
+                None => eprintln!("  slower: {}", bucket.rows),
+            }
+        }
+        eprintln!("Slowest clients:");
+        for client in &latency.slowest_clients {
+            eprintln!(
+                "  client {}: {} rows, {:.1?} total, {:.1?} max",
+                client.client,
+                client.rows,
+                Duration::from_micros(client.total_us),
+                Duration::from_micros(client.max_us)
+            );
+        }
+        if let Some(threshold_us) = latency.slow_threshold_us {
+            eprintln!("Slow rows (over {:?}): {}", Duration::from_micros(threshold_us), latency.slow_rows);
+        }
+
+        let resources = &self.resources;
+        eprintln!("Wall time: {:.1?}", Duration::from_secs_f64(resources.wall_time_secs));
+        if let Some(cpu_time_secs) = resources.cpu_time_secs {
+            eprintln!("CPU time: {:.1?}", Duration::from_secs_f64(cpu_time_secs));
+        }
+        if let Some(peak_rss_bytes) = resources.peak_rss_bytes {
+            eprintln!("Peak RSS: {:.1} MiB", peak_rss_bytes as f64 / (1024.0 * 1024.0));
+        }
+        eprintln!("Throughput: {:.0} rows/s, {:.1} MiB/s", resources.rows_per_sec, resources.bytes_per_sec / (1024.0 * 1024.0));
+    }
+
+    /// JSON run report
+    pub fn write_json(&self, path: &str) -> Result<(), ProcessorError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Prometheus text exposition format, for the node exporter textfile collector.
+    /// Every rejection reason is listed, with zero if it did not occur.
+    pub fn write_prometheus(&self, path: &str) -> Result<(), ProcessorError> {
+        let mut metrics = String::new();
+
+        metrics.push_str("# HELP trx_rows_processed_total Transaction rows processed\n");
+        metrics.push_str("# TYPE trx_rows_processed_total counter\n");
+        metrics.push_str(&format!("trx_rows_processed_total {}\n", self.rows_processed));
+
+        metrics.push_str("# HELP trx_rejections_total Transaction rows rejected, by reason\n");
+        metrics.push_str("# TYPE trx_rejections_total counter\n");
+        for reason in RejectionReason::ALL {
+            let count = self.rejections.get(&reason).copied().unwrap_or(0);
+            metrics.push_str(&format!("trx_rejections_total{{reason=\"{}\"}} {}\n", reason, count));
+        }
+
+        metrics.push_str("# HELP trx_partition_violations_total Rows of a client that arrived on another partition than its first row\n");
+        metrics.push_str("# TYPE trx_partition_violations_total counter\n");
+        metrics.push_str(&format!("trx_partition_violations_total {}\n", self.partition_violations));
+
+        // Cumulative buckets, as Prometheus histograms expect
+        metrics.push_str("# HELP trx_row_processing_seconds Time spent processing a row\n");
+        metrics.push_str("# TYPE trx_row_processing_seconds histogram\n");
+        let mut rows = 0;
+        for bucket in &self.latency.buckets {
+            rows += bucket.rows;
+            let le = match bucket.le_us {
+                Some(le_us) => (le_us as f64 / 1_000_000.0).to_string(),
+                None => "+Inf".to_string(),
+            };
+            metrics.push_str(&format!("trx_row_processing_seconds_bucket{{le=\"{}\"}} {}\n", le, rows));
+        }
+        metrics.push_str(&format!("trx_row_processing_seconds_sum {}\n", self.latency.total_us as f64 / 1_000_000.0));
+        metrics.push_str(&format!("trx_row_processing_seconds_count {}\n", rows));
+
+        let resources = &self.resources;
+        metrics.push_str("# HELP trx_run_wall_seconds Wall time of the run\n");
+        metrics.push_str("# TYPE trx_run_wall_seconds gauge\n");
+        metrics.push_str(&format!("trx_run_wall_seconds {}\n", resources.wall_time_secs));
+        if let Some(cpu_time_secs) = resources.cpu_time_secs {
+            metrics.push_str("# HELP trx_run_cpu_seconds User and system CPU time of the run\n");
+            metrics.push_str("# TYPE trx_run_cpu_seconds gauge\n");
+            metrics.push_str(&format!("trx_run_cpu_seconds {}\n", cpu_time_secs));
+        }
+        if let Some(peak_rss_bytes) = resources.peak_rss_bytes {
+            metrics.push_str("# HELP trx_run_peak_rss_bytes Peak resident memory of the run\n");
+            metrics.push_str("# TYPE trx_run_peak_rss_bytes gauge\n");
+            metrics.push_str(&format!("trx_run_peak_rss_bytes {}\n", peak_rss_bytes));
+        }
+
+        metrics.push_str("# HELP trx_accounts Accounts at the end of the run\n");
+        metrics.push_str("# TYPE trx_accounts gauge\n");
+        metrics.push_str(&format!("trx_accounts {}\n", self.accounts));
+        metrics.push_str("# HELP trx_locked_accounts Locked accounts at the end of the run\n");
+        metrics.push_str("# TYPE trx_locked_accounts gauge\n");
+        metrics.push_str(&format!("trx_locked_accounts {}\n", self.locked_accounts));
+        if let Some(retries) = self.store_retries {
+            metrics.push_str("# HELP trx_store_retries_total Store operations tried again after a transient failure\n");
+            metrics.push_str("# TYPE trx_store_retries_total counter\n");
+            metrics.push_str(&format!("trx_store_retries_total {}\n", retries.retries));
+            metrics.push_str("# HELP trx_store_failures_total Store operations that failed through every retry or with the circuit open\n");
+            metrics.push_str("# TYPE trx_store_failures_total counter\n");
+            metrics.push_str(&format!("trx_store_failures_total {}\n", retries.failures));
+            metrics.push_str("# HELP trx_store_circuit_opened_total Times the store circuit breaker opened\n");
+            metrics.push_str("# TYPE trx_store_circuit_opened_total counter\n");
+            metrics.push_str(&format!("trx_store_circuit_opened_total {}\n", retries.circuit_opened));
+        }
+
+        // Collectors may read the file at any time, so it is replaced in one rename
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, metrics)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
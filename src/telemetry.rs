@@ -0,0 +1,267 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+
+use crate::http;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::TransactionInput;
+
+const DEFAULT_PORT: u16 = 4318;
+const SERVICE_NAME: &str = "trx-processor";
+/// Spans per export request, the default batch size of the OpenTelemetry SDKs
+const MAX_EXPORT_BATCH: usize = 512;
+
+/// OTLP span kind INTERNAL
+const SPAN_KIND_INTERNAL: u8 = 1;
+/// OTLP status code ERROR
+const STATUS_ERROR: u8 = 2;
+
+struct Span {
+    span_id: u64,
+    parent_span_id: u64,
+    name: &'static str,
+    start: u64,
+    end: u64,
+    attributes: Vec<Value>,
+    error: Option<String>,
+}
+
+impl Span {
+    fn to_json(&self, trace_id: &str) -> Value {
+        let mut span = json!({
+            "traceId": trace_id,
+            "spanId": format!("{:016x}", self.span_id),
+            "name": self.name,
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": self.end.to_string(),
+            "attributes": self.attributes,
+        });
+        if self.parent_span_id != 0 {
+            span["parentSpanId"] = json!(format!("{:016x}", self.parent_span_id));
+        }
+        if let Some(ref message) = self.error {
+            span["status"] = json!({ "code": STATUS_ERROR, "message": message });
+        }
+        span
+    }
+}
+
+/// A record span that was started but not finished yet
+pub struct RecordSpan {
+    start: u64,
+    attributes: Vec<Value>,
+}
+
+/// Batch span the record spans are collected under
+struct OpenBatch {
+    span_id: u64,
+    start: u64,
+    rows: u64,
+    spans: Vec<Span>,
+}
+
+/// Exports spans over OTLP/HTTP with JSON encoding, enabled with `--otlp-endpoint`.
+///
+/// A run is one trace: a `trx.run` root span, a `trx.batch` span per committed batch (the
+/// whole file without `--store`) and a `trx.record` span per processed row, carrying the
+/// client, tx and type of the row. Spans are exported when their batch ends, so a
+/// long-running file shows up batch by batch.
+pub struct Tracer {
+    address: String,
+    host: String,
+    path: String,
+    ids: RandomState,
+    next_id: AtomicU64,
+    trace_id: String,
+    run_span_id: u64,
+    run_start: u64,
+    batch: Mutex<Option<OpenBatch>>,
+    exported: AtomicU64,
+    dropped: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl Tracer {
+    /// Exports to `http://host[:port][/path]`, posting to `<path>/v1/traces`
+    pub fn new(endpoint: &str) -> Result<Self, ProcessorError> {
+        let Some(rest) = endpoint.strip_prefix("http://") else {
+            return Err(ProcessorError::InvalidArguments(format!(
+                "unsupported OTLP endpoint {}, expected http://<host>[:port]",
+                endpoint
+            )));
+        };
+        let (host, base_path) = match rest.split_once('/') {
+            Some((host, path)) => (host, path.trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            return Err(ProcessorError::InvalidArguments(format!("OTLP endpoint {} has no host", endpoint)));
+        }
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+        let path = match base_path {
+            "" => "/v1/traces".to_string(),
+            base_path => format!("/{}/v1/traces", base_path),
+        };
+
+        let mut tracer = Tracer {
+            address,
+            host: host.to_string(),
+            path,
+            ids: RandomState::new(),
+            next_id: AtomicU64::new(0),
+            trace_id: String::new(),
+            run_span_id: 0,
+            run_start: now(),
+            batch: Mutex::new(None),
+            exported: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        };
+        tracer.trace_id = format!("{:016x}{:016x}", tracer.new_id(), tracer.new_id());
+        tracer.run_span_id = tracer.new_id();
+        Ok(tracer)
+    }
+
+    /// Random non-zero id, zero means "no parent" in OTLP
+    fn new_id(&self) -> u64 {
+        self.ids.hash_one(self.next_id.fetch_add(1, Ordering::Relaxed)).max(1)
+    }
+
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    pub fn exported(&self) -> u64 {
+        self.exported.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().clone()
+    }
+
+    pub fn start_record(&self, record: &TransactionInput) -> RecordSpan {
+        RecordSpan {
+            start: now(),
+            attributes: vec![
+                int_attribute("trx.client", record.client.into()),
+                string_attribute("trx.tx", &record.tx.to_string()),
+                string_attribute("trx.type", &record.transaction_type.to_string()),
+            ],
+        }
+    }
+
+    /// Finishes a record span under the current batch, opening one if needed
+    pub fn end_record(&self, span: RecordSpan, error: Option<&ProcessorError>) {
+        let end = now();
+        let mut batch = self.batch.lock();
+        let batch = batch.get_or_insert_with(|| OpenBatch {
+            span_id: self.new_id(),
+            start: span.start,
+            rows: 0,
+            spans: Vec::new(),
+        });
+        batch.rows += 1;
+        batch.spans.push(Span {
+            span_id: self.new_id(),
+            parent_span_id: batch.span_id,
+            name: "trx.record",
+            start: span.start,
+            end,
+            attributes: span.attributes,
+            error: error.map(ToString::to_string),
+        });
+    }
+
+    /// Finishes the current batch span, if any row was recorded, and exports its spans
+    pub fn end_batch(&self, error: Option<&ProcessorError>) {
+        let Some(batch) = self.batch.lock().take() else {
+            return;
+        };
+
+        let mut spans = batch.spans;
+        spans.push(Span {
+            span_id: batch.span_id,
+            parent_span_id: self.run_span_id,
+            name: "trx.batch",
+            start: batch.start,
+            end: now(),
+            attributes: vec![int_attribute("trx.rows", batch.rows)],
+            error: error.map(ToString::to_string),
+        });
+        self.export(&spans);
+    }
+
+    /// Ends the batch still open and the run span
+    pub fn finish(&self, rows_processed: u64) {
+        self.end_batch(None);
+        self.export(&[Span {
+            span_id: self.run_span_id,
+            parent_span_id: 0,
+            name: "trx.run",
+            start: self.run_start,
+            end: now(),
+            attributes: vec![int_attribute("trx.rows", rows_processed)],
+            error: None,
+        }]);
+    }
+
+    /// Failed exports are counted and the spans dropped, tracing never fails a run
+    fn export(&self, spans: &[Span]) {
+        for chunk in spans.chunks(MAX_EXPORT_BATCH) {
+            match self.post(chunk) {
+                Ok(()) => self.exported.fetch_add(chunk.len() as u64, Ordering::Relaxed),
+                Err(err) => {
+                    *self.last_error.lock() = Some(err.to_string());
+                    self.dropped.fetch_add(chunk.len() as u64, Ordering::Relaxed)
+                }
+            };
+        }
+    }
+
+    fn post(&self, spans: &[Span]) -> Result<(), ProcessorError> {
+        let body = serde_json::to_vec(&json!({
+            "resourceSpans": [{
+                "resource": { "attributes": [string_attribute("service.name", SERVICE_NAME)] },
+                "scopeSpans": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans.iter().map(|span| span.to_json(&self.trace_id)).collect::<Vec<_>>(),
+                }],
+            }],
+        }))?;
+
+        let response = http::post_json(&self.address, &self.host, &self.path, &body)?;
+        if !(200..300).contains(&response.status) {
+            return Err(io::Error::other(format!("OTLP collector answered {}", response.status)).into());
+        }
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP JSON encodes 64 bit integers as strings
+fn int_attribute(key: &str, value: u64) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
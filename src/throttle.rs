@@ -0,0 +1,43 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// How far behind its schedule the limiter may fall and catch up on afterwards. Being
+/// ahead by less than this is not slept off either, as sleeps that short overshoot.
+const SLACK: Duration = Duration::from_millis(10);
+
+/// Paces rows to at most `--max-tps` per second, so that the stores, sinks and callouts a run
+/// feeds are not flooded during a backfill. Row `n` is due `n / rate` seconds after the
+/// first; a run held up by something else does not make up for more than `SLACK` of it.
+pub struct RateLimiter {
+    interval: Duration,
+    next_due: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(rows_per_sec: f64) -> Self {
+        RateLimiter { interval: Duration::from_secs_f64(1.0 / rows_per_sec), next_due: Mutex::new(None) }
+    }
+
+    /// Waits until the next row is due
+    pub fn acquire(&self) {
+        let wait = {
+            let mut next_due = self.next_due.lock();
+            let now = Instant::now();
+            let due = next_due.map_or(now, |due| due.max(now.checked_sub(SLACK).unwrap_or(now)));
+            *next_due = Some(due + self.interval);
+            due.checked_duration_since(now).filter(|wait| *wait >= SLACK)
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Parses a `--max-tps` rate such as `500` or `0.5`
+pub fn parse_rate(value: &str) -> Option<f64> {
+    let rate: f64 = value.parse().ok()?;
+    // Rates so small that the interval between rows overflows a Duration are refused
+    (rate.is_finite() && rate > 0.0 && Duration::try_from_secs_f64(1.0 / rate).is_ok()).then_some(rate)
+}
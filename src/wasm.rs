@@ -0,0 +1,59 @@
+use wasm_bindgen::prelude::*;
+
+use crate::model::account::ShortfallPolicy;
+use crate::model::error::ProcessorError;
+use crate::model::transaction::TxIdKind;
+use crate::processor::TransactionProcessor;
+
+fn js_error(err: ProcessorError) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// The engine for the browser. State is kept in memory between calls,
+/// so several CSV chunks can be replayed one after the other.
+#[wasm_bindgen(js_name = TransactionProcessor)]
+pub struct WasmTransactionProcessor {
+    inner: TransactionProcessor,
+}
+
+#[wasm_bindgen(js_class = TransactionProcessor)]
+impl WasmTransactionProcessor {
+    /// Options match the CLI flags: `txIdType` is `u32`, `u64` or `string`,
+    /// `shortfallPolicy` is `reject`, `negative` or `partial`
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        tx_id_type: Option<String>,
+        allow_adjustments: Option<bool>,
+        shortfall_policy: Option<String>,
+    ) -> Result<WasmTransactionProcessor, JsError> {
+        let tx_id_type = tx_id_type.as_deref().unwrap_or("u32");
+        let tx_id_kind = TxIdKind::from_name(tx_id_type)
+            .ok_or_else(|| JsError::new(&format!("invalid txIdType '{}'", tx_id_type)))?;
+        let shortfall_policy = shortfall_policy.as_deref().unwrap_or("reject");
+        let shortfall_policy = ShortfallPolicy::from_name(shortfall_policy)
+            .ok_or_else(|| JsError::new(&format!("invalid shortfallPolicy '{}'", shortfall_policy)))?;
+
+        Ok(WasmTransactionProcessor {
+            inner: TransactionProcessor::new()
+                .with_tx_id_kind(tx_id_kind)
+                .with_adjustments(allow_adjustments.unwrap_or(false))
+                .with_shortfall_policy(shortfall_policy),
+        })
+    }
+
+    /// Processes CSV text in the input file format, header row included
+    #[wasm_bindgen(js_name = processCsvString)]
+    pub fn process_csv_string(&self, csv: &str) -> Result<(), JsError> {
+        self.inner.process_reader(csv.as_bytes()).map_err(js_error)
+    }
+
+    /// Accounts as objects with the report columns. Amounts are strings, so no precision is lost.
+    #[wasm_bindgen(js_name = getAccounts)]
+    pub fn get_accounts(&self) -> Result<JsValue, JsError> {
+        let accounts: Vec<_> = self.inner.accounts().map_err(js_error)?
+            .iter()
+            .map(|account| account.to_output())
+            .collect();
+        Ok(serde_wasm_bindgen::to_value(&accounts)?)
+    }
+}
@@ -0,0 +1,112 @@
+//! Time stamped outputs follow the injected clock, so they can be checked exactly.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Local, SecondsFormat, TimeZone, Utc};
+use trx_processor::clock::{Clock, MockClock};
+use trx_processor::dead_letter::{DeadLetter, DeadLetterQueue};
+use trx_processor::jobs::{self, JobQueue};
+use trx_processor::logger::{LogTimeFormat, LogTimezone, Logger};
+use trx_processor::processor::{csv_reader, TransactionProcessor};
+use trx_processor::replay;
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()
+}
+
+#[test]
+fn test_mock_clock_only_moves_when_told() {
+    let clock = MockClock::new(start());
+    assert_eq!(clock.now(), start());
+
+    clock.advance(Duration::hours(25));
+    assert_eq!(clock.now(), start() + Duration::hours(25));
+
+    clock.set(start());
+    assert_eq!(clock.now(), start());
+}
+
+#[test]
+fn test_dead_letters_are_stamped_with_the_clock() {
+    let path = std::env::temp_dir().join(format!("trx_clock_dead_letter_{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let clock = Arc::new(MockClock::new(start()));
+    let processor = TransactionProcessor::new()
+        .with_clock(clock.clone())
+        .with_dead_letter_queue(DeadLetterQueue::open(path.to_str().unwrap()).unwrap());
+
+    processor.process_reader("type,client,tx,amount\nrefund,1,1,10\n".as_bytes()).unwrap();
+    clock.advance(Duration::minutes(5));
+    processor.process_reader("type,client,tx,amount\nrefund,1,2,10\n".as_bytes()).unwrap();
+
+    let letters: Vec<DeadLetter> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(letters.iter().map(|letter| letter.at).collect::<Vec<_>>(), [start(), start() + Duration::minutes(5)]);
+}
+
+#[test]
+fn test_log_lines_are_stamped_with_the_clock() {
+    let path = std::env::temp_dir().join(format!("trx_clock_log_{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let clock = Arc::new(MockClock::new(start()));
+    let logger = Logger::new(path.to_str().unwrap()).unwrap().with_clock(clock);
+    logger.log("DEPOSIT SUCCESS: client=1, tx=1, amount=10");
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let timestamp = start().with_timezone(&Local).to_rfc3339_opts(SecondsFormat::Millis, true);
+    assert_eq!(log, format!("[{}] DEPOSIT SUCCESS: client=1, tx=1, amount=10\n", timestamp));
+}
+
+#[test]
+fn test_log_lines_follow_the_configured_timezone_and_format() {
+    let path = std::env::temp_dir().join(format!("trx_clock_log_utc_{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let clock = Arc::new(MockClock::new(start()));
+    let logger = Logger::new(path.to_str().unwrap()).unwrap().with_clock(clock.clone()).with_timezone(LogTimezone::Utc);
+    logger.log("DEPOSIT SUCCESS: client=1, tx=1, amount=10");
+    let logger = logger.with_time_format(LogTimeFormat::parse("%d/%m/%Y %H:%M").unwrap());
+    logger.log("DEPOSIT SUCCESS: client=1, tx=2, amount=10");
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(
+        log,
+        "[2024-03-01T08:00:00.000Z] DEPOSIT SUCCESS: client=1, tx=1, amount=10\n\
+         [01/03/2024 08:00] DEPOSIT SUCCESS: client=1, tx=2, amount=10\n"
+    );
+}
+
+#[test]
+fn test_replay_restamps_rows_with_the_clock() {
+    let input = "type,client,tx,amount,timestamp\ndeposit,1,1,10,2020-01-01T00:00:00Z\ndeposit,1,2,10,2020-01-01T00:00:00.001Z\n";
+    let clock = MockClock::new(start());
+    let mut output = Vec::new();
+    replay::replay(csv_reader(input.as_bytes()), &mut output, 1.0, true, &clock).unwrap();
+
+    let stamp = start().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let expected = format!("type,client,tx,amount,timestamp\ndeposit,1,1,10,{}\ndeposit,1,2,10,{}\n", stamp, stamp);
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn test_jobs_are_stamped_with_the_clock() {
+    let dir = std::env::temp_dir().join(format!("trx_clock_jobs_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let clock = Arc::new(MockClock::new(start()));
+    let queue = JobQueue::open(dir.to_str().unwrap()).unwrap().with_clock(clock);
+    let spec = jobs::spec("tests/fixtures/sample_transactions.csv", Vec::new(), None, None, None).unwrap();
+    queue.enqueue(vec![spec]).unwrap();
+
+    let enqueued_at = queue.jobs().unwrap()[0].enqueued_at;
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_eq!(enqueued_at, start());
+}
@@ -0,0 +1,127 @@
+//! Differential tests: generated inputs are run through the processor and through the
+//! sequential reference engine, and the resulting accounts must match exactly.
+
+use std::collections::BTreeMap;
+use std::thread;
+
+use rust_decimal::Decimal;
+use trx_processor::model::transaction::{TransactionInput, TransactionType, TxId};
+use trx_processor::processor::TransactionProcessor;
+use trx_processor::reference::{ReferenceAccount, ReferenceEngine};
+
+const CLIENTS: u16 = 5;
+const ROWS: usize = 2_000;
+
+/// Small deterministic generator, so failures can be reproduced from the seed
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
+
+/// Rows for the core transaction types. Transaction ids are unique per client, and
+/// disputes mostly refer to the client's own earlier ids, so most of them apply.
+fn generate(seed: u64) -> Vec<TransactionInput> {
+    let mut rng = Lcg(seed);
+    let mut issued = vec![0_u64; CLIENTS as usize + 1];
+
+    (0..ROWS)
+        .map(|_| {
+            let client = rng.next(CLIENTS as u64) as u16 + 1;
+            let (transaction_type, tx, amount) = match rng.next(10) {
+                0..=3 => {
+                    issued[client as usize] += 1;
+                    (TransactionType::Deposit, issued[client as usize], Some(Decimal::new(rng.next(100_000) as i64, 4)))
+                }
+                4..=6 => {
+                    issued[client as usize] += 1;
+                    (TransactionType::Withdrawal, issued[client as usize], Some(Decimal::new(rng.next(100_000) as i64, 4)))
+                }
+                kind => {
+                    let transaction_type = match kind {
+                        7 => TransactionType::Dispute,
+                        8 => TransactionType::Resolve,
+                        _ => TransactionType::Chargeback,
+                    };
+                    (transaction_type, rng.next(issued[client as usize] + 2), None)
+                }
+            };
+
+            TransactionInput {
+                transaction_type,
+                client,
+                tx: TxId::Numeric(client as u64 * 1_000_000 + tx),
+                amount,
+                effective_date: None,
+                timestamp: None,
+                reason_code: None,
+                metadata: None,
+                partition: None,
+                idempotency_key: None,
+                target_client: None,
+            }
+        })
+        .collect()
+}
+
+fn reference_accounts(records: &[TransactionInput]) -> BTreeMap<u16, ReferenceAccount> {
+    let mut reference = ReferenceEngine::new();
+    for record in records {
+        reference.process(record);
+    }
+    reference.accounts().clone()
+}
+
+fn processor_accounts(processor: &TransactionProcessor) -> BTreeMap<u16, ReferenceAccount> {
+    processor
+        .accounts()
+        .unwrap()
+        .into_iter()
+        .map(|account| {
+            (account.client_id, ReferenceAccount {
+                available: account.available,
+                held: account.held,
+                locked: account.locked,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_sequential_processing_matches_reference() {
+    for seed in 0..20 {
+        let records = generate(seed);
+        let processor = TransactionProcessor::new();
+        for record in &records {
+            processor.process_record(record.clone()).unwrap();
+        }
+
+        assert_eq!(processor_accounts(&processor), reference_accounts(&records), "seed {}", seed);
+    }
+}
+
+#[test]
+fn test_concurrent_processing_matches_reference() {
+    for seed in 0..20 {
+        let records = generate(seed);
+        let processor = TransactionProcessor::new();
+
+        // One thread per client, each keeping the order of its client's rows
+        thread::scope(|scope| {
+            for client in 1..=CLIENTS {
+                let records = &records;
+                let processor = &processor;
+                scope.spawn(move || {
+                    for record in records.iter().filter(|record| record.client == client) {
+                        processor.process_record(record.clone()).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(processor_accounts(&processor), reference_accounts(&records), "seed {}", seed);
+    }
+}
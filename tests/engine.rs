@@ -0,0 +1,735 @@
+//! Unit tests of the engine handlers, run against in-memory stores without any input file.
+//! Every rejection the engine can decide has a test here; duplicates, freezes, screening
+//! and the review queue are decided by the processor and covered by the CLI tests.
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use trx_processor::account_csv::AccountCsvWriter;
+use trx_processor::dedup::{DedupFilter, DedupWindow};
+use trx_processor::engine::{self, CustomTransaction, EngineConfig, EngineState, Outcome, Rejection};
+use trx_processor::model::account::{Account, AccountRow, AmountFormat, FormatAmounts, ShortfallPolicy};
+use trx_processor::model::error::ProcessorError;
+use trx_processor::model::rejection::RejectionReason;
+use trx_processor::model::transaction::{Transaction, TransactionInput, TransactionState, TransactionType, TxId};
+use trx_processor::processor::TransactionProcessor;
+use trx_processor::rules::RulesConfig;
+use trx_processor::scheduler::FairScheduler;
+use trx_processor::store::{MemoryAccountStore, MemoryTransactionStore, Stores, TransactionStore};
+
+/// Stores with empty accounts for clients 1 and 2
+fn stores() -> Stores {
+    let stores = Stores {
+        accounts: Box::new(MemoryAccountStore::new()),
+        transactions: Box::new(MemoryTransactionStore::new()),
+    };
+    stores.accounts.ensure(1).unwrap();
+    stores.accounts.ensure(2).unwrap();
+    stores
+}
+
+fn row(transaction_type: TransactionType, client: u16, tx: u64, amount: Option<&str>) -> TransactionInput {
+    TransactionInput {
+        transaction_type,
+        client,
+        tx: TxId::Numeric(tx),
+        amount: amount.map(|amount| Decimal::from_str(amount).unwrap()),
+        effective_date: None,
+        timestamp: None,
+        reason_code: None,
+        metadata: None,
+        partition: None,
+        idempotency_key: None,
+        target_client: None,
+    }
+}
+
+fn apply(state: &dyn EngineState, config: &EngineConfig, record: TransactionInput) -> Outcome {
+    engine::apply(state, config, &record).unwrap()
+}
+
+fn rejection(state: &dyn EngineState, config: &EngineConfig, record: TransactionInput) -> Option<RejectionReason> {
+    apply(state, config, record).rejection_reason()
+}
+
+fn account(stores: &Stores, client: u16) -> Account {
+    stores.accounts.get(client).unwrap().unwrap()
+}
+
+fn amount(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+/// Client 1 with a deposit of 100 as tx 1
+fn funded() -> Stores {
+    let stores = stores();
+    let outcome = apply(&stores, &EngineConfig::default(), row(TransactionType::Deposit, 1, 1, Some("100")));
+    assert_eq!(outcome, Outcome::Applied("DEPOSIT SUCCESS: client=1, tx=1, amount=100".to_string()));
+    stores
+}
+
+/// Client 1 with tx 1 of 100 under dispute
+fn disputed() -> Stores {
+    let stores = funded();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Dispute, 1, 1, None)), None);
+    stores
+}
+
+// ============================================================================
+// Deposits And Withdrawals
+// ============================================================================
+
+#[test]
+fn test_amount_rows_without_amount() {
+    let stores = funded();
+    for transaction_type in [TransactionType::Deposit, TransactionType::Withdrawal, TransactionType::Reserve] {
+        assert_eq!(rejection(&stores, &EngineConfig::default(), row(transaction_type, 1, 2, None)), Some(RejectionReason::MissingAmount));
+    }
+}
+
+#[test]
+fn test_amount_rows_with_non_positive_amount() {
+    let stores = funded();
+    for transaction_type in [TransactionType::Deposit, TransactionType::Withdrawal, TransactionType::Reserve] {
+        for value in ["0", "-5"] {
+            assert_eq!(
+                rejection(&stores, &EngineConfig::default(), row(transaction_type.clone(), 1, 2, Some(value))),
+                Some(RejectionReason::NonPositiveAmount)
+            );
+        }
+    }
+    assert_eq!(account(&stores, 1).available, amount("100"));
+}
+
+#[test]
+fn test_deposit_to_locked_account() {
+    let stores = funded();
+    stores.accounts.update(1, &mut |account| account.locked = true).unwrap();
+
+    let outcome = apply(&stores, &EngineConfig::default(), row(TransactionType::Deposit, 1, 2, Some("5")));
+    assert_eq!(
+        outcome,
+        Outcome::Rejected(Rejection {
+            reason: RejectionReason::AccountLocked,
+            message: "DEPOSIT REJECTED: client=1, tx=2, amount=5".to_string(),
+            detail: None,
+        })
+    );
+    assert!(stores.transaction(&TxId::Numeric(2)).unwrap().is_none());
+}
+
+#[test]
+fn test_withdrawal_over_available_funds() {
+    let stores = funded();
+    let outcome = apply(&stores, &EngineConfig::default(), row(TransactionType::Withdrawal, 1, 2, Some("100.0001")));
+    assert_eq!(outcome.rejection_reason(), Some(RejectionReason::InsufficientFundsOrLocked));
+    assert!(outcome.risk_event(&TransactionType::Withdrawal).is_some());
+
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Withdrawal, 1, 3, Some("100"))), None);
+    assert_eq!(account(&stores, 1).available, Decimal::ZERO);
+}
+
+#[test]
+fn test_withdrawal_from_locked_account() {
+    let stores = funded();
+    stores.accounts.update(1, &mut |account| account.locked = true).unwrap();
+    assert_eq!(
+        rejection(&stores, &EngineConfig::default(), row(TransactionType::Withdrawal, 1, 2, Some("1"))),
+        Some(RejectionReason::InsufficientFundsOrLocked)
+    );
+}
+
+// ============================================================================
+// Disputes, Resolves And Chargebacks
+// ============================================================================
+
+#[test]
+fn test_dispute_lifecycle_rows_of_unknown_transaction() {
+    let stores = funded();
+    for transaction_type in [TransactionType::Dispute, TransactionType::Resolve, TransactionType::Chargeback] {
+        assert_eq!(rejection(&stores, &EngineConfig::default(), row(transaction_type, 1, 9, None)), Some(RejectionReason::TransactionNotFound));
+    }
+}
+
+#[test]
+fn test_dispute_lookups_through_tx_bloom() {
+    let stores = funded().with_tx_bloom(100).unwrap();
+    let config = EngineConfig::default();
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Dispute, 1, 9, None)), Some(RejectionReason::TransactionNotFound));
+
+    // Tx 1 was stored before the filter was filled, tx 2 after
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Dispute, 1, 1, None)), None);
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Deposit, 1, 2, Some("10"))), None);
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Dispute, 1, 2, None)), None);
+    assert_eq!(account(&stores, 1).held, amount("110"));
+}
+
+#[test]
+fn test_dispute_lifecycle_of_cold_transactions() {
+    let stores = stores().with_cold_tier(2);
+    stores.accounts.ensure(1).unwrap();
+    let config = EngineConfig::default();
+    for tx in 1..=10 {
+        assert_eq!(rejection(&stores, &config, row(TransactionType::Deposit, 1, tx, Some("10"))), None);
+    }
+
+    // Tx 1 was compressed long ago, tx 9 is still hot
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Dispute, 1, 1, None)), None);
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Dispute, 1, 9, None)), None);
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Dispute, 1, 11, None)), Some(RejectionReason::TransactionNotFound));
+    assert_eq!(stores.transactions.get(&TxId::Numeric(3)).unwrap().unwrap().amount, amount("10"));
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Chargeback, 1, 1, None)), None);
+
+    let transactions = stores.transactions.iterate().unwrap();
+    assert_eq!(transactions.len(), 10);
+    assert_eq!(transactions.iter().find(|transaction| transaction.tx_id == TxId::Numeric(1)).unwrap().state, TransactionState::ChargedBack);
+    assert_eq!(account(&stores, 1).held, amount("10"));
+    assert!(account(&stores, 1).locked);
+}
+
+#[test]
+fn test_dispute_lifecycle_rows_of_other_client() {
+    let stores = funded();
+    for transaction_type in [TransactionType::Dispute, TransactionType::Resolve, TransactionType::Chargeback] {
+        let outcome = apply(&stores, &EngineConfig::default(), row(transaction_type, 2, 1, None));
+        let Outcome::Rejected(rejected) = outcome else {
+            panic!("row of client 2 applied to the transaction of client 1");
+        };
+        assert_eq!(rejected.reason, RejectionReason::ClientMismatch);
+        assert_eq!(rejected.detail.as_deref(), Some("tx_client=1"));
+    }
+    assert_eq!(account(&stores, 1).held, Decimal::ZERO);
+}
+
+#[test]
+fn test_dispute_of_reserve() {
+    let stores = funded();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Reserve, 1, 2, Some("10"))), None);
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Dispute, 1, 2, None)), Some(RejectionReason::NonDepositTransaction));
+}
+
+#[test]
+fn test_dispute_twice() {
+    let stores = disputed();
+    let outcome = apply(&stores, &EngineConfig::default(), row(TransactionType::Dispute, 1, 1, None));
+    let Outcome::Rejected(rejected) = outcome else {
+        panic!("second dispute applied");
+    };
+    assert_eq!(rejected.reason, RejectionReason::InvalidState);
+    assert_eq!(rejected.detail.as_deref(), Some("state=UnderDispute"));
+    assert_eq!(account(&stores, 1).held, amount("100"));
+}
+
+#[test]
+fn test_dispute_after_chargeback() {
+    let stores = disputed();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Chargeback, 1, 1, None)), None);
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Dispute, 1, 1, None)), Some(RejectionReason::InvalidState));
+}
+
+#[test]
+fn test_dispute_not_eligible() {
+    let stores = funded();
+    let config = EngineConfig {
+        dispute_rules: Some(serde_json::from_str::<RulesConfig>(r#"{"dispute": {"max_amount": "50"}}"#).unwrap()),
+        ..EngineConfig::default()
+    };
+    assert_eq!(rejection(&stores, &config, row(TransactionType::Dispute, 1, 1, None)), Some(RejectionReason::NotEligible));
+}
+
+#[test]
+fn test_dispute_without_available_funds() {
+    let stores = funded();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Withdrawal, 1, 2, Some("60"))), None);
+
+    assert_eq!(
+        rejection(&stores, &EngineConfig::default(), row(TransactionType::Dispute, 1, 1, None)),
+        Some(RejectionReason::InsufficientAvailableFunds)
+    );
+    assert_eq!(stores.transaction(&TxId::Numeric(1)).unwrap().unwrap().state, TransactionState::Normal);
+}
+
+#[test]
+fn test_dispute_partial_hold() {
+    let stores = funded();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Withdrawal, 1, 2, Some("60"))), None);
+
+    let config = EngineConfig { shortfall_policy: ShortfallPolicy::Partial, ..EngineConfig::default() };
+    let outcome = apply(&stores, &config, row(TransactionType::Dispute, 1, 1, None));
+    assert_eq!(outcome, Outcome::Applied("DISPUTE PARTIAL: client=1, tx=1, amount=100, shortfall=60 (flagged for review)".to_string()));
+    assert_eq!(account(&stores, 1).held, amount("40"));
+    assert_eq!(account(&stores, 1).shortfall, amount("60"));
+}
+
+#[test]
+fn test_dispute_with_unknown_reason_code() {
+    let stores = disputed();
+    let config = EngineConfig::default();
+    for transaction_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+        let mut record = row(transaction_type, 1, 1, None);
+        record.reason_code = Some("NOPE".to_string());
+        let Outcome::Rejected(rejected) = apply(&stores, &config, record) else {
+            panic!("row with unknown reason code applied");
+        };
+        assert_eq!(rejected.reason, RejectionReason::UnknownReasonCode);
+        assert_eq!(rejected.detail.as_deref(), Some("reason_code=NOPE"));
+    }
+
+    // Known card network codes are kept on the transaction
+    let mut record = row(TransactionType::Chargeback, 1, 1, None);
+    record.reason_code = Some("10.4".to_string());
+    assert_eq!(rejection(&stores, &config, record), None);
+    assert_eq!(stores.transaction(&TxId::Numeric(1)).unwrap().unwrap().reason_code.as_deref(), Some("10.4"));
+}
+
+#[test]
+fn test_resolve_and_chargeback_without_dispute() {
+    let stores = funded();
+    for transaction_type in [TransactionType::Resolve, TransactionType::Chargeback] {
+        let Outcome::Rejected(rejected) = apply(&stores, &EngineConfig::default(), row(transaction_type, 1, 1, None)) else {
+            panic!("row applied to an undisputed transaction");
+        };
+        assert_eq!(rejected.reason, RejectionReason::NotUnderDispute);
+        assert_eq!(rejected.detail.as_deref(), Some("state=Normal"));
+    }
+}
+
+#[test]
+fn test_resolve_and_chargeback_without_held_funds() {
+    let stores = disputed();
+    // Held funds taken out behind the engine's back, e.g. by a corrupted store
+    stores.accounts.update(1, &mut |account| account.held = Decimal::ZERO).unwrap();
+
+    for transaction_type in [TransactionType::Resolve, TransactionType::Chargeback] {
+        assert_eq!(rejection(&stores, &EngineConfig::default(), row(transaction_type, 1, 1, None)), Some(RejectionReason::InsufficientHeldFunds));
+    }
+    assert_eq!(stores.transaction(&TxId::Numeric(1)).unwrap().unwrap().state, TransactionState::UnderDispute);
+}
+
+#[test]
+fn test_transition_without_account() {
+    let stores = stores();
+    stores.store_transaction(Transaction::new(TxId::Numeric(1), 3, TransactionType::Deposit, amount("10"), None)).unwrap();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Dispute, 3, 1, None)), Some(RejectionReason::AccountNotFound));
+}
+
+#[test]
+fn test_resolve_releases_and_chargeback_locks() {
+    let stores = disputed();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Resolve, 1, 1, None)), None);
+    assert_eq!(account(&stores, 1).available, amount("100"));
+
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Dispute, 1, 1, None)), None);
+    let outcome = apply(&stores, &EngineConfig::default(), row(TransactionType::Chargeback, 1, 1, None));
+    assert!(outcome.risk_event(&TransactionType::Chargeback).is_some());
+    let account = account(&stores, 1);
+    assert!(account.locked);
+    assert_eq!(account.total(), Decimal::ZERO);
+}
+
+// ============================================================================
+// Adjustments
+// ============================================================================
+
+fn adjustment(value: Option<&str>, effective_date: Option<NaiveDate>) -> TransactionInput {
+    let mut record = row(TransactionType::Adjustment, 1, 2, value);
+    record.effective_date = effective_date;
+    record
+}
+
+#[test]
+fn test_adjustment_rejections() {
+    let stores = funded();
+    let date = NaiveDate::from_ymd_opt(2024, 3, 1);
+    let enabled = EngineConfig { allow_adjustments: true, ..EngineConfig::default() };
+
+    assert_eq!(rejection(&stores, &EngineConfig::default(), adjustment(Some("5"), date)), Some(RejectionReason::AdjustmentsDisabled));
+    assert_eq!(rejection(&stores, &enabled, adjustment(None, date)), Some(RejectionReason::MissingAmount));
+    assert_eq!(rejection(&stores, &enabled, adjustment(Some("0"), date)), Some(RejectionReason::ZeroAmount));
+    assert_eq!(rejection(&stores, &enabled, adjustment(Some("5"), None)), Some(RejectionReason::MissingEffectiveDate));
+    assert_eq!(rejection(&stores, &enabled, adjustment(Some("-100.5"), date)), Some(RejectionReason::InsufficientFunds));
+    assert_eq!(account(&stores, 1).available, amount("100"));
+
+    assert_eq!(rejection(&stores, &enabled, adjustment(Some("-100"), date)), None);
+    assert_eq!(account(&stores, 1).available, Decimal::ZERO);
+}
+
+// ============================================================================
+// Freezes
+// ============================================================================
+
+#[test]
+fn test_freeze_twice_and_unfreeze_twice() {
+    let stores = funded();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Freeze, 1, 2, None)), None);
+
+    let Outcome::Rejected(rejected) = apply(&stores, &EngineConfig::default(), row(TransactionType::Freeze, 1, 3, None)) else {
+        panic!("second freeze applied");
+    };
+    assert_eq!((rejected.reason, rejected.detail.as_deref()), (RejectionReason::InvalidState, Some("frozen=true")));
+
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Unfreeze, 1, 4, None)), None);
+    let Outcome::Rejected(rejected) = apply(&stores, &EngineConfig::default(), row(TransactionType::Unfreeze, 1, 5, None)) else {
+        panic!("second unfreeze applied");
+    };
+    assert_eq!((rejected.reason, rejected.detail.as_deref()), (RejectionReason::InvalidState, Some("frozen=false")));
+}
+
+// ============================================================================
+// Merges
+// ============================================================================
+
+fn merge(client: u16, tx: u64, target_client: Option<u16>) -> TransactionInput {
+    TransactionInput { target_client, ..row(TransactionType::Merge, client, tx, None) }
+}
+
+#[test]
+fn test_merge_rejections() {
+    let stores = funded();
+    let enabled = EngineConfig { allow_merges: true, ..EngineConfig::default() };
+
+    assert_eq!(rejection(&stores, &EngineConfig::default(), merge(1, 2, Some(2))), Some(RejectionReason::MergesDisabled));
+    assert_eq!(rejection(&stores, &enabled, merge(1, 2, None)), Some(RejectionReason::InvalidMergeTarget));
+    assert_eq!(rejection(&stores, &enabled, merge(1, 2, Some(1))), Some(RejectionReason::InvalidMergeTarget));
+    assert_eq!(rejection(&stores, &enabled, merge(1, 2, Some(3))), Some(RejectionReason::AccountNotFound));
+
+    stores.accounts.update(2, &mut |account| account.frozen = true).unwrap();
+    assert_eq!(rejection(&stores, &enabled, merge(1, 2, Some(2))), Some(RejectionReason::AccountFrozen));
+    stores.accounts.update(2, &mut |account| account.locked = true).unwrap();
+    assert_eq!(rejection(&stores, &enabled, merge(1, 2, Some(2))), Some(RejectionReason::AccountLocked));
+    assert_eq!(account(&stores, 1).available, amount("100"));
+
+    stores.accounts.ensure(3).unwrap();
+    assert_eq!(rejection(&stores, &enabled, merge(1, 2, Some(3))), None);
+    let Outcome::Rejected(rejected) = apply(&stores, &enabled, merge(1, 3, Some(2))) else {
+        panic!("merged a closed account");
+    };
+    assert_eq!((rejected.reason, rejected.detail.as_deref()), (RejectionReason::AccountMerged, Some("merged_into=3")));
+    let Outcome::Rejected(rejected) = apply(&stores, &enabled, merge(2, 4, Some(1))) else {
+        panic!("merged into a closed account");
+    };
+    assert_eq!((rejected.reason, rejected.detail.as_deref()), (RejectionReason::InvalidMergeTarget, Some("merged_into=3")));
+}
+
+#[test]
+fn test_merge_moves_funds_holds_and_transactions() {
+    let stores = disputed();
+    let enabled = EngineConfig { allow_merges: true, ..EngineConfig::default() };
+    assert_eq!(rejection(&stores, &enabled, row(TransactionType::Deposit, 2, 2, Some("50"))), None);
+
+    let outcome = apply(&stores, &enabled, merge(1, 3, Some(2)));
+    assert_eq!(
+        outcome,
+        Outcome::Applied("MERGE SUCCESS: client=1, tx=3, target_client=2, available=0, held=100, transactions=1 (account closed)".to_string())
+    );
+
+    let source = account(&stores, 1);
+    assert_eq!((source.available, source.held, source.merged_into), (Decimal::ZERO, Decimal::ZERO, Some(2)));
+    assert!(source.holds.is_empty());
+    let target = account(&stores, 2);
+    assert_eq!((target.available, target.held), (amount("50"), amount("100")));
+    assert_eq!(target.holds.get(&TxId::Numeric(1)), Some(&amount("100")));
+    assert_eq!(stores.transactions.get(&TxId::Numeric(1)).unwrap().unwrap().client_id, 2);
+    assert!(stores.transactions.for_client(1).unwrap().is_empty());
+    let moved: Vec<_> = stores.transactions.for_client(2).unwrap().into_iter().map(|tx| tx.tx_id).collect();
+    assert_eq!(moved, vec![TxId::Numeric(2), TxId::Numeric(1)]);
+
+    // The dispute continues on the target
+    assert_eq!(rejection(&stores, &enabled, row(TransactionType::Resolve, 1, 1, None)), Some(RejectionReason::ClientMismatch));
+    assert_eq!(rejection(&stores, &enabled, row(TransactionType::Resolve, 2, 1, None)), None);
+    assert_eq!(account(&stores, 2).available, amount("150"));
+}
+
+#[test]
+fn test_transactions_for_client_follow_merges() {
+    let processor = TransactionProcessor::new().with_merges(true);
+    let deposits = "type,client,tx,amount\ndeposit,1,3,10\ndeposit,2,1,5\ndeposit,1,2,7\nwithdrawal,1,4,1\n";
+    processor.process_reader(deposits.as_bytes()).unwrap();
+    let ids = |client| processor.transactions_for(client).unwrap().into_iter().map(|tx| tx.tx_id).collect::<Vec<_>>();
+    assert_eq!(ids(1), vec![TxId::Numeric(3), TxId::Numeric(2)]);
+
+    let merge = "type,client,tx,amount,target_client\nmerge,1,5,,2\n";
+    processor.process_reader(merge.as_bytes()).unwrap();
+    assert!(ids(1).is_empty());
+    assert_eq!(ids(2), vec![TxId::Numeric(1), TxId::Numeric(3), TxId::Numeric(2)]);
+}
+
+#[test]
+fn test_memory_store_reuses_slots_of_removed_transactions() {
+    let store = MemoryTransactionStore::with_capacity(4);
+    let deposit = |tx, client| Transaction::new(TxId::Numeric(tx), client, TransactionType::Deposit, amount("1"), None);
+    for tx in 1..=3 {
+        assert!(store.insert(deposit(tx, 1)).unwrap());
+    }
+    store.remove(&TxId::Numeric(2)).unwrap();
+    // The freed slot now holds another client's transaction, which client 1 must not see
+    assert!(store.insert(deposit(4, 2)).unwrap());
+    assert!(!store.insert(deposit(1, 2)).unwrap());
+
+    let ids = |client| store.for_client(client).unwrap().into_iter().map(|tx| tx.tx_id).collect::<Vec<_>>();
+    assert_eq!(ids(1), vec![TxId::Numeric(3)]);
+    assert_eq!(ids(2), vec![TxId::Numeric(4), TxId::Numeric(1)]);
+    assert_eq!(store.get(&TxId::Numeric(2)).unwrap().map(|tx| tx.tx_id), None);
+    assert_eq!(store.iterate().unwrap().len(), 3);
+}
+
+// ============================================================================
+// Admin Operations
+// ============================================================================
+
+#[test]
+fn test_admin_only_rows_and_non_admin_rows() {
+    let stores = funded();
+    stores.accounts.update(1, &mut |account| account.locked = true).unwrap();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Unlock, 1, 2, None)), Some(RejectionReason::AdminOnly));
+
+    let admin = |record: TransactionInput| engine::apply_admin(&stores, &EngineConfig::default(), &record).unwrap().rejection_reason();
+    assert_eq!(admin(row(TransactionType::Deposit, 1, 3, Some("5"))), Some(RejectionReason::NotAdminOperation));
+    assert_eq!(admin(row(TransactionType::Unlock, 1, 4, None)), None);
+    assert!(!account(&stores, 1).locked);
+    assert_eq!(admin(row(TransactionType::Unlock, 1, 5, None)), Some(RejectionReason::InvalidState));
+
+    // Adjustments and merges need no flag
+    let mut adjustment = row(TransactionType::Adjustment, 1, 6, Some("-10"));
+    adjustment.effective_date = NaiveDate::from_ymd_opt(2024, 3, 1);
+    assert_eq!(admin(adjustment), None);
+    assert_eq!(admin(merge(1, 7, Some(2))), None);
+    assert_eq!(account(&stores, 2).available, amount("90"));
+}
+
+// ============================================================================
+// Reservations
+// ============================================================================
+
+#[test]
+fn test_reserve_over_available_funds() {
+    let stores = funded();
+    assert_eq!(
+        rejection(&stores, &EngineConfig::default(), row(TransactionType::Reserve, 1, 2, Some("150"))),
+        Some(RejectionReason::InsufficientFundsOrLocked)
+    );
+    assert!(stores.transaction(&TxId::Numeric(2)).unwrap().is_none());
+}
+
+#[test]
+fn test_capture_and_cancel_of_unknown_or_foreign_reserve() {
+    let stores = funded();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Reserve, 1, 2, Some("10"))), None);
+
+    for transaction_type in [TransactionType::Capture, TransactionType::Cancel] {
+        assert_eq!(rejection(&stores, &EngineConfig::default(), row(transaction_type.clone(), 1, 9, None)), Some(RejectionReason::TransactionNotFound));
+        assert_eq!(rejection(&stores, &EngineConfig::default(), row(transaction_type, 2, 2, None)), Some(RejectionReason::ClientMismatch));
+    }
+}
+
+#[test]
+fn test_capture_and_cancel_of_closed_or_non_reserve() {
+    let stores = funded();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Reserve, 1, 2, Some("10"))), None);
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Capture, 1, 2, None)), None);
+    assert_eq!(account(&stores, 1).total(), amount("90"));
+
+    for transaction_type in [TransactionType::Capture, TransactionType::Cancel] {
+        let Outcome::Rejected(rejected) = apply(&stores, &EngineConfig::default(), row(transaction_type.clone(), 1, 2, None)) else {
+            panic!("closed reserve applied");
+        };
+        assert_eq!((rejected.reason, rejected.detail.as_deref()), (RejectionReason::NotReserved, Some("state=Captured")));
+        // A deposit is no reserve
+        assert_eq!(rejection(&stores, &EngineConfig::default(), row(transaction_type, 1, 1, None)), Some(RejectionReason::NotReserved));
+    }
+}
+
+#[test]
+fn test_capture_and_cancel_without_held_funds() {
+    let stores = funded();
+    assert_eq!(rejection(&stores, &EngineConfig::default(), row(TransactionType::Reserve, 1, 2, Some("10"))), None);
+    stores.accounts.update(1, &mut |account| account.held = Decimal::ZERO).unwrap();
+
+    for transaction_type in [TransactionType::Capture, TransactionType::Cancel] {
+        assert_eq!(rejection(&stores, &EngineConfig::default(), row(transaction_type, 1, 2, None)), Some(RejectionReason::InsufficientHeldFunds));
+    }
+}
+
+// ============================================================================
+// Review Decisions
+// ============================================================================
+
+#[test]
+fn test_review_decisions_without_review_queue() {
+    let stores = funded();
+    for transaction_type in [TransactionType::Approve, TransactionType::Reject] {
+        assert_eq!(rejection(&stores, &EngineConfig::default(), row(transaction_type, 1, 1, None)), Some(RejectionReason::NotInReview));
+    }
+}
+
+// ============================================================================
+// Custom Types
+// ============================================================================
+
+/// Charges the amount, declining on a locked account
+struct Fee;
+
+impl CustomTransaction for Fee {
+    fn apply(&self, state: &dyn EngineState, record: &TransactionInput) -> Result<Outcome, ProcessorError> {
+        let amount = record.amount.unwrap_or_default();
+        let charged = state.update_account(record.client, &mut |account| {
+            if account.locked {
+                return false;
+            }
+            account.available -= amount;
+            true
+        })?;
+        if !charged {
+            return Ok(Outcome::rejected(RejectionReason::AccountLocked, format!("FEE REJECTED: client={}, tx={}", record.client, record.tx)));
+        }
+        Ok(Outcome::Applied(format!("FEE SUCCESS: client={}, tx={}", record.client, record.tx)))
+    }
+}
+
+#[test]
+fn test_custom_type_goes_to_its_handler() {
+    let stores = funded();
+    let fee = || row(TransactionType::Custom("fee".to_string()), 1, 2, Some("2.5"));
+    assert!(matches!(engine::apply(&stores, &EngineConfig::default(), &fee()), Err(ProcessorError::UnknownTransactionType(name)) if name == "fee"));
+
+    let mut config = EngineConfig::default();
+    config.custom_types.register("fee", Arc::new(Fee));
+    assert_eq!(apply(&stores, &config, fee()), Outcome::Applied("FEE SUCCESS: client=1, tx=2".to_string()));
+    assert_eq!(account(&stores, 1).available, amount("97.5"));
+
+    stores.accounts.update(1, &mut |account| account.locked = true).unwrap();
+    assert_eq!(rejection(&stores, &config, fee()), Some(RejectionReason::AccountLocked));
+    assert_eq!(account(&stores, 1).available, amount("97.5"));
+}
+
+#[test]
+fn test_custom_type_rows_from_csv() {
+    let csv = "type,client,tx,amount\ndeposit,1,1,10\nfee,1,2,4\n";
+    let processor = TransactionProcessor::new().with_custom_type("fee", Fee);
+    processor.process_reader(csv.as_bytes()).unwrap();
+    assert_eq!(processor.account(1).unwrap().unwrap().available, amount("6"));
+    assert_eq!(processor.row_counts().get("fee"), Some(&1));
+
+    // Unregistered, the row cannot be read
+    let err = TransactionProcessor::new().process_reader(csv.as_bytes()).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown transaction type: fee");
+}
+
+// ============================================================================
+// Fair Scheduling
+// ============================================================================
+
+#[test]
+fn test_fair_scheduler_takes_turns_between_clients() {
+    let scheduler = FairScheduler::new(2);
+    for tx in 1..=6 {
+        assert!(scheduler.push(row(TransactionType::Deposit, 1, tx, Some("1"))));
+    }
+    assert!(scheduler.push(row(TransactionType::Deposit, 2, 7, Some("1"))));
+    scheduler.close();
+
+    // A single worker, so the turns are the order rows are applied in
+    let applied = Mutex::new(Vec::new());
+    scheduler.work(|record| {
+        applied.lock().unwrap().push((record.client, record.tx));
+        Ok(())
+    });
+    scheduler.finish().unwrap();
+
+    let tx = TxId::Numeric;
+    assert_eq!(applied.into_inner().unwrap(), vec![(1, tx(1)), (1, tx(2)), (2, tx(7)), (1, tx(3)), (1, tx(4)), (1, tx(5)), (1, tx(6))]);
+}
+
+#[test]
+fn test_fair_scheduler_stops_on_the_first_failure() {
+    let scheduler = FairScheduler::new(1);
+    scheduler.push(row(TransactionType::Deposit, 1, 1, Some("1")));
+    scheduler.push(row(TransactionType::Deposit, 1, 2, Some("1")));
+    scheduler.close();
+
+    scheduler.work(|record| match record.tx {
+        TxId::Numeric(1) => Err(ProcessorError::StoreError("down".to_string())),
+        _ => panic!("no row is applied after a failure"),
+    });
+    assert!(!scheduler.push(row(TransactionType::Deposit, 1, 3, Some("1"))));
+    assert_eq!(scheduler.finish().unwrap_err().to_string(), "Store error: down");
+}
+
+// ============================================================================
+// Account Report
+// ============================================================================
+
+#[test]
+fn test_account_csv_writer_matches_serde() {
+    let mut account = Account::new(7);
+    account.available = amount("12.50");
+    account.held = amount("-3");
+    account.last_activity = Some(NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_milli_opt(9, 30, 0, 250).unwrap().and_utc());
+    let mut v1 = account.to_output();
+    v1.risk_score = Some(40);
+    v1.shortfall = Some(amount("1.25"));
+    v1.needs_review = Some(true);
+    let v2 = account.to_output_v2();
+
+    for rows in [vec![AccountRow::V1(account.to_output())], vec![AccountRow::V1(v1)], vec![AccountRow::V2(v2.clone()), AccountRow::V2(v2)]] {
+        let mut serde = csv::Writer::from_writer(Vec::new());
+        let mut manual = AccountCsvWriter::new(Vec::new());
+        for row in &rows {
+            serde.serialize(row).unwrap();
+            manual.write(row).unwrap();
+        }
+        let manual = String::from_utf8(manual.into_inner().unwrap()).unwrap();
+        assert_eq!(manual, String::from_utf8(serde.into_inner().unwrap()).unwrap());
+    }
+
+    // Rows brought into a format serialize as the writer formats them
+    for format in [AmountFormat::Fixed4, AmountFormat::Minimal] {
+        let mut row = AccountRow::V1(account.to_output());
+        let mut manual = AccountCsvWriter::new(Vec::new()).with_amount_format(format);
+        manual.write(&row).unwrap();
+        row.format_amounts(format);
+        let mut serde = csv::Writer::from_writer(Vec::new());
+        serde.serialize(&row).unwrap();
+        assert_eq!(manual.into_inner().unwrap(), serde.into_inner().unwrap());
+    }
+}
+
+#[test]
+fn test_amount_format_is_per_processor() {
+    let input = "type,client,tx,amount\ndeposit,1,1,1.25\ndeposit,1,2,0.75\n";
+    let report = |format| {
+        let processor = TransactionProcessor::new().with_amount_format(format);
+        processor.process_reader(input.as_bytes()).unwrap();
+        let mut output = Vec::new();
+        processor.output_accounts(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(report(AmountFormat::Fixed4), "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n");
+    assert_eq!(report(AmountFormat::Minimal), "client,available,held,total,locked\n1,2,0,2,false\n");
+    assert_eq!(report(AmountFormat::Raw), "client,available,held,total,locked\n1,2.00,0,2.00,false\n");
+}
+
+// ============================================================================
+// De-Duplication
+// ============================================================================
+
+#[test]
+fn test_dedup_time_window_evicts_keys_seen_before_the_first_timestamp() {
+    let filter = DedupFilter::new(Some(DedupWindow::Time(Duration::from_secs(60))));
+    let start = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(8, 0, 0).unwrap().and_utc();
+
+    assert!(!filter.check_and_insert("untimed", None));
+    assert!(!filter.check_and_insert("first", Some(start)));
+    assert!(filter.contains("untimed"));
+
+    // Two minutes on, both are out of the window rather than the untimed key holding up
+    // every key behind it
+    assert!(!filter.check_and_insert("later", Some(start + chrono::Duration::minutes(2))));
+    assert!(!filter.contains("untimed"));
+    assert!(!filter.contains("first"));
+    assert!(filter.contains("later"));
+}
@@ -0,0 +1,12 @@
+;; Validation plugin that never returns, the source of plugin_endless.wasm.
+;; Rebuild with `wat2wasm plugin_endless.wat`.
+(module
+  (memory (export "memory") 1)
+
+  (func (export "alloc") (param $len i32) (result i32)
+    (i32.const 1024))
+
+  (func (export "validate") (param $ptr i32) (param $len i32) (result i64)
+    (loop $forever
+      (br $forever))
+    (i64.const 0)))
@@ -0,0 +1,19 @@
+;; Validation plugin rejecting every withdrawal, the source of plugin_pause_withdrawals.wasm.
+;; Rebuild with `wat2wasm plugin_pause_withdrawals.wat`.
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 16) "withdrawals paused")
+
+  ;; Every input is written to the same buffer at 1024, grown to fit
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $missing i32)
+    (local.set $missing (i32.sub (i32.add (i32.const 1024) (local.get $len)) (i32.mul (memory.size) (i32.const 65536))))
+    (if (i32.gt_s (local.get $missing) (i32.const 0))
+      (then (drop (memory.grow (i32.add (i32.div_u (local.get $missing) (i32.const 65536)) (i32.const 1))))))
+    (i32.const 1024))
+
+  ;; The input starts with {"transaction":{"type":" so byte 24 is the first letter of the type
+  (func (export "validate") (param $ptr i32) (param $len i32) (result i64)
+    (if (i32.eq (i32.load8_u (i32.add (local.get $ptr) (i32.const 24))) (i32.const 119))
+      (then (return (i64.or (i64.shl (i64.const 16) (i64.const 32)) (i64.const 18)))))
+    (i64.const 0)))
@@ -0,0 +1,86 @@
+//! Golden-file cases: every directory under `tests/cases` holds an `input.csv` and the
+//! `expected.csv` report, plus an optional `args` file with extra flags, one per line.
+//! Rows are compared regardless of order, and amounts regardless of trailing zeros.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use rust_decimal::Decimal;
+
+type Table = (Vec<String>, Vec<Vec<String>>);
+
+/// Header and rows of a report, with amounts normalized and rows sorted
+fn read_table(csv: &[u8]) -> Result<Table, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(csv);
+    let headers = reader.headers()?.iter().map(str::to_string).collect();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let row = record?
+            .iter()
+            .map(|cell| match cell.parse::<Decimal>() {
+                Ok(amount) => amount.normalize().to_string(),
+                Err(_) => cell.to_string(),
+            })
+            .collect();
+        rows.push(row);
+    }
+    rows.sort();
+
+    Ok((headers, rows))
+}
+
+fn run_case(dir: &Path) -> Result<(), String> {
+    let args = match fs::read_to_string(dir.join("args")) {
+        Ok(args) => args.lines().map(str::trim).filter(|arg| !arg.is_empty()).map(str::to_string).collect(),
+        Err(_) => Vec::new(),
+    };
+    let expected = fs::read(dir.join("expected.csv")).map_err(|err| format!("expected.csv: {}", err))?;
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(dir.join("input.csv"))
+        .args(&args)
+        .output()
+        .map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(format!("run failed: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+
+    let (expected_headers, expected_rows) = read_table(&expected).map_err(|err| format!("expected.csv: {}", err))?;
+    let (actual_headers, actual_rows) = read_table(&output.stdout).map_err(|err| format!("output: {}", err))?;
+
+    let mut differences = Vec::new();
+    if expected_headers != actual_headers {
+        differences.push(format!("- {}\n+ {}", expected_headers.join(","), actual_headers.join(",")));
+    }
+    for row in expected_rows.iter().filter(|row| !actual_rows.contains(row)) {
+        differences.push(format!("- {}", row.join(",")));
+    }
+    for row in actual_rows.iter().filter(|row| !expected_rows.contains(row)) {
+        differences.push(format!("+ {}", row.join(",")));
+    }
+
+    match differences.is_empty() {
+        true => Ok(()),
+        false => Err(differences.join("\n")),
+    }
+}
+
+#[test]
+fn test_golden_cases() {
+    let mut dirs: Vec<_> = fs::read_dir("tests/cases")
+        .expect("tests/cases")
+        .map(|entry| entry.expect("case directory").path())
+        .filter(|path| path.join("input.csv").exists())
+        .collect();
+    dirs.sort();
+    assert!(!dirs.is_empty(), "no cases found in tests/cases");
+
+    let failures: Vec<String> = dirs
+        .iter()
+        .filter_map(|dir| run_case(dir).err().map(|err| format!("{}:\n{}", dir.display(), err)))
+        .collect();
+
+    assert!(failures.is_empty(), "{} of {} golden case(s) failed\n\n{}", failures.len(), dirs.len(), failures.join("\n\n"));
+}
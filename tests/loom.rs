@@ -0,0 +1,292 @@
+//! Loom explores every interleaving of the threads below and fails on a deadlock.
+//! Run with `RUSTFLAGS="--cfg loom" cargo test --release --test loom`.
+#![cfg(loom)]
+
+use std::collections::HashMap;
+
+use loom::sync::{Arc, Mutex};
+use loom::thread;
+use rust_decimal::Decimal;
+use trx_processor::locking::lock_in_order;
+use trx_processor::model::account::Account;
+use trx_processor::model::error::ProcessorError;
+use trx_processor::model::transaction::{Transaction, TransactionInput, TransactionState, TransactionType, TxId};
+use trx_processor::processor::TransactionProcessor;
+use trx_processor::reference::{ReferenceAccount, ReferenceEngine};
+use trx_processor::store::{AccountStore, TransactionStore};
+
+/// Locks the accounts in `clients`, in whatever order they are given, and moves one unit
+/// from the first to the last
+fn transfer(accounts: &[Mutex<i64>], clients: &[usize]) {
+    let mut guards = lock_in_order(clients, |client| (client, accounts[client].lock().unwrap()));
+    let (from, to) = (clients[0], clients[clients.len() - 1]);
+
+    for (client, balance) in guards.iter_mut() {
+        if *client == from {
+            **balance -= 1;
+        }
+        if *client == to {
+            **balance += 1;
+        }
+    }
+}
+
+#[test]
+fn test_opposite_lock_orders_do_not_deadlock() {
+    loom::model(|| {
+        let accounts = Arc::new([Mutex::new(10), Mutex::new(10)]);
+
+        let other = {
+            let accounts = accounts.clone();
+            thread::spawn(move || transfer(&accounts[..], &[0, 1]))
+        };
+        transfer(&accounts[..], &[1, 0]);
+        other.join().unwrap();
+
+        assert_eq!(*accounts[0].lock().unwrap() + *accounts[1].lock().unwrap(), 20);
+    });
+}
+
+#[test]
+fn test_lock_cycle_does_not_deadlock() {
+    loom::model(|| {
+        let accounts = Arc::new([Mutex::new(0), Mutex::new(0), Mutex::new(0)]);
+
+        let threads: Vec<_> = [[0, 1], [1, 2]]
+            .into_iter()
+            .map(|clients| {
+                let accounts = accounts.clone();
+                thread::spawn(move || transfer(&accounts[..], &clients))
+            })
+            .collect();
+        transfer(&accounts[..], &[2, 0]);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let total: i64 = accounts.iter().map(|balance| *balance.lock().unwrap()).sum();
+        assert_eq!(total, 0);
+    });
+}
+
+#[test]
+fn test_repeated_client_is_locked_once() {
+    loom::model(|| {
+        let accounts = [Mutex::new(0)];
+        let guards = lock_in_order(&[0, 0], |client| accounts[client].lock().unwrap());
+        assert_eq!(guards.len(), 1);
+    });
+}
+
+// ============================================================================
+// State Layer Tests
+// ============================================================================
+
+/// Account store on a loom mutex, so loom can interleave threads around every access
+#[derive(Default)]
+struct LoomAccountStore {
+    accounts: Mutex<HashMap<u16, Account>>,
+}
+
+impl AccountStore for LoomAccountStore {
+    fn get(&self, client_id: u16) -> Result<Option<Account>, ProcessorError> {
+        Ok(self.accounts.lock().unwrap().get(&client_id).cloned())
+    }
+
+    fn ensure(&self, client_id: u16) -> Result<(), ProcessorError> {
+        self.accounts.lock().unwrap().entry(client_id).or_insert_with(|| Account::new(client_id));
+        Ok(())
+    }
+
+    fn update(&self, client_id: u16, f: &mut dyn FnMut(&mut Account)) -> Result<bool, ProcessorError> {
+        Ok(self.accounts.lock().unwrap().get_mut(&client_id).map(f).is_some())
+    }
+
+    fn iterate(&self) -> Result<Vec<Account>, ProcessorError> {
+        Ok(self.accounts.lock().unwrap().values().cloned().collect())
+    }
+
+    fn write_batch(&self, accounts: &[Account]) -> Result<(), ProcessorError> {
+        let mut stored = self.accounts.lock().unwrap();
+        for account in accounts {
+            stored.insert(account.client_id, account.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Transaction store on a loom mutex
+#[derive(Default)]
+struct LoomTransactionStore {
+    transactions: Mutex<HashMap<TxId, Transaction>>,
+}
+
+impl TransactionStore for LoomTransactionStore {
+    fn get(&self, tx_id: &TxId) -> Result<Option<Transaction>, ProcessorError> {
+        Ok(self.transactions.lock().unwrap().get(tx_id).cloned())
+    }
+
+    fn insert(&self, transaction: Transaction) -> Result<bool, ProcessorError> {
+        Ok(self.transactions.lock().unwrap().insert(transaction.tx_id.clone(), transaction).is_none())
+    }
+
+    fn set_state(&self, tx_id: &TxId, state: TransactionState) -> Result<(), ProcessorError> {
+        if let Some(transaction) = self.transactions.lock().unwrap().get_mut(tx_id) {
+            transaction.state = state;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, tx_id: &TxId) -> Result<(), ProcessorError> {
+        self.transactions.lock().unwrap().remove(tx_id);
+        Ok(())
+    }
+
+    fn iterate(&self) -> Result<Vec<Transaction>, ProcessorError> {
+        Ok(self.transactions.lock().unwrap().values().cloned().collect())
+    }
+
+    fn write_batch(&self, transactions: &[Transaction], removed: &[TxId]) -> Result<(), ProcessorError> {
+        let mut stored = self.transactions.lock().unwrap();
+        for transaction in transactions {
+            stored.insert(transaction.tx_id.clone(), transaction.clone());
+        }
+        for tx_id in removed {
+            stored.remove(tx_id);
+        }
+        Ok(())
+    }
+}
+
+fn record(transaction_type: TransactionType, tx: u64, amount: Option<i64>) -> TransactionInput {
+    TransactionInput {
+        transaction_type,
+        client: 1,
+        tx: TxId::Numeric(tx),
+        amount: amount.map(Decimal::from),
+        effective_date: None,
+        timestamp: None,
+        reason_code: None,
+        metadata: None,
+        partition: None,
+        idempotency_key: None,
+        target_client: None,
+    }
+}
+
+/// Client 1's account after every possible serial order of `concurrent` following `setup`
+fn serial_outcomes(setup: &[TransactionInput], concurrent: &[TransactionInput]) -> Vec<ReferenceAccount> {
+    fn permutations(remaining: Vec<usize>) -> Vec<Vec<usize>> {
+        if remaining.is_empty() {
+            return vec![vec![]];
+        }
+        remaining
+            .iter()
+            .flat_map(|&first| {
+                let rest = remaining.iter().copied().filter(|&i| i != first).collect();
+                permutations(rest).into_iter().map(move |order| [vec![first], order].concat())
+            })
+            .collect()
+    }
+    let orders = permutations((0..concurrent.len()).collect());
+
+    orders
+        .into_iter()
+        .map(|order| {
+            let mut reference = ReferenceEngine::new();
+            for record in setup.iter().chain(order.iter().map(|&i| &concurrent[i])) {
+                reference.process(record);
+            }
+            reference.accounts()[&1].clone()
+        })
+        .collect()
+}
+
+/// Runs `setup`, then every row of `concurrent` on its own thread, and checks that the
+/// result equals one of the serial orders, with the transaction states matching the balances
+fn check_linearizable(setup: Vec<TransactionInput>, concurrent: Vec<TransactionInput>) {
+    let expected = serial_outcomes(&setup, &concurrent);
+
+    // Bounding preemptions keeps the search tractable, most ordering bugs need only a few
+    let mut model = loom::model::Builder::new();
+    model.preemption_bound = Some(3);
+    model.check(move || {
+        let processor = Arc::new(
+            TransactionProcessor::new()
+                .with_account_store(Box::new(LoomAccountStore::default()))
+                .with_transaction_store(Box::new(LoomTransactionStore::default())),
+        );
+        for record in &setup {
+            processor.process_record(record.clone()).unwrap();
+        }
+
+        let threads: Vec<_> = concurrent
+            .iter()
+            .cloned()
+            .map(|record| {
+                let processor = processor.clone();
+                thread::spawn(move || processor.process_record(record).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let account = processor.account(1).unwrap().unwrap();
+        let actual = ReferenceAccount { available: account.available, held: account.held, locked: account.locked };
+        assert!(expected.contains(&actual), "{:?} is not the result of any serial order", actual);
+
+        let disputed: Decimal = processor
+            .transactions()
+            .unwrap()
+            .iter()
+            .filter(|transaction| transaction.state == TransactionState::UnderDispute)
+            .map(|transaction| transaction.amount)
+            .sum();
+        assert_eq!(disputed, account.held, "held funds do not match the disputed transactions");
+    });
+}
+
+#[test]
+fn test_concurrent_deposit_dispute_withdrawal() {
+    check_linearizable(
+        vec![record(TransactionType::Deposit, 1, Some(10))],
+        vec![
+            record(TransactionType::Deposit, 2, Some(5)),
+            record(TransactionType::Dispute, 1, None),
+            record(TransactionType::Withdrawal, 3, Some(12)),
+        ],
+    );
+}
+
+/// The state check on the transaction and the hold on the account are separate store
+/// operations, so a duplicate dispute slipping in between would hold the funds twice
+#[test]
+fn test_concurrent_dispute_state_transitions() {
+    check_linearizable(
+        vec![
+            record(TransactionType::Deposit, 1, Some(10)),
+            record(TransactionType::Deposit, 2, Some(10)),
+        ],
+        vec![
+            record(TransactionType::Dispute, 1, None),
+            record(TransactionType::Dispute, 1, None),
+            record(TransactionType::Resolve, 1, None),
+        ],
+    );
+}
+
+#[test]
+fn test_concurrent_resolve_and_chargeback() {
+    check_linearizable(
+        vec![
+            record(TransactionType::Deposit, 1, Some(10)),
+            record(TransactionType::Dispute, 1, None),
+        ],
+        vec![
+            record(TransactionType::Resolve, 1, None),
+            record(TransactionType::Chargeback, 1, None),
+            record(TransactionType::Dispute, 1, None),
+        ],
+    );
+}
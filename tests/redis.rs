@@ -0,0 +1,312 @@
+//! The redis store shared by two instances, against a fake server speaking the part of RESP
+//! the store uses, WATCH/MULTI/EXEC included, so no Redis is needed to run it.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+use trx_processor::model::error::ProcessorError;
+use trx_processor::store::{self, AccountStore, BatchStore};
+
+/// How late a stalled reply comes, well past the timeout of the instances in the tests
+const STALL: Duration = Duration::from_millis(500);
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct Db {
+    values: HashMap<String, Vec<u8>>,
+    sets: HashMap<String, BTreeSet<String>>,
+    /// Bumped by every write of a key, for WATCH
+    versions: HashMap<String, u64>,
+    /// EXECs still to refuse as if a watched key had changed
+    refused_execs: usize,
+    /// Replies still to send late, as a hung server would
+    stalled_replies: usize,
+}
+
+enum Reply {
+    Status(&'static str),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+impl Reply {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Status(status) => out.extend_from_slice(format!("+{}\r\n", status).as_bytes()),
+            Reply::Integer(value) => out.extend_from_slice(format!(":{}\r\n", value).as_bytes()),
+            Reply::Bulk(None) | Reply::Array(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(data)) => {
+                out.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(Some(items)) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+impl Db {
+    fn write(&mut self, key: &str) {
+        *self.versions.entry(key.to_string()).or_default() += 1;
+    }
+
+    fn version(&self, key: &str) -> u64 {
+        self.versions.get(key).copied().unwrap_or_default()
+    }
+
+    fn run(&mut self, args: &[String]) -> Reply {
+        match args[0].as_str() {
+            "SELECT" => Reply::Status("OK"),
+            "GET" => Reply::Bulk(self.values.get(&args[1]).cloned()),
+            "SET" => {
+                if args.get(3).is_some_and(|flag| flag == "NX") && self.values.contains_key(&args[1]) {
+                    return Reply::Bulk(None);
+                }
+                self.values.insert(args[1].clone(), args[2].clone().into_bytes());
+                self.write(&args[1]);
+                Reply::Status("OK")
+            }
+            "DEL" => {
+                let removed = self.values.remove(&args[1]).is_some();
+                self.write(&args[1]);
+                Reply::Integer(removed as i64)
+            }
+            "SADD" => Reply::Integer(self.sets.entry(args[1].clone()).or_default().insert(args[2].clone()) as i64),
+            "SREM" => Reply::Integer(self.sets.entry(args[1].clone()).or_default().remove(&args[2]) as i64),
+            "SMEMBERS" => Reply::Array(Some(
+                self.sets.get(&args[1]).into_iter().flatten().map(|member| Reply::Bulk(Some(member.clone().into_bytes()))).collect(),
+            )),
+            command => panic!("unexpected command {}", command),
+        }
+    }
+}
+
+/// Serves one connection: commands between MULTI and EXEC are queued, and EXEC runs them
+/// only if no key watched since changed
+fn serve(stream: TcpStream, db: Arc<Mutex<Db>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut watched: Vec<(String, u64)> = Vec::new();
+    let mut queued: Option<Vec<Vec<String>>> = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let count: usize = line.trim_end()[1..].parse().unwrap();
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            let len: usize = line.trim_end()[1..].parse().unwrap();
+            let mut data = vec![0; len + 2];
+            reader.read_exact(&mut data).unwrap();
+            data.truncate(len);
+            args.push(String::from_utf8(data).unwrap());
+        }
+
+        let mut db = db.lock().unwrap();
+        let stalled = db.stalled_replies > 0;
+        db.stalled_replies = db.stalled_replies.saturating_sub(1);
+        let reply = match (args[0].as_str(), queued.as_mut()) {
+            ("WATCH", _) => {
+                watched.extend(args[1..].iter().map(|key| (key.clone(), db.version(key))));
+                Reply::Status("OK")
+            }
+            ("UNWATCH", _) => {
+                watched.clear();
+                Reply::Status("OK")
+            }
+            ("MULTI", _) => {
+                queued = Some(Vec::new());
+                Reply::Status("OK")
+            }
+            ("DISCARD", _) => {
+                queued = None;
+                watched.clear();
+                Reply::Status("OK")
+            }
+            ("EXEC", _) => {
+                let commands = queued.take().unwrap();
+                let clean = watched.drain(..).all(|(key, version)| db.version(&key) == version);
+                let refused = db.refused_execs > 0;
+                db.refused_execs = db.refused_execs.saturating_sub(1);
+                match clean && !refused {
+                    true => Reply::Array(Some(commands.iter().map(|command| db.run(command)).collect())),
+                    false => Reply::Array(None),
+                }
+            }
+            (_, Some(queue)) => {
+                queue.push(args);
+                Reply::Status("QUEUED")
+            }
+            (_, None) => db.run(&args),
+        };
+        drop(db);
+        if stalled {
+            thread::sleep(STALL);
+        }
+
+        let mut out = Vec::new();
+        reply.encode(&mut out);
+        writer.write_all(&out).unwrap();
+    }
+}
+
+/// Starts a fake server and returns its `redis://` location
+fn start_server() -> (String, Arc<Mutex<Db>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("redis://{}", listener.local_addr().unwrap());
+    let db = Arc::new(Mutex::new(Db::default()));
+    let shared = db.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let db = shared.clone();
+            thread::spawn(move || serve(stream.unwrap(), db));
+        }
+    });
+    (url, db)
+}
+
+/// One processor instance's view of the shared store
+fn instance(url: &str) -> BatchStore {
+    let stores = store::open_stores_with_timeout(url, TIMEOUT).unwrap();
+    BatchStore::new(Arc::from(stores.accounts), Arc::from(stores.transactions))
+}
+
+fn deposit(batch: &BatchStore, client_id: u16, amount: i64) {
+    batch.ensure(client_id).unwrap();
+    assert!(batch.update(client_id, &mut |account| account.available += Decimal::from(amount)).unwrap());
+}
+
+fn available(url: &str, client_id: u16) -> Decimal {
+    store::open_stores(url).unwrap().accounts.get(client_id).unwrap().unwrap().available
+}
+
+#[test]
+fn test_concurrent_batches_of_one_client_are_rebased() {
+    let (url, _) = start_server();
+    let seed = instance(&url);
+    deposit(&seed, 1, 100);
+    seed.commit().unwrap();
+
+    // Both instances read client 1 at 100, the second commits first
+    let first = instance(&url);
+    let second = instance(&url);
+    deposit(&first, 1, 10);
+    deposit(&second, 1, 5);
+    second.commit().unwrap();
+
+    // The first instance's deposit is applied again on top of the second's rather than
+    // overwriting it
+    let changes = first.commit().unwrap();
+    assert_eq!(available(&url, 1), Decimal::from(115));
+    assert_eq!(changes.accounts[0].before.as_ref().unwrap().available, Decimal::from(105));
+    assert_eq!(changes.accounts[0].after.available, Decimal::from(115));
+    assert_eq!(first.retry_stats().retries, 1);
+}
+
+#[test]
+fn test_conflict_that_would_overdraw_fails() {
+    let (url, _) = start_server();
+    let seed = instance(&url);
+    deposit(&seed, 1, 100);
+    seed.commit().unwrap();
+
+    // Both withdrawals were checked against 100, together they would overdraw it
+    let first = instance(&url);
+    let second = instance(&url);
+    deposit(&first, 1, -80);
+    deposit(&second, 1, -50);
+    second.commit().unwrap();
+
+    let err = first.commit().unwrap_err();
+    assert!(matches!(err, ProcessorError::StoreUnavailable(_)), "{}", err);
+    assert!(err.to_string().contains("overdraw client 1"), "{}", err);
+    assert_eq!(available(&url, 1), Decimal::from(50));
+}
+
+#[test]
+fn test_concurrent_batches_of_other_clients_commit() {
+    let (url, _) = start_server();
+    let seed = instance(&url);
+    deposit(&seed, 1, 100);
+    deposit(&seed, 2, 100);
+    seed.commit().unwrap();
+
+    let first = instance(&url);
+    let second = instance(&url);
+    deposit(&first, 1, 10);
+    deposit(&second, 2, 5);
+    second.commit().unwrap();
+    first.commit().unwrap();
+
+    assert_eq!(available(&url, 1), Decimal::from(110));
+    assert_eq!(available(&url, 2), Decimal::from(105));
+
+    // The connection was left out of any transaction, so a later batch still commits
+    deposit(&first, 1, 1);
+    first.commit().unwrap();
+    assert_eq!(available(&url, 1), Decimal::from(111));
+}
+
+#[test]
+fn test_refused_exec_is_retried() {
+    let (url, db) = start_server();
+    let batch = instance(&url);
+    deposit(&batch, 1, 100);
+    batch.commit().unwrap();
+
+    // Refused as when another writer touches a watched key between the check and EXEC
+    db.lock().unwrap().refused_execs = 2;
+    deposit(&batch, 1, 10);
+    batch.commit().unwrap();
+    assert_eq!(available(&url, 1), Decimal::from(110));
+    assert_eq!(db.lock().unwrap().refused_execs, 0);
+}
+
+#[test]
+fn test_hung_server_times_out() {
+    // Accepts connections and never replies
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("redis://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let streams: Vec<_> = listener.incoming().collect();
+        drop(streams);
+    });
+
+    let stores = store::open_stores_with_timeout(&url, TIMEOUT).unwrap();
+    let started = Instant::now();
+    let err = stores.accounts.get(1).unwrap_err();
+    assert!(matches!(err, ProcessorError::IoError(_)), "{}", err);
+    assert!(started.elapsed() < STALL, "{:?}", started.elapsed());
+}
+
+#[test]
+fn test_timed_out_connection_is_replaced() {
+    let (url, db) = start_server();
+    let batch = instance(&url);
+    deposit(&batch, 1, 100);
+    batch.commit().unwrap();
+
+    // The late reply would answer the next command on the same connection, so the retry
+    // connects again
+    db.lock().unwrap().stalled_replies = 1;
+    deposit(&batch, 2, 10);
+    batch.commit().unwrap();
+    assert_eq!(batch.retry_stats().retries, 1);
+    assert_eq!(available(&url, 1), Decimal::from(100));
+    assert_eq!(available(&url, 2), Decimal::from(10));
+}
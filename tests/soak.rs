@@ -0,0 +1,231 @@
+//! Soak run: a processor is fed randomized rounds of rows on threads by client, and every
+//! few rounds its accounts are checked against the reference engine, its held funds against
+//! the transactions under dispute, and a saved snapshot against the live state. Memory may
+//! only grow with the transaction ids created: stored deposits, and withdrawal ids
+//! remembered to detect duplicates. As nothing stored is ever evicted, the run goes on in
+//! epochs of a fresh processor each, and the peak memory of later epochs must stay near
+//! that of the second.
+//!
+//! `cargo test` runs it for a second; `TRX_SOAK_SECS` sets how long it runs:
+//!
+//! ```bash
+//! TRX_SOAK_SECS=14400 cargo test --release --test soak -- --nocapture
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+use trx_processor::model::transaction::{TransactionInput, TransactionState, TransactionType, TxId, TxIdKind};
+use trx_processor::processor::TransactionProcessor;
+use trx_processor::reference::{ReferenceAccount, ReferenceEngine};
+use trx_processor::resources::ResourceUsage;
+use trx_processor::snapshot::{AccountSnapshot, Snapshot};
+
+/// Many clients, so that chargebacks lock only a few accounts however long the run
+const CLIENTS: u16 = 50_000;
+const THREADS: u16 = 8;
+const ROUND_ROWS: usize = 100_000;
+/// Rounds of one processor, about 1.5 GB of peak memory
+const EPOCH_ROUNDS: u64 = 20;
+/// Rounds between checks, as a check copies the whole state
+const CHECK_EVERY: u64 = 10;
+/// Growth of peak RSS allowed per transaction id created, a few times what one takes with
+/// the reference engine and the copies of a check
+const BYTES_PER_ID: u64 = 2 * 1024;
+/// Ids created before growth is judged, below that the allocator's slack dominates
+const JUDGED_FROM: u64 = 1_000_000;
+
+/// Small deterministic generator, so failures can be reproduced from the seed
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
+
+/// Rows of the core transaction types over rounds. Transaction ids stay unique per client
+/// across rounds, and disputes mostly refer to the client's own recent ids.
+struct Stream {
+    rng: Lcg,
+    issued: Vec<u64>,
+    /// Ids created by deposits and withdrawals so far
+    created: u64,
+}
+
+impl Stream {
+    fn new(seed: u64) -> Self {
+        Stream { rng: Lcg(seed), issued: vec![0; CLIENTS as usize + 1], created: 0 }
+    }
+
+    fn round(&mut self) -> Vec<TransactionInput> {
+        (0..ROUND_ROWS).map(|_| self.row()).collect()
+    }
+
+    fn row(&mut self) -> TransactionInput {
+        let client = self.rng.next(CLIENTS as u64) as u16 + 1;
+        let issued = &mut self.issued[client as usize];
+        let (transaction_type, tx, amount) = match self.rng.next(1_000) {
+            0..=549 => {
+                *issued += 1;
+                self.created += 1;
+                (TransactionType::Deposit, *issued, Some(Decimal::new(self.rng.next(100_000) as i64, 4)))
+            }
+            550..=899 => {
+                *issued += 1;
+                self.created += 1;
+                (TransactionType::Withdrawal, *issued, Some(Decimal::new(self.rng.next(100_000) as i64, 4)))
+            }
+            kind => {
+                let transaction_type = match kind {
+                    900..=959 => TransactionType::Dispute,
+                    960..=998 => TransactionType::Resolve,
+                    _ => TransactionType::Chargeback,
+                };
+                // Mostly recent ids, as disputes of old deposits are rare; one past the last
+                // issued id refers to nothing
+                let back = self.rng.next(8).min(*issued);
+                (transaction_type, *issued - back + self.rng.next(2), None)
+            }
+        };
+
+        TransactionInput {
+            transaction_type,
+            client,
+            tx: TxId::Numeric(client as u64 * 1_000_000_000 + tx),
+            amount,
+            effective_date: None,
+            timestamp: None,
+            reason_code: None,
+            metadata: None,
+            partition: None,
+            idempotency_key: None,
+            target_client: None,
+        }
+    }
+}
+
+/// Rows spread over threads by client, each thread keeping the order of its clients' rows
+fn feed(processor: &TransactionProcessor, records: &[TransactionInput]) {
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            scope.spawn(move || {
+                for record in records.iter().filter(|record| record.client % THREADS == thread) {
+                    processor.process_record(record.clone()).unwrap();
+                }
+            });
+        }
+    });
+}
+
+/// Checks the processor after a round and returns its stored transactions
+fn check(processor: &TransactionProcessor, reference: &ReferenceEngine, round: u64) -> u64 {
+    let accounts: BTreeMap<u16, ReferenceAccount> = processor
+        .accounts()
+        .unwrap()
+        .into_iter()
+        .map(|account| {
+            (account.client_id, ReferenceAccount { available: account.available, held: account.held, locked: account.locked })
+        })
+        .collect();
+    assert_eq!(&accounts, reference.accounts(), "accounts diverged from the reference in round {}", round);
+
+    let transactions = processor.transactions().unwrap();
+    let mut disputed: BTreeMap<u16, Decimal> = BTreeMap::new();
+    for transaction in transactions.iter().filter(|transaction| transaction.state == TransactionState::UnderDispute) {
+        *disputed.entry(transaction.client_id).or_default() += transaction.amount;
+    }
+    for (client, account) in &accounts {
+        assert_eq!(account.held, disputed.get(client).copied().unwrap_or_default(), "held funds of client {} in round {}", client, round);
+    }
+
+    let path = env::temp_dir().join(format!("trx_soak_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    Snapshot::capture(processor).unwrap().save(path).unwrap();
+    let saved = Snapshot::load(path).unwrap();
+    let _ = std::fs::remove_file(path);
+    let live: Vec<AccountSnapshot> = accounts
+        .iter()
+        .map(|(client, account)| AccountSnapshot { client: *client, available: account.available, held: account.held, locked: account.locked })
+        .collect();
+    assert_eq!(saved.accounts, live, "snapshot of round {} differs from the live state", round);
+    assert_eq!(saved.transactions.len(), transactions.len(), "snapshot of round {} lost transactions", round);
+
+    transactions.len() as u64
+}
+
+#[test]
+fn test_soak() {
+    let duration = env::var("TRX_SOAK_SECS").map_or(Duration::from_secs(1), |secs| Duration::from_secs(secs.parse().unwrap()));
+    let seed: u64 = env::var("TRX_SOAK_SEED").map_or(7, |seed| seed.parse().unwrap());
+    let baseline = ResourceUsage::measure(Duration::ZERO, 0, 0).peak_rss_bytes;
+    let started = Instant::now();
+    let mut rows = 0;
+    // Peak RSS after the second epoch, once the allocator settled, which later epochs must stay near
+    let mut settled_peak = None;
+
+    let mut epoch = 0;
+    let mut done = false;
+    while !done {
+        epoch += 1;
+        let processor = TransactionProcessor::new().with_tx_id_kind(TxIdKind::U64);
+        let mut reference = ReferenceEngine::new();
+        let mut stream = Stream::new(seed + epoch);
+
+        for round in 1..=EPOCH_ROUNDS {
+            let records = stream.round();
+            feed(&processor, &records);
+            for record in &records {
+                reference.process(record);
+            }
+            rows += ROUND_ROWS as u64;
+            done = started.elapsed() >= duration;
+            if round % CHECK_EVERY != 0 && round != EPOCH_ROUNDS && !done {
+                continue;
+            }
+            let stored = check(&processor, &reference, round);
+
+            let usage = ResourceUsage::measure(started.elapsed(), rows, 0);
+            let growth = match (baseline, usage.peak_rss_bytes) {
+                (Some(baseline), Some(peak)) => Some(peak.saturating_sub(baseline)),
+                _ => None,
+            };
+            eprintln!(
+                "soak: seed={}, epoch={}, round={}, ids={}, stored={}, rss_growth={}, rows_per_sec={:.0}",
+                seed + epoch,
+                epoch,
+                round,
+                stream.created,
+                stored,
+                growth.map_or("n/a".to_string(), |growth| growth.to_string()),
+                usage.rows_per_sec
+            );
+            if let Some(growth) = growth.filter(|_| epoch == 1 && stream.created >= JUDGED_FROM) {
+                assert!(
+                    growth <= stream.created * BYTES_PER_ID,
+                    "memory grew by {} bytes for {} transaction ids in round {}",
+                    growth,
+                    stream.created,
+                    round
+                );
+            }
+            if done {
+                break;
+            }
+        }
+
+        // A finished epoch frees its processor, so the peak must not keep climbing
+        let peak = ResourceUsage::measure(Duration::ZERO, 0, 0).peak_rss_bytes;
+        match (settled_peak, peak) {
+            (None, _) if epoch == 2 => settled_peak = peak,
+            (Some(settled), Some(peak)) => {
+                assert!(peak <= settled + settled / 4, "peak RSS climbed from {} to {} bytes by epoch {}", settled, peak, epoch)
+            }
+            _ => {}
+        }
+    }
+}
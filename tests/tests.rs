@@ -31,6 +31,94 @@ fn test_sample_transactions() {
         .success();
 }
 
+#[test]
+fn test_log_timezone_requires_log_transactions() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--log-timezone", "utc"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--log-timezone' and '--log-time-format' require '--log-transactions'"));
+}
+
+#[test]
+fn test_invalid_log_time_format() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--log-transactions", "--log-time-format", "%Q"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value '%Q' for '--log-time-format'"));
+}
+
+#[test]
+fn test_log_per_client() {
+    let dir = std::env::temp_dir().join(format!("trx_log_per_client_{}", std::process::id()));
+    let input = dir.join("input.csv");
+    let logs = dir.join("logs");
+    std::fs::create_dir_all(&dir).unwrap();
+    // More clients than log files are kept open
+    let mut rows = String::from("type,client,tx,amount\n");
+    for client in 1..=300 {
+        rows.push_str(&format!("deposit,{},{},1.0\n", client, client));
+    }
+    rows.push_str("withdrawal,7,301,5.0\n");
+    std::fs::write(&input, rows).unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input.to_str().unwrap(), "--log-per-client", logs.to_str().unwrap(), "--log-timezone", "utc"])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_dir(&logs).unwrap().count(), 300);
+    let client_7 = std::fs::read_to_string(logs.join("client-7.log")).unwrap();
+    assert_eq!(client_7.lines().count(), 2);
+    assert!(client_7.contains("Z] DEPOSIT SUCCESS: client=7, tx=7, amount=1"));
+    assert!(client_7.contains("WITHDRAWAL REJECTED: client=7, tx=301, amount=5"));
+
+    let buckets = dir.join("buckets");
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input.to_str().unwrap(), "--log-per-client", buckets.to_str().unwrap(), "--log-buckets", "16"])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_dir(&buckets).unwrap().count(), 16);
+    let bucket_7 = std::fs::read_to_string(buckets.join("bucket-7.log")).unwrap();
+    assert!(bucket_7.contains("client=7, tx=7") && bucket_7.contains("client=23, tx=23"));
+    let _ = std::fs::remove_dir_all(dir);
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--log-buckets", "16"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--log-buckets' requires '--log-per-client'"));
+}
+
+#[test]
+fn test_log_level_per_category() {
+    let logs = std::env::temp_dir().join(format!("trx_log_level_{}", std::process::id()));
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/cases/mixed_clients/input.csv", "--log-per-client", logs.to_str().unwrap()])
+        .args(["--log-level", "off,disputes=info,withdrawals=warn"])
+        .assert()
+        .success();
+
+    let log = ["client-1.log", "client-2.log"].map(|file| std::fs::read_to_string(logs.join(file)).unwrap()).concat();
+    let lines: Vec<&str> = log.lines().map(|line| line.split_once("] ").unwrap().1).collect();
+    assert_eq!(lines, [
+        "DISPUTE SUCCESS: client=1, tx=1, amount=100 (moved to held)",
+        "WITHDRAWAL REJECTED: client=1, tx=4, amount=50, reason=insufficient_funds_or_locked",
+        "RESOLVE SUCCESS: client=1, tx=1, amount=100 (moved to available)",
+        "DISPUTE SUCCESS: client=2, tx=6, amount=300 (moved to held)",
+        "DISPUTE REJECTED: client=2, tx=6, reason=invalid_state (state=UnderDispute)",
+        "WITHDRAWAL REJECTED: client=2, tx=7, amount=100, reason=insufficient_funds_or_locked",
+        "CHARGEBACK SUCCESS: client=2, tx=6, amount=300 (account locked)",
+    ]);
+    let _ = std::fs::remove_dir_all(logs);
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--log-transactions", "--log-level", "refunds=off"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value 'refunds=off' for '--log-level'"));
+}
+
 
 // ============================================================================
 // Basic Transaction Flow Tests
@@ -264,4 +352,3223 @@ fn test_zero_and_negative_amounts() {
     // Client 1: deposit 0 (fails), deposit -10 (fails), deposit 100, withdrawal 0 (fails), withdrawal -5 (fails), withdrawal 50
     // Result: 100 - 50 = 50
     assert!(output_str.contains("1,50,0,50,false"));
-}
\ No newline at end of file
+}
+// ============================================================================
+// URL Input Tests
+// ============================================================================
+
+/// Serves `body` to every request whose headers contain `expected_header`, 403 to the others
+fn serve_input(body: &'static str, expected_header: &'static str) -> String {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut authorized = false;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                authorized |= line.to_lowercase().starts_with(&expected_header.to_lowercase());
+            }
+            match authorized {
+                true => write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap(),
+                false => write!(stream, "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n").unwrap(),
+            }
+        }
+    });
+    format!("http://{}", address)
+}
+
+#[test]
+fn test_url_input_with_authorization_from_env() {
+    let body = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n";
+    let url = serve_input(body, "Authorization: Bearer secret-token");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(format!("{}/settlement.csv?signature=abc", url))
+        .env("TRX_INPUT_AUTHORIZATION", "Bearer secret-token")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,6,0,6,false"));
+}
+
+#[test]
+fn test_url_input_failure_hides_signature() {
+    let url = serve_input("", "Authorization: Bearer secret-token");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(format!("{}/settlement.csv?signature=abc", url))
+        .env_remove("TRX_INPUT_AUTHORIZATION")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!("{}/settlement.csv answered 403", url)))
+        .stderr(predicate::str::contains("signature=abc").not());
+}
+
+#[test]
+fn test_url_input_cannot_be_cached() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["https://example.com/settlement.csv", "--cache-dir", "cache"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--cache-dir' and '--manifest' require a local input file"));
+}
+
+#[test]
+fn test_source_option_takes_the_input() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["--source", "tests/fixtures/basic_deposits_withdrawals.csv"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,750,0,750,false"));
+}
+
+#[test]
+fn test_invalid_sftp_location() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--upload-report", "sftp://bank@sftp.example.com"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid SFTP location sftp://bank@sftp.example.com"));
+}
+
+#[test]
+fn test_upload_report_requires_a_report() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--quiet", "--upload-report", "sftp://bank@sftp.example.com/in/accounts.csv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--upload-report' cannot be combined with '--quiet'"));
+}
+
+#[cfg(not(feature = "sftp"))]
+#[test]
+fn test_sftp_source_requires_sftp_feature() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["--source", "sftp://bank@sftp.example.com/out/settlement.csv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("sftp:// locations require a build with the sftp feature"));
+}
+
+// ============================================================================
+// Transaction Id Type Tests
+// ============================================================================
+
+#[test]
+fn test_u64_tx_ids_rejected_by_default() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/u64_tx_ids.csv")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid transaction id: 5000000000"));
+}
+
+#[test]
+fn test_u64_tx_ids() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/u64_tx_ids.csv", "--tx-id-type", "u64"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    // Client 1: 100 + 50, dispute and chargeback the 100 deposit
+    assert!(output_str.contains("1,50,0,50,true"));
+}
+
+#[test]
+fn test_string_tx_ids() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/string_tx_ids.csv", "--tx-id-type", "string"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    // Client 1: 100 + 50 + 25 = 175, dispute the uuid deposit (held), "007" is distinct from "7"
+    // and is disputed then resolved
+    assert!(output_str.contains("1,75,100,175,false"));
+}
+
+// ============================================================================
+// Snapshot Tests
+// ============================================================================
+
+#[test]
+fn test_snapshot_diff() {
+    let dir = std::env::temp_dir();
+    let before = dir.join(format!("trx_snapshot_before_{}.bin", std::process::id()));
+    let after = dir.join(format!("trx_snapshot_after_{}.bin", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/dispute_and_resolve.csv")
+        .arg("--snapshot")
+        .arg(&before)
+        .assert()
+        .success();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/multiple_clients.csv")
+        .arg("--snapshot")
+        .arg(&after)
+        .assert()
+        .success();
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("snapshot-diff")
+        .arg(&before)
+        .arg(&after)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    // Client 1 goes from 25 to 50, clients 2 and 3 are new
+    assert!(output_str.contains("BALANCE CHANGED: client=1, available=25 -> 50, held=0 -> 0"));
+    assert!(output_str.contains("NEW ACCOUNT: client=2"));
+    assert!(output_str.contains("NEW ACCOUNT: client=3"));
+
+    // Comparing a snapshot with itself reports nothing
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("snapshot-diff")
+        .arg(&after)
+        .arg(&after)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No differences"));
+
+    let _ = std::fs::remove_file(before);
+    let _ = std::fs::remove_file(after);
+}
+
+// ============================================================================
+// Adjustment Tests
+// ============================================================================
+
+#[test]
+fn test_adjustments_require_flag() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/adjustments.csv")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    // All adjustments are ignored without --allow-adjustments
+    assert!(output_str.contains("1,100,0,100,false"));
+    assert!(output_str.contains("2,0,0,0,true"));
+}
+
+#[test]
+fn test_adjustments() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/adjustments.csv", "--allow-adjustments"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    // Client 1: 100 - 30 + 12.5, missing effective date (ignored), overdraw (ignored)
+    assert!(output_str.contains("1,82.5,0,82.5,false"));
+
+    // Client 2: charged back and locked, the correction still applies
+    assert!(output_str.contains("2,5,0,5,true"));
+}
+
+// ============================================================================
+// Account Merge Tests
+// ============================================================================
+
+#[test]
+fn test_merge() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/merge.csv", "--allow-merges"])
+        .assert()
+        .success()
+        // Client 1 is closed, its disputed deposit is resolved on client 2
+        .stdout("client,available,held,total,locked\n1,0,0,0,false\n2,55,0,55,false\n")
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=1, tx=5, reason=account_merged (merged_into=2)"))
+        .stderr(predicate::str::contains("reason=invalid_merge_target (target_client=2)"))
+        .stderr(predicate::str::contains("MERGE REJECTED: client=2, tx=8, target_client=9, reason=account_not_found"));
+}
+
+#[test]
+fn test_merges_require_flag() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/merge.csv")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,35,100,135,false"))
+        .stderr(predicate::str::contains("reason=merges_disabled"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/merge.csv", "--allow-merges", "--shard", "0/2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--allow-merges' cannot be combined with '--shard'"));
+}
+
+#[test]
+fn test_undo_merge() {
+    let dir = std::env::temp_dir().join(format!("trx_undo_merge_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit").display().to_string();
+    let merge_file = dir.join("merge.csv");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&merge_file, "type,client,tx,amount,target_client\nmerge,1,10,,3\n").unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients.csv", "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,50,0,50,false"));
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([merge_file.to_str().unwrap(), "--allow-merges", "--store", &store, "--audit-dir", &audit_dir, "--run-id", "merge"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,0,0,0,false"))
+        .stdout(predicate::str::contains("3,200,0,200,false"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["undo", "--run", "merge", "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("COMPENSATED ACCOUNT: client=1, available=50"));
+
+    // The source account is open again
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([merge_file.to_str().unwrap(), "--allow-merges", "--store", &store])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("account_merged").not());
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// ============================================================================
+// Admin Operations Tests
+// ============================================================================
+
+#[test]
+fn test_admin_ops() {
+    // After the file, the unlock lifts the chargeback lock and the adjustment needs no flag
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_and_chargeback.csv", "--admin-ops", "tests/fixtures/admin_ops.csv", "--admin-ops-at", "after"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,60,0,60,false\n")
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=1, tx=102, reason=not_admin_operation"))
+        .stderr(predicate::str::contains("UNLOCK REJECTED: client=1, tx=103, reason=invalid_state (locked=false)"))
+        .stderr(predicate::str::contains("4 admin operation(s) from tests/fixtures/admin_ops.csv, 2 rejected"));
+
+    // Before the file, there is no lock yet to lift
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_and_chargeback.csv", "--admin-ops", "tests/fixtures/admin_ops.csv"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,60,0,60,true\n");
+}
+
+#[test]
+fn test_admin_only_rows_in_the_input_file() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/admin_ops.csv")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("UNLOCK REJECTED: client=1, tx=100, reason=admin_only"))
+        .stderr(predicate::str::contains("reason=adjustments_disabled"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_and_chargeback.csv", "--admin-ops", "tests/fixtures/admin_ops.csv", "--stream-output"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--admin-ops' cannot be combined with"));
+}
+
+#[test]
+fn test_admin_ops_audit_section() {
+    let dir = std::env::temp_dir().join(format!("trx_admin_ops_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_and_chargeback.csv", "--admin-ops", "tests/fixtures/admin_ops.csv", "--admin-ops-at", "after"])
+        .args(["--store", &store, "--audit-dir", audit_dir.to_str().unwrap(), "--run-id", "admin"])
+        .assert()
+        .success();
+
+    // One batch for the input file, one for the admin file
+    let trail = std::fs::read_to_string(audit_dir.join("admin.jsonl")).unwrap();
+    let batches: Vec<&str> = trail.lines().collect();
+    assert_eq!(batches.len(), 2);
+    assert!(!batches[0].contains("\"section\""));
+    assert!(batches[1].contains("\"section\":\"admin_ops\""));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// ============================================================================
+// Disabled Type Tests
+// ============================================================================
+
+#[test]
+fn test_disabled_types() {
+    let report = std::env::temp_dir().join(format!("trx_disabled_types_{}.json", std::process::id()));
+
+    // Without its dispute and chargeback, the deposit stays and the account is not locked
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_and_chargeback.csv", "--disable", "dispute,chargeback", "--report", report.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,225,0,225,false\n")
+        .stderr(predicate::str::contains("CHARGEBACK REJECTED: client=1, tx=1, reason=type_disabled"));
+
+    let summary: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(summary["rejections"]["type_disabled"], 2);
+    assert_eq!(summary["rows_disabled"], serde_json::json!({ "chargeback": 1, "dispute": 1 }));
+    let _ = std::fs::remove_file(report);
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_and_chargeback.csv", "--disable", "dispute,refund"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value 'refund' for '--disable'"));
+}
+
+// ============================================================================
+
+#[test]
+fn test_balance_at() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["balance-at", "tests/fixtures/timestamped.csv", "--client", "1", "--at", "2024-03-01T00:00:00Z"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,120,0,120,false\n");
+
+    // Dispute at 08:00 is included, the later rows are not
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["balance-at", "tests/fixtures/timestamped.csv", "--client", "1", "--at", "2024-03-01T09:00:00Z"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,20,100,120,false"));
+}
+
+#[test]
+fn test_balance_at_before_first_activity() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["balance-at", "tests/fixtures/timestamped.csv", "--client", "2", "--at", "2024-02-01T00:00:00Z"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,0,0,0,false"));
+}
+
+#[test]
+fn test_balance_at_invalid_timestamp() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["balance-at", "tests/fixtures/timestamped.csv", "--client", "1", "--at", "yesterday"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value 'yesterday' for '--at'"));
+}
+
+#[test]
+fn test_cut_by_day() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/timestamped.csv", "--cut-by", "day"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    assert!(output_str.starts_with("date,client,available,held,total,locked\n"));
+
+    // Client 1 closing balances carry over from day to day
+    assert!(output_str.contains("2024-02-27,1,100,0,100,false"));
+    assert!(output_str.contains("2024-02-29,1,120,0,120,false"));
+    assert!(output_str.contains("2024-03-01,1,20,100,120,false"));
+    assert!(output_str.contains("2024-03-02,1,0,0,0,true"));
+
+    // Client 2 only appears once it has an account
+    assert!(!output_str.contains("2024-02-29,2"));
+    assert!(output_str.contains("2024-03-02,2,10,0,10,false"));
+}
+
+#[test]
+fn test_cut_by_day_requires_timestamps() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--cut-by", "day"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("timestamp"));
+}
+
+// ============================================================================
+// Dispute Rules Tests
+// ============================================================================
+
+#[test]
+fn test_dispute_rules() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_rules.csv", "--dispute-rules", "tests/fixtures/dispute_rules.json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    // Client 1: only tx 1 is disputable, tx 2 exceeds the cap and tx 3 is older than 120 days
+    assert!(output_str.contains("1,1200,100,1300,false"));
+
+    // Client 2: gold tier is exempt from the amount cap
+    assert!(output_str.contains("2,0,1000,1000,false"));
+}
+
+#[test]
+fn test_without_dispute_rules_everything_is_disputable() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/dispute_rules.csv")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    assert!(output_str.contains("1,0,1300,1300,false"));
+    assert!(output_str.contains("2,0,1000,1000,false"));
+}
+
+// ============================================================================
+// Risk Scoring Tests
+// ============================================================================
+
+#[test]
+fn test_risk_scores() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/risk_scoring.csv", "--risk"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    assert!(output_str.starts_with("client,available,held,total,locked,risk_score\n"));
+
+    // Client 1: two failed withdrawals (2 x 5) and a dispute (10)
+    assert!(output_str.contains("1,110,0,110,false,20"));
+    assert!(output_str.contains("2,50,0,50,false,0"));
+}
+
+#[test]
+fn test_risk_lock_threshold() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/risk_scoring.csv", "--risk-lock-threshold", "20"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    // Client 1 is locked by the dispute, so the last deposit is rejected
+    assert!(output_str.contains("1,100,0,100,true,20"));
+    assert!(output_str.contains("2,50,0,50,false,0"));
+}
+
+// ============================================================================
+// Anomaly Report Tests
+// ============================================================================
+
+#[test]
+fn test_anomaly_report() {
+    let report = std::env::temp_dir().join(format!("trx_anomalies_{}.csv", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/anomalies.csv")
+        .arg("--anomalies")
+        .arg(&report)
+        .assert()
+        .success();
+
+    let report_str = std::fs::read_to_string(&report).unwrap();
+    let _ = std::fs::remove_file(&report);
+
+    assert!(report_str.starts_with("client,tx,kind,detail\n"));
+
+    // Client 1: 500 is far above the usual ~10 deposits
+    assert!(report_str.contains("1,6,large_deposit,"));
+
+    // Client 2: 95 of a 100 deposit withdrawn right away
+    assert!(report_str.contains("2,8,deposit_withdraw_cycle,"));
+
+    // Client 3: three disputes in a row
+    assert!(report_str.contains("3,11,dispute_storm,"));
+
+    assert_eq!(report_str.lines().count(), 4);
+}
+
+#[test]
+fn test_balance_history() {
+    let history = std::env::temp_dir().join(format!("trx_balance_history_{}.csv", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients.csv", "--sample-every", "3", "--history-clients", "2", "--balance-history"])
+        .arg(&history)
+        .assert()
+        .success();
+
+    let history_str = std::fs::read_to_string(&history).unwrap();
+    let _ = std::fs::remove_file(&history);
+
+    assert_eq!(
+        history_str,
+        concat!(
+            "row,timestamp,client,available,held,total,locked\n",
+            // Client 2 after each of its rows, every account after every third row
+            "2,,2,200,0,200,false\n",
+            "3,,1,100,0,100,false\n",
+            "3,,2,200,0,200,false\n",
+            "3,,3,300,0,300,false\n",
+            "6,,1,50,0,50,false\n",
+            "6,,2,200,0,200,false\n",
+            "6,,3,150,0,150,false\n",
+            "7,,2,0,200,200,false\n",
+            "9,,1,50,0,50,false\n",
+            "9,,2,0,0,0,true\n",
+            "9,,3,150,0,150,false\n",
+        )
+    );
+}
+
+#[test]
+fn test_exposure_report() {
+    let report = std::env::temp_dir().join(format!("trx_exposure_{}.json", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients.csv", "--exposure"])
+        .arg(&report)
+        .assert()
+        .success();
+
+    let exposure: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let _ = std::fs::remove_file(&report);
+
+    // Client 2's deposit is held after row 7 and charged back after row 9
+    assert_eq!(exposure["peak_total_held"], "200");
+    assert_eq!(exposure["peak_total_held_row"], 7);
+    assert_eq!(
+        exposure["clients"][1],
+        serde_json::json!({
+            "client": 2,
+            "max_drawdown": "200",
+            "drawdown_from": "200",
+            "drawdown_to": "0",
+            "peak_held": "200",
+            "peak_held_row": 7,
+        })
+    );
+    assert_eq!(exposure["clients"][2]["max_drawdown"], "150");
+}
+
+#[test]
+fn test_dispute_stats() {
+    let csv = std::env::temp_dir().join(format!("trx_dispute_stats_{}.csv", std::process::id()));
+    let json = std::env::temp_dir().join(format!("trx_dispute_stats_{}.json", std::process::id()));
+
+    for path in [&csv, &json] {
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .args(["tests/fixtures/dispute_stats.csv", "--dispute-stats"])
+            .arg(path)
+            .assert()
+            .success();
+    }
+
+    let csv_str = std::fs::read_to_string(&csv).unwrap();
+    let json_str = std::fs::read_to_string(&json).unwrap();
+    let _ = std::fs::remove_file(&csv);
+    let _ = std::fs::remove_file(&json);
+
+    // Client 1: resolved after 12h, charged back after 48h. Client 2: resolved after 24h, one still open
+    assert_eq!(
+        csv_str,
+        concat!(
+            "client,disputes,resolved,charged_back,open,chargeback_ratio,mean_seconds_to_resolve,max_seconds_to_resolve,mean_seconds_to_chargeback,max_seconds_to_chargeback\n",
+            "1,2,1,1,0,0.5,43200,43200,172800,172800\n",
+            "2,2,1,0,1,0,86400,86400,,\n",
+            ",4,2,1,1,0.3333,64800,86400,172800,172800\n",
+        )
+    );
+
+    let stats: serde_json::Value = serde_json::from_str(&json_str).unwrap();
+    assert_eq!(stats["overall"]["chargeback_ratio"], "0.3333");
+    assert_eq!(stats["overall"]["mean_seconds_to_resolve"], 64800);
+    assert_eq!(stats["clients"][1]["client"], 2);
+    assert_eq!(stats["clients"][1]["open"], 1);
+}
+
+#[test]
+fn test_dispute_chains() {
+    let csv = std::env::temp_dir().join(format!("trx_dispute_chains_{}.csv", std::process::id()));
+    let json = std::env::temp_dir().join(format!("trx_dispute_chains_{}.json", std::process::id()));
+
+    for path in [&csv, &json] {
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .args(["tests/fixtures/dispute_stats.csv", "--dispute-chains"])
+            .arg(path)
+            .assert()
+            .success();
+    }
+
+    let csv_str = std::fs::read_to_string(&csv).unwrap();
+    let json_str = std::fs::read_to_string(&json).unwrap();
+    let _ = std::fs::remove_file(&csv);
+    let _ = std::fs::remove_file(&json);
+
+    assert_eq!(
+        csv_str,
+        concat!(
+            "client,tx,type,amount,timestamp,disputes,resolves,chargebacks,opened_at,closed_at,final_state,shortfall,reason_code,chain\n",
+            "1,1,deposit,100,2024-03-01T08:00:00Z,1,1,0,2024-03-02T08:00:00Z,2024-03-02T20:00:00Z,Normal,0,,dispute@2024-03-02T08:00:00Z>resolve@2024-03-02T20:00:00Z\n",
+            "1,2,deposit,50,2024-03-01T09:00:00Z,1,0,1,2024-03-02T09:00:00Z,2024-03-04T09:00:00Z,ChargedBack,0,,dispute@2024-03-02T09:00:00Z>chargeback@2024-03-04T09:00:00Z\n",
+            "2,3,deposit,70,2024-03-01T09:30:00Z,1,1,0,2024-03-02T10:00:00Z,2024-03-03T10:00:00Z,Normal,0,,dispute@2024-03-02T10:00:00Z>resolve@2024-03-03T10:00:00Z\n",
+            "2,4,deposit,30,2024-03-01T10:00:00Z,1,0,0,2024-03-02T11:00:00Z,,UnderDispute,0,,dispute@2024-03-02T11:00:00Z\n",
+        )
+    );
+
+    let chains: serde_json::Value = serde_json::from_str(&json_str).unwrap();
+    assert_eq!(chains[3]["tx"], "4");
+    assert_eq!(chains[3]["final_state"], "UnderDispute");
+    assert_eq!(chains[3]["closed_at"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_aml_report() {
+    let report = std::env::temp_dir().join(format!("trx_aml_{}.csv", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/aml.csv", "--aml-thresholds", "tests/fixtures/aml_thresholds.json", "--aml-report"])
+        .arg(&report)
+        .assert()
+        .success();
+
+    let report_str = std::fs::read_to_string(&report).unwrap();
+    let _ = std::fs::remove_file(&report);
+
+    assert_eq!(
+        report_str,
+        concat!(
+            "client,threshold,total,first_seen,last_seen,transactions\n",
+            // Client 1 crosses 10000 within 24h, falls below when tx 1 leaves the window and crosses again
+            "1,deposits_24h,11000,2024-03-01T08:00:00Z,2024-03-02T06:00:00Z,1 3 4\n",
+            "1,deposits_24h,12000,2024-03-01T20:00:00Z,2024-03-02T10:00:00Z,3 4 6\n",
+            "1,withdrawals_1h,2100,2024-03-02T11:00:00Z,2024-03-02T11:30:00Z,7 8\n",
+        )
+    );
+}
+
+#[test]
+fn test_aml_thresholds_require_report() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/aml.csv", "--aml-thresholds", "tests/fixtures/aml_thresholds.json"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--aml-thresholds' and '--aml-report' must be given together"));
+}
+
+// ============================================================================
+// Screening Tests
+// ============================================================================
+
+#[test]
+fn test_screening_denylist() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/screening.csv", "--screening-denylist", "tests/fixtures/denylist.csv"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,60,0,60,false"))
+        .stdout(predicate::str::contains("2,0,0,0,false"))
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=2, tx=2, reason=screening_hit (ofac)"))
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=3, tx=3, reason=screening_hit (denylisted)"));
+}
+
+#[test]
+fn test_screening_callout() {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    // Screening service flagging client 2
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/screen", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let answer = match String::from_utf8(body).unwrap().contains("\"client\":2") {
+                true => r#"{"hit":true,"reason":"pep"}"#,
+                false => r#"{"hit":false}"#,
+            };
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", answer.len(), answer).unwrap();
+        }
+    });
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/screening.csv", "--screening-url", &url])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,60,0,60,false"))
+        .stdout(predicate::str::contains("3,20,0,20,false"))
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=2, tx=2, reason=screening_hit (pep)"));
+}
+
+#[test]
+fn test_screening_unavailable_rejects() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/screening.csv", "--screening-url", "http://127.0.0.1:1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,0,0,0,false"))
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=1, tx=1, reason=screening_unavailable (I/O error"));
+}
+
+#[cfg(feature = "plugins")]
+#[test]
+fn test_plugin_rejects_rows() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/screening.csv", "--plugin", "tests/fixtures/plugin_pause_withdrawals.wasm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,100,0,100,false"))
+        .stderr(predicate::str::contains(
+            "WITHDRAWAL REJECTED: client=1, tx=4, reason=plugin_rejected (plugin_pause_withdrawals: withdrawals paused)",
+        ));
+}
+
+#[cfg(feature = "plugins")]
+#[test]
+fn test_plugin_out_of_fuel_rejects() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/screening.csv", "--plugin", "tests/fixtures/plugin_endless.wasm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,0,0,0,false"))
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=1, tx=1, reason=plugin_failed (plugin_endless: all fuel consumed"));
+}
+
+#[cfg(not(feature = "plugins"))]
+#[test]
+fn test_plugin_requires_feature() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/screening.csv", "--plugin", "tests/fixtures/plugin_pause_withdrawals.wasm"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot load plugin plugin_pause_withdrawals, built without the plugins feature"));
+}
+
+#[cfg(feature = "scripting")]
+#[test]
+fn test_script_fees_and_limits() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/fee_rules.csv", "--script", "tests/fixtures/fee_rules.rhai"])
+        .assert()
+        .success()
+        // 40 withdrawn with a fee of 0.40, the second withdrawal is over the limit
+        .stdout(predicate::str::contains("1,59.60,0,59.60,false"))
+        .stdout(predicate::str::contains("2,10,0,10,false"))
+        .stderr(predicate::str::contains(
+            "WITHDRAWAL REJECTED: client=1, tx=3, reason=script_rejected (fee_rules.rhai: over half of the available funds)",
+        ));
+}
+
+#[cfg(not(feature = "scripting"))]
+#[test]
+fn test_script_requires_feature() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/fee_rules.csv", "--script", "tests/fixtures/fee_rules.rhai"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot load script fee_rules.rhai, built without the scripting feature"));
+}
+
+#[test]
+fn test_review_queue_parks_until_approved() {
+    let queue = std::env::temp_dir().join(format!("trx_review_queue_{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&queue);
+
+    // The deposit over the limit and the one of the denylisted client wait for review
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/review.csv", "--review-over", "1000", "--screening-denylist", "tests/fixtures/denylist.csv", "--review-queue"])
+        .arg(&queue)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,100,0,100,false"))
+        .stdout(predicate::str::contains("2,0,0,0,false"))
+        .stderr(predicate::str::contains("2 transaction(s) waiting for review"));
+
+    let queue_str = std::fs::read_to_string(&queue).unwrap();
+    assert!(queue_str.contains("\"reason\": \"over_limit\""));
+    assert!(queue_str.contains("\"reason\": \"screening_hit: ofac\""));
+
+    // A later run approves one, rejects the other and decides nothing for an unknown tx
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/review_decisions.csv", "--review-queue"])
+        .arg(&queue)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,5000,0,5000,false"))
+        .stdout(predicate::str::contains("2,0,0,0,false"))
+        .stderr(predicate::str::contains("APPROVE REJECTED: client=1, tx=9, reason=not_in_review"))
+        .stderr(predicate::str::contains("waiting for review").not());
+
+    let queue_str = std::fs::read_to_string(&queue).unwrap();
+    let _ = std::fs::remove_file(&queue);
+    assert_eq!(queue_str, "[]");
+}
+
+#[test]
+fn test_review_over_requires_queue() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/review.csv", "--review-over", "1000"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--review-over' requires '--review-queue'"));
+}
+
+// ============================================================================
+// Acknowledgement File Tests
+// ============================================================================
+
+#[test]
+fn test_ack_file_lists_every_row() {
+    let ack = std::env::temp_dir().join(format!("trx_ack_{}.csv", std::process::id()));
+    let queue = std::env::temp_dir().join(format!("trx_ack_queue_{}.json", std::process::id()));
+    let dead_letter = std::env::temp_dir().join(format!("trx_ack_dead_letter_{}.jsonl", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/ack.csv", "--review-over", "1000", "--review-queue"])
+        .arg(&queue)
+        .arg("--dead-letter")
+        .arg(&dead_letter)
+        .arg("--ack-out")
+        .arg(&ack)
+        .assert()
+        .success();
+
+    let ack_str = std::fs::read_to_string(&ack).unwrap();
+    let _ = std::fs::remove_file(&ack);
+    let _ = std::fs::remove_file(&queue);
+    let _ = std::fs::remove_file(&dead_letter);
+    assert_eq!(
+        ack_str,
+        "line,client,tx,type,status,reason\n\
+         2,1,1,deposit,ACK,\n\
+         3,1,2,withdrawal,NAK,insufficient_funds_or_locked\n\
+         4,,,,NAK,malformed\n\
+         5,1,9,dispute,NAK,transaction_not_found\n\
+         6,2,4,deposit,PEND,in_review\n\
+         7,1,5,withdrawal,ACK,\n"
+    );
+}
+
+#[test]
+fn test_ack_file_custom_format() {
+    let ack = std::env::temp_dir().join(format!("trx_ack_format_{}.txt", std::process::id()));
+    let dead_letter = std::env::temp_dir().join(format!("trx_ack_format_dead_letter_{}.jsonl", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/ack.csv", "--ack-format", "tests/fixtures/ack_format.txt", "--dead-letter"])
+        .arg(&dead_letter)
+        .arg("--ack-out")
+        .arg(&ack)
+        .assert()
+        .success();
+
+    let ack_str = std::fs::read_to_string(&ack).unwrap();
+    let _ = std::fs::remove_file(&ack);
+    let _ = std::fs::remove_file(&dead_letter);
+    assert_eq!(
+        ack_str,
+        "H|ack.csv|000006\n\
+         D|0002|1   |ACK|\n\
+         D|0003|2   |NAK|insufficient_funds_or_locked\n\
+         D|0004|    |NAK|malformed\n\
+         D|0005|9   |NAK|transaction_not_found\n\
+         D|0006|4   |ACK|\n\
+         D|0007|5   |ACK|\n\
+         T|000003|000003|000000\n"
+    );
+}
+
+#[test]
+fn test_ack_format_unknown_placeholder() {
+    let format = std::env::temp_dir().join(format!("trx_ack_bad_format_{}.txt", std::process::id()));
+    std::fs::write(&format, "record: {line},{amount}\n").unwrap();
+
+    let assert = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/ack.csv", "--ack-out", "ack.csv", "--ack-format"])
+        .arg(&format)
+        .assert();
+    let _ = std::fs::remove_file(&format);
+    assert.failure().stderr(predicate::str::contains("unknown placeholder {amount} in ack format"));
+}
+
+#[test]
+fn test_ack_format_requires_ack_out() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/ack.csv", "--ack-format", "tests/fixtures/ack_format.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--ack-format' requires '--ack-out'"));
+}
+
+// ============================================================================
+// Stats Tests
+// ============================================================================
+
+#[test]
+fn test_stats() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["stats", "tests/fixtures/sample_transactions.csv"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    assert!(output_str.contains("Transactions: 13"));
+    assert!(output_str.contains("Top clients by volume:\n  client=2, volume=500\n  client=1, volume=275\n"));
+    assert!(output_str.contains("  tx=6, client=2, type=Deposit, amount=300\n"));
+    assert!(output_str.contains("  100-1000: 4\n"));
+    assert!(output_str.contains("Dispute rate: 3/4 deposits (75.00%)"));
+    assert!(output_str.contains("Chargeback rate: 1/3 disputes (33.33%)"));
+
+    // No timestamps in this file
+    assert!(!output_str.contains("Hourly throughput"));
+}
+
+#[test]
+fn test_stats_hourly_throughput() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["stats", "tests/fixtures/timestamped.csv"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Hourly throughput:\n  2024-02-27 09:00: 1\n"));
+}
+
+#[test]
+fn test_schema_report() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["schema", "tests/fixtures/schema_issues.csv"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Rows: 5 (2 failing to parse)\n"))
+        .stdout(predicate::str::contains("  type: 0 empty (0.00%), 0 invalid, values deposit=3 dispute=1 withdrawal=1\n"))
+        .stdout(predicate::str::contains("  client: 0 empty (0.00%), 1 invalid (first: '70000'), range 1 to 2\n"))
+        .stdout(predicate::str::contains("  amount: 1 empty (20.00%), 1 invalid (first: 'abc'), range -3 to 10.5\n"))
+        .stdout(predicate::str::contains("range 2024-03-01T08:00:00Z to 2024-03-02T10:00:00Z\n"))
+        .stdout(predicate::str::contains("  note: not used\n"))
+        .stdout(predicate::str::contains("  line 4: CSV error: CSV deserialize error: record 3 (line: 4, byte: 123): Invalid amount: abc\n"));
+}
+
+#[test]
+fn test_schema_checks_tx_id_type() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["schema", "tests/fixtures/string_tx_ids.csv"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Rows: 6 (2 failing to parse)"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["schema", "tests/fixtures/string_tx_ids.csv", "--tx-id-type", "string"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Rows: 6 (0 failing to parse)\n"))
+        .stdout(predicate::str::contains("  tx: 0 empty (0.00%), 0 invalid\n"));
+}
+
+// ============================================================================
+// Query Tests
+// ============================================================================
+
+#[test]
+fn test_query_accounts() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["query", "tests/fixtures/sample_transactions.csv", "select client, total, locked from accounts order by total desc limit 1"])
+        .assert()
+        .success()
+        .stdout("client,total,locked\n1,75,false\n");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["query", "tests/fixtures/sample_transactions.csv", "SELECT client FROM accounts WHERE locked = true AND disputes >= 1"])
+        .assert()
+        .success()
+        .stdout("client\n2\n");
+}
+
+#[test]
+fn test_query_transactions() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([
+            "query",
+            "tests/fixtures/sample_transactions.csv",
+            "select tx, client, amount, state from transactions where state = 'charged_back' or (client = 1 and amount < 100) order by tx",
+        ])
+        .assert()
+        .success()
+        .stdout("tx,client,amount,state\n2,1,50,normal\n6,2,300,charged_back\n");
+}
+
+#[test]
+fn test_query_errors() {
+    // Checked before the file is processed
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["query", "tests/fixtures/sample_transactions.csv", "select balance from accounts"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown column 'balance'"))
+        .stderr(predicate::str::contains("REJECTED").not());
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["query", "tests/fixtures/sample_transactions.csv", "select client from accounts where total > 'high'"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot compare total (75) with 'high'"));
+}
+
+// ============================================================================
+
+#[test]
+fn test_file_account_store_persists_between_runs() {
+    let dir = std::env::temp_dir().join(format!("trx_accounts_{}", std::process::id()));
+    let store = format!("file://{}", dir.display());
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,750,0,750,false"));
+
+    // Second run starts from the balances left by the first
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients_next_day.csv", "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,50,0,50,false"))
+        .stdout(predicate::str::contains("2,750,0,750,true"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_file_store_keeps_disputes_between_runs() {
+    let dir = std::env::temp_dir().join(format!("trx_file_disputes_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    std::fs::create_dir_all(&dir).unwrap();
+    let day1 = dir.join("day1.csv");
+    let day2 = dir.join("day2.csv");
+    std::fs::write(&day1, "type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\n").unwrap();
+    std::fs::write(&day2, "type,client,tx,amount\nresolve,1,1,\n").unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([day1.to_str().unwrap(), "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,0,10,10,false"));
+
+    // The dispute opened the day before is still there to resolve
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([day2.to_str().unwrap(), "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,10,0,10,false"))
+        .stderr(predicate::str::contains("REJECTED").not());
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_file_store_replays_transactions_from_journal() {
+    let dir = std::env::temp_dir().join(format!("trx_file_journal_{}", std::process::id()));
+    let accounts = dir.join("accounts");
+    let store = format!("file://{}", accounts.display());
+    std::fs::create_dir_all(&dir).unwrap();
+    let day1 = dir.join("day1.csv");
+    let day2 = dir.join("day2.csv");
+    std::fs::write(&day1, "type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\n").unwrap();
+    std::fs::write(&day2, "type,client,tx,amount\nresolve,1,1,\n").unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([day1.to_str().unwrap(), "--store", &store])
+        .assert()
+        .success();
+
+    // A crash halfway through appending the batch: the journal holds the whole batch, the
+    // log only the start of its line
+    let log_path = accounts.join("transactions.jsonl");
+    let log = std::fs::read_to_string(&log_path).unwrap();
+    let entry: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+    let account: serde_json::Value = serde_json::from_slice(&std::fs::read(accounts.join("1.json")).unwrap()).unwrap();
+    let journal = serde_json::json!({ "accounts": [account], "transactions": [entry["transaction"]] });
+    std::fs::write(accounts.join("batch.journal"), journal.to_string()).unwrap();
+    std::fs::write(&log_path, &log[..log.len() - 10]).unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([day2.to_str().unwrap(), "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,10,0,10,false"))
+        .stderr(predicate::str::contains("REJECTED").not());
+    assert!(!accounts.join("batch.journal").exists());
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_unsupported_store() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--store", "ftp://example"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unsupported store"));
+}
+
+#[test]
+fn test_healthcheck() {
+    let dir = std::env::temp_dir().join(format!("trx_healthcheck_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["healthcheck", "--store", &store, "--audit-dir", audit_dir.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ok   store\nok   journal\nok   audit"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_healthcheck_unreachable_store() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["healthcheck", "--store", "redis://127.0.0.1:1"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("FAIL store: I/O error"))
+        .stderr(predicate::str::contains("1 of 1 check(s) failed"));
+}
+
+#[test]
+fn test_redis_store_unreachable() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--store", "redis://127.0.0.1:1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("I/O error"));
+}
+
+#[test]
+fn test_cold_tier_and_presizing_match_default() {
+    let run = |args: &[&str]| {
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .arg("tests/fixtures/dispute_and_chargeback.csv")
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+
+    // Tx 1 is compressed by the time it is disputed
+    assert_eq!(run(&["--cold-after", "1"]), run(&[]));
+    assert_eq!(run(&["--expected-transactions", "1000"]), run(&[]));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_and_chargeback.csv", "--cold-after", "1", "--store", "redis://127.0.0.1:1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--cold-after' cannot be combined with a redis or file store"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_and_chargeback.csv", "--cold-after", "1", "--expected-transactions", "1000"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--expected-transactions' cannot be combined with '--cold-after'"));
+}
+
+// ============================================================================
+// Batch Commit Tests
+// ============================================================================
+
+#[test]
+fn test_failed_file_is_rolled_back() {
+    let dir = std::env::temp_dir().join(format!("trx_batch_rollback_{}", std::process::id()));
+    let store = format!("file://{}", dir.display());
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/batch_with_bad_row.csv", "--store", &store])
+        .assert()
+        .failure();
+
+    // Nothing of the failed file reached the store, so there are no accounts to print
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/empty.csv", "--store", &store])
+        .assert()
+        .success()
+        .stdout("");
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_commit_every_keeps_completed_batches() {
+    let dir = std::env::temp_dir().join(format!("trx_batch_commit_{}", std::process::id()));
+    let store = format!("file://{}", dir.display());
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/batch_with_bad_row.csv", "--store", &store, "--commit-every", "2"])
+        .assert()
+        .failure();
+
+    // The first two rows were committed, the third was rolled back with the bad row
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/empty.csv", "--store", &store])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    assert!(output_str.contains("1,100,0,100,false"));
+    assert!(output_str.contains("2,50,0,50,false"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_commit_interval_keeps_completed_batches() {
+    let dir = std::env::temp_dir().join(format!("trx_batch_interval_{}", std::process::id()));
+    let store = format!("file://{}", dir.display());
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/batch_with_bad_row.csv", "--store", &store, "--commit-interval", "0s"])
+        .assert()
+        .failure();
+
+    // Every row was due at once, so only the bad row is missing
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/empty.csv", "--store", &store])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    assert!(output_str.contains("1,125,0,125,false"));
+    assert!(output_str.contains("2,50,0,50,false"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// ============================================================================
+// Undo Tests
+// ============================================================================
+
+#[test]
+fn test_undo_reverses_a_run() {
+    let dir = std::env::temp_dir().join(format!("trx_undo_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit").display().to_string();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--store", &store, "--audit-dir", &audit_dir, "--run-id", "good"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Run id: good"));
+
+    // The wrong file: moves client 1, locks client 2 and creates client 3
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients_next_day.csv", "--store", &store, "--audit-dir", &audit_dir, "--run-id", "bad"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,750,0,750,true"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["undo", "--run", "bad", "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("COMPENSATED ACCOUNT: client=1, available=-50, held=0, locked=false"))
+        .stdout(predicate::str::contains("COMPENSATED ACCOUNT: client=2, available=0, held=0, locked=false"));
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/empty.csv", "--store", &store])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    assert!(output_str.contains("1,0,0,0,false"));
+    assert!(output_str.contains("2,750,0,750,false"));
+    assert!(output_str.contains("3,0,0,0,false"));
+
+    // A run can only be undone once
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["undo", "--run", "bad", "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already undone"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_undo_restores_holds_ledger() {
+    let dir = std::env::temp_dir().join(format!("trx_undo_holds_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit").display().to_string();
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,100\ndispute,1,1,\n").unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(&input)
+        .args(["--store", &store, "--audit-dir", &audit_dir, "--run-id", "r1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,0,100,100,false"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["undo", "--run", "r1", "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("COMPENSATED ACCOUNT: client=1, available=0, held=-100, locked=false"));
+
+    // The hold is gone from the ledger with the held funds, so the reconciliation of the next
+    // run finds nothing
+    let account = std::fs::read_to_string(dir.join("accounts").join("1.json")).unwrap();
+    assert!(!account.contains("holds"), "{}", account);
+    assert!(account.contains(r#""tx_count":0,"disputes":0"#), "{}", account);
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/empty.csv", "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,0,0,0,false"))
+        .stderr(predicate::str::contains("HOLDS MISMATCH").not());
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_undo_unknown_run() {
+    let dir = std::env::temp_dir().join(format!("trx_undo_unknown_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit").display().to_string();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["undo", "--run", "missing", "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no audit trail for run 'missing'"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// ============================================================================
+// Proposal Tests
+// ============================================================================
+
+#[test]
+fn test_proposal_is_only_committed_by_apply() {
+    let dir = std::env::temp_dir().join(format!("trx_proposal_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let proposals = dir.join("proposals.bin").display().to_string();
+    let audit_dir = dir.join("audit").display().to_string();
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // The output shows the proposed state
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--store", &store, "--propose", &proposals, "--run-id", "p1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,750,0,750,false"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/empty.csv", "--store", &store])
+        .assert()
+        .success()
+        .stdout("");
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["apply", &proposals, "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("APPLIED ACCOUNT: client=1, available=0, held=0, locked=false"))
+        .stdout(predicate::str::contains("APPLIED ACCOUNT: client=2, available=750, held=0, locked=false"))
+        .stdout(predicate::str::contains("Applied proposal p1"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/empty.csv", "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,750,0,750,false"));
+
+    // The apply was recorded as run apply-p1, so it cannot happen twice
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["apply", &proposals, "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("run 'apply-p1' already has an audit trail"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_apply_carries_the_whole_run() {
+    let dir = std::env::temp_dir().join(format!("trx_proposal_holds_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let proposals = dir.join("proposals.bin").display().to_string();
+    let audit_dir = dir.join("audit").display().to_string();
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,1,100\ndispute,1,1,\n").unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(&input)
+        .args(["--store", &store, "--propose", &proposals, "--run-id", "p1"])
+        .assert()
+        .success();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["apply", &proposals, "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("APPLIED ACCOUNT: client=1, available=0, held=100, locked=false"));
+
+    // The holds ledger and counters come with the balances, and the disputed transaction with them
+    let account = std::fs::read_to_string(dir.join("accounts").join("1.json")).unwrap();
+    assert!(account.contains(r#""tx_count":2,"disputes":1"#), "{}", account);
+    assert!(account.contains(r#""holds":{"1":"100"}"#), "{}", account);
+    let trail = std::fs::read_to_string(dir.join("audit").join("apply-p1.jsonl")).unwrap();
+    assert!(trail.contains(r#""state":"UnderDispute""#), "{}", trail);
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/empty.csv", "--store", &store])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,0,100,100,false"))
+        .stderr(predicate::str::contains("HOLDS MISMATCH").not());
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_propose_requires_store() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--propose", "proposals.bin"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--propose' requires '--store'"));
+}
+
+// ============================================================================
+// Result Cache Tests
+// ============================================================================
+
+#[test]
+fn test_cache_reuses_result_of_same_file_and_options() {
+    let dir = std::env::temp_dir().join(format!("trx_cache_{}", std::process::id()));
+    let cache_dir = dir.join("cache");
+    let input = dir.join("input.csv");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy("tests/fixtures/basic_deposits_withdrawals.csv", &input).unwrap();
+    let run = |extra: &[&str]| {
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .args([input.to_str().unwrap(), "--cache-dir", cache_dir.to_str().unwrap(), "--summary"])
+            .args(extra)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("2,750,0,750,false"))
+    };
+
+    run(&[]).stderr(predicate::str::contains("Cached result").not());
+    run(&[]).stderr(predicate::str::contains("Cached result")).stderr(predicate::str::contains("Rows processed: 6"));
+    // Other options or other contents miss the cache
+    run(&["--risk"]).stderr(predicate::str::contains("Cached result").not());
+    std::fs::write(&input, std::fs::read_to_string(&input).unwrap() + "deposit, 3, 6, 1.0\n").unwrap();
+    run(&[]).stderr(predicate::str::contains("Cached result").not());
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_cache_requires_no_store() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--cache-dir", "cache", "--store", "memory://"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--cache-dir' cannot be combined with '--store'"));
+}
+
+// ============================================================================
+// Sharding Tests
+// ============================================================================
+
+#[test]
+fn test_merged_shards_match_single_run() {
+    let dir = std::env::temp_dir().join(format!("trx_shards_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut reports = Vec::new();
+    for shard in ["0/3", "1/3", "2/3"] {
+        let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .args(["tests/fixtures/multiple_clients.csv", "--shard", shard])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let report = dir.join(format!("shard_{}.csv", reports.len()));
+        std::fs::write(&report, output.stdout).unwrap();
+        reports.push(report.to_str().unwrap().to_string());
+    }
+
+    let single = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/multiple_clients.csv")
+        .output()
+        .unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("merge-reports")
+        .args(&reports)
+        .assert()
+        .success()
+        .stdout(String::from_utf8(single.stdout).unwrap());
+
+    // The same shard twice overlaps
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["merge-reports", &reports[0], &reports[0]])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("the reports overlap"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_coordinate_merges_workers() {
+    let single = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--risk"])
+        .output()
+        .unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["coordinate", "tests/fixtures/sample_transactions.csv", "--workers", "3", "--", "--risk"])
+        .assert()
+        .success()
+        .stdout(String::from_utf8(single.stdout).unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_coordinate_sends_each_host_its_share() {
+    let dir = std::env::temp_dir().join(format!("trx_coordinate_hosts_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // Stands in for ssh: keeps the rows the worker was sent and runs the command locally
+    let ssh = dir.join("ssh");
+    std::fs::write(&ssh, format!("#!/bin/sh\ntee \"{}/$1.$$.csv\" | sh -c \"$2\"\n", dir.display())).unwrap();
+    std::fs::set_permissions(&ssh, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let binary = std::path::Path::new(assert_cmd::cargo::cargo_bin!("trx_processor"));
+    let mut paths = vec![dir.clone(), binary.parent().unwrap().to_path_buf()];
+    paths.extend(std::env::split_paths(&std::env::var_os("PATH").unwrap()));
+    let path = std::env::join_paths(paths).unwrap();
+
+    let input = "tests/fixtures/sample_transactions.csv";
+    let single = Command::new(binary).args([input, "--risk"]).output().unwrap();
+    Command::new(binary)
+        .args(["coordinate", input, "--workers", "2", "--hosts", "host-a,host-b", "--", "--risk"])
+        .env("PATH", path)
+        .assert()
+        .success()
+        .stdout(String::from_utf8(single.stdout).unwrap());
+
+    // Each host got the header and the rows of its clients only, every row once
+    let mut shares = Vec::new();
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "csv") {
+            shares.push(std::fs::read_to_string(path).unwrap());
+        }
+    }
+    assert_eq!(shares.len(), 2);
+    let rows: Vec<Vec<&str>> = shares.iter().map(|share| share.lines().skip(1).collect()).collect();
+    let clients = |rows: &[&str]| rows.iter().map(|row| row.split(',').nth(1).unwrap().trim().to_string()).collect::<std::collections::BTreeSet<_>>();
+    assert!(clients(&rows[0]).is_disjoint(&clients(&rows[1])));
+    let input_rows = std::fs::read_to_string(input).unwrap().lines().skip(1).filter(|row| !row.trim().is_empty()).count();
+    assert_eq!(rows[0].len() + rows[1].len(), input_rows);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_coordinate_rejects_shared_outputs() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["coordinate", "tests/fixtures/sample_transactions.csv", "--workers", "2", "--", "--report", "run.json"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("worker options cannot write files"));
+}
+
+#[test]
+fn test_invalid_shard() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients.csv", "--shard", "3/3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--shard"));
+}
+
+// ============================================================================
+// Job Queue Tests
+// ============================================================================
+
+#[test]
+fn test_worker_processes_queue_in_order() {
+    let dir = std::env::temp_dir().join(format!("trx_jobs_{}", std::process::id()));
+    let queue = dir.join("queue").display().to_string();
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit").display().to_string();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["enqueue", "--queue", &queue, "tests/fixtures/basic_deposits_withdrawals.csv", "tests/fixtures/multiple_clients_next_day.csv"])
+        .args(["--", "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .success()
+        .stdout("job-1\njob-2\n");
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["enqueue", "--queue", &queue, "tests/fixtures/missing.csv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot enqueue 'tests/fixtures/missing.csv'"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["worker", "--queue", &queue])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains("job-1 started"))
+        .stderr(predicate::str::contains("2 job(s) done"));
+
+    // The second file ran on the balances the first one left in the store
+    let report = std::fs::read_to_string(dir.join("queue").join("job-2.csv")).unwrap();
+    assert!(report.contains("2,750,0,750,true"), "{}", report);
+    assert!(dir.join("audit").join("job-2-1.jsonl").exists());
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["worker", "--queue", &queue, "--status"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("job-1    done      1"))
+        .stdout(predicate::str::contains("run job-2-1"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_failed_job_stops_queue_until_skipped() {
+    let dir = std::env::temp_dir().join(format!("trx_jobs_failed_{}", std::process::id()));
+    let queue = dir.join("queue").display().to_string();
+    let rules = dir.join("rules.json");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&rules, "{ broken").unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["enqueue", "--queue", &queue, "tests/fixtures/multiple_clients.csv", "--", "--dispute-rules", rules.to_str().unwrap()])
+        .assert()
+        .success();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["enqueue", "--queue", &queue, "tests/fixtures/basic_deposits_withdrawals.csv"])
+        .assert()
+        .success();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["worker", "--queue", &queue])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Job failed: job-1"))
+        .stderr(predicate::str::contains("JSON error"))
+        .stderr(predicate::str::contains("job-2 started").not());
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["worker", "--queue", &queue])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!("requeue it with 'enqueue --queue {} --requeue job-1'", queue)));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["enqueue", "--queue", &queue, "--skip", "job-1"])
+        .assert()
+        .success();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["worker", "--queue", &queue])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("1 job(s) done"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_worker_undoes_interrupted_job() {
+    let dir = std::env::temp_dir().join(format!("trx_jobs_interrupted_{}", std::process::id()));
+    let queue = dir.join("queue");
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit").display().to_string();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["enqueue", "--queue", queue.to_str().unwrap(), "tests/fixtures/multiple_clients.csv", "--", "--store", &store, "--audit-dir", &audit_dir])
+        .assert()
+        .success();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["worker", "--queue", queue.to_str().unwrap()])
+        .assert()
+        .success();
+
+    // As if the worker had died after the attempt committed its rows
+    let jobs = std::fs::read_to_string(queue.join("jobs.json")).unwrap();
+    std::fs::write(queue.join("jobs.json"), jobs.replace("\"done\"", "\"running\"")).unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["worker", "--queue", queue.to_str().unwrap()])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("job-1 was interrupted and is queued again"));
+
+    // Undone and applied again, not applied twice
+    let report = std::fs::read_to_string(queue.join("job-1.csv")).unwrap();
+    assert!(report.contains("1,50,0,50,false"), "{}", report);
+    assert!(dir.join("audit").join("undo-job-1-1.jsonl").exists());
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// ============================================================================
+// Run Summary Tests
+// ============================================================================
+
+#[test]
+fn test_summary_counts_rejections_per_reason() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--summary"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Rows processed: 13"))
+        .stderr(predicate::str::contains("Rows rejected: 4"))
+        .stderr(predicate::str::contains("  insufficient_funds_or_locked: 2"))
+        .stderr(predicate::str::contains("  invalid_state: 1"))
+        .stderr(predicate::str::contains("Wall time: "))
+        .stderr(predicate::str::contains("Peak RSS: "))
+        .stderr(predicate::str::contains(" rows/s, "));
+}
+
+#[test]
+fn test_partition_violations_are_flagged() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/partitions.csv", "--summary"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,90,0,90,false"))
+        .stderr(predicate::str::contains("PARTITION MISMATCH: client=1, tx=4, partition=1, expected_partition=0"))
+        .stderr(predicate::str::contains("Partition violations: 1\n"));
+}
+
+#[test]
+fn test_slow_threshold() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--summary", "--slow-threshold", "0us"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("SLOW TRANSACTION: client=1, tx=1, type=deposit, elapsed="))
+        .stderr(predicate::str::contains("Slowest clients:\n  client "))
+        .stderr(predicate::str::contains("Slow rows (over 0ns): 13\n"));
+}
+
+#[test]
+fn test_invalid_slow_threshold() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--slow-threshold", "5min"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--slow-threshold"));
+}
+
+#[test]
+fn test_json_report_and_metrics() {
+    let report = std::env::temp_dir().join(format!("trx_report_{}.json", std::process::id()));
+    let metrics = std::env::temp_dir().join(format!("trx_metrics_{}.prom", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([
+            "tests/fixtures/sample_transactions.csv",
+            "--report", report.to_str().unwrap(),
+            "--metrics", metrics.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    let report_str = std::fs::read_to_string(&report).unwrap();
+    assert!(report_str.contains("\"account_locked\": 1"));
+    assert!(report_str.contains("\"rows_rejected\": 4"));
+    assert!(report_str.contains("\"rows_per_sec\": "));
+    assert!(report_str.contains("\"bytes_per_sec\": "));
+
+    let metrics_str = std::fs::read_to_string(&metrics).unwrap();
+    assert!(metrics_str.contains("trx_rows_processed_total 13"));
+    assert!(metrics_str.contains("trx_rejections_total{reason=\"insufficient_funds_or_locked\"} 2"));
+    // Reasons that did not occur are still exported
+    assert!(metrics_str.contains("trx_rejections_total{reason=\"client_mismatch\"} 0"));
+    assert!(metrics_str.contains("trx_row_processing_seconds_bucket{le=\"+Inf\"} 13"));
+    assert!(metrics_str.contains("trx_row_processing_seconds_count 13"));
+
+    let _ = std::fs::remove_file(report);
+    let _ = std::fs::remove_file(metrics);
+}
+
+#[test]
+fn test_summary_by_cohort() {
+    let report = std::env::temp_dir().join(format!("trx_cohorts_{}.json", std::process::id()));
+
+    // Client 3 is missing from the mapping
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients.csv", "--cohorts", "tests/fixtures/cohorts.csv", "--summary", "--report", report.to_str().unwrap()])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(concat!(
+            "By channel:\n",
+            "  paid_search: 1 client(s), 4 rows (2 rejected), deposits 100, withdrawals 50, chargebacks 0\n",
+            "  referral: 1 client(s), 3 rows (0 rejected), deposits 200, withdrawals 0, chargebacks 1\n",
+            "  unmapped: 1 client(s), 2 rows (0 rejected), deposits 300, withdrawals 150, chargebacks 0\n",
+        )));
+
+    let summary: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let _ = std::fs::remove_file(&report);
+    assert_eq!(
+        summary["cohorts"]["country"]["DE"],
+        serde_json::json!({ "clients": 2, "rows": 7, "rows_rejected": 2, "deposits": "300", "withdrawals": "50", "chargebacks": 1 })
+    );
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients.csv", "--cohorts", "tests/fixtures/cohorts.csv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--cohorts' requires '--summary' or '--report'"));
+}
+
+#[test]
+fn test_trace_export() {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    // Minimal OTLP collector that accepts every request and hands the bodies back
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, bodies) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            // Handed back before answering, so every body is in once the run has finished
+            sender.send(String::from_utf8(body).unwrap()).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+        }
+    });
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--otlp-endpoint", &endpoint])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("span(s) exported\n"));
+
+    let exported: String = bodies.try_iter().collect();
+    assert!(exported.contains("\"name\":\"trx.run\""));
+    assert!(exported.contains("\"name\":\"trx.batch\""));
+    assert!(exported.contains("\"name\":\"trx.record\""));
+    assert!(exported.contains("{\"key\":\"trx.client\",\"value\":{\"intValue\":\"2\"}}"));
+}
+
+#[test]
+fn test_trace_export_failure_does_not_fail_run() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--otlp-endpoint", "http://127.0.0.1:1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,750,0,750,false"))
+        .stderr(predicate::str::contains("0 span(s) exported"))
+        .stderr(predicate::str::contains("dropped: I/O error"));
+}
+
+// ============================================================================
+// Run Manifest Tests
+// ============================================================================
+
+#[test]
+fn test_manifest_records_digests_of_inputs_and_outputs() {
+    use trx_processor::manifest::{DigestWriter, FileDigest, RunManifest};
+
+    let dir = std::env::temp_dir().join(format!("trx_manifest_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let manifest_path = dir.join("manifest.json");
+    let snapshot_path = dir.join("snapshot.bin");
+    let input = "tests/fixtures/basic_deposits_withdrawals.csv";
+    let arguments = [input, "--snapshot", snapshot_path.to_str().unwrap(), "--manifest", manifest_path.to_str().unwrap()];
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor")).args(arguments).output().unwrap();
+    assert!(output.status.success());
+    let manifest = RunManifest::load(manifest_path.to_str().unwrap()).unwrap();
+    let mut report = DigestWriter::new(std::io::sink());
+    std::io::Write::write_all(&mut report, &output.stdout).unwrap();
+
+    assert_eq!(manifest.engine, "trx_processor");
+    assert_eq!(manifest.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(manifest.arguments, arguments);
+    assert!(manifest.started_at <= manifest.finished_at);
+    assert_eq!(manifest.inputs, [FileDigest::of(input).unwrap()]);
+    assert_eq!(
+        manifest.outputs,
+        [report.digest("-"), FileDigest::of(snapshot_path.to_str().unwrap()).unwrap()]
+    );
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_manifest_cannot_be_cached() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--cache-dir", "cache", "--manifest", "manifest.json"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--cache-dir' cannot be combined"));
+}
+
+// ============================================================================
+// Integrity Tests
+// ============================================================================
+
+#[test]
+fn test_verify_checksum_sidecar() {
+    use trx_processor::manifest::FileDigest;
+
+    let dir = std::env::temp_dir().join(format!("trx_verify_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("settlement.csv");
+    let content = std::fs::read_to_string("tests/fixtures/multiple_clients.csv").unwrap();
+    std::fs::write(&input, &content).unwrap();
+    let sha256 = FileDigest::of(input.to_str().unwrap()).unwrap().sha256;
+    std::fs::write(dir.join("settlement.csv.sha256"), format!("{}  settlement.csv\n", sha256)).unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input.to_str().unwrap(), "--verify"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("settlement.csv verified: sha256"));
+
+    // A transfer cut short no longer matches, and nothing is processed
+    std::fs::write(&input, &content[..content.len() / 2]).unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input.to_str().unwrap(), "--verify"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains(format!("Integrity check failed: {}: sha256 is", input.display())))
+        .stderr(predicate::str::contains(format!("settlement.csv.sha256 expects {}", sha256)));
+
+    std::fs::remove_file(dir.join("settlement.csv.sha256")).unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input.to_str().unwrap(), "--verify"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("settlement.csv.sha256 nor"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_verify_provider_manifest() {
+    let dir = std::env::temp_dir().join(format!("trx_input_manifest_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let manifest = dir.join("delivery.json");
+    let input = "tests/fixtures/multiple_clients.csv";
+    let bytes = std::fs::metadata(input).unwrap().len();
+
+    std::fs::write(&manifest, format!(r#"{{"files": [{{"name": "other.csv", "rows": 1}}, {{"name": "multiple_clients.csv", "rows": 9, "bytes": {}}}]}}"#, bytes)).unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--input-manifest", manifest.to_str().unwrap()])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("multiple_clients.csv verified: bytes, rows"));
+
+    std::fs::write(&manifest, r#"{"rows": 12}"#).unwrap();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--input-manifest", manifest.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!("9 rows, {} expects 12", manifest.display())));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// ============================================================================
+// Preflight Tests
+// ============================================================================
+
+#[test]
+fn test_preflight_refuses_duplicate_and_deviating_files() {
+    use trx_processor::preflight::Baseline;
+
+    let dir = std::env::temp_dir().join(format!("trx_preflight_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let baseline = dir.join("baseline.json");
+    let preflight = |input: &str| {
+        let mut command = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"));
+        command.args([input, "--preflight", baseline.to_str().unwrap()]);
+        command
+    };
+
+    // The first run starts the baseline
+    preflight("tests/fixtures/multiple_clients.csv").assert().success();
+    preflight("tests/fixtures/multiple_clients.csv")
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("Quarantined: tests/fixtures/multiple_clients.csv deviates from the baseline"))
+        .stderr(predicate::str::contains("same content as tests/fixtures/multiple_clients.csv"));
+
+    // A third of the volume of the earlier file
+    preflight("tests/fixtures/dispute_and_chargeback.csv")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("volume: 275 against a median of 800 over the last 1 run(s)"));
+    preflight("tests/fixtures/dispute_and_chargeback.csv")
+        .arg("--force")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,50,0,50,true"))
+        .stderr(predicate::str::contains("Preflight overridden with '--force'"));
+
+    let runs = Baseline::load(baseline.to_str().unwrap()).unwrap().runs;
+    assert_eq!(runs.iter().map(|run| run.rows).collect::<Vec<_>>(), [9, 6]);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+// ============================================================================
+// Notification Tests
+// ============================================================================
+
+/// Accepts one Slack webhook post and hands back its body
+fn fake_slack() -> (String, std::sync::mpsc::Receiver<String>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/services/T000/B000/XXXX", listener.local_addr().unwrap());
+    let (sender, bodies) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut stream = listener.incoming().next().unwrap().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(value) = line.to_lowercase().strip_prefix("content-length: ") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        sender.send(String::from_utf8(body).unwrap()).unwrap();
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+    });
+    (url, bodies)
+}
+
+/// Accepts one message over SMTP and hands back the whole conversation
+fn fake_smtp() -> (String, std::sync::mpsc::Receiver<String>) {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = listener.local_addr().unwrap().to_string();
+    let (sender, conversations) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut stream = listener.incoming().next().unwrap().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut conversation = String::new();
+        let mut in_data = false;
+        stream.write_all(b"220 mail.test ESMTP\r\n").unwrap();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            conversation.push_str(&line);
+            let reply: &[u8] = match line.as_str() {
+                ".\r\n" if in_data => {
+                    in_data = false;
+                    b"250 queued\r\n"
+                }
+                _ if in_data => continue,
+                "DATA\r\n" => {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                }
+                "QUIT\r\n" => b"221 bye\r\n",
+                _ => b"250-mail.test\r\n250 ok\r\n",
+            };
+            stream.write_all(reply).unwrap();
+        }
+        sender.send(conversation).unwrap();
+    });
+    (server, conversations)
+}
+
+#[test]
+fn test_notify_posts_summary_to_slack_and_mail() {
+    let (slack_url, slack_bodies) = fake_slack();
+    let (smtp_server, conversations) = fake_smtp();
+    let config = std::env::temp_dir().join(format!("trx_notify_{}.json", std::process::id()));
+    std::fs::write(
+        &config,
+        format!(
+            r#"{{"slack_webhook": "{}", "smtp": {{"server": "{}", "from": "trx@example.com", "to": ["ops@example.com"]}}, "report_url": "https://reports.example.com/latest"}}"#,
+            slack_url, smtp_server
+        ),
+    )
+    .unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--notify", config.to_str().unwrap()])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Notification failed").not());
+    let _ = std::fs::remove_file(&config);
+
+    let slack: serde_json::Value = serde_json::from_str(&slack_bodies.recv().unwrap()).unwrap();
+    let text = slack["text"].as_str().unwrap();
+    assert!(text.starts_with("*Settlement run for sample_transactions.csv finished*\n"), "{}", text);
+    assert!(text.contains("Rows processed: "), "{}", text);
+    assert!(text.contains("Report: https://reports.example.com/latest"), "{}", text);
+
+    let conversation = conversations.recv().unwrap();
+    assert!(conversation.contains("MAIL FROM:<trx@example.com>\r\nRCPT TO:<ops@example.com>\r\nDATA\r\n"), "{}", conversation);
+    assert!(conversation.contains("Subject: Settlement run for sample_transactions.csv finished\r\n"), "{}", conversation);
+    assert!(conversation.contains("Report: https://reports.example.com/latest\r\n.\r\nQUIT\r\n"), "{}", conversation);
+}
+
+#[test]
+fn test_notify_reports_failed_runs() {
+    let (slack_url, slack_bodies) = fake_slack();
+    let config = std::env::temp_dir().join(format!("trx_notify_failed_{}.json", std::process::id()));
+    std::fs::write(&config, format!(r#"{{"slack_webhook": "{}", "on_success": false}}"#, slack_url)).unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["missing_settlement.csv", "--notify", config.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Error: I/O error"));
+    let _ = std::fs::remove_file(&config);
+
+    let slack: serde_json::Value = serde_json::from_str(&slack_bodies.recv().unwrap()).unwrap();
+    let text = slack["text"].as_str().unwrap();
+    assert!(text.starts_with("*Settlement run for missing_settlement.csv FAILED*\nError: I/O error"), "{}", text);
+}
+
+#[test]
+fn test_notify_failure_does_not_fail_the_run() {
+    let config = std::env::temp_dir().join(format!("trx_notify_unreachable_{}.json", std::process::id()));
+    // Nothing listens on the discard port
+    std::fs::write(&config, r#"{"smtp": {"server": "127.0.0.1:9", "from": "trx@example.com", "to": ["ops@example.com"]}}"#).unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--notify", config.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,750,0,750,false"))
+        .stderr(predicate::str::contains("Notification failed"));
+    let _ = std::fs::remove_file(&config);
+}
+
+// ============================================================================
+// Dispute Shortfall Tests
+// ============================================================================
+
+#[test]
+fn test_dispute_shortfall_rejects_by_default() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/dispute_shortfall.csv")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("client,available,held,total,locked\n"))
+        .stdout(predicate::str::contains("1,30,0,30,false"));
+}
+
+#[test]
+fn test_dispute_shortfall_negative() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_shortfall.csv", "--dispute-shortfall", "negative"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("client,available,held,total,locked,shortfall,needs_review"))
+        .stdout(predicate::str::contains("1,-70,100,30,false,0,true"));
+}
+
+#[test]
+fn test_dispute_shortfall_partial() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/dispute_shortfall.csv", "--dispute-shortfall", "partial"])
+        .assert()
+        .success()
+        // Client 1 could only hold 30 of the disputed 100
+        .stdout(predicate::str::contains("1,0,30,30,false,70,true"))
+        // Resolving releases what was held and clears the shortfall, the review flag stays
+        .stdout(predicate::str::contains("2,20,0,20,false,0,true"));
+}
+
+// ============================================================================
+// Reservation Tests
+// ============================================================================
+
+#[test]
+fn test_reserve_capture_cancel() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/reservations.csv", "--summary"])
+        .assert()
+        .success()
+        // 30 captured, 20 cancelled back to available, 10 still reserved
+        .stdout(predicate::str::contains("1,60,10,70,false"))
+        // Capturing the cancelled reserve and disputing a reserve are rejected
+        .stderr(predicate::str::contains("  not_reserved: 1"))
+        .stderr(predicate::str::contains("  non_deposit_transaction: 1"))
+        .stderr(predicate::str::contains("  insufficient_funds_or_locked: 1"));
+}
+
+// ============================================================================
+// Output Schema Tests
+// ============================================================================
+
+#[test]
+fn test_output_schema_v1_is_pinned() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/timestamped.csv", "--output-schema", "v1"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,0,0,0,true\n2,10,0,10,false\n");
+}
+
+#[test]
+fn test_output_schema_v2_is_pinned() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/timestamped.csv", "--output-schema", "v2"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "client,available,held,total,locked,tx_count,disputes,last_activity,frozen\n",
+            "1,0,0,0,true,6,1,2024-03-02T16:00:00Z,false\n",
+            "2,10,0,10,false,1,0,2024-03-01T10:00:00Z,false\n",
+        ));
+}
+
+#[test]
+fn test_amount_format_raw_by_default() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/amount_format.csv")
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,2.00,0,2.00,false\n2,10,0,10,false\n");
+}
+
+#[test]
+fn test_amount_format_fixed4() {
+    let aml_report = std::env::temp_dir().join(format!("trx_amount_format_aml_{}.csv", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/aml.csv", "--amount-format", "fixed4", "--aml-thresholds", "tests/fixtures/aml_thresholds.json", "--aml-report"])
+        .arg(&aml_report)
+        .assert()
+        .success();
+
+    // Reports other than the account report use the same format
+    let report_str = std::fs::read_to_string(&aml_report).unwrap();
+    let _ = std::fs::remove_file(&aml_report);
+    assert!(report_str.contains("1,deposits_24h,11000.0000,"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/amount_format.csv", "--amount-format", "fixed4"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n2,10.0000,0.0000,10.0000,false\n");
+}
+
+#[test]
+fn test_amount_format_minimal() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/amount_format.csv", "--amount-format", "minimal"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,2,0,2,false\n2,10,0,10,false\n");
+}
+
+#[test]
+fn test_amount_parsing_lenient_strips_symbols_and_grouping() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/provider_amounts.csv", "--amount-parsing", "lenient"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,1334.56,0,1334.56,false\n2,2000.6234,0,2000.6234,false\n");
+
+    // By default, only the plain and scientific amounts are read
+    let dead_letter = std::env::temp_dir().join(format!("trx_amount_parsing_default_{}.jsonl", std::process::id()));
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/provider_amounts.csv", "--dead-letter"])
+        .arg(&dead_letter)
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,100,0,100,false\n2,0.1234,0,0.1234,false\n")
+        .stderr(predicate::str::contains("Invalid amount: $1,234.56"));
+    let _ = std::fs::remove_file(dead_letter);
+}
+
+#[test]
+fn test_amount_parsing_lenient_refuses_ambiguous_grouping() {
+    let dead_letter = std::env::temp_dir().join(format!("trx_amount_parsing_grouping_{}.jsonl", std::process::id()));
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/ambiguous_amounts.csv", "--amount-parsing", "lenient", "--dead-letter"])
+        .arg(&dead_letter)
+        .assert()
+        .success()
+        // None of client 1's amounts groups by thousands, so none is read
+        .stdout("client,available,held,total,locked\n2,1236802.0,0,1236802.0,false\n")
+        .stderr(predicate::str::contains("Invalid amount: 1,5 (grouping separators only go between groups of three digits)"))
+        .stderr(predicate::str::contains("Invalid amount: 1.234,56 (grouping separators only go between groups of three digits)"))
+        .stderr(predicate::str::contains("Invalid amount: 12,34.5"))
+        .stderr(predicate::str::contains("Invalid amount: 1,234 567"));
+
+    let dead_letters = std::fs::read_to_string(&dead_letter).unwrap();
+    let _ = std::fs::remove_file(dead_letter);
+    assert_eq!(dead_letters.lines().count(), 4);
+}
+
+#[test]
+fn test_amount_parsing_strict_gives_reasons() {
+    let dead_letter = std::env::temp_dir().join(format!("trx_amount_parsing_strict_{}.jsonl", std::process::id()));
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/provider_amounts.csv", "--amount-parsing", "strict", "--dead-letter"])
+        .arg(&dead_letter)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Invalid amount: 1e2 (scientific notation is not accepted)"))
+        .stderr(predicate::str::contains("Invalid amount: 0.12345 (more than 4 decimal places)"));
+
+    let dead_letters = std::fs::read_to_string(&dead_letter).unwrap();
+    let _ = std::fs::remove_file(dead_letter);
+    assert_eq!(dead_letters.lines().count(), 4);
+}
+
+#[test]
+fn test_output_schema_v2_without_timestamps() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--output-schema", "v2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,0,0,0,true,6,1,,false\n"));
+}
+
+#[test]
+fn test_freeze_blocks_outflows_until_unfrozen() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/freeze.csv", "--output-schema", "v2"])
+        .assert()
+        .success()
+        // The withdrawal while frozen is rejected, the deposit and the withdrawal after unfreezing apply
+        .stdout(predicate::str::contains("1,90,0,90,false,6,0,,false\n"))
+        .stdout(predicate::str::contains("2,10,0,10,false,3,0,,true\n"))
+        .stderr(predicate::str::contains("WITHDRAWAL REJECTED: client=1, tx=3, reason=account_frozen"))
+        .stderr(predicate::str::contains("FREEZE REJECTED: client=2, tx=9, reason=invalid_state (frozen=true)"));
+}
+
+#[test]
+fn test_freeze_policy_all_blocks_deposits() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/freeze.csv", "--freeze-policy", "all"])
+        .assert()
+        .success()
+        // A freeze is not a lock, the V1 report does not show it
+        .stdout("client,available,held,total,locked\n1,70,0,70,false\n2,10,0,10,false\n")
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=1, tx=4, reason=account_frozen"));
+}
+
+#[test]
+fn test_unknown_output_schema() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--output-schema", "v3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value 'v3' for '--output-schema'"));
+}
+
+// ============================================================================
+// Pretty And Quiet Output Tests
+// ============================================================================
+
+#[test]
+fn test_pretty_table() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/large_amounts.csv", "--pretty"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            " client |      available |   held |          total | locked\n",
+            "--------+----------------+--------+----------------+--------\n",
+            "      1 | 1,234,567.5000 | 0.0000 | 1,234,567.5000 | false\n",
+            "      2 |         2.2500 | 0.0000 |         2.2500 | false\n",
+        ));
+}
+
+#[test]
+fn test_pretty_table_with_locale() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/large_amounts.csv", "--pretty", "--locale", "de-DE"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("      1 | 1.234.567,5000 | 0,0000 | 1.234.567,5000 | false\n"));
+}
+
+#[test]
+fn test_locale_does_not_change_csv() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/large_amounts.csv", "--locale", "fr"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,1234567.5,0,1234567.5,false"));
+}
+
+#[test]
+fn test_unknown_locale() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/large_amounts.csv", "--pretty", "--locale", "xx"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--locale"));
+}
+
+#[test]
+fn test_quiet_suppresses_report() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--quiet", "--summary"])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains("Rows processed: 13"));
+}
+
+#[test]
+fn test_stream_output_in_input_order() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/stream_output.csv", "--stream-output"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n2,60,0,60,false\n1,0,50,50,false\n3,10,0,10,false\n");
+}
+
+#[test]
+fn test_stream_output_requires_grouped_clients() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/multiple_clients.csv", "--stream-output"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("1,100,0,100,false"))
+        .stderr(predicate::str::contains("client 1 appears again at tx 4"));
+}
+
+#[test]
+fn test_pretty_and_quiet_conflict() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--quiet", "--pretty"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("mutually exclusive"));
+}
+
+#[test]
+fn test_report_template_fixed_width() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--report-template", "tests/fixtures/report_template.txt"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+
+    assert!(lines[0].starts_with("Hsample_transactions.csv "), "{}", report);
+    assert_eq!(lines[0].len(), 1 + 24 + 8, "{}", report);
+    assert_eq!(lines[1..], ["D00001          75A", "D00002           0L", "T000002000004"]);
+}
+
+#[test]
+fn test_report_template_escapes_html() {
+    let dir = std::env::temp_dir().join(format!("trx_template_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("a&b.csv");
+    std::fs::copy("tests/fixtures/sample_transactions.csv", &input).unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input.to_str().unwrap(), "--report-template", "tests/fixtures/report_template.html"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<h1>a&amp;b.csv</h1>"))
+        .stdout(predicate::str::contains("<tr><td>1</td><td>75</td></tr>"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_report_template_replaces_the_csv() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--report-template", "tests/fixtures/report_template.txt", "--pretty"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--report-template' cannot be combined with '--pretty'"));
+}
+
+#[test]
+fn test_output_format_html_dashboard() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--output-format", "html"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report = String::from_utf8(output).unwrap();
+
+    assert!(report.starts_with("<!DOCTYPE html>"), "{}", report);
+    assert!(report.contains("<h1>Accounts: sample_transactions.csv</h1>"), "{}", report);
+    assert!(report.contains("<span class=\"label\">deposit</span>"), "{}", report);
+    assert!(report.contains("4 (30.8%)"), "{}", report);
+    assert!(report.contains("<span class=\"label\">insufficient_funds_or_locked</span>"), "{}", report);
+    assert!(report.contains("<tr><td>1</td><td>75</td><td>0</td><td>75</td><td>false</td></tr>"), "{}", report);
+    assert!(report.contains("<tr class=\"locked\"><td>2</td>"), "{}", report);
+    // Self-contained, so it can be mailed or archived as a single file
+    assert!(!report.contains("src=\"http") && !report.contains("href=\"http"), "{}", report);
+}
+
+#[test]
+fn test_output_format_html_conflicts() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--output-format", "html", "--quiet"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--output-format html' cannot be combined"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--output-format", "pdf"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value 'pdf' for '--output-format'"));
+}
+
+// ============================================================================
+// Diagnostics Tests
+// ============================================================================
+
+#[test]
+fn test_rejected_rows_are_warned_about() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--color", "never"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "warning: line 6: WITHDRAWAL REJECTED: client=1, tx=4, amount=50, reason=insufficient_funds_or_locked\n  | withdrawal, 1, 4, 50.0\n"
+        ))
+        .stderr(predicate::str::contains("\x1b[").not());
+}
+
+#[test]
+fn test_color_always() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/sample_transactions.csv", "--color", "always"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("\x1b[1;33mwarning\x1b[0m: line 6:"));
+}
+
+#[test]
+fn test_malformed_row_is_an_error() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/batch_with_bad_row.csv", "--color", "never"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("error: line 5: "))
+        .stderr(predicate::str::contains("  | refund, 1, 4, 10.0\n"));
+}
+
+#[test]
+fn test_malformed_rows_go_to_dead_letter_file() {
+    let dead_letter = std::env::temp_dir().join(format!("trx_dead_letter_{}.jsonl", std::process::id()));
+
+    // The same file twice, as if a source redelivered it
+    for _ in 0..2 {
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .arg("tests/fixtures/batch_with_bad_row.csv")
+            .arg("--dead-letter")
+            .arg(&dead_letter)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("1,125,0,125,false"))
+            .stderr(predicate::str::contains("1 malformed row(s) written to "));
+    }
+
+    let dead_letter_str = std::fs::read_to_string(&dead_letter).unwrap();
+    let _ = std::fs::remove_file(&dead_letter);
+
+    let lines: Vec<&str> = dead_letter_str.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(
+        r#"{"source":"tests/fixtures/batch_with_bad_row.csv","line":5,"row":"refund,1,4,10.0","error":"Unknown transaction type: refund","#
+    ));
+    assert!(lines[0].contains(r#""attempts":1,"#));
+    assert!(lines[1].contains(r#""attempts":2,"#));
+}
+
+#[test]
+fn test_identical_dead_letters_keep_their_own_attempts() {
+    let dead_letter = std::env::temp_dir().join(format!("trx_repeated_dead_letter_{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&dead_letter);
+
+    // Lines 3 and 5 are the same row
+    for _ in 0..2 {
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .arg("tests/fixtures/repeated_bad_rows.csv")
+            .arg("--dead-letter")
+            .arg(&dead_letter)
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("2 malformed row(s) written to "));
+    }
+
+    let dead_letter_str = std::fs::read_to_string(&dead_letter).unwrap();
+    let _ = std::fs::remove_file(&dead_letter);
+
+    let attempts: Vec<(u64, u64)> = dead_letter_str
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|letter| (letter["line"].as_u64().unwrap(), letter["attempts"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(attempts, [(3, 1), (5, 1), (3, 2), (5, 2)]);
+}
+
+// ============================================================================
+// Scenario Tests
+// ============================================================================
+
+#[test]
+fn test_scenario_passes() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["scenario", "tests/fixtures/scenario_chargeback.yaml"])
+        .assert()
+        .success()
+        .stdout("PASS chargeback locks the account\n");
+}
+
+#[test]
+fn test_scenario_reports_mismatches() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["scenario", "tests/fixtures/scenario_chargeback.yaml", "tests/fixtures/scenario_failing.yaml"])
+        .assert()
+        .failure()
+        .stdout(concat!(
+            "PASS chargeback locks the account\n",
+            "FAIL withdrawal beyond the balance\n",
+            "  client 1 available: expected -10, got 10\n",
+            "  client 2: account does not exist\n",
+        ))
+        .stderr(predicate::str::contains("1 of 2 scenario(s) failed"));
+}
+
+// ============================================================================
+// Reason Code And Metadata Tests
+// ============================================================================
+
+#[test]
+fn test_reason_codes_in_open_disputes_and_audit() {
+    let dir = std::env::temp_dir().join(format!("trx_reason_codes_{}", std::process::id()));
+    let store = format!("file://{}", dir.join("accounts").display());
+    let audit_dir = dir.join("audit").display().to_string();
+    let open_disputes = dir.join("open_disputes.csv");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/reason_codes.csv", "--store", &store, "--audit-dir", &audit_dir, "--run-id", "codes"])
+        .arg("--open-disputes")
+        .arg(&open_disputes)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("tx=2, reason=unknown_reason_code (reason_code=99.9)"));
+
+    // Tx 2 was rejected and tx 3 charged back, only tx 1 is still disputed
+    let report_str = std::fs::read_to_string(&open_disputes).unwrap();
+    assert_eq!(report_str, "client,tx,amount,held,reason_code,metadata\n1,1,100,100,10.4,\n");
+
+    // Tx 3 was disputed without a code and keeps the one of its chargeback
+    let audit_str = std::fs::read_to_string(dir.join("audit").join("codes.jsonl")).unwrap();
+    assert!(audit_str.contains(r#""state":"ChargedBack","timestamp":null,"reason_code":"4837""#));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_custom_reason_code_list() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/reason_codes.csv", "--reason-codes", "tests/fixtures/reason_codes.txt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,150,30,180,false"))
+        .stderr(predicate::str::contains("tx=1, reason=unknown_reason_code (reason_code=10.4)"))
+        .stderr(predicate::str::contains("tx=3, reason=unknown_reason_code (reason_code=4837)"));
+}
+
+#[test]
+fn test_dispute_metadata_in_open_disputes() {
+    let open_disputes = std::env::temp_dir().join(format!("trx_dispute_metadata_{}.csv", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/dispute_metadata.csv")
+        .arg("--open-disputes")
+        .arg(&open_disputes)
+        .assert()
+        .success();
+
+    let report_str = std::fs::read_to_string(&open_disputes).unwrap();
+    let _ = std::fs::remove_file(&open_disputes);
+
+    // The metadata is re-encoded as compact JSON inside a quoted cell
+    assert!(report_str.contains(r#"1,1,100,100,,"{""case_id"":""CRM-1042"",""evidence"":[""https://crm.example.com/cases/1042/receipt.pdf""]}""#));
+    assert!(report_str.contains("1,2,50,50,,\n"));
+}
+
+#[test]
+fn test_holds_mismatch_without_row_context() {
+    let dir = std::env::temp_dir().join(format!("trx_holds_mismatch_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    // An account stored before the holds ledger existed
+    std::fs::write(
+        dir.join("1.json"),
+        r#"{"client_id":1,"available":"75","held":"25","locked":false,"frozen":false,"shortfall":"0","needs_review":false,"tx_count":1,"disputes":1,"last_activity":null}"#,
+    )
+    .unwrap();
+    let input = dir.join("input.csv");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,1,2,5\n").unwrap();
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(&input)
+        .arg("--store")
+        .arg(format!("file://{}", dir.display()))
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("warning: HOLDS MISMATCH: client=1, held=25, ledger=0\n"), "{}", stderr);
+    assert!(!stderr.contains("deposit, 1, 2, 5"), "{}", stderr);
+}
+
+#[test]
+fn test_open_disputes_held_from_holds_ledger() {
+    let open_disputes = std::env::temp_dir().join(format!("trx_holds_ledger_{}.csv", std::process::id()));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/holds_ledger.csv", "--dispute-shortfall", "partial", "--open-disputes"])
+        .arg(&open_disputes)
+        .assert()
+        .success()
+        // Tx 1 and tx 3 could each hold only part of their amount, resolving tx 1 releases its 30
+        // and leaves the 40 of tx 3 and the reserved 10 held
+        .stdout(predicate::str::contains("1,30,50,80,false,10,true"))
+        .stderr(predicate::str::contains("HOLDS MISMATCH").not());
+
+    let report_str = std::fs::read_to_string(&open_disputes).unwrap();
+    let _ = std::fs::remove_file(&open_disputes);
+
+    assert_eq!(report_str, "client,tx,amount,held,reason_code,metadata\n1,3,50,40,,\n");
+}
+
+#[test]
+fn test_held_funds_outside_holds_ledger_are_reported() {
+    let dir = std::env::temp_dir().join(format!("trx_holds_mismatch_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // Account stored before the ledger was kept
+    std::fs::write(dir.join("1.json"), r#"{"client_id":1,"available":"0","held":"25","locked":false}"#).unwrap();
+    let store = format!("file://{}", dir.display());
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/basic_deposits_withdrawals.csv", "--store", &store])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("1 account(s) with held funds not matching their holds ledger"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_invalid_dispute_metadata_is_an_error() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/dispute_metadata_invalid.csv")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("error: line 3: "))
+        .stderr(predicate::str::contains("Invalid metadata: key must be a string"));
+}
+
+// ============================================================================
+// Chaos Tests
+// ============================================================================
+
+#[test]
+fn test_duplicate_rows_are_rejected() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/duplicate_rows.csv", "--dedup-window", "1000"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,20,100,120,false"))
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=1, tx=1, reason=duplicate_transaction"))
+        .stderr(predicate::str::contains("WITHDRAWAL REJECTED: client=1, tx=3, reason=duplicate_transaction"))
+        .stderr(predicate::str::contains("DISPUTE REJECTED: client=1, tx=1, reason=invalid_state"));
+
+    // Without a filter only the stored deposit is recognized, nothing is remembered
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/duplicate_rows.csv")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,90,0,90,false"))
+        .stderr(predicate::str::contains("DEPOSIT REJECTED: client=1, tx=1, reason=duplicate_transaction"))
+        .stderr(predicate::str::contains("WITHDRAWAL REJECTED").not());
+}
+
+#[test]
+fn test_idempotency_keys_drop_redelivered_rows() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg("tests/fixtures/idempotency_keys.csv")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("reason=duplicate_transaction").not());
+
+    for args in [&["--dedup-window", "1000"][..], &["--dedup-window", "1000", "--dedup-bloom"][..], &["--dedup-window", "10m"][..]] {
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .arg("tests/fixtures/idempotency_keys.csv")
+            .args(args)
+            .assert()
+            .success()
+            // The redelivered resolve would release the second dispute
+            .stdout(predicate::str::contains("1,20,100,120,false"))
+            .stderr(predicate::str::contains("WITHDRAWAL REJECTED: client=1, tx=2, reason=duplicate_transaction"))
+            .stderr(predicate::str::contains("RESOLVE REJECTED: client=1, tx=1, reason=duplicate_transaction"));
+    }
+}
+
+#[test]
+fn test_dedup_bloom_requires_key_window() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["tests/fixtures/idempotency_keys.csv", "--dedup-window", "10m", "--dedup-bloom"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--dedup-bloom' requires a key count '--dedup-window'"));
+}
+
+#[test]
+fn test_chaos_runs_match_a_clean_run() {
+    let input = "tests/cases/mixed_clients/input.csv";
+    let clean = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(input)
+        .output()
+        .unwrap()
+        .stdout;
+
+    // These seeds inject store failures, delays and duplicates between them
+    for seed in ["2", "4", "5", "8"] {
+        let dir = std::env::temp_dir().join(format!("trx_chaos_{}_{}", std::process::id(), seed));
+        let store = format!("file://{}", dir.display());
+
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .args([input, "--chaos", seed, "--store", &store, "--commit-every", "3"])
+            .assert()
+            .success()
+            .stdout(clean.clone())
+            .stderr(predicate::str::contains(format!("Chaos: seed={}, ", seed)));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+#[test]
+fn test_store_retries_ride_out_chaos_failures() {
+    let input = "tests/cases/mixed_clients/input.csv";
+    let dir = std::env::temp_dir().join(format!("trx_store_retries_{}", std::process::id()));
+    let store = format!("file://{}", dir.display());
+
+    // Seed 2 fails one store write, which a single attempt cannot get past
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--chaos", "2", "--store", &store, "--commit-every", "3", "--store-retries", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Store unavailable: 1 attempt(s) failed"));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--chaos", "2", "--store", &store, "--commit-every", "3", "--store-backoff", "1ms", "--summary"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Store retries: 1 (0 failed operations, circuit opened 0 times)"));
+    let _ = std::fs::remove_dir_all(dir);
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--store-retries", "0", "--store", "memory://"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value '0' for '--store-retries'"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--store-timeout", "0s", "--store", "memory://"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value '0s' for '--store-timeout'"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--store-timeout", "1s"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--store-timeout' require '--store'"));
+}
+
+#[test]
+fn test_workers_match_a_clean_run() {
+    let input = "tests/cases/mixed_clients/input.csv";
+    let clean = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(input)
+        .output()
+        .unwrap()
+        .stdout;
+
+    // A budget of one row hands clients between the workers after every row
+    for budget in ["1", "64"] {
+        Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+            .args([input, "--workers", "4", "--client-budget", budget])
+            .assert()
+            .success()
+            .stdout(clean.clone());
+    }
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--workers", "2", "--store", "memory://"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--workers' cannot be combined"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--client-budget", "8"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--client-budget' and '--pin-cores' require '--workers'"));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--pin-cores"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'--client-budget' and '--pin-cores' require '--workers'"));
+}
+
+#[test]
+fn test_pinned_workers_match_a_clean_run() {
+    let input = "tests/cases/mixed_clients/input.csv";
+    let clean = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(input)
+        .output()
+        .unwrap()
+        .stdout;
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--workers", "4", "--pin-cores"])
+        .assert()
+        .success()
+        .stdout(clean);
+}
+
+#[test]
+fn test_max_tps_paces_rows() {
+    let input = "tests/cases/mixed_clients/input.csv";
+    let clean = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(input)
+        .output()
+        .unwrap()
+        .stdout;
+
+    // 12 rows at 40 per second, 275ms between the first and the last
+    let started = std::time::Instant::now();
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--max-tps", "40"])
+        .assert()
+        .success()
+        .stdout(clean);
+    assert!(started.elapsed() >= std::time::Duration::from_millis(250));
+
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--max-tps", "0"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value '0' for '--max-tps'"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dump_dir_on_sigusr1() {
+    let input = "tests/cases/mixed_clients/input.csv";
+    let clean = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .arg(input)
+        .output()
+        .unwrap()
+        .stdout;
+    let dir = std::env::temp_dir().join(format!("trx_dumps_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // 12 rows at 10 per second, so the signal arrives mid-run
+    let child = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args([input, "--max-tps", "10", "--dump-dir", dir.to_str().unwrap()])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(400));
+    assert!(Command::new("kill").args(["-USR1", &child.id().to_string()]).status().unwrap().success());
+    let output = child.wait_with_output().unwrap();
+
+    // The run goes on to the same report
+    assert!(output.status.success());
+    assert_eq!(output.stdout, clean);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("State dumped to"));
+    let mut names: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+    names.sort();
+    assert_eq!(names.len(), 2);
+    assert!(names[0].starts_with("accounts-") && names[0].ends_with(".csv"));
+    assert!(names[1].starts_with("open-disputes-") && names[1].ends_with(".csv"));
+    let accounts = std::fs::read_to_string(dir.join(&names[0])).unwrap();
+    assert!(accounts.starts_with("client,available,held,total,locked"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// ============================================================================
+// Replay Tests
+// ============================================================================
+
+#[test]
+fn test_replay_keeps_scaled_pace() {
+    let started = std::time::Instant::now();
+
+    // Rows are 2 seconds apart, 6 seconds in total, replayed 20 times faster
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["replay", "tests/fixtures/replay.csv", "--speed", "20x"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "type,client,tx,amount,timestamp\n",
+            "deposit,1,1,100.0,2024-03-01T08:00:00Z\n",
+            "deposit,1,2,50.0,2024-03-01T08:00:02Z\n",
+            "withdrawal,1,3,30.0,2024-03-01T08:00:04Z\n",
+            "dispute,1,1,,2024-03-01T08:00:06Z\n",
+        ));
+
+    assert!(started.elapsed() >= std::time::Duration::from_millis(300));
+}
+
+#[test]
+fn test_replay_restamps_rows() {
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["replay", "tests/fixtures/replay.csv", "--speed", "1000x", "--timestamps"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output_str = String::from_utf8(output).unwrap();
+
+    assert_eq!(output_str.lines().count(), 5);
+    assert!(!output_str.contains("2024-03-01"));
+}
+
+#[test]
+fn test_replay_invalid_speed() {
+    Command::new(assert_cmd::cargo::cargo_bin!("trx_processor"))
+        .args(["replay", "tests/fixtures/replay.csv", "--speed", "0x"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value '0x' for '--speed'"));
+}